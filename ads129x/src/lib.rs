@@ -423,6 +423,12 @@ pub struct Sample {
 impl Sample {
     pub const VOLTS_PER_LSB: f32 = 2.42 / (1 << 23) as f32;
 
+    /// Creates a sample from a raw ADC code, for example one read back from a recording.
+    #[inline]
+    pub const fn new(raw: i32) -> Self {
+        Self { sample: raw }
+    }
+
     #[inline]
     pub fn voltage(self) -> f32 {
         (self.sample as f32) * Self::VOLTS_PER_LSB
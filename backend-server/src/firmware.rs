@@ -0,0 +1,93 @@
+//! Firmware images offered to devices.
+//!
+//! Images are stored as `{firmware_dir}/{hardware version}/{commit hash}.bin`. The most recently
+//! modified image of a hardware version is the current one.
+
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// The commit hash that the throughput test asks for. It never matches a real image.
+pub const SPEED_TEST_COMMIT: &str = "0000000";
+
+pub struct Image {
+    /// The commit the image was built from.
+    pub commit: String,
+    pub path: PathBuf,
+}
+
+impl Image {
+    /// Whether a device running `commit` already has this image. Devices may report a shorter
+    /// hash than the file name.
+    pub fn is_installed(&self, commit: &str) -> bool {
+        !commit.is_empty() && self.commit.starts_with(commit)
+    }
+}
+
+/// Returns the current image for a hardware version, or `None` if there is none.
+pub fn find_image(firmware_dir: &Path, hardware: &str) -> io::Result<Option<Image>> {
+    let entries = match fs::read_dir(firmware_dir.join(hardware)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut newest: Option<(SystemTime, Image)> = None;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("bin")) {
+            continue;
+        }
+        let Some(commit) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        let modified = fs::metadata(&path)?.modified()?;
+        let is_newer = match &newest {
+            Some((newest_modified, _)) => modified > *newest_modified,
+            None => true,
+        };
+        if is_newer {
+            let image = Image {
+                commit: commit.to_string(),
+                path: path.clone(),
+            };
+            newest = Some((modified, image));
+        }
+    }
+
+    Ok(newest.map(|(_, image)| image))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn finds_newest_image() {
+        let dir = std::env::temp_dir().join(format!("backend-server-fw-{}", std::process::id()));
+        let hw_dir = dir.join("v4");
+        fs::create_dir_all(&hw_dir).unwrap();
+
+        assert!(find_image(&dir, "v1").unwrap().is_none());
+
+        fs::write(hw_dir.join("1111111.bin"), b"old").unwrap();
+        fs::write(hw_dir.join("notes.txt"), b"not an image").unwrap();
+        // Leave enough time for the file system to record a different modification time.
+        thread::sleep(Duration::from_millis(50));
+        fs::write(hw_dir.join("2222222.bin"), b"new").unwrap();
+
+        let image = find_image(&dir, "v4").unwrap().unwrap();
+        assert_eq!(image.commit, "2222222");
+        assert!(image.is_installed("2222222"));
+        assert!(!image.is_installed("1111111"));
+        assert!(!image.is_installed(""));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,478 @@
+use std::{cell::RefCell, fs, path::PathBuf, str::FromStr};
+
+use bad_server::{
+    connector::Connection, handler::RequestHandler, method::Method, request::Request,
+    request_body::ReadError, response::ResponseStatus, HandleError, Header,
+};
+use request_signing::{ReceivedRequest, NONCE_HEADER, REQUEST_TIME_HEADER, SIGNATURE_HEADER};
+
+use crate::{
+    firmware::{self, SPEED_TEST_COMMIT},
+    measurement,
+    state::{
+        is_valid_name, unix_time, AuthError, ChunkError, FinishError, PairingStatus, State,
+        MAX_UPLOAD_SIZE,
+    },
+};
+
+/// Shared by the handlers. Requests are handled one at a time, but the state must not be
+/// borrowed across an `.await` regardless.
+pub struct Backend {
+    pub state: RefCell<State>,
+    pub firmware_dir: PathBuf,
+    /// Size of the download that the throughput test receives if there is no firmware image.
+    pub speed_test_size: usize,
+}
+
+/// Splits the part of `path` after `prefix` into its segments.
+fn path_parameters<'p>(path: &'p str, prefix: &str) -> Option<Vec<&'p str>> {
+    let path = path.strip_prefix(prefix)?;
+    let path = path.split('?').next().unwrap_or(path);
+
+    let segments = path.split('/').collect::<Vec<_>>();
+    segments
+        .iter()
+        .all(|segment| is_valid_name(segment))
+        .then_some(segments)
+}
+
+/// Reads the whole request body. Returns `None` if the body is too large.
+async fn read_body<C: Connection>(
+    request: &mut Request<'_, '_, C>,
+) -> Result<Option<Vec<u8>>, HandleError<C>> {
+    let mut body = Vec::new();
+
+    // A body without a length would be read until the client closes the connection.
+    if request.header("Content-Length").is_none() && request.header("Transfer-Encoding").is_none() {
+        return Ok(Some(body));
+    }
+
+    let mut buffer = [0; 4096];
+    while !request.is_complete() {
+        let read = request.read(&mut buffer).await?;
+        if read == 0 {
+            return Err(HandleError::Read(ReadError::UnexpectedEof));
+        }
+        if body.len() + read > MAX_UPLOAD_SIZE {
+            return Ok(None);
+        }
+        body.extend_from_slice(&buffer[..read]);
+    }
+
+    Ok(Some(body))
+}
+
+fn header_value<T: FromStr, C: Connection>(request: &Request<'_, '_, C>, name: &str) -> Option<T> {
+    request
+        .header(name)
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Why a request of a device is refused.
+struct Refused(ResponseStatus, &'static str);
+
+impl Backend {
+    /// Reads the body of a request, and checks that the request has been signed by the device.
+    async fn read_signed_body<C: Connection>(
+        &self,
+        request: &mut Request<'_, '_, C>,
+        serial: &str,
+    ) -> Result<Result<Vec<u8>, Refused>, HandleError<C>> {
+        let Some(body) = read_body(request).await? else {
+            return Ok(Err(Refused(
+                ResponseStatus::RequestEntityTooLarge,
+                "Body too large",
+            )));
+        };
+
+        if !self.authenticate(request, serial, &body) {
+            return Ok(Err(Refused(ResponseStatus::Unauthorized, "Unauthorized")));
+        }
+
+        Ok(Ok(body))
+    }
+
+    /// Checks that the request has been signed by the device.
+    fn authenticate<C: Connection>(
+        &self,
+        request: &Request<'_, '_, C>,
+        serial: &str,
+        body: &[u8],
+    ) -> bool {
+        let received = ReceivedRequest {
+            method: request.method.as_str(),
+            path: request.path,
+            time: request.header(REQUEST_TIME_HEADER),
+            nonce: request.header(NONCE_HEADER),
+            signature: request.header(SIGNATURE_HEADER),
+            body,
+        };
+
+        let result = self.state.borrow_mut().authenticate(serial, &received);
+        match result {
+            Ok(()) => true,
+            Err(AuthError::UnknownDevice) => {
+                log::warn!("Device {serial} is not registered");
+                false
+            }
+            Err(AuthError::Rejected(e)) => {
+                log::warn!("Request of device {serial} rejected: {e:?}");
+                false
+            }
+        }
+    }
+
+    /// Decodes and stores a measurement.
+    async fn save_measurement<C: Connection>(
+        &self,
+        request: Request<'_, '_, C>,
+        serial: &str,
+        timestamp: u64,
+        body: &[u8],
+    ) -> Result<(), HandleError<C>> {
+        let summary = match measurement::check(body) {
+            Ok(summary) => summary,
+            Err(e) => {
+                log::warn!("Invalid measurement from {serial}: {e:#}");
+                return request
+                    .send_error_response(ResponseStatus::BadRequest, "Invalid measurement")
+                    .await;
+            }
+        };
+
+        // Devices without a clock send 0.
+        let timestamp = if timestamp == 0 {
+            summary.start_time.unwrap_or_else(unix_time)
+        } else {
+            timestamp
+        };
+
+        let dir = self.state.borrow().device_dir(serial);
+        match measurement::store(&dir, timestamp, body) {
+            Ok(path) => {
+                log::info!(
+                    "Stored {:.1} s measurement from {serial} as {}",
+                    summary.samples as f32 / summary.sample_rate.max(1) as f32,
+                    path.display()
+                );
+                request
+                    .send_response_with_status(ResponseStatus::Created, "")
+                    .await
+            }
+            Err(e) => {
+                log::error!("Failed to store measurement: {e}");
+                request
+                    .send_error_response(ResponseStatus::InternalServerError, "Failed to store")
+                    .await
+            }
+        }
+    }
+}
+
+/// `POST /pairing/{serial}` starts pairing, `GET /pairing/{serial}/{code}` polls it.
+/// `POST /pairing/{serial}/{code}/claim` stands in for the app, which registers the device that
+/// displays the code.
+pub struct Pairing<'a> {
+    pub backend: &'a Backend,
+}
+
+impl<C: Connection> RequestHandler<C> for Pairing<'_> {
+    async fn handle(&self, request: Request<'_, '_, C>) -> Result<(), HandleError<C>> {
+        let Some(parameters) = path_parameters(request.path, "/pairing/") else {
+            return request
+                .send_error_response(ResponseStatus::NotFound, "Not found")
+                .await;
+        };
+
+        let response = {
+            let mut state = self.backend.state.borrow_mut();
+            match (request.method, parameters.as_slice()) {
+                (Method::Post, [serial]) => state
+                    .start_pairing(serial)
+                    .map(|code| (ResponseStatus::Ok, code)),
+                (Method::Get, [serial, code]) => Ok(match state.poll_pairing(serial, code) {
+                    PairingStatus::Waiting => (ResponseStatus::Accepted, String::new()),
+                    PairingStatus::Claimed(token) => (ResponseStatus::Ok, token),
+                    PairingStatus::Expired => (ResponseStatus::NotFound, String::new()),
+                }),
+                (Method::Post, [serial, code, "claim"]) => {
+                    state.claim(serial, code).map(|claimed| match claimed {
+                        true => (ResponseStatus::Ok, String::new()),
+                        false => (ResponseStatus::NotFound, String::new()),
+                    })
+                }
+                _ => Ok((ResponseStatus::NotFound, String::new())),
+            }
+        };
+
+        match response {
+            Ok((status, body)) => request.send_response_with_status(status, body).await,
+            Err(e) => {
+                log::error!("Failed to register device: {e}");
+                request
+                    .send_error_response(ResponseStatus::InternalServerError, "Failed to register")
+                    .await
+            }
+        }
+    }
+}
+
+/// `POST /upload_data/{serial}` uploads a measurement in a single request.
+pub struct UploadData<'a> {
+    pub backend: &'a Backend,
+}
+
+impl<C: Connection> RequestHandler<C> for UploadData<'_> {
+    async fn handle(&self, mut request: Request<'_, '_, C>) -> Result<(), HandleError<C>> {
+        let parameters = path_parameters(request.path, "/upload_data/");
+        let Some(&[serial]) = parameters.as_deref() else {
+            return request
+                .send_error_response(ResponseStatus::NotFound, "Not found")
+                .await;
+        };
+
+        let body = match self.backend.read_signed_body(&mut request, serial).await? {
+            Ok(body) => body,
+            Err(Refused(status, message)) => {
+                return request.send_error_response(status, message).await
+            }
+        };
+        let timestamp = header_value(&request, "X-Timestamp").unwrap_or(0);
+
+        self.backend
+            .save_measurement(request, serial, timestamp, &body)
+            .await
+    }
+}
+
+/// Resumable uploads:
+///  - `POST /upload_session/{serial}` starts a session and returns its id,
+///  - `PUT /upload_session/{serial}/{session}/{index}` uploads a chunk,
+///  - `POST /upload_session/{serial}/{session}/finish` completes the upload.
+pub struct UploadSession<'a> {
+    pub backend: &'a Backend,
+}
+
+impl<C: Connection> RequestHandler<C> for UploadSession<'_> {
+    async fn handle(&self, mut request: Request<'_, '_, C>) -> Result<(), HandleError<C>> {
+        let Some(parameters) = path_parameters(request.path, "/upload_session/") else {
+            return request
+                .send_error_response(ResponseStatus::NotFound, "Not found")
+                .await;
+        };
+        let serial = parameters[0];
+
+        let body = match self.backend.read_signed_body(&mut request, serial).await? {
+            Ok(body) => body,
+            Err(Refused(status, message)) => {
+                return request.send_error_response(status, message).await
+            }
+        };
+
+        match (request.method, parameters.as_slice()) {
+            (Method::Post, [_]) => {
+                let Some(size) = header_value::<usize, _>(&request, "X-Upload-Size") else {
+                    return request
+                        .send_error_response(ResponseStatus::BadRequest, "Missing upload size")
+                        .await;
+                };
+                if size > MAX_UPLOAD_SIZE {
+                    return request
+                        .send_error_response(ResponseStatus::RequestEntityTooLarge, "Too large")
+                        .await;
+                }
+                let timestamp = header_value(&request, "X-Timestamp").unwrap_or(0);
+
+                let session = self
+                    .backend
+                    .state
+                    .borrow_mut()
+                    .start_session(serial, size, timestamp);
+
+                log::info!("Upload session {session} started by {serial}, {size} bytes");
+                request
+                    .send_response_with_status(ResponseStatus::Created, session)
+                    .await
+            }
+            (Method::Put, [_, session, _index]) => {
+                let (Some(offset), Some(crc32)) = (
+                    header_value(&request, "X-Offset"),
+                    header_value(&request, "X-Crc32"),
+                ) else {
+                    return request
+                        .send_error_response(ResponseStatus::BadRequest, "Missing chunk headers")
+                        .await;
+                };
+
+                let result = self
+                    .backend
+                    .state
+                    .borrow_mut()
+                    .put_chunk(serial, session, offset, crc32, &body);
+
+                match result {
+                    Ok(()) => request.send_response("").await,
+                    Err(ChunkError::UnknownSession) => {
+                        request
+                            .send_error_response(ResponseStatus::NotFound, "Unknown session")
+                            .await
+                    }
+                    Err(ChunkError::OutOfBounds) => {
+                        request
+                            .send_error_response(ResponseStatus::BadRequest, "Chunk out of bounds")
+                            .await
+                    }
+                    Err(ChunkError::ChecksumMismatch) => {
+                        request
+                            .send_error_response(ResponseStatus::BadRequest, "Checksum mismatch")
+                            .await
+                    }
+                }
+            }
+            (Method::Post, [_, session, "finish"]) => {
+                let result = self
+                    .backend
+                    .state
+                    .borrow_mut()
+                    .finish_session(serial, session);
+
+                match result {
+                    Ok(upload) => {
+                        self.backend
+                            .save_measurement(request, serial, upload.timestamp, &upload.data)
+                            .await
+                    }
+                    Err(FinishError::UnknownSession) => {
+                        request
+                            .send_error_response(ResponseStatus::NotFound, "Unknown session")
+                            .await
+                    }
+                    Err(FinishError::Incomplete) => {
+                        request
+                            .send_error_response(ResponseStatus::NotFound, "Upload incomplete")
+                            .await
+                    }
+                }
+            }
+            _ => {
+                request
+                    .send_error_response(ResponseStatus::NotFound, "Not found")
+                    .await
+            }
+        }
+    }
+}
+
+/// `GET /firmware/{hardware}/{serial}/{commit}` returns the current firmware image, or 304 if the
+/// device already runs it.
+pub struct Firmware<'a> {
+    pub backend: &'a Backend,
+}
+
+impl<C: Connection> RequestHandler<C> for Firmware<'_> {
+    async fn handle(&self, mut request: Request<'_, '_, C>) -> Result<(), HandleError<C>> {
+        let parameters = path_parameters(request.path, "/firmware/");
+        let Some(&[hardware, serial, commit]) = parameters.as_deref() else {
+            return request
+                .send_error_response(ResponseStatus::NotFound, "Not found")
+                .await;
+        };
+
+        let _body = match self.backend.read_signed_body(&mut request, serial).await? {
+            Ok(body) => body,
+            Err(Refused(status, message)) => {
+                return request.send_error_response(status, message).await
+            }
+        };
+
+        // The throughput test always gets filler data, even if there is an image to send.
+        if commit == SPEED_TEST_COMMIT {
+            return send_speed_test(request, self.backend.speed_test_size).await;
+        }
+
+        let image = match firmware::find_image(&self.backend.firmware_dir, hardware) {
+            Ok(image) => image,
+            Err(e) => {
+                log::error!("Failed to look for firmware: {e}");
+                return request
+                    .send_error_response(ResponseStatus::InternalServerError, "Internal error")
+                    .await;
+            }
+        };
+
+        match image {
+            Some(image) if image.is_installed(commit) => {
+                log::info!("{serial} is up to date");
+                request
+                    .send_response_with_status(ResponseStatus::NotModified, "")
+                    .await
+            }
+            Some(image) => match fs::read(&image.path) {
+                Ok(data) => {
+                    log::info!("Sending firmware {} to {serial}", image.commit);
+                    request.send_response(data).await
+                }
+                Err(e) => {
+                    log::error!("Failed to read {}: {e}", image.path.display());
+                    request
+                        .send_error_response(ResponseStatus::InternalServerError, "Internal error")
+                        .await
+                }
+            },
+            None => {
+                log::warn!("No firmware for hardware {hardware}");
+                request
+                    .send_error_response(ResponseStatus::NotFound, "No firmware")
+                    .await
+            }
+        }
+    }
+}
+
+/// Sends `size` bytes of filler data for the throughput test.
+async fn send_speed_test<C: Connection>(
+    request: Request<'_, '_, C>,
+    size: usize,
+) -> Result<(), HandleError<C>> {
+    let length = size.to_string();
+
+    let mut response = request.start_response(ResponseStatus::Ok).await?;
+    response
+        .send_header(Header {
+            name: "Content-Length",
+            value: length.as_bytes(),
+        })
+        .await?;
+    let mut response = response.start_body().await?;
+
+    let chunk = [0xA5; 4096];
+    let mut remaining = size;
+    while remaining > 0 {
+        let len = remaining.min(chunk.len());
+        response.write(&chunk[..len]).await?;
+        remaining -= len;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splits_parameters() {
+        assert_eq!(
+            path_parameters("/pairing/A1B2/C3D4", "/pairing/"),
+            Some(vec!["A1B2", "C3D4"])
+        );
+        assert_eq!(
+            path_parameters("/upload_data/A1B2?x=1", "/upload_data/"),
+            Some(vec!["A1B2"])
+        );
+        assert_eq!(path_parameters("/pairing/", "/pairing/"), None);
+        assert_eq!(path_parameters("/pairing/A1B2//claim", "/pairing/"), None);
+        assert_eq!(path_parameters("/pairing/../token", "/pairing/"), None);
+        assert_eq!(path_parameters("/firmware/v4", "/pairing/"), None);
+    }
+}
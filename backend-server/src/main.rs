@@ -0,0 +1,122 @@
+//! A minimal backend for developing the firmware against.
+//!
+//! Implements device pairing, measurement uploads (single request and resumable sessions), and
+//! firmware updates. Requests are authenticated by their signatures, the same way the production
+//! backend does it. Data is stored in plain files:
+//!  - `{data_dir}/{serial}/token` is the token issued to a device when it is paired,
+//!  - `{data_dir}/{serial}/{timestamp}.bin` are the uploaded measurements,
+//!  - `{firmware_dir}/{hardware version}/{commit hash}.bin` are the firmware images.
+
+#![allow(stable_features)]
+#![feature(async_fn_in_trait)]
+#![allow(unknown_lints, async_fn_in_trait)]
+
+use std::{cell::RefCell, net::IpAddr, path::PathBuf};
+
+use bad_server::{
+    connector::std_compat::StdTcpSocket, handler::RequestHandler, method::Method, BadServer,
+};
+use clap::Parser;
+use log::LevelFilter;
+
+use crate::{
+    handlers::{Backend, Firmware, Pairing, UploadData, UploadSession},
+    state::State,
+};
+
+mod firmware;
+mod handlers;
+mod measurement;
+mod state;
+
+#[derive(Debug, Parser)]
+#[command(about = "Card/IO development backend")]
+struct Args {
+    /// Address to listen on. The default accepts connections from the device.
+    #[arg(long, default_value = "0.0.0.0")]
+    address: IpAddr,
+
+    #[arg(long, default_value_t = 8081)]
+    port: u16,
+
+    /// Where device tokens and measurements are stored.
+    #[arg(long, default_value = "backend-data")]
+    data_dir: PathBuf,
+
+    /// Where firmware images are looked for.
+    #[arg(long, default_value = "firmware")]
+    firmware_dir: PathBuf,
+
+    /// Register devices as soon as they start pairing, without claiming the code.
+    #[arg(long)]
+    auto_claim: bool,
+
+    /// Size of the download used by the throughput test, in bytes.
+    #[arg(long, default_value_t = 1024 * 1024)]
+    speed_test_size: usize,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    simple_logger::SimpleLogger::new()
+        .with_level(LevelFilter::Info)
+        .env()
+        .init()
+        .unwrap();
+
+    smol::block_on(run(args));
+}
+
+async fn run(args: Args) {
+    log::info!(
+        "Listening on {}:{}, storing data in {}",
+        args.address,
+        args.port,
+        args.data_dir.display()
+    );
+
+    let backend = Backend {
+        state: RefCell::new(State::new(args.data_dir, args.auto_claim)),
+        firmware_dir: args.firmware_dir,
+        speed_test_size: args.speed_test_size,
+    };
+
+    let mut socket = StdTcpSocket::with_address(args.address);
+
+    BadServer::new()
+        .with_handler(RequestHandler::with_prefix(
+            Method::Post,
+            "/pairing/",
+            Pairing { backend: &backend },
+        ))
+        .with_handler(RequestHandler::with_prefix(
+            Method::Get,
+            "/pairing/",
+            Pairing { backend: &backend },
+        ))
+        .with_handler(RequestHandler::with_prefix(
+            Method::Post,
+            "/upload_data/",
+            UploadData { backend: &backend },
+        ))
+        .with_handler(RequestHandler::with_prefix(
+            Method::Post,
+            "/upload_session/",
+            UploadSession { backend: &backend },
+        ))
+        .with_handler(RequestHandler::with_prefix(
+            Method::Put,
+            "/upload_session/",
+            UploadSession { backend: &backend },
+        ))
+        .with_handler(RequestHandler::with_prefix(
+            Method::Get,
+            "/firmware/",
+            Firmware { backend: &backend },
+        ))
+        .with_request_buffer_size::<4096>()
+        .with_header_count::<32>()
+        .listen(&mut socket, args.port)
+        .await;
+}
@@ -0,0 +1,103 @@
+//! Checks and stores uploaded measurements.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result as AnyResult};
+use signal_processing::{compressing_buffer::EkgDecoder, measurement::Measurement};
+
+/// What the server logs about a received measurement.
+pub struct Summary {
+    pub samples: usize,
+    pub sample_rate: u16,
+    pub start_time: Option<u64>,
+}
+
+/// Decodes an uploaded measurement: a 4-byte little-endian format version, followed by the
+/// measurement.
+pub fn check(body: &[u8]) -> AnyResult<Summary> {
+    let [v0, v1, v2, v3, payload @ ..] = body else {
+        bail!("Measurement is too short");
+    };
+    let version = u32::from_le_bytes([*v0, *v1, *v2, *v3]);
+
+    let measurement = Measurement::parse(version, payload)
+        .map_err(|e| anyhow!("Failed to parse measurement: {e:?}"))?;
+    let header = &measurement.header;
+
+    let Some(mut decoder) = EkgDecoder::new(header.sample_format) else {
+        bail!("Unsupported sample format: {}", header.sample_format);
+    };
+
+    let mut reader = measurement.samples;
+    let mut samples = 0;
+    while decoder
+        .read(&mut reader)
+        .map_err(|e| anyhow!("Failed to decode sample {samples}: {e:?}"))?
+        .is_some()
+    {
+        samples += 1;
+    }
+
+    Ok(Summary {
+        samples,
+        sample_rate: header.sample_rate.unwrap_or(1000),
+        start_time: header.start_time,
+    })
+}
+
+/// Stores an uploaded measurement as `{timestamp}.bin` in `dir`, without overwriting earlier
+/// uploads. The file can be exported with `cargo xtask export --source uploaded`.
+pub fn store(dir: &Path, timestamp: u64, body: &[u8]) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let mut path = dir.join(format!("{timestamp}.bin"));
+    let mut index = 1;
+    while path.exists() {
+        path = dir.join(format!("{timestamp}-{index}.bin"));
+        index += 1;
+    }
+
+    fs::write(&path, body)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(check(&[]).is_err());
+        assert!(check(&[0xFF; 16]).is_err());
+    }
+
+    #[test]
+    fn counts_samples() {
+        // Version 0 measurements only hold varint encoded samples.
+        let summary = check(&[0, 0, 0, 0, 2, 4, 6]).unwrap();
+        assert_eq!(summary.samples, 3);
+    }
+
+    #[test]
+    fn rejects_corrupted_samples() {
+        // The varint is longer than 32 bits.
+        assert!(check(&[0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+    }
+
+    #[test]
+    fn keeps_uploads_with_same_timestamp() {
+        let dir = std::env::temp_dir().join(format!("backend-server-m-{}", std::process::id()));
+
+        let first = store(&dir, 1234, b"first").unwrap();
+        let second = store(&dir, 1234, b"second").unwrap();
+
+        assert_eq!(first.file_name().unwrap(), "1234.bin");
+        assert_eq!(second.file_name().unwrap(), "1234-1.bin");
+        assert_eq!(fs::read(first).unwrap(), b"first");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
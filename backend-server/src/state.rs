@@ -0,0 +1,468 @@
+//! Registered devices, pairing codes and upload sessions.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use request_signing::{ReceivedRequest, Verifier, VerifyError};
+
+/// How long a pairing code can be claimed. The device displays it for 5 minutes.
+const PAIRING_TIME: Duration = Duration::from_secs(5 * 60);
+
+/// Number of requests remembered per device to detect replays.
+const REPLAY_WINDOW: usize = 256;
+
+/// Upload sessions are limited to this size, in bytes.
+pub const MAX_UPLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Returns `bytes` random bytes as uppercase hex.
+fn random_hex(bytes: usize) -> String {
+    let mut random = vec![0; bytes];
+    getrandom::getrandom(&mut random).expect("Failed to generate random data");
+
+    random.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+/// Whether `segment` can be used in a path, both in URLs and on disk.
+pub fn is_valid_name(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment.len() <= 40
+        && !segment.starts_with('.')
+        && segment
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_' || c == b'.')
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    UnknownDevice,
+    Rejected(VerifyError),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PairingStatus {
+    Waiting,
+    Claimed(String),
+    Expired,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ChunkError {
+    UnknownSession,
+    OutOfBounds,
+    ChecksumMismatch,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FinishError {
+    UnknownSession,
+    Incomplete,
+}
+
+struct Device {
+    token: String,
+    verifier: Verifier<REPLAY_WINDOW>,
+}
+
+struct Pairing {
+    code: String,
+    started: Instant,
+    /// The token issued when the code was claimed.
+    token: Option<String>,
+}
+
+struct UploadSession {
+    serial: String,
+    timestamp: u64,
+    data: Vec<u8>,
+    /// Offset and length of the received chunks. A resent chunk replaces the previous one.
+    chunks: BTreeMap<usize, usize>,
+}
+
+impl UploadSession {
+    fn is_complete(&self) -> bool {
+        let mut end = 0;
+        for (&offset, &len) in &self.chunks {
+            if offset > end {
+                return false;
+            }
+            end = end.max(offset + len);
+        }
+
+        end == self.data.len()
+    }
+}
+
+/// A completed upload.
+pub struct Upload {
+    pub timestamp: u64,
+    pub data: Vec<u8>,
+}
+
+pub struct State {
+    data_dir: PathBuf,
+    /// Claim pairing codes as soon as they are issued, instead of waiting for the app.
+    auto_claim: bool,
+    devices: HashMap<String, Device>,
+    pairings: HashMap<String, Pairing>,
+    sessions: HashMap<String, UploadSession>,
+}
+
+impl State {
+    pub fn new(data_dir: PathBuf, auto_claim: bool) -> Self {
+        Self {
+            data_dir,
+            auto_claim,
+            devices: HashMap::new(),
+            pairings: HashMap::new(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// The directory where the data of a device is stored.
+    pub fn device_dir(&self, serial: &str) -> PathBuf {
+        self.data_dir.join(serial)
+    }
+
+    fn token_file(&self, serial: &str) -> PathBuf {
+        self.device_dir(serial).join("token")
+    }
+
+    fn device(&mut self, serial: &str) -> Option<&mut Device> {
+        if !self.devices.contains_key(serial) {
+            let token = fs::read_to_string(self.token_file(serial)).ok()?;
+            self.devices.insert(
+                serial.to_string(),
+                Device {
+                    token: token.trim().to_string(),
+                    verifier: Verifier::new(),
+                },
+            );
+        }
+
+        self.devices.get_mut(serial)
+    }
+
+    /// Checks that a request has been signed by a registered device.
+    pub fn authenticate(
+        &mut self,
+        serial: &str,
+        request: &ReceivedRequest<'_>,
+    ) -> Result<(), AuthError> {
+        let Some(device) = self.device(serial) else {
+            return Err(AuthError::UnknownDevice);
+        };
+
+        device
+            .verifier
+            .verify(device.token.as_bytes(), request, unix_time())
+            .map_err(AuthError::Rejected)
+    }
+
+    /// Issues a new pairing code for a device.
+    pub fn start_pairing(&mut self, serial: &str) -> io::Result<String> {
+        let code = random_hex(4);
+        self.pairings.insert(
+            serial.to_string(),
+            Pairing {
+                code: code.clone(),
+                started: Instant::now(),
+                token: None,
+            },
+        );
+
+        if self.auto_claim {
+            self.claim(serial, &code)?;
+        } else {
+            log::info!("Claim device {serial} with: POST /pairing/{serial}/{code}/claim");
+        }
+
+        Ok(code)
+    }
+
+    fn pending_pairing(&mut self, serial: &str, code: &str) -> Option<&mut Pairing> {
+        let pairing = self.pairings.get(serial)?;
+        if pairing.started.elapsed() > PAIRING_TIME {
+            self.pairings.remove(serial);
+            return None;
+        }
+        if pairing.code != code {
+            return None;
+        }
+
+        self.pairings.get_mut(serial)
+    }
+
+    /// Registers the device that displays `code`, the way the app does when the user scans the
+    /// code. Returns `false` if the code is not valid.
+    pub fn claim(&mut self, serial: &str, code: &str) -> io::Result<bool> {
+        if self.pending_pairing(serial, code).is_none() {
+            return Ok(false);
+        }
+
+        let token = random_hex(32);
+        fs::create_dir_all(self.device_dir(serial))?;
+        fs::write(self.token_file(serial), &token)?;
+
+        // Requests signed with the old token are no longer accepted.
+        self.devices.insert(
+            serial.to_string(),
+            Device {
+                token: token.clone(),
+                verifier: Verifier::new(),
+            },
+        );
+        if let Some(pairing) = self.pending_pairing(serial, code) {
+            pairing.token = Some(token);
+        }
+
+        log::info!("Device {serial} registered");
+        Ok(true)
+    }
+
+    pub fn poll_pairing(&mut self, serial: &str, code: &str) -> PairingStatus {
+        let Some(pairing) = self.pending_pairing(serial, code) else {
+            return PairingStatus::Expired;
+        };
+
+        match pairing.token.clone() {
+            Some(token) => {
+                self.pairings.remove(serial);
+                PairingStatus::Claimed(token)
+            }
+            None => PairingStatus::Waiting,
+        }
+    }
+
+    /// Starts an upload of `size` bytes and returns the session id.
+    pub fn start_session(&mut self, serial: &str, size: usize, timestamp: u64) -> String {
+        // A device uploads one measurement at a time, older sessions are abandoned.
+        self.sessions.retain(|_, session| session.serial != serial);
+
+        let id = random_hex(8);
+        self.sessions.insert(
+            id.clone(),
+            UploadSession {
+                serial: serial.to_string(),
+                timestamp,
+                data: vec![0; size],
+                chunks: BTreeMap::new(),
+            },
+        );
+
+        id
+    }
+
+    fn session(&mut self, serial: &str, id: &str) -> Option<&mut UploadSession> {
+        self.sessions
+            .get_mut(id)
+            .filter(|session| session.serial == serial)
+    }
+
+    pub fn put_chunk(
+        &mut self,
+        serial: &str,
+        id: &str,
+        offset: usize,
+        crc32: u32,
+        chunk: &[u8],
+    ) -> Result<(), ChunkError> {
+        let Some(session) = self.session(serial, id) else {
+            return Err(ChunkError::UnknownSession);
+        };
+
+        let end = offset.checked_add(chunk.len());
+        let Some(target) = end.and_then(|end| session.data.get_mut(offset..end)) else {
+            return Err(ChunkError::OutOfBounds);
+        };
+        if CRC.checksum(chunk) != crc32 {
+            return Err(ChunkError::ChecksumMismatch);
+        }
+
+        target.copy_from_slice(chunk);
+        session.chunks.insert(offset, chunk.len());
+
+        Ok(())
+    }
+
+    /// Ends an upload session. An incomplete session is discarded, so that the device starts
+    /// over.
+    pub fn finish_session(&mut self, serial: &str, id: &str) -> Result<Upload, FinishError> {
+        let Some(session) = self.session(serial, id) else {
+            return Err(FinishError::UnknownSession);
+        };
+        let complete = session.is_complete();
+
+        let session = self.sessions.remove(id).unwrap();
+        if !complete {
+            return Err(FinishError::Incomplete);
+        }
+
+        Ok(Upload {
+            timestamp: session.timestamp,
+            data: session.data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use request_signing::{body_hash, HexDigest, SignedRequest};
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("backend-server-{name}-{}", random_hex(4)));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn pairing_issues_token_once_claimed() {
+        let dir = temp_dir("pairing");
+        let mut state = State::new(dir.clone(), false);
+
+        let code = state.start_pairing("A1B2").unwrap();
+        assert_eq!(state.poll_pairing("A1B2", &code), PairingStatus::Waiting);
+        assert_eq!(state.poll_pairing("A1B2", "wrong"), PairingStatus::Expired);
+
+        assert!(!state.claim("A1B2", "wrong").unwrap());
+        assert!(state.claim("A1B2", &code).unwrap());
+
+        let PairingStatus::Claimed(token) = state.poll_pairing("A1B2", &code) else {
+            panic!("Device not claimed");
+        };
+        assert_eq!(fs::read_to_string(dir.join("A1B2/token")).unwrap(), token);
+
+        // The code can only be used once.
+        assert_eq!(state.poll_pairing("A1B2", &code), PairingStatus::Expired);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn authenticates_registered_devices() {
+        let dir = temp_dir("auth");
+        let mut state = State::new(dir.clone(), true);
+
+        let code = state.start_pairing("A1B2").unwrap();
+        let PairingStatus::Claimed(token) = state.poll_pairing("A1B2", &code) else {
+            panic!("Device not claimed");
+        };
+
+        let time = unix_time();
+        let signature = HexDigest::new(
+            &SignedRequest {
+                method: "POST",
+                path: "/upload_data/A1B2",
+                time,
+                nonce: 1,
+                body_hash: body_hash(b"data"),
+            }
+            .signature(token.as_bytes()),
+        );
+        let time = time.to_string();
+        let request = ReceivedRequest {
+            method: "POST",
+            path: "/upload_data/A1B2",
+            time: Some(&time),
+            nonce: Some("1"),
+            signature: Some(signature.as_str()),
+            body: b"data",
+        };
+
+        assert_eq!(
+            state.authenticate("C3D4", &request),
+            Err(AuthError::UnknownDevice)
+        );
+        assert_eq!(state.authenticate("A1B2", &request), Ok(()));
+
+        // The token is loaded from disk after a restart.
+        let mut state = State::new(dir.clone(), false);
+        let request = ReceivedRequest {
+            nonce: Some("2"),
+            ..request
+        };
+        assert_eq!(
+            state.authenticate("A1B2", &request),
+            Err(AuthError::Rejected(VerifyError::InvalidSignature))
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn assembles_chunked_upload() {
+        let mut state = State::new(temp_dir("chunks"), false);
+        let data = (0..100).collect::<Vec<u8>>();
+
+        let id = state.start_session("A1B2", data.len(), 1234);
+        let crc = |chunk: &[u8]| CRC.checksum(chunk);
+
+        for (offset, chunk) in [(0, &data[..40]), (80, &data[80..])] {
+            assert_eq!(
+                state.put_chunk("A1B2", &id, offset, crc(chunk), chunk),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            state.put_chunk("C3D4", &id, 40, crc(&data[40..80]), &data[40..80]),
+            Err(ChunkError::UnknownSession)
+        );
+        assert_eq!(
+            state.put_chunk("A1B2", &id, 40, 0, &data[40..80]),
+            Err(ChunkError::ChecksumMismatch)
+        );
+        assert_eq!(
+            state.put_chunk("A1B2", &id, 80, crc(&data[40..80]), &data[40..80]),
+            Err(ChunkError::OutOfBounds)
+        );
+        assert_eq!(
+            state.put_chunk("A1B2", &id, 40, crc(&data[40..80]), &data[40..80]),
+            Ok(())
+        );
+
+        let upload = state.finish_session("A1B2", &id).unwrap();
+        assert_eq!(upload.timestamp, 1234);
+        assert_eq!(upload.data, data);
+
+        assert!(matches!(
+            state.finish_session("A1B2", &id),
+            Err(FinishError::UnknownSession)
+        ));
+    }
+
+    #[test]
+    fn discards_incomplete_upload() {
+        let mut state = State::new(temp_dir("incomplete"), false);
+        let data = [1; 10];
+
+        let id = state.start_session("A1B2", 20, 0);
+        assert_eq!(
+            state.put_chunk("A1B2", &id, 10, CRC.checksum(&data), &data),
+            Ok(())
+        );
+
+        assert!(matches!(
+            state.finish_session("A1B2", &id),
+            Err(FinishError::Incomplete)
+        ));
+        assert!(matches!(
+            state.finish_session("A1B2", &id),
+            Err(FinishError::UnknownSession)
+        ));
+    }
+}
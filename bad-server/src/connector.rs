@@ -40,21 +40,25 @@ pub mod embassy_net_compat {
 
 #[cfg(feature = "std")]
 pub mod std_compat {
-    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
 
     use async_io::Async;
-    use embedded_io::Io;
+    use embedded_io_async::{ErrorKind, ErrorType};
     use smol::io::{AsyncReadExt, AsyncWriteExt};
 
     use super::*;
 
     pub struct StdTcpSocket {
+        address: IpAddr,
+        listener: Option<Async<TcpListener>>,
         socket: Option<Async<TcpStream>>,
     }
 
     impl Clone for StdTcpSocket {
         fn clone(&self) -> Self {
             Self {
+                address: self.address,
+                listener: None,
                 socket: match self.socket {
                     Some(ref socket) => {
                         Some(Async::new(socket.get_ref().try_clone().unwrap()).unwrap())
@@ -66,8 +70,19 @@ pub mod std_compat {
     }
 
     impl StdTcpSocket {
+        /// Creates a socket that only accepts connections from this computer.
         pub fn new() -> Self {
-            Self { socket: None }
+            Self::with_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        }
+
+        /// Creates a socket that listens on the given local address. Use
+        /// [`Ipv4Addr::UNSPECIFIED`] to accept connections from other devices.
+        pub fn with_address(address: IpAddr) -> Self {
+            Self {
+                address,
+                listener: None,
+                socket: None,
+            }
         }
     }
 
@@ -79,13 +94,13 @@ pub mod std_compat {
         }
     }
 
-    impl embedded_io::Error for StdError {
-        fn kind(&self) -> embedded_io::ErrorKind {
-            embedded_io::ErrorKind::Other
+    impl embedded_io_async::Error for StdError {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
         }
     }
 
-    impl Io for StdTcpSocket {
+    impl ErrorType for StdTcpSocket {
         type Error = StdError;
     }
 
@@ -107,7 +122,12 @@ pub mod std_compat {
         type AcceptError = StdError;
 
         async fn listen(&mut self, port: u16) -> Result<(), Self::AcceptError> {
-            let listener = Async::<TcpListener>::bind(SocketAddr::from(([127, 0, 0, 1], port)))?;
+            // The listener is kept so that connections are not refused between two requests.
+            if self.listener.is_none() {
+                let address = SocketAddr::new(self.address, port);
+                self.listener = Some(Async::<TcpListener>::bind(address)?);
+            }
+            let listener = self.listener.as_ref().unwrap();
             let (socket, _) = listener.accept().await?;
 
             self.socket = Some(socket);
@@ -121,7 +141,8 @@ pub mod std_compat {
             };
             let socket = socket.into_inner().unwrap();
 
-            socket.shutdown(std::net::Shutdown::Both).unwrap();
+            // The client may have closed the connection already.
+            let _ = socket.shutdown(std::net::Shutdown::Both);
             debug!("Socket closed");
         }
     }
@@ -39,7 +39,12 @@ pub trait RequestHandler<C: Connection>: Sized {
     async fn handle(&self, request: Request<'_, '_, C>) -> Result<(), HandleError<C>>;
 
     fn new(method: Method, path: &str, handler: Self) -> RequestWithMatcher<'_, C, Self> {
-        RequestWithMatcher::new(method, path, handler)
+        RequestWithMatcher::new(method, PathMatch::Exact(path), handler)
+    }
+
+    /// Handles requests whose path starts with `prefix`, for paths that contain parameters.
+    fn with_prefix(method: Method, prefix: &str, handler: Self) -> RequestWithMatcher<'_, C, Self> {
+        RequestWithMatcher::new(method, PathMatch::Prefix(prefix), handler)
     }
 
     fn get(path: &str, handler: Self) -> RequestWithMatcher<'_, C, Self> {
@@ -107,15 +112,30 @@ impl<C: Connection> RequestHandler<C> for StaticHandler<'_> {
     }
 }
 
+#[derive(Clone, Copy)]
+enum PathMatch<'a> {
+    Exact(&'a str),
+    Prefix(&'a str),
+}
+
+impl PathMatch<'_> {
+    fn matches(self, path: &str) -> bool {
+        match self {
+            Self::Exact(expected) => expected == path,
+            Self::Prefix(prefix) => path.starts_with(prefix),
+        }
+    }
+}
+
 pub struct RequestWithMatcher<'a, C: Connection, H: RequestHandler<C>> {
     method: Method,
-    path: &'a str,
+    path: PathMatch<'a>,
     handler: H,
     _connection: PhantomData<C>,
 }
 
 impl<'a, C: Connection, H: RequestHandler<C>> RequestWithMatcher<'a, C, H> {
-    fn new(method: Method, path: &'a str, handler: H) -> Self {
+    fn new(method: Method, path: PathMatch<'a>, handler: H) -> Self {
         Self {
             method,
             path,
@@ -133,7 +153,7 @@ where
     type Connection = C;
 
     fn handles(&self, request: &Request<'_, '_, C>) -> bool {
-        self.method == request.method && self.path == request.path
+        self.method == request.method && self.path.matches(request.path)
     }
 
     async fn handle(&self, request: Request<'_, '_, C>) -> Result<(), HandleError<C>> {
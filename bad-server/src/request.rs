@@ -98,6 +98,14 @@ impl<'req, 's, C: Connection> Request<'req, 's, C> {
         self.send_response_impl(ResponseStatus::Ok, body).await
     }
 
+    pub async fn send_response_with_status(
+        self,
+        status: ResponseStatus,
+        body: impl AsRef<[u8]>,
+    ) -> Result<(), HandleError<C>> {
+        self.send_response_impl(status, body).await
+    }
+
     pub async fn send_error_response(
         self,
         status: ResponseStatus,
@@ -10,8 +10,11 @@ use crate::{connector::Connection, HandleError};
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ResponseStatus {
     Ok = 200,
+    Created = 201,
+    Accepted = 202,
     NotModified = 304,
     BadRequest = 400,
+    Unauthorized = 401,
     NotFound = 404,
     RequestEntityTooLarge = 413,
     InternalServerError = 500,
@@ -22,8 +25,11 @@ impl ResponseStatus {
     pub fn name(self) -> &'static str {
         match self {
             Self::Ok => "OK",
+            Self::Created => "Created",
+            Self::Accepted => "Accepted",
             Self::NotModified => "Not Modified",
             Self::BadRequest => "Bad Request",
+            Self::Unauthorized => "Unauthorized",
             Self::NotFound => "Not Found",
             Self::RequestEntityTooLarge => "Request Entity Too Large",
             Self::InternalServerError => "Internal Server Error",
@@ -40,7 +40,8 @@ pub async fn run() {
 
     let context = SharedWebContext::new(WebContext {
         known_networks,
-        backend_url: heapless::String::from("http://localhost:8080"),
+        backend_url: heapless::String::from("http://localhost:8081"),
+        server_key_pin: heapless::String::new(),
     });
 
     config_site::create(&context, "Example")
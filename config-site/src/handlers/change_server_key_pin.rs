@@ -0,0 +1,66 @@
+use bad_server::{
+    connector::Connection, handler::RequestHandler, request::Request, response::ResponseStatus,
+    HandleError,
+};
+use logger::{debug, warn};
+
+use crate::data::SharedWebContext;
+
+pub struct ChangeServerKeyPin<'a> {
+    pub context: &'a SharedWebContext,
+}
+
+impl<C: Connection> RequestHandler<C> for ChangeServerKeyPin<'_> {
+    async fn handle(&self, mut request: Request<'_, '_, C>) -> Result<(), HandleError<C>> {
+        let mut buf = [0u8; 100];
+
+        debug!("Reading POST data");
+        let post_data = request.read_all(&mut buf).await?;
+
+        if !request.is_complete() {
+            return request
+                .send_error_response(ResponseStatus::RequestEntityTooLarge, "POST body too large")
+                .await;
+        }
+
+        let post_body = match core::str::from_utf8(post_data) {
+            Ok(body) => body.trim(),
+            Err(_err) => {
+                warn!("Invalid UTF-8 in POST body: {:?}", post_data);
+                return request
+                    .send_error_response(ResponseStatus::BadRequest, "Input is not valid text")
+                    .await;
+            }
+        };
+        debug!("POST body: {:?}", post_body);
+
+        if !validate_pin(post_body) {
+            return request
+                .send_error_response(
+                    ResponseStatus::BadRequest,
+                    "Input is not a SHA-256 hash in hex format",
+                )
+                .await;
+        }
+
+        let result = {
+            // Scope-limit the lock guard
+            let mut context = self.context.lock().await;
+            context.server_key_pin.clear();
+            context.server_key_pin.push_str(post_body)
+        };
+
+        if result.is_err() {
+            return request
+                .send_error_response(ResponseStatus::BadRequest, "Pin is too long")
+                .await;
+        }
+
+        request.send_response("").await
+    }
+}
+
+/// An empty pin disables verification.
+fn validate_pin(pin: &str) -> bool {
+    pin.is_empty() || (pin.len() == 64 && pin.bytes().all(|b| b.is_ascii_hexdigit()))
+}
@@ -1,8 +1,10 @@
 pub mod add_new_network;
 pub mod backend_url;
 pub mod change_backend_url;
+pub mod change_server_key_pin;
 pub mod delete_network;
 pub mod list_known_networks;
+pub mod server_key_pin;
 
 use bad_server::{handler::StaticHandler, Header};
 
@@ -0,0 +1,22 @@
+use bad_server::{
+    connector::Connection, handler::RequestHandler, request::Request, response::ResponseStatus,
+    HandleError,
+};
+
+use crate::data::SharedWebContext;
+
+pub struct ServerKeyPin<'a> {
+    pub context: &'a SharedWebContext,
+}
+
+impl<C: Connection> RequestHandler<C> for ServerKeyPin<'_> {
+    async fn handle(&self, request: Request<'_, '_, C>) -> Result<(), HandleError<C>> {
+        let response = request.start_response(ResponseStatus::Ok).await?;
+        let mut response = response.start_chunked_body().await?;
+
+        let context = self.context.lock().await;
+        response.write(&context.server_key_pin).await?;
+
+        response.end_chunked_response().await
+    }
+}
@@ -16,8 +16,9 @@ use crate::{
     data::SharedWebContext,
     handlers::{
         add_new_network::AddNewNetwork, backend_url::BackendUrl,
-        change_backend_url::ChangeBackendUrl, delete_network::DeleteNetwork,
-        list_known_networks::ListKnownNetworks, HEADER_FONT, INDEX_HANDLER,
+        change_backend_url::ChangeBackendUrl, change_server_key_pin::ChangeServerKeyPin,
+        delete_network::DeleteNetwork, list_known_networks::ListKnownNetworks,
+        server_key_pin::ServerKeyPin, HEADER_FONT, INDEX_HANDLER,
     },
 };
 
@@ -52,4 +53,6 @@ where
         .with_handler(RequestHandler::post("/dn", DeleteNetwork { context }))
         .with_handler(RequestHandler::get("/bu", BackendUrl { context }))
         .with_handler(RequestHandler::post("/cbu", ChangeBackendUrl { context }))
+        .with_handler(RequestHandler::get("/kp", ServerKeyPin { context }))
+        .with_handler(RequestHandler::post("/ckp", ChangeServerKeyPin { context }))
 }
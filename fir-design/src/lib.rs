@@ -0,0 +1,203 @@
+//! Windowed-sinc FIR filter design.
+//!
+//! Shared by the `designfilt!` macro and the runtime filter design of `signal-processing`, so that
+//! filters designed at compile time and at runtime have the same coefficients.
+//!
+//! Frequencies are normalized to the sample rate, so 0.5 is the Nyquist frequency. The
+//! calculations are done in double precision using `libm`.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    Kaiser { beta: f64 },
+}
+
+impl Window {
+    /// Returns the `n`th coefficient of a window of `len` samples.
+    pub fn coefficient(self, n: usize, len: usize) -> f64 {
+        if len == 1 {
+            return 1.0;
+        }
+
+        let x = n as f64 / (len - 1) as f64;
+        match self {
+            Self::Rectangular => 1.0,
+            Self::Hann => 0.5 - 0.5 * libm::cos(2.0 * PI * x),
+            Self::Hamming => 0.54 - 0.46 * libm::cos(2.0 * PI * x),
+            Self::Blackman => 0.42 - 0.5 * libm::cos(2.0 * PI * x) + 0.08 * libm::cos(4.0 * PI * x),
+            Self::Kaiser { beta } => {
+                let r = 2.0 * x - 1.0;
+                bessel_i0(beta * libm::sqrt(1.0 - r * r)) / bessel_i0(beta)
+            }
+        }
+    }
+}
+
+/// Zeroth order modified Bessel function of the first kind.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x = x / 2.0;
+
+    for k in 1..50 {
+        term *= half_x / k as f64;
+        let squared = term * term;
+        sum += squared;
+        if squared < sum * 1e-16 {
+            break;
+        }
+    }
+
+    sum
+}
+
+/// Computes the filter order and Kaiser window parameter that achieve the given stopband
+/// attenuation (in dB) with the given transition width.
+pub fn kaiser_parameters(attenuation: f64, transition_width: f64) -> (usize, f64) {
+    let beta = if attenuation > 50.0 {
+        0.1102 * (attenuation - 8.7)
+    } else if attenuation >= 21.0 {
+        0.5842 * libm::pow(attenuation - 21.0, 0.4) + 0.07886 * (attenuation - 21.0)
+    } else {
+        0.0
+    };
+
+    let order = (attenuation - 7.95) / (2.285 * 2.0 * PI * transition_width);
+
+    (libm::ceil(order).max(1.0) as usize, beta)
+}
+
+fn sinc_lowpass(coeffs: &mut [f64], cutoff: f64, window: Window) {
+    let len = coeffs.len();
+    let center = (len - 1) as f64 / 2.0;
+
+    for (n, coeff) in coeffs.iter_mut().enumerate() {
+        let t = n as f64 - center;
+        let ideal = if t == 0.0 {
+            2.0 * cutoff
+        } else {
+            libm::sin(2.0 * PI * cutoff * t) / (PI * t)
+        };
+
+        *coeff = ideal * window.coefficient(n, len);
+    }
+}
+
+/// Designs a lowpass filter with unity gain at DC into `coeffs`. The order of the filter is
+/// `coeffs.len() - 1`.
+pub fn lowpass(coeffs: &mut [f64], cutoff: f64, window: Window) {
+    sinc_lowpass(coeffs, cutoff, window);
+
+    let gain = coeffs.iter().sum::<f64>();
+    coeffs.iter_mut().for_each(|c| *c /= gain);
+}
+
+/// Designs a highpass filter with unity gain at the Nyquist frequency into `coeffs`. The order of
+/// the filter is `coeffs.len() - 1`, which must be even.
+pub fn highpass(coeffs: &mut [f64], cutoff: f64, window: Window) {
+    assert!(
+        coeffs.len() % 2 == 1,
+        "highpass FIR filters must have an even order"
+    );
+
+    // Spectral inversion of the complementary lowpass filter
+    lowpass(coeffs, cutoff, window);
+    coeffs.iter_mut().for_each(|c| *c = -*c);
+    coeffs[coeffs.len() / 2] += 1.0;
+
+    // The sign of the response at Nyquist depends on the group delay, only the magnitude matters.
+    let gain = coeffs
+        .iter()
+        .enumerate()
+        .map(|(n, c)| if n % 2 == 0 { *c } else { -*c })
+        .sum::<f64>();
+    let gain = libm::fabs(gain);
+    coeffs.iter_mut().for_each(|c| *c /= gain);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Returns the magnitude response of `coeffs` at the normalized `frequency`.
+    fn gain(coeffs: &[f64], frequency: f64) -> f64 {
+        let (re, im) = coeffs
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (n, c)| {
+                let phase = 2.0 * PI * frequency * n as f64;
+                (re + c * phase.cos(), im - c * phase.sin())
+            });
+        re.hypot(im)
+    }
+
+    fn db(gain: f64) -> f64 {
+        20.0 * gain.log10()
+    }
+
+    /// Returns the largest gain between `from` and `to`.
+    fn peak_gain(coeffs: &[f64], from: f64, to: f64) -> f64 {
+        (0..=1000)
+            .map(|i| gain(coeffs, from + (to - from) * i as f64 / 1000.0))
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn lowpass_response() {
+        let mut coeffs = [0.0; 101];
+        lowpass(&mut coeffs, 0.1, Window::Hamming);
+
+        assert!((gain(&coeffs, 0.0) - 1.0).abs() < 1e-12);
+        // The windowed-sinc design is -6dB at the cutoff
+        assert!(
+            (gain(&coeffs, 0.1) - 0.5).abs() < 0.01,
+            "{}",
+            gain(&coeffs, 0.1)
+        );
+        // The Hamming window has a transition band of around 3.3/N and over 50dB attenuation
+        let stopband = db(peak_gain(&coeffs, 0.1 + 4.0 / 101.0, 0.5));
+        assert!(stopband < -50.0, "{stopband}dB");
+    }
+
+    #[test]
+    fn highpass_response() {
+        let mut coeffs = [0.0; 101];
+        highpass(&mut coeffs, 0.1, Window::Hamming);
+
+        assert!((gain(&coeffs, 0.5) - 1.0).abs() < 1e-12);
+        assert!(
+            (gain(&coeffs, 0.1) - 0.5).abs() < 0.01,
+            "{}",
+            gain(&coeffs, 0.1)
+        );
+        // Spectral inversion turns the passband ripple of the lowpass filter into the stopband
+        let stopband = db(peak_gain(&coeffs, 0.0, 0.1 - 4.0 / 101.0));
+        assert!(stopband < -45.0, "{stopband}dB");
+    }
+
+    #[test]
+    fn kaiser_meets_attenuation() {
+        for attenuation in [30.0, 60.0, 80.0] {
+            let (order, beta) = kaiser_parameters(attenuation, 0.05);
+
+            let mut coeffs = vec![0.0; order + 1];
+            lowpass(&mut coeffs, 0.2, Window::Kaiser { beta });
+
+            // The cutoff is in the middle of the transition band
+            let passband = peak_gain(&coeffs, 0.0, 0.175);
+            let stopband = db(peak_gain(&coeffs, 0.225, 0.5));
+            assert!(db(passband) < 0.5, "{attenuation}dB: {passband}");
+            assert!(
+                stopband < -attenuation + 1.0,
+                "{attenuation}dB: {stopband}dB"
+            );
+        }
+    }
+}
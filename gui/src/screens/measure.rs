@@ -5,7 +5,7 @@ use embedded_graphics::{
     pixelcolor::BinaryColor,
     prelude::{DrawTarget, OriginDimensions, Point},
     primitives::{Line, Primitive, PrimitiveStyle},
-    text::{Baseline, Text},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
     Drawable,
 };
 use itertools::Itertools;
@@ -119,6 +119,7 @@ pub struct EcgScreen {
     buffer: SlidingWindow<128>,
     pub heart_rate: Option<NonZeroU8>,
     pub elapsed_secs: usize,
+    pub poor_signal: bool,
     camera: RefCell<Camera>,
 }
 
@@ -128,6 +129,7 @@ impl EcgScreen {
             buffer: SlidingWindow::new(),
             heart_rate: None,
             elapsed_secs: 0,
+            poor_signal: false,
             camera: RefCell::new(Camera {
                 min_limit: Limit::new(LimitKind::Min),
                 max_limit: Limit::new(LimitKind::Max),
@@ -223,6 +225,20 @@ impl Drawable for EcgScreen {
                 .draw(display)?;
         }
 
+        if self.poor_signal {
+            let top_right = Point::new(display.bounding_box().size.width as i32 - 1, 0);
+            Text::with_text_style(
+                "Poor signal",
+                top_right,
+                NORMAL_TEXT,
+                TextStyleBuilder::new()
+                    .alignment(Alignment::Right)
+                    .baseline(Baseline::Top)
+                    .build(),
+            )
+            .draw(display)?;
+        }
+
         let (min, max) = self.limits();
 
         let scaler = unwrap!(self.camera.try_borrow_mut()).update(min, max, display);
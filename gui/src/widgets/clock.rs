@@ -0,0 +1,99 @@
+use embedded_graphics::{
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, Point, Size},
+    primitives::Rectangle,
+    text::{renderer::TextRenderer, Baseline, Text},
+    Drawable,
+};
+use embedded_layout::prelude::*;
+use ufmt::uwrite;
+
+use crate::screens::NORMAL_TEXT;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TimeOfDay {
+    pub hours: u8,
+    pub minutes: u8,
+}
+
+impl TimeOfDay {
+    /// Returns the time of day of a Unix timestamp.
+    pub fn from_timestamp(timestamp: u64) -> Self {
+        let seconds = timestamp % (24 * 60 * 60);
+
+        Self {
+            hours: (seconds / 3600) as u8,
+            minutes: (seconds / 60 % 60) as u8,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ClockView {
+    pub time: Option<TimeOfDay>,
+    top_left: Point,
+}
+
+impl ClockView {
+    #[inline]
+    pub fn new(time: Option<TimeOfDay>) -> Self {
+        Self {
+            time,
+            top_left: Point::zero(),
+        }
+    }
+
+    fn size() -> Size {
+        // The trailing space separates the clock from the icons next to it.
+        NORMAL_TEXT
+            .measure_string("00:00 ", Point::zero(), Baseline::Top)
+            .bounding_box
+            .size
+    }
+}
+
+impl View for ClockView {
+    #[inline]
+    fn translate_impl(&mut self, by: Point) {
+        self.top_left += by;
+    }
+
+    #[inline]
+    fn bounds(&self) -> Rectangle {
+        let size = if self.time.is_some() {
+            Self::size()
+        } else {
+            Size::zero()
+        };
+
+        Rectangle::new(self.top_left, size)
+    }
+}
+
+impl Drawable for ClockView {
+    type Color = BinaryColor;
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        if let Some(time) = self.time {
+            let mut string = heapless::String::<8>::new();
+            let hour_pad = if time.hours < 10 { "0" } else { "" };
+            let minute_pad = if time.minutes < 10 { "0" } else { "" };
+            _ = uwrite!(
+                &mut string,
+                "{}{}:{}{}",
+                hour_pad,
+                time.hours,
+                minute_pad,
+                time.minutes
+            );
+
+            Text::with_baseline(&string, self.top_left, NORMAL_TEXT, Baseline::Top).draw(target)?;
+        }
+
+        Ok(())
+    }
+}
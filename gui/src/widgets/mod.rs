@@ -1,5 +1,6 @@
 pub mod battery;
 pub mod battery_small;
+pub mod clock;
 pub mod progress_bar;
 pub mod slot;
 pub mod status_bar;
@@ -11,6 +11,7 @@ use crate::{
     screens::BatteryInfo,
     widgets::{
         battery_small::{Battery, BatteryStyle},
+        clock::ClockView,
         wifi_access_point::WifiAccessPointStateView,
         wifi_client::WifiClientStateView,
     },
@@ -18,6 +19,7 @@ use crate::{
 
 #[derive(ViewGroup, Clone, Copy)]
 pub struct StatusBar {
+    pub clock: ClockView,
     pub battery: Battery,
     pub wifi_sta: WifiClientStateView,
     pub wifi_ap: WifiAccessPointStateView,
@@ -51,6 +53,7 @@ impl Drawable for StatusBar {
 
         views.align_to_mut(&display.bounding_box(), horizontal::Right, vertical::Top);
 
+        views.clock.draw(display)?;
         views.battery.draw(display)?;
         views.wifi_sta.draw(display)?;
         views.wifi_ap.draw(display)?;
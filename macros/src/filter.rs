@@ -3,16 +3,25 @@ use quote::quote;
 use sci_rs::signal::filter::design::{
     iirfilter_dyn, BaFormatFilter, DigitalFilter, FilterBandType, FilterOutputType, FilterType,
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, f64::consts::PI};
 use syn::{
     parse::{Parse, ParseBuffer},
     Lit, LitStr, Token,
 };
 
+mod fir;
+
+use fir::Window;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum FilterKind {
     HighPassIir,
     LowPassIir,
+    BandPassIir,
+    BandStopIir,
+    NotchIir,
+    LowPassFir,
+    HighPassFir,
 }
 
 impl Parse for FilterKind {
@@ -23,6 +32,11 @@ impl Parse for FilterKind {
         let filter_kind = match filter_kind.as_str() {
             "highpassiir" => FilterKind::HighPassIir,
             "lowpassiir" => FilterKind::LowPassIir,
+            "bandpassiir" => FilterKind::BandPassIir,
+            "bandstopiir" => FilterKind::BandStopIir,
+            "notchiir" => FilterKind::NotchIir,
+            "lowpassfir" => FilterKind::LowPassFir,
+            "highpassfir" => FilterKind::HighPassFir,
 
             _ => {
                 return Err(syn::Error::new(
@@ -36,10 +50,16 @@ impl Parse for FilterKind {
     }
 }
 
+#[derive(Clone)]
+enum OptionValue {
+    Number(f32),
+    Text(LitStr),
+}
+
 pub struct FilterSpec {
     filter_kind: FilterKind,
     span: Span,
-    options: HashMap<String, f32>,
+    options: HashMap<String, OptionValue>,
 }
 
 impl Parse for FilterSpec {
@@ -57,9 +77,15 @@ impl Parse for FilterSpec {
             let value = input.parse::<Lit>()?;
 
             let value = match value {
-                Lit::Int(lit) => lit.base10_parse().unwrap(),
-                Lit::Float(lit) => lit.base10_parse().unwrap(),
-                _ => return Err(syn::Error::new_spanned(value, "expected a number")),
+                Lit::Int(lit) => OptionValue::Number(lit.base10_parse().unwrap()),
+                Lit::Float(lit) => OptionValue::Number(lit.base10_parse().unwrap()),
+                Lit::Str(lit) => OptionValue::Text(lit),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        value,
+                        "expected a number or a string",
+                    ))
+                }
             };
 
             if options.insert(keystr, value).is_some() {
@@ -78,20 +104,80 @@ impl Parse for FilterSpec {
     }
 }
 
+impl FilterSpec {
+    fn number(&self, key: &str, name: &str) -> syn::Result<Option<f32>> {
+        match self.options.get(key) {
+            Some(OptionValue::Number(value)) => Ok(Some(*value)),
+            Some(OptionValue::Text(lit)) => Err(syn::Error::new(
+                lit.span(),
+                format!("'{name}' must be a number"),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn required_number(&self, key: &str, name: &str) -> syn::Result<f32> {
+        self.number(key, name)?
+            .ok_or_else(|| syn::Error::new(self.span, format!("missing required option '{name}'")))
+    }
+
+    fn text(&self, key: &str, name: &str) -> syn::Result<Option<(String, Span)>> {
+        match self.options.get(key) {
+            Some(OptionValue::Text(lit)) => {
+                Ok(Some((lit.value().to_ascii_lowercase(), lit.span())))
+            }
+            Some(OptionValue::Number(_)) => Err(syn::Error::new(
+                self.span,
+                format!("'{name}' must be a string"),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the frequency normalized to the sample rate.
+    fn normalized_frequency(&self, key: &str, name: &str) -> syn::Result<f64> {
+        let frequency = self.required_number(key, name)? as f64;
+        // Without a sample rate, frequencies are normalized to the Nyquist frequency
+        let sample_rate = self.number("samplerate", "SampleRate")?.unwrap_or(2.0) as f64;
+
+        let normalized = frequency / sample_rate;
+        if normalized <= 0.0 || normalized >= 0.5 {
+            return Err(syn::Error::new(
+                self.span,
+                format!("'{name}' must be between 0 and the Nyquist frequency"),
+            ));
+        }
+
+        Ok(normalized)
+    }
+}
+
 fn validate_filter_option(filter_kind: FilterKind, key: &LitStr) -> syn::Result<String> {
-    let expected = match filter_kind {
-        FilterKind::HighPassIir => &[
+    let expected: &[&str] = match filter_kind {
+        FilterKind::HighPassIir | FilterKind::LowPassIir => &[
             "filterorder",
             "passbandfrequency",
             "halfpowerfrequency",
             "passbandripple",
+            "designmethod",
             "samplerate",
         ],
-        FilterKind::LowPassIir => &[
+        FilterKind::BandPassIir | FilterKind::BandStopIir => &[
             "filterorder",
+            "halfpowerfrequency1",
+            "halfpowerfrequency2",
+            "designmethod",
+            "samplerate",
+        ],
+        FilterKind::NotchIir => &["centerfrequency", "qualityfactor", "samplerate"],
+        FilterKind::LowPassFir | FilterKind::HighPassFir => &[
+            "filterorder",
+            "cutofffrequency",
             "passbandfrequency",
-            "halfpowerfrequency",
-            "passbandripple",
+            "stopbandfrequency",
+            "stopbandattenuation",
+            "designmethod",
+            "window",
             "samplerate",
         ],
     };
@@ -107,35 +193,70 @@ fn validate_filter_option(filter_kind: FilterKind, key: &LitStr) -> syn::Result<
     Ok(value)
 }
 
-fn iir(args: FilterSpec, ty: FilterBandType) -> TokenStream {
-    let Some(&order) = args.options.get("filterorder") else {
-        return syn::Error::new(args.span, "missing required option 'FilterOrder'")
-            .to_compile_error();
-    };
+fn band_edges(args: &FilterSpec) -> syn::Result<Vec<f32>> {
+    let low = args.required_number("halfpowerfrequency1", "HalfPowerFrequency1")?;
+    let high = args.required_number("halfpowerfrequency2", "HalfPowerFrequency2")?;
+
+    if low >= high {
+        return Err(syn::Error::new(
+            args.span,
+            "'HalfPowerFrequency1' must be lower than 'HalfPowerFrequency2'",
+        ));
+    }
 
-    let kind = match ty {
-        FilterBandType::Lowpass => quote! { LowPass },
-        FilterBandType::Highpass => quote! { HighPass },
-        FilterBandType::Bandpass => {
-            return syn::Error::new(args.span, "bandpass filters are not supported")
-                .to_compile_error();
+    Ok(vec![low, high])
+}
+
+fn iir(args: FilterSpec, ty: FilterBandType) -> syn::Result<TokenStream> {
+    let order = args.required_number("filterorder", "FilterOrder")?;
+
+    if let Some((method, span)) = args.text("designmethod", "DesignMethod")? {
+        if method != "butter" {
+            return Err(syn::Error::new(
+                span,
+                format!("unsupported design method for IIR filters: {method}"),
+            ));
         }
-        FilterBandType::Bandstop => {
-            return syn::Error::new(args.span, "bandstop filters are not supported")
-                .to_compile_error();
+    }
+
+    let (kind, cutoff) = match ty {
+        FilterBandType::Lowpass => (
+            quote! { LowPass },
+            vec![args.required_number("halfpowerfrequency", "HalfPowerFrequency")?],
+        ),
+        FilterBandType::Highpass => (
+            quote! { HighPass },
+            vec![args.required_number("halfpowerfrequency", "HalfPowerFrequency")?],
+        ),
+        FilterBandType::Bandpass => (quote! { BandPass }, band_edges(&args)?),
+        FilterBandType::Bandstop => (quote! { BandStop }, band_edges(&args)?),
+    };
+
+    // Like MATLAB, the order of band filters is that of the resulting filter, while sci-rs
+    // expects the order of the lowpass prototype.
+    let order = match ty {
+        FilterBandType::Bandpass | FilterBandType::Bandstop => {
+            if order as usize % 2 != 0 {
+                return Err(syn::Error::new(
+                    args.span,
+                    "'FilterOrder' of band filters must be even",
+                ));
+            }
+            order as usize / 2
         }
+        _ => order as usize,
     };
 
     let filter = iirfilter_dyn(
-        order as usize,
-        vec![args.options.get("halfpowerfrequency").copied().unwrap()],
+        order,
+        cutoff,
         None,
         None,
         Some(ty),
         Some(FilterType::Butterworth),
         Some(false),
         Some(FilterOutputType::Ba),
-        args.options.get("samplerate").copied(),
+        args.number("samplerate", "SampleRate")?,
     );
 
     let DigitalFilter::Ba(BaFormatFilter { mut b, mut a }) = filter else {
@@ -162,14 +283,127 @@ fn iir(args: FilterSpec, ty: FilterBandType) -> TokenStream {
 
     let n = a.len();
 
-    quote! {
+    Ok(quote! {
         Iir::<#kind, #n>::new(&[#(#b,)*], &[#(#a,)*])
+    })
+}
+
+/// Second order IIR notch filter, as designed by MATLAB's `iirnotch`.
+fn notch(args: FilterSpec) -> syn::Result<TokenStream> {
+    let w0 = 2.0 * PI * args.normalized_frequency("centerfrequency", "CenterFrequency")?;
+    let q = args.required_number("qualityfactor", "QualityFactor")? as f64;
+
+    if q <= 0.0 {
+        return Err(syn::Error::new(
+            args.span,
+            "'QualityFactor' must be positive",
+        ));
     }
+
+    let bandwidth = w0 / q;
+    let gain = 1.0 / (1.0 + (bandwidth / 2.0).tan());
+    let cos_w0 = w0.cos();
+
+    let b = [gain, -2.0 * gain * cos_w0, gain].map(|c| c as f32);
+    // Reversed and without the leading 1, as `Iir` expects
+    let a = [2.0 * gain - 1.0, -2.0 * gain * cos_w0].map(|c| c as f32);
+
+    Ok(quote! {
+        Iir::<BandStop, 2>::new(&[#(#b,)*], &[#(#a,)*])
+    })
+}
+
+fn fir(args: FilterSpec, highpass: bool) -> syn::Result<TokenStream> {
+    let method = args.text("designmethod", "DesignMethod")?;
+
+    let coeffs = match method
+        .as_ref()
+        .map(|(method, span)| (method.as_str(), *span))
+    {
+        None | Some(("window", _)) => {
+            let order = args.required_number("filterorder", "FilterOrder")? as usize;
+            let cutoff = args.normalized_frequency("cutofffrequency", "CutoffFrequency")?;
+
+            let window = match args.text("window", "Window")? {
+                Some((name, span)) => fir::window_from_name(&name).ok_or_else(|| {
+                    syn::Error::new(
+                        span,
+                        format!(
+                            "unknown window: {name}, expected one of: {}",
+                            fir::WINDOW_NAMES.join(", ")
+                        ),
+                    )
+                })?,
+                None => Window::Hamming,
+            };
+
+            if highpass && order % 2 != 0 {
+                return Err(syn::Error::new(
+                    args.span,
+                    "highpass FIR filters must have an even 'FilterOrder'",
+                ));
+            }
+
+            if highpass {
+                fir::highpass(order, cutoff, window)
+            } else {
+                fir::lowpass(order, cutoff, window)
+            }
+        }
+        Some(("kaiserwin", _)) => {
+            let passband = args.normalized_frequency("passbandfrequency", "PassbandFrequency")?;
+            let stopband = args.normalized_frequency("stopbandfrequency", "StopbandFrequency")?;
+            let attenuation = args.required_number("stopbandattenuation", "StopbandAttenuation")?;
+
+            let transition_width = if highpass {
+                passband - stopband
+            } else {
+                stopband - passband
+            };
+            if transition_width <= 0.0 {
+                return Err(syn::Error::new(
+                    args.span,
+                    "the stopband must be outside of the passband",
+                ));
+            }
+
+            let (mut order, beta) = fir::kaiser_parameters(attenuation as f64, transition_width);
+            let window = Window::Kaiser { beta };
+            let cutoff = (passband + stopband) / 2.0;
+
+            if highpass {
+                order += order % 2;
+                fir::highpass(order, cutoff, window)
+            } else {
+                fir::lowpass(order, cutoff, window)
+            }
+        }
+        Some((method, span)) => {
+            return Err(syn::Error::new(
+                span,
+                format!("unsupported design method for FIR filters: {method}"),
+            ));
+        }
+    };
+
+    let n = coeffs.len();
+    let coeffs = coeffs.into_iter().map(|c| c as f32);
+
+    Ok(quote! {
+        Fir::<#n>::from_coeffs(&[#(#coeffs,)*])
+    })
 }
 
 pub fn run(args: FilterSpec) -> TokenStream {
-    match args.filter_kind {
+    let result = match args.filter_kind {
         FilterKind::HighPassIir => iir(args, FilterBandType::Highpass),
         FilterKind::LowPassIir => iir(args, FilterBandType::Lowpass),
-    }
+        FilterKind::BandPassIir => iir(args, FilterBandType::Bandpass),
+        FilterKind::BandStopIir => iir(args, FilterBandType::Bandstop),
+        FilterKind::NotchIir => notch(args),
+        FilterKind::LowPassFir => fir(args, false),
+        FilterKind::HighPassFir => fir(args, true),
+    };
+
+    result.unwrap_or_else(|error| error.to_compile_error())
 }
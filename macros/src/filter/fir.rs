@@ -0,0 +1,32 @@
+//! Windowed-sinc FIR filter design.
+//!
+//! The design itself is shared with the runtime design in `signal-processing`, see `fir-design`.
+//! Frequencies are normalized to the sample rate, so 0.5 is the Nyquist frequency.
+
+pub use fir_design::{kaiser_parameters, Window};
+
+pub const WINDOW_NAMES: &[&str] = &["rectangular", "hann", "hamming", "blackman"];
+
+pub fn window_from_name(name: &str) -> Option<Window> {
+    match name {
+        "rectangular" | "rect" => Some(Window::Rectangular),
+        "hann" | "hanning" => Some(Window::Hann),
+        "hamming" => Some(Window::Hamming),
+        "blackman" => Some(Window::Blackman),
+        _ => None,
+    }
+}
+
+/// Designs a lowpass filter with unity gain at DC.
+pub fn lowpass(order: usize, cutoff: f64, window: Window) -> Vec<f64> {
+    let mut coeffs = vec![0.0; order + 1];
+    fir_design::lowpass(&mut coeffs, cutoff, window);
+    coeffs
+}
+
+/// Designs a highpass filter with unity gain at the Nyquist frequency. `order` must be even.
+pub fn highpass(order: usize, cutoff: f64, window: Window) -> Vec<f64> {
+    let mut coeffs = vec![0.0; order + 1];
+    fir_design::highpass(&mut coeffs, cutoff, window);
+    coeffs
+}
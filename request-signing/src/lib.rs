@@ -0,0 +1,382 @@
+//! Signing of the requests that the device sends to the backend.
+//!
+//! The device and the backend share a secret, the device token received when pairing. Every
+//! request carries three headers:
+//!
+//! - [`REQUEST_TIME_HEADER`]: the time of the request, in seconds since the Unix epoch.
+//! - [`NONCE_HEADER`]: a number that is not reused for requests sent in the same second.
+//! - [`SIGNATURE_HEADER`]: the HMAC-SHA256 of the request, as lowercase hex.
+//!
+//! The signed message is the method, the path, the request time, the nonce and the SHA-256 hash
+//! of the body. The path is the part of the URL after the backend URL, for example
+//! `/upload_data/{serial}`.
+//!
+//! The request time limits how long a captured request can be replayed, and the [`Verifier`]
+//! rejects requests that it has already seen within that time.
+
+#![cfg_attr(not(test), no_std)]
+
+use core::str;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest as _, Sha256};
+
+pub const REQUEST_TIME_HEADER: &str = "X-Request-Time";
+pub const NONCE_HEADER: &str = "X-Nonce";
+pub const SIGNATURE_HEADER: &str = "X-Signature";
+
+/// Requests whose time differs from the verifier's clock by more than this are rejected, in
+/// seconds.
+pub const MAX_CLOCK_SKEW: u64 = 5 * 60;
+
+pub type Digest = [u8; 32];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Computes the SHA-256 hash of a body that is not available in one piece.
+#[derive(Clone, Default)]
+pub struct BodyHasher(Sha256);
+
+impl BodyHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finalize(self) -> Digest {
+        self.0.finalize().into()
+    }
+}
+
+pub fn body_hash(body: &[u8]) -> Digest {
+    let mut hasher = BodyHasher::new();
+    hasher.update(body);
+    hasher.finalize()
+}
+
+/// The signed parts of a request.
+#[derive(Clone, Copy, Debug)]
+pub struct SignedRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    pub time: u64,
+    pub nonce: u64,
+    pub body_hash: Digest,
+}
+
+impl SignedRequest<'_> {
+    fn mac(&self, key: &[u8]) -> HmacSha256 {
+        // HMAC accepts keys of any length.
+        let mut mac = HmacSha256::new_from_slice(key).unwrap();
+
+        // Neither the method nor the path can contain a newline, so the message is unambiguous.
+        mac.update(self.method.as_bytes());
+        mac.update(b"\n");
+        mac.update(self.path.as_bytes());
+        mac.update(b"\n");
+        mac.update(&self.time.to_be_bytes());
+        mac.update(&self.nonce.to_be_bytes());
+        mac.update(&self.body_hash);
+
+        mac
+    }
+
+    pub fn signature(&self, key: &[u8]) -> Digest {
+        self.mac(key).finalize().into_bytes().into()
+    }
+
+    fn has_signature(&self, key: &[u8], signature: &Digest) -> bool {
+        // Compares in constant time.
+        self.mac(key).verify_slice(signature).is_ok()
+    }
+}
+
+/// A [`Digest`] formatted as lowercase hex.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct HexDigest([u8; 64]);
+
+impl HexDigest {
+    pub fn new(digest: &Digest) -> Self {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+        let mut hex = [0; 64];
+        for (byte, pair) in digest.iter().zip(hex.chunks_exact_mut(2)) {
+            pair[0] = DIGITS[(byte >> 4) as usize];
+            pair[1] = DIGITS[(byte & 0xF) as usize];
+        }
+
+        Self(hex)
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only contains ASCII digits.
+        unsafe { str::from_utf8_unchecked(&self.0) }
+    }
+
+    pub fn parse(hex: &str) -> Option<Digest> {
+        let hex = hex.as_bytes();
+        if hex.len() != 64 {
+            return None;
+        }
+
+        let mut digest = [0; 32];
+        for (byte, pair) in digest.iter_mut().zip(hex.chunks_exact(2)) {
+            let high = (pair[0] as char).to_digit(16)?;
+            let low = (pair[1] as char).to_digit(16)?;
+            *byte = (high << 4 | low) as u8;
+        }
+
+        Some(digest)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VerifyError {
+    /// A header is missing or can't be parsed.
+    Malformed,
+    /// The request time is too far from the verifier's clock.
+    Expired,
+    /// The same request has been received before.
+    Replayed,
+    InvalidSignature,
+}
+
+/// A request as received by the backend.
+#[derive(Clone, Copy, Debug)]
+pub struct ReceivedRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    /// The value of [`REQUEST_TIME_HEADER`].
+    pub time: Option<&'a str>,
+    /// The value of [`NONCE_HEADER`].
+    pub nonce: Option<&'a str>,
+    /// The value of [`SIGNATURE_HEADER`].
+    pub signature: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+/// Checks request signatures for the backend, remembering the last `N` accepted requests of a
+/// device to detect replays. `N` should be larger than the number of requests a device sends in
+/// `2 * MAX_CLOCK_SKEW`, because older requests are forgotten.
+pub struct Verifier<const N: usize> {
+    /// Request time and nonce of accepted requests.
+    recent: [Option<(u64, u64)>; N],
+    next: usize,
+}
+
+impl<const N: usize> Default for Verifier<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Verifier<N> {
+    pub const fn new() -> Self {
+        Self {
+            recent: [None; N],
+            next: 0,
+        }
+    }
+
+    /// Verifies a request signed with `key`. `now` is the current time, in seconds since the
+    /// Unix epoch.
+    pub fn verify(
+        &mut self,
+        key: &[u8],
+        request: &ReceivedRequest<'_>,
+        now: u64,
+    ) -> Result<(), VerifyError> {
+        let parse = |value: Option<&str>| value.and_then(|value| value.parse::<u64>().ok());
+
+        let time = parse(request.time).ok_or(VerifyError::Malformed)?;
+        let nonce = parse(request.nonce).ok_or(VerifyError::Malformed)?;
+        let signature = request
+            .signature
+            .and_then(HexDigest::parse)
+            .ok_or(VerifyError::Malformed)?;
+
+        if time.abs_diff(now) > MAX_CLOCK_SKEW {
+            return Err(VerifyError::Expired);
+        }
+
+        let signed = SignedRequest {
+            method: request.method,
+            path: request.path,
+            time,
+            nonce,
+            body_hash: body_hash(request.body),
+        };
+        if !signed.has_signature(key, &signature) {
+            return Err(VerifyError::InvalidSignature);
+        }
+
+        // Checked after the signature so that forged requests can't fill the list.
+        if self.recent.contains(&Some((time, nonce))) {
+            return Err(VerifyError::Replayed);
+        }
+
+        if N > 0 {
+            self.recent[self.next] = Some((time, nonce));
+            self.next = (self.next + 1) % N;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &[u8] = b"device-token";
+    const NOW: u64 = 1_700_000_000;
+
+    fn sign(method: &str, path: &str, time: u64, nonce: u64, body: &[u8]) -> HexDigest {
+        let request = SignedRequest {
+            method,
+            path,
+            time,
+            nonce,
+            body_hash: body_hash(body),
+        };
+
+        HexDigest::new(&request.signature(KEY))
+    }
+
+    fn received<'a>(
+        path: &'a str,
+        time: &'a str,
+        nonce: &'a str,
+        signature: &'a HexDigest,
+        body: &'a [u8],
+    ) -> ReceivedRequest<'a> {
+        ReceivedRequest {
+            method: "POST",
+            path,
+            time: Some(time),
+            nonce: Some(nonce),
+            signature: Some(signature.as_str()),
+            body,
+        }
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        // Test case 2
+        let mut mac = HmacSha256::new_from_slice(b"Jefe").unwrap();
+        mac.update(b"what do ya want for nothing?");
+        let result: Digest = mac.finalize().into_bytes().into();
+
+        assert_eq!(
+            HexDigest::new(&result).as_str(),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let digest = body_hash(b"body");
+        let hex = HexDigest::new(&digest);
+
+        assert_eq!(HexDigest::parse(hex.as_str()), Some(digest));
+        assert_eq!(HexDigest::parse(&hex.as_str()[1..]), None);
+        assert_eq!(HexDigest::parse(&hex.as_str().replace('a', "g")), None);
+    }
+
+    #[test]
+    fn accepts_signed_request() {
+        let mut verifier = Verifier::<4>::new();
+        let signature = sign("POST", "/upload_data/1234", NOW, 1, b"data");
+
+        let request = received("/upload_data/1234", "1700000000", "1", &signature, b"data");
+        assert_eq!(verifier.verify(KEY, &request, NOW + 10), Ok(()));
+    }
+
+    #[test]
+    fn rejects_modified_request() {
+        let mut verifier = Verifier::<4>::new();
+        let signature = sign("POST", "/upload_data/1234", NOW, 1, b"data");
+
+        let wrong_body = received("/upload_data/1234", "1700000000", "1", &signature, b"date");
+        let wrong_path = received("/upload_data/1235", "1700000000", "1", &signature, b"data");
+        let wrong_time = received("/upload_data/1234", "1700000001", "1", &signature, b"data");
+        let wrong_nonce = received("/upload_data/1234", "1700000000", "2", &signature, b"data");
+        let wrong_method = ReceivedRequest {
+            method: "PUT",
+            ..received("/upload_data/1234", "1700000000", "1", &signature, b"data")
+        };
+
+        for request in [wrong_body, wrong_path, wrong_time, wrong_nonce, wrong_method] {
+            assert_eq!(
+                verifier.verify(KEY, &request, NOW),
+                Err(VerifyError::InvalidSignature)
+            );
+        }
+
+        let request = received("/upload_data/1234", "1700000000", "1", &signature, b"data");
+        assert_eq!(
+            verifier.verify(b"other-token", &request, NOW),
+            Err(VerifyError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn rejects_replayed_request() {
+        let mut verifier = Verifier::<4>::new();
+        let signature = sign("POST", "/upload_data/1234", NOW, 1, b"data");
+        let request = received("/upload_data/1234", "1700000000", "1", &signature, b"data");
+
+        assert_eq!(verifier.verify(KEY, &request, NOW), Ok(()));
+        assert_eq!(
+            verifier.verify(KEY, &request, NOW),
+            Err(VerifyError::Replayed)
+        );
+    }
+
+    #[test]
+    fn rejects_old_and_future_requests() {
+        let mut verifier = Verifier::<4>::new();
+        let signature = sign("POST", "/upload_data/1234", NOW, 1, b"data");
+        let request = received("/upload_data/1234", "1700000000", "1", &signature, b"data");
+
+        assert_eq!(
+            verifier.verify(KEY, &request, NOW + MAX_CLOCK_SKEW + 1),
+            Err(VerifyError::Expired)
+        );
+        assert_eq!(
+            verifier.verify(KEY, &request, NOW - MAX_CLOCK_SKEW - 1),
+            Err(VerifyError::Expired)
+        );
+        assert_eq!(
+            verifier.verify(KEY, &request, NOW + MAX_CLOCK_SKEW),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_request() {
+        let mut verifier = Verifier::<4>::new();
+        let signature = sign("POST", "/upload_data/1234", NOW, 1, b"data");
+
+        let request = ReceivedRequest {
+            nonce: None,
+            ..received("/upload_data/1234", "1700000000", "1", &signature, b"data")
+        };
+        assert_eq!(
+            verifier.verify(KEY, &request, NOW),
+            Err(VerifyError::Malformed)
+        );
+
+        let request = ReceivedRequest {
+            signature: Some("abcd"),
+            ..received("/upload_data/1234", "1700000000", "1", &signature, b"data")
+        };
+        assert_eq!(
+            verifier.verify(KEY, &request, NOW),
+            Err(VerifyError::Malformed)
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Index of the stored measurements
+//!
+//! The catalog keeps a record of every stored measurement, so that the upload state and the
+//! header of a measurement don't need to be read from its file. This module contains the encoding
+//! of the catalog file and the bookkeeping that doesn't depend on the storage.
+//!
+//! The catalog file starts with the format version and the number of entries, followed by the
+//! entries and a CRC of everything before it. The file is replaced as a whole on every change, and
+//! an update can be interrupted, so a catalog with a wrong CRC is rejected.
+//!
+//! Version 2 added the failed upload counter. Version 1 catalogs are read with no failures.
+//! A measurement that can't be read for [`MAX_LOAD_FAILURES`] upload attempts is marked as
+//! failed, and is no longer uploaded in the background.
+
+use alloc::vec::Vec;
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+use crate::measurement::MeasurementHeader;
+
+/// The version of the catalogs written by [`encode`].
+pub const VERSION: u8 = 2;
+
+/// Length of the version and the entry count at the start of the catalog, in bytes.
+pub const HEADER_SIZE: usize = 3;
+
+const CRC_SIZE: usize = 4;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Failed upload attempts after which an unreadable measurement is given up.
+pub const MAX_LOAD_FAILURES: u8 = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UploadState {
+    Pending,
+    Uploaded,
+    /// The file couldn't be read too many times. It can still be uploaded from the menu.
+    Failed,
+}
+
+/// What we know about a stored measurement.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MeasurementInfo {
+    /// Size of the file, in bytes.
+    pub size: u32,
+    /// Start of the recording, in seconds since the Unix epoch.
+    pub start_time: Option<u64>,
+    /// Length of the recording, in seconds.
+    pub duration: Option<u32>,
+    /// Signal quality score, in percent.
+    pub quality: Option<u8>,
+}
+
+impl MeasurementInfo {
+    pub fn from_header(size: usize, header: &MeasurementHeader<'_>) -> Self {
+        Self {
+            size: size as u32,
+            start_time: header.start_time,
+            duration: header.duration_secs(),
+            quality: header
+                .quality
+                .map(|quality| (quality.score * 100.0).clamp(0.0, 100.0) as u8),
+        }
+    }
+
+    /// Describes a measurement without a header.
+    pub fn unknown(size: usize) -> Self {
+        Self {
+            size: size as u32,
+            start_time: None,
+            duration: None,
+            quality: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CatalogEntry {
+    /// Identifies the file of the measurement.
+    pub id: u32,
+    pub upload: UploadState,
+    /// Failed background upload attempts since the last successful upload.
+    pub failures: u8,
+    pub info: MeasurementInfo,
+}
+
+impl CatalogEntry {
+    const ENCODED_SIZE: usize = 23;
+    const V1_ENCODED_SIZE: usize = 22;
+
+    /// Creates the entry of a measurement that hasn't been uploaded yet.
+    pub fn pending(id: u32, info: MeasurementInfo) -> Self {
+        Self {
+            id,
+            upload: UploadState::Pending,
+            failures: 0,
+            info,
+        }
+    }
+
+    fn encoded_size(version: u8) -> Option<usize> {
+        match version {
+            1 => Some(Self::V1_ENCODED_SIZE),
+            VERSION => Some(Self::ENCODED_SIZE),
+            _ => None,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0; Self::ENCODED_SIZE];
+        bytes[0..4].copy_from_slice(&self.id.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.info.size.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.info.start_time.unwrap_or(u64::MAX).to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.info.duration.unwrap_or(u32::MAX).to_le_bytes());
+        bytes[20] = match self.upload {
+            UploadState::Pending => 0,
+            UploadState::Uploaded => 1,
+            UploadState::Failed => 2,
+        };
+        bytes[21] = self.info.quality.unwrap_or(u8::MAX);
+        bytes[22] = self.failures;
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let failures = match bytes.len() {
+            Self::ENCODED_SIZE => bytes[22],
+            Self::V1_ENCODED_SIZE => 0,
+            _ => return None,
+        };
+        let bytes: [u8; Self::V1_ENCODED_SIZE] = bytes[..Self::V1_ENCODED_SIZE].try_into().ok()?;
+        let u32_at =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+        let mut start_time = [0; 8];
+        start_time.copy_from_slice(&bytes[8..16]);
+        let start_time = u64::from_le_bytes(start_time);
+
+        Some(Self {
+            id: u32_at(0),
+            upload: match bytes[20] {
+                0 => UploadState::Pending,
+                1 => UploadState::Uploaded,
+                2 => UploadState::Failed,
+                _ => return None,
+            },
+            failures,
+            info: MeasurementInfo {
+                size: u32_at(4),
+                start_time: (start_time != u64::MAX).then_some(start_time),
+                duration: Some(u32_at(16)).filter(|duration| *duration != u32::MAX),
+                quality: Some(bytes[21]).filter(|quality| *quality != u8::MAX),
+            },
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DecodeError {
+    UnknownVersion(u8),
+    /// The CRC doesn't match the contents.
+    Corrupted,
+    InvalidEntry,
+}
+
+/// Returns the length of the catalog that follows the header, in bytes.
+pub fn body_len(header: [u8; HEADER_SIZE]) -> Result<usize, DecodeError> {
+    let [version, count_lo, count_hi] = header;
+    let entry_size =
+        CatalogEntry::encoded_size(version).ok_or(DecodeError::UnknownVersion(version))?;
+    let count = u16::from_le_bytes([count_lo, count_hi]) as usize;
+
+    Ok(count * entry_size + CRC_SIZE)
+}
+
+/// Decodes the entries of a catalog. `body` is the [`body_len`] bytes after the header.
+pub fn decode(header: [u8; HEADER_SIZE], body: &[u8]) -> Result<Vec<CatalogEntry>, DecodeError> {
+    let [version, ..] = header;
+    let entry_size =
+        CatalogEntry::encoded_size(version).ok_or(DecodeError::UnknownVersion(version))?;
+    if body.len() != body_len(header)? {
+        return Err(DecodeError::Corrupted);
+    }
+
+    let (data, crc) = body.split_at(body.len() - CRC_SIZE);
+    let mut digest = CRC.digest();
+    digest.update(&header);
+    digest.update(data);
+    if digest.finalize().to_le_bytes() != crc {
+        return Err(DecodeError::Corrupted);
+    }
+
+    data.chunks_exact(entry_size)
+        .map(|bytes| CatalogEntry::from_bytes(bytes).ok_or(DecodeError::InvalidEntry))
+        .collect()
+}
+
+/// Returns the length of the catalog file of `count` entries, in bytes.
+pub fn encoded_len(count: usize) -> usize {
+    HEADER_SIZE + count * CatalogEntry::ENCODED_SIZE + CRC_SIZE
+}
+
+/// Encodes a catalog file. At most `u16::MAX` entries are stored.
+pub fn encode(entries: &[CatalogEntry]) -> Vec<u8> {
+    let count = entries.len().min(u16::MAX as usize);
+
+    let mut bytes = Vec::with_capacity(encoded_len(count));
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&(count as u16).to_le_bytes());
+    for entry in &entries[..count] {
+        bytes.extend_from_slice(&entry.to_bytes());
+    }
+
+    let crc = CRC.checksum(&bytes);
+    bytes.extend_from_slice(&crc.to_le_bytes());
+    bytes
+}
+
+/// Records a measurement in `entries`, which are ordered by id. Replaces the previous record of
+/// the same id.
+pub fn insert(entries: &mut Vec<CatalogEntry>, entry: CatalogEntry) {
+    match entries.binary_search_by_key(&entry.id, |e| e.id) {
+        Ok(index) => entries[index] = entry,
+        Err(index) => entries.insert(index, entry),
+    }
+}
+
+/// A measurement file found in the storage.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StoredFile {
+    pub id: u32,
+    /// Size of the file, in bytes.
+    pub size: usize,
+}
+
+/// The differences between the catalog and the stored files.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Reconciliation {
+    /// Number of records dropped, because their file is missing.
+    pub missing: usize,
+    /// Files without a record, ordered by id. They need to be added with [`insert`].
+    pub unrecorded: Vec<StoredFile>,
+}
+
+impl Reconciliation {
+    pub fn is_empty(&self) -> bool {
+        self.missing == 0 && self.unrecorded.is_empty()
+    }
+}
+
+/// Drops the records of missing files from `entries`, and returns the files that are not
+/// recorded. The device can stop between writing a measurement file and recording it, and the
+/// catalog can be lost, so these files are measurements that haven't been uploaded.
+///
+/// `files` must be ordered by id.
+pub fn reconcile(entries: &mut Vec<CatalogEntry>, files: &[StoredFile]) -> Reconciliation {
+    let count = entries.len();
+    entries.retain(|entry| {
+        files
+            .binary_search_by_key(&entry.id, |file| file.id)
+            .is_ok()
+    });
+
+    let unrecorded = files
+        .iter()
+        .filter(|file| {
+            entries
+                .binary_search_by_key(&file.id, |entry| entry.id)
+                .is_err()
+        })
+        .copied()
+        .collect();
+
+    Reconciliation {
+        missing: count - entries.len(),
+        unrecorded,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(id: u32, upload: UploadState, failures: u8) -> CatalogEntry {
+        CatalogEntry {
+            id,
+            upload,
+            failures,
+            info: MeasurementInfo {
+                size: 1000 + id,
+                start_time: Some(1_700_000_000 + id as u64),
+                duration: Some(30),
+                quality: Some(85),
+            },
+        }
+    }
+
+    fn entries() -> Vec<CatalogEntry> {
+        vec![
+            entry(1, UploadState::Uploaded, 0),
+            entry(4, UploadState::Pending, 2),
+            entry(7, UploadState::Failed, MAX_LOAD_FAILURES),
+            CatalogEntry::pending(9, MeasurementInfo::unknown(123)),
+        ]
+    }
+
+    fn decode_bytes(bytes: &[u8]) -> Result<Vec<CatalogEntry>, DecodeError> {
+        let (header, body) = bytes.split_at(HEADER_SIZE);
+        let header = header.try_into().unwrap();
+        assert_eq!(body_len(header)?, body.len());
+        decode(header, body)
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries = entries();
+        let bytes = encode(&entries);
+
+        assert_eq!(bytes.len(), encoded_len(entries.len()));
+        assert_eq!(decode_bytes(&bytes), Ok(entries));
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let entries = entries();
+
+        let mut bytes = vec![1];
+        bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for entry in &entries {
+            // Version 1 entries don't have the failure counter at the end.
+            let v2 = entry.to_bytes();
+            bytes.extend_from_slice(&v2[..CatalogEntry::V1_ENCODED_SIZE]);
+        }
+        let crc = CRC.checksum(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+
+        let expected = entries
+            .into_iter()
+            .map(|entry| CatalogEntry {
+                failures: 0,
+                ..entry
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(decode_bytes(&bytes), Ok(expected));
+    }
+
+    #[test]
+    fn rejects_corrupted_catalog() {
+        let bytes = encode(&entries());
+
+        for i in 0..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[i] ^= 0x10;
+
+            let header = corrupted[..HEADER_SIZE].try_into().unwrap();
+            let result = decode(header, &corrupted[HEADER_SIZE..]);
+            assert!(result.is_err(), "byte {i}: {result:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut bytes = encode(&entries());
+        bytes[0] = 3;
+
+        assert_eq!(decode_bytes(&bytes), Err(DecodeError::UnknownVersion(3)));
+    }
+
+    #[test]
+    fn reconcile_drops_missing_files() {
+        let mut entries = entries();
+        let files = [1, 7, 9].map(|id| StoredFile { id, size: 100 });
+
+        let reconciliation = reconcile(&mut entries, &files);
+
+        assert_eq!(reconciliation.missing, 1);
+        assert!(reconciliation.unrecorded.is_empty());
+        assert_eq!(entries.iter().map(|e| e.id).collect::<Vec<_>>(), [1, 7, 9]);
+        // The remaining records are kept as they were
+        assert_eq!(entries[1].upload, UploadState::Failed);
+    }
+
+    #[test]
+    fn reconcile_returns_unrecorded_files() {
+        let mut entries = entries();
+        let files = [0, 1, 4, 5, 7, 9, 12].map(|id| StoredFile { id, size: 100 });
+
+        let reconciliation = reconcile(&mut entries, &files);
+
+        assert_eq!(reconciliation.missing, 0);
+        assert_eq!(
+            reconciliation.unrecorded,
+            [0, 5, 12].map(|id| StoredFile { id, size: 100 })
+        );
+
+        for file in reconciliation.unrecorded {
+            insert(
+                &mut entries,
+                CatalogEntry::pending(file.id, MeasurementInfo::unknown(file.size)),
+            );
+        }
+
+        let ids = entries.iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(ids, [0, 1, 4, 5, 7, 9, 12]);
+        for id in [0, 5, 12] {
+            let entry = entries.iter().find(|e| e.id == id).unwrap();
+            assert_eq!(entry.upload, UploadState::Pending);
+            assert_eq!(entry.info, MeasurementInfo::unknown(100));
+        }
+    }
+
+    #[test]
+    fn reconcile_empty_catalog_records_every_file() {
+        let mut entries = Vec::new();
+        let files = [3, 8].map(|id| StoredFile { id, size: 100 });
+
+        let reconciliation = reconcile(&mut entries, &files);
+
+        assert!(entries.is_empty());
+        assert_eq!(reconciliation.unrecorded, files);
+    }
+
+    #[test]
+    fn insert_replaces_record() {
+        let mut entries = entries();
+        insert(&mut entries, entry(4, UploadState::Uploaded, 0));
+
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[1].upload, UploadState::Uploaded);
+    }
+}
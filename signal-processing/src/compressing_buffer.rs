@@ -1,8 +1,14 @@
 //! Compressing i32 buffer
 //!
-//! This buffer tries to compress a sequence of i32 values by storing the varint-encoded
-//! difference from the last. This is useful for storing a sequence of values that are
-//! close to each other, such as a sequence of samples from a sensor.
+//! This buffer tries to compress a sequence of i32 values that are close to each other, such as a
+//! sequence of samples from a sensor. Two formats are supported:
+//!
+//! - Version 0 ([`VarintFormat`]) stores the varint-encoded difference from the last value.
+//! - Version 1 ([`RiceFormat`]) stores blocks of samples. Each sample is predicted from the
+//!   previous ones, and the prediction errors are Rice coded with a parameter chosen for each
+//!   block.
+//!
+//! The buffer uses [`EkgFormat`], the decoders of all versions are available via [`EkgDecoder`].
 
 use core::{fmt::Debug, slice};
 
@@ -10,12 +16,78 @@ use embedded_io::{Read, ReadExactError, Write};
 
 use crate::buffer::Buffer;
 
+/// The format used by [`CompressingBuffer`].
+pub type EkgFormat = RiceFormat;
+
+/// An error while decoding samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DecodeError<E> {
+    /// The reader returned an error.
+    Read(E),
+    /// The data is corrupted.
+    Invalid,
+}
+
+const fn zigzag_encode(val: i32) -> u32 {
+    ((val << 1) ^ (val >> 31)) as u32
+}
+
+const fn zigzag_decode(val: u32) -> i32 {
+    (val >> 1) as i32 ^ -((val & 1) as i32)
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> Result<Option<u8>, R::Error> {
+    let mut byte = 0;
+    match reader.read_exact(slice::from_mut(&mut byte)) {
+        Ok(()) => Ok(Some(byte)),
+        Err(ReadExactError::UnexpectedEof) => Ok(None),
+        Err(ReadExactError::Other(e)) => Err(e),
+    }
+}
+
+fn write_varint<W: Write>(mut value: u32, writer: &mut W) -> Result<usize, W::Error> {
+    let mut buffer = [0; 8];
+    let mut idx = 0;
+    while value >= 0x80 {
+        buffer[idx] = (value as u8) | 0x80;
+        value >>= 7;
+        idx += 1;
+    }
+    buffer[idx] = value as u8;
+    idx += 1;
+
+    writer.write_all(&buffer[..idx])?;
+
+    Ok(idx)
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> Result<Option<u32>, DecodeError<R::Error>> {
+    let mut value = 0;
+    let mut idx = 0;
+    loop {
+        let Some(byte) = read_byte(reader).map_err(DecodeError::Read)? else {
+            return Ok(None);
+        };
+        if idx == 4 && byte > 0x0F {
+            // The value doesn't fit into 32 bits.
+            return Err(DecodeError::Invalid);
+        }
+        value |= ((byte & 0x7F) as u32) << (idx * 7);
+        idx += 1;
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+}
+
+/// Stores the varint-encoded difference of each value from the previous one.
 #[derive(Clone, Copy, Default)]
-pub struct EkgFormat {
+pub struct VarintFormat {
     previous: i32,
 }
 
-impl EkgFormat {
+impl VarintFormat {
     pub const VERSION: u8 = 0;
 
     pub const fn new() -> Self {
@@ -26,54 +98,373 @@ impl EkgFormat {
         let diff = sample - self.previous;
         self.previous = sample;
 
-        const fn zigzag_encode(val: i32) -> u32 {
-            ((val << 1) ^ (val >> 31)) as u32
+        write_varint(zigzag_encode(diff), writer)
+    }
+
+    pub fn read<R: Read>(&mut self, reader: &mut R) -> Result<Option<i32>, DecodeError<R::Error>> {
+        let Some(diff) = read_varint(reader)? else {
+            return Ok(None);
+        };
+        let diff = zigzag_decode(diff);
+
+        let value = self.previous.wrapping_add(diff);
+        self.previous = value;
+
+        Ok(Some(value))
+    }
+}
+
+/// Writes bits into a byte slice, most significant bit first.
+struct BitWriter<'a> {
+    buffer: &'a mut [u8],
+    bits: usize,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, bits: 0 }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        let byte = &mut self.buffer[self.bits / 8];
+        let offset = self.bits % 8;
+        if offset == 0 {
+            *byte = 0;
+        }
+        if bit {
+            *byte |= 0x80 >> offset;
+        }
+        self.bits += 1;
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        for bit in (0..count).rev() {
+            self.write_bit(value & (1 << bit) != 0);
+        }
+    }
+
+    /// Returns the number of bytes written, including the last, partially filled one.
+    fn bytes(&self) -> usize {
+        self.bits.div_ceil(8)
+    }
+}
+
+/// Reads bits from a reader, most significant bit first.
+struct BitReader<'a, R> {
+    reader: &'a mut R,
+    byte: u8,
+    bits_left: u32,
+}
+
+impl<'a, R: Read> BitReader<'a, R> {
+    fn new(reader: &'a mut R) -> Self {
+        Self {
+            reader,
+            byte: 0,
+            bits_left: 0,
+        }
+    }
+
+    fn read_bit(&mut self) -> Result<Option<bool>, R::Error> {
+        if self.bits_left == 0 {
+            let Some(byte) = read_byte(self.reader)? else {
+                return Ok(None);
+            };
+            self.byte = byte;
+            self.bits_left = 8;
         }
 
-        let mut diff = zigzag_encode(diff);
+        self.bits_left -= 1;
+        Ok(Some(self.byte & (1 << self.bits_left) != 0))
+    }
 
-        let mut buffer = [0; 8];
-        let mut idx = 0;
-        while diff >= 0x80 {
-            buffer[idx] = (diff as u8) | 0x80;
-            diff >>= 7;
-            idx += 1;
+    fn read_bits(&mut self, count: u32) -> Result<Option<u32>, R::Error> {
+        let mut value = 0;
+        for _ in 0..count {
+            let Some(bit) = self.read_bit()? else {
+                return Ok(None);
+            };
+            value = (value << 1) | bit as u32;
         }
-        buffer[idx] = diff as u8;
-        idx += 1;
+        Ok(Some(value))
+    }
+}
+
+/// Stores blocks of samples, using linear prediction and Rice coding.
+///
+/// A block starts with a two byte header: the number of samples in the block, then the predictor
+/// order in the upper 3 bits and the Rice parameter in the lower 5 bits. The header is followed by
+/// the varint-encoded difference of the first sample from the last sample of the previous block.
+/// The rest of the samples are stored as Rice coded prediction errors, padded to a whole byte.
+///
+/// The predictor order and the Rice parameter are chosen for each block, to minimize its size.
+/// Like with [`VarintFormat`], the blocks only depend on the previous ones through the DC offset,
+/// so the oldest blocks can be dropped.
+#[derive(Clone, Copy)]
+pub struct RiceFormat {
+    previous: i32,
+    /// Samples waiting to be written, or decoded samples waiting to be read.
+    block: [i32; Self::BLOCK_SIZE],
+    len: usize,
+    read_idx: usize,
+}
+
+impl Default for RiceFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RiceFormat {
+    pub const VERSION: u8 = 1;
+
+    /// The number of samples in a full block.
+    pub const BLOCK_SIZE: usize = 64;
+
+    /// Quotients this large are replaced by an escape code, followed by the raw value.
+    const ESCAPE: u32 = 16;
+    const MAX_RICE_PARAMETER: u32 = 24;
+
+    /// The size of the largest possible block, in bytes.
+    pub const MAX_BLOCK_SIZE: usize =
+        2 + 5 + ((Self::BLOCK_SIZE - 1) * (Self::ESCAPE as usize + 32)).div_ceil(8);
+
+    pub const fn new() -> Self {
+        Self {
+            previous: 0,
+            block: [0; Self::BLOCK_SIZE],
+            len: 0,
+            read_idx: 0,
+        }
+    }
+
+    /// Returns the number of samples that are not yet written, or decoded but not yet read.
+    pub fn pending(&self) -> usize {
+        self.len - self.read_idx
+    }
 
-        writer.write_all(&buffer[..idx])?;
+    /// Buffers a sample, and writes the block when it is full. Returns the number of bytes
+    /// written, which is at most [`Self::MAX_BLOCK_SIZE`].
+    pub fn write<W: Write>(&mut self, sample: i32, writer: &mut W) -> Result<usize, W::Error> {
+        self.block[self.len] = sample;
+        self.len += 1;
 
-        Ok(idx)
+        if self.len == Self::BLOCK_SIZE {
+            self.flush(writer)
+        } else {
+            Ok(0)
+        }
     }
 
-    pub fn read<R: Read>(&mut self, reader: &mut R) -> Result<Option<i32>, R::Error> {
-        const fn zigzag_decode(val: u32) -> i32 {
-            (val >> 1) as i32 ^ -((val & 1) as i32)
+    /// Writes the buffered samples as a partial block.
+    pub fn flush<W: Write>(&mut self, writer: &mut W) -> Result<usize, W::Error> {
+        if self.len == 0 {
+            return Ok(0);
         }
 
-        let mut diff = 0;
-        let mut idx = 0;
-        let mut byte = 0;
-        loop {
-            if let Err(e) = reader.read_exact(slice::from_mut(&mut byte)) {
-                match e {
-                    ReadExactError::UnexpectedEof => return Ok(None),
-                    ReadExactError::Other(e) => return Err(e),
+        let mut buffer = [0; Self::MAX_BLOCK_SIZE];
+        let bytes = self.encode_block(&mut buffer);
+        self.len = 0;
+
+        writer.write_all(&buffer[..bytes])?;
+
+        Ok(bytes)
+    }
+
+    fn residuals(&self, order: u8) -> impl Iterator<Item = u32> + '_ {
+        let samples = &self.block[..self.len];
+        samples.windows(2).enumerate().map(move |(i, pair)| {
+            let diff = pair[1].wrapping_sub(pair[0]);
+            let residual = if order == 2 {
+                let previous = samples[i].wrapping_sub(if i == 0 {
+                    self.previous
+                } else {
+                    samples[i - 1]
+                });
+                diff.wrapping_sub(previous)
+            } else {
+                diff
+            };
+            zigzag_encode(residual)
+        })
+    }
+
+    fn rice_cost(value: u32, k: u32) -> usize {
+        let quotient = value >> k;
+        if quotient < Self::ESCAPE {
+            (quotient + 1 + k) as usize
+        } else {
+            (Self::ESCAPE + 32) as usize
+        }
+    }
+
+    fn encode_block(&mut self, buffer: &mut [u8]) -> usize {
+        let mut best = (usize::MAX, 1, 0);
+        for order in [1, 2] {
+            for k in 0..=Self::MAX_RICE_PARAMETER {
+                let cost = self
+                    .residuals(order)
+                    .map(|residual| Self::rice_cost(residual, k))
+                    .sum::<usize>();
+                if cost < best.0 {
+                    best = (cost, order, k);
                 }
             }
-            diff |= ((byte & 0x7F) as u32) << (idx * 7);
-            idx += 1;
-            if byte & 0x80 == 0 {
-                break;
+        }
+        let (_, order, k) = best;
+
+        buffer[0] = self.len as u8;
+        buffer[1] = (order << 5) | k as u8;
+
+        let first_diff = zigzag_encode(self.block[0].wrapping_sub(self.previous));
+        let header = 2 + unwrap!(write_varint(first_diff, &mut &mut buffer[2..]));
+
+        let mut bits = BitWriter::new(&mut buffer[header..]);
+        for residual in self.residuals(order) {
+            let quotient = residual >> k;
+            if quotient < Self::ESCAPE {
+                for _ in 0..quotient {
+                    bits.write_bit(true);
+                }
+                bits.write_bit(false);
+                bits.write_bits(residual, k);
+            } else {
+                for _ in 0..Self::ESCAPE {
+                    bits.write_bit(true);
+                }
+                bits.write_bits(residual, 32);
             }
         }
-        let diff = zigzag_decode(diff);
+        let bytes = header + bits.bytes();
 
-        let value = self.previous + diff;
-        self.previous = value;
+        self.previous = self.block[self.len - 1];
 
-        Ok(Some(value))
+        bytes
+    }
+
+    fn decode_block<R: Read>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<Option<()>, DecodeError<R::Error>> {
+        self.len = 0;
+        self.read_idx = 0;
+
+        let Some(count) = read_byte(reader).map_err(DecodeError::Read)? else {
+            return Ok(None);
+        };
+        let Some(params) = read_byte(reader).map_err(DecodeError::Read)? else {
+            return Ok(None);
+        };
+
+        let count = count as usize;
+        let order = params >> 5;
+        let k = (params & 0x1F) as u32;
+        if count == 0
+            || count > Self::BLOCK_SIZE
+            || !matches!(order, 1 | 2)
+            || k > Self::MAX_RICE_PARAMETER
+        {
+            return Err(DecodeError::Invalid);
+        }
+
+        let Some(first_diff) = read_varint(reader)? else {
+            return Ok(None);
+        };
+
+        let mut diff = zigzag_decode(first_diff);
+        let mut sample = self.previous.wrapping_add(diff);
+        self.block[0] = sample;
+
+        let mut bits = BitReader::new(reader);
+        for idx in 1..count {
+            let mut quotient = 0;
+            while quotient < Self::ESCAPE {
+                let Some(bit) = bits.read_bit().map_err(DecodeError::Read)? else {
+                    return Ok(None);
+                };
+                if !bit {
+                    break;
+                }
+                quotient += 1;
+            }
+
+            let residual = if quotient < Self::ESCAPE {
+                let Some(remainder) = bits.read_bits(k).map_err(DecodeError::Read)? else {
+                    return Ok(None);
+                };
+                (quotient << k) | remainder
+            } else {
+                let Some(residual) = bits.read_bits(32).map_err(DecodeError::Read)? else {
+                    return Ok(None);
+                };
+                residual
+            };
+            let residual = zigzag_decode(residual);
+
+            diff = if order == 2 {
+                diff.wrapping_add(residual)
+            } else {
+                residual
+            };
+            sample = sample.wrapping_add(diff);
+            self.block[idx] = sample;
+        }
+
+        self.len = count;
+        self.previous = sample;
+
+        Ok(Some(()))
+    }
+
+    pub fn read<R: Read>(&mut self, reader: &mut R) -> Result<Option<i32>, DecodeError<R::Error>> {
+        if self.pending() == 0 && self.decode_block(reader)?.is_none() {
+            return Ok(None);
+        }
+
+        let sample = self.block[self.read_idx];
+        self.read_idx += 1;
+
+        Ok(Some(sample))
+    }
+
+    /// Drops the decoded samples that were not read yet, then reads and drops the next block.
+    /// Returns the number of dropped samples.
+    pub fn skip_block<R: Read>(&mut self, reader: &mut R) -> Result<usize, DecodeError<R::Error>> {
+        let mut dropped = self.pending();
+
+        if self.decode_block(reader)?.is_some() {
+            dropped += self.len;
+        }
+        self.len = 0;
+        self.read_idx = 0;
+
+        Ok(dropped)
+    }
+}
+
+/// Decodes samples written in any supported version of the format.
+#[derive(Clone, Copy)]
+#[allow(clippy::large_enum_variant)]
+pub enum EkgDecoder {
+    V0(VarintFormat),
+    V1(RiceFormat),
+}
+
+impl EkgDecoder {
+    /// Returns a decoder for the given format version, or `None` if the version is not supported.
+    pub const fn new(version: u8) -> Option<Self> {
+        match version {
+            VarintFormat::VERSION => Some(Self::V0(VarintFormat::new())),
+            RiceFormat::VERSION => Some(Self::V1(RiceFormat::new())),
+            _ => None,
+        }
+    }
+
+    pub fn read<R: Read>(&mut self, reader: &mut R) -> Result<Option<i32>, DecodeError<R::Error>> {
+        match self {
+            Self::V0(format) => format.read(reader),
+            Self::V1(format) => format.read(reader),
+        }
     }
 }
 
@@ -120,23 +511,54 @@ impl<const N: usize> CompressingBuffer<N> {
     }
 
     pub fn push(&mut self, item: i32) {
-        let mut buffer = [0u8; 8];
-        let bytes = unwrap!(self.writer.write(item, &mut &mut buffer[..]));
+        let mut block = [0u8; EkgFormat::MAX_BLOCK_SIZE];
+        let bytes = unwrap!(self.writer.write(item, &mut &mut block[..]));
 
-        while self.space() < bytes {
-            if self.pop().is_none() {
+        self.element_count += 1;
+        self.store_block(&block[..bytes]);
+    }
+
+    /// Compresses the samples that don't fill a whole block yet. This may drop the oldest
+    /// samples to make room.
+    pub fn flush(&mut self) {
+        let mut block = [0u8; EkgFormat::MAX_BLOCK_SIZE];
+        let bytes = unwrap!(self.writer.flush(&mut &mut block[..]));
+
+        self.store_block(&block[..bytes]);
+    }
+
+    fn store_block(&mut self, block: &[u8]) {
+        let Some(&samples) = block.first() else {
+            return;
+        };
+
+        while self.space() < block.len() {
+            // The samples that are decoded but not popped don't take up space, drop them too.
+            let dropped = unwrap!(self.reader.skip_block(&mut self.buffer));
+            self.element_count -= dropped;
+
+            if self.buffer.is_empty() {
+                // The block doesn't fit into the buffer at all. The next block is encoded relative
+                // to its last sample.
+                self.element_count -= samples as usize;
+                self.reader = RiceFormat {
+                    previous: self.writer.previous,
+                    ..RiceFormat::new()
+                };
                 return;
             }
         }
 
-        for byte in buffer.iter().take(bytes).copied() {
+        for byte in block.iter().copied() {
             self.buffer.push(byte);
         }
-
-        self.element_count += 1;
     }
 
     pub fn pop(&mut self) -> Option<i32> {
+        if self.reader.pending() == 0 && self.buffer.is_empty() {
+            self.flush();
+        }
+
         let sample = unwrap!(self.reader.read(&mut self.buffer));
 
         if sample.is_some() {
@@ -168,16 +590,26 @@ impl<const N: usize> CompressingBuffer<N> {
 
     pub fn clear(&mut self) {
         self.element_count = 0;
-        self.reader.previous = 0;
-        self.writer.previous = 0;
+        self.reader = EkgFormat::new();
+        self.writer = EkgFormat::new();
         self.buffer.clear();
     }
 
+    /// Returns the compressed samples. Samples that are not [flushed](Self::flush) yet, or that
+    /// are decoded by [`pop`](Self::pop) but not returned yet, are not included.
     pub fn as_slices(&self) -> (&[u8], &[u8]) {
         self.buffer.as_slices()
     }
 
+    /// Flushes the buffer, and returns the compressed samples. Samples that are decoded by
+    /// [`pop`](Self::pop) but not returned yet are dropped.
     pub fn make_contiguous(&mut self) -> &[u8] {
+        self.flush();
+        self.element_count -= self.reader.pending();
+        self.reader = RiceFormat {
+            previous: self.reader.previous,
+            ..RiceFormat::new()
+        };
         self.buffer.make_contiguous()
     }
 }
@@ -205,7 +637,10 @@ mod test {
     fn first_element_is_stored() {
         let mut buffer = CompressingBuffer::<100>::new();
         buffer.push(1);
-        assert_eq!(buffer.byte_count(), 1);
+        buffer.flush();
+
+        // block header and the first difference
+        assert_eq!(buffer.byte_count(), 3);
     }
 
     #[test]
@@ -260,6 +695,7 @@ mod test {
         buffer.push(0);
         buffer.push(-6);
         buffer.push(32);
+        buffer.flush();
 
         // -1 because we explicitly don't store the first element
         assert!(buffer.byte_count() < (buffer.len() - 1) * 4);
@@ -285,4 +721,153 @@ mod test {
 
         assert_eq!(&output[output.len() - 4..], [32, 0, -6, 32]);
     }
+
+    /// A synthetic ECG signal at 72 bpm in ADC units, with one bit of noise.
+    fn ecg(n: usize) -> i32 {
+        fn gaussian(t: f32, center: f32, width: f32, amplitude: f32) -> f32 {
+            let x = (t - center) / width;
+            amplitude * (-0.5 * x * x).exp()
+        }
+
+        let t = (n as f32 / 1000.0) % (60.0 / 72.0);
+        let noise = ((n as u32).wrapping_mul(2_654_435_761) >> 31) as f32;
+
+        // 1 mV is about 3500 LSB
+        (10_000.0
+            + gaussian(t, 0.15, 0.025, 500.0)
+            + gaussian(t, 0.30, 0.010, 4000.0)
+            + gaussian(t, 0.55, 0.040, 1000.0)
+            + noise) as i32
+    }
+
+    #[test]
+    fn rice_format_round_trip() {
+        let samples = (0..1000)
+            .map(ecg)
+            .chain([i32::MAX, i32::MIN, 0, -1, i32::MAX, 5])
+            .collect::<Vec<_>>();
+
+        let mut encoded = Vec::new();
+        let mut block = [0; RiceFormat::MAX_BLOCK_SIZE];
+        let mut format = RiceFormat::new();
+        for sample in samples.iter().copied() {
+            let bytes = format.write(sample, &mut &mut block[..]).unwrap();
+            encoded.extend_from_slice(&block[..bytes]);
+        }
+        let bytes = format.flush(&mut &mut block[..]).unwrap();
+        encoded.extend_from_slice(&block[..bytes]);
+
+        let mut decoder = EkgDecoder::new(RiceFormat::VERSION).unwrap();
+        let mut reader = encoded.as_slice();
+        let mut decoded = Vec::new();
+        while let Some(sample) = decoder.read(&mut reader).unwrap() {
+            decoded.push(sample);
+        }
+
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn varint_format_can_be_decoded() {
+        let samples = (0..1000).map(ecg).collect::<Vec<_>>();
+
+        let mut encoded = Vec::new();
+        let mut format = VarintFormat::new();
+        for sample in samples.iter().copied() {
+            let mut bytes = [0; 8];
+            let len = format.write(sample, &mut &mut bytes[..]).unwrap();
+            encoded.extend_from_slice(&bytes[..len]);
+        }
+
+        let mut decoder = EkgDecoder::new(VarintFormat::VERSION).unwrap();
+        let mut reader = encoded.as_slice();
+        let mut decoded = Vec::new();
+        while let Some(sample) = decoder.read(&mut reader).unwrap() {
+            decoded.push(sample);
+        }
+
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn rice_format_is_smaller_than_varint_format() {
+        let mut varint_bytes = 0;
+        let mut varint_format = VarintFormat::new();
+
+        let mut buffer = CompressingBuffer::<100_000>::new();
+        for sample in (0..60_000).map(ecg) {
+            let mut bytes = [0; 8];
+            varint_bytes += varint_format.write(sample, &mut &mut bytes[..]).unwrap();
+            buffer.push(sample);
+        }
+        buffer.flush();
+
+        assert!(
+            buffer.byte_count() * 2 < varint_bytes,
+            "{} vs {}",
+            buffer.byte_count(),
+            varint_bytes
+        );
+    }
+
+    #[test]
+    fn dropping_a_block_that_does_not_fit_keeps_the_next_one() {
+        let mut buffer = CompressingBuffer::<16>::new();
+
+        // Noise doesn't compress into 16 bytes
+        for n in 0..64_i32 {
+            buffer.push(n.wrapping_mul(2_654_435_761u32 as i32));
+        }
+        assert!(buffer.is_empty());
+
+        buffer.push(1000);
+        buffer.push(1001);
+
+        assert_eq!(buffer.pop(), Some(1000));
+        assert_eq!(buffer.pop(), Some(1001));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn invalid_blocks_are_rejected() {
+        // An empty block, an unknown predictor order, a too large Rice parameter, a block that is
+        // longer than the maximum, and a varint that doesn't fit 32 bits.
+        let blocks: [&[u8]; 5] = [
+            &[0, 0x20, 0],
+            &[1, 0x60, 0],
+            &[1, 0x3F, 0],
+            &[65, 0x20, 0],
+            &[1, 0x20, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F],
+        ];
+
+        for block in blocks {
+            let mut decoder = EkgDecoder::new(RiceFormat::VERSION).unwrap();
+            let mut reader = block;
+            assert_eq!(
+                decoder.read(&mut reader),
+                Err(DecodeError::Invalid),
+                "{block:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn overwriting_drops_whole_blocks() {
+        let mut buffer = CompressingBuffer::<1000>::new();
+
+        for n in 0..10_000 {
+            buffer.push(ecg(n));
+        }
+        buffer.flush();
+
+        let len = buffer.len();
+        let mut output = Vec::new();
+        while let Some(item) = buffer.pop() {
+            output.push(item);
+        }
+
+        assert_eq!(output.len(), len);
+        assert!(buffer.is_empty());
+        assert_eq!(output, (10_000 - len..10_000).map(ecg).collect::<Vec<_>>());
+    }
 }
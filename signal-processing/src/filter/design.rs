@@ -0,0 +1,251 @@
+//! Runtime filter design.
+//!
+//! IIR filters are derived from analog Butterworth or Chebyshev (type I) prototypes using the
+//! bilinear transform. FIR filters are designed using the windowed-sinc method of `fir-design`,
+//! which `designfilt!` uses as well.
+//!
+//! The calculations are done in double precision using `libm`, because the approximations in
+//! `micromath` are not accurate enough to place poles close to the unit circle.
+
+use core::f64::consts::PI;
+
+use num_complex::Complex;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DesignError {
+    /// The filter order is zero, or odd for a band filter.
+    InvalidOrder,
+    /// The filter needs more second-order sections than available.
+    OrderTooHigh,
+    /// A frequency is outside of (0, fs/2), or the band edges are not in increasing order.
+    InvalidFrequency,
+    /// The wrong number of frequencies was given for the type of the filter.
+    FrequencyCount,
+    /// The passband ripple of a Chebyshev filter must be positive.
+    InvalidRipple,
+}
+
+/// The analog prototype of an IIR filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Prototype {
+    /// Maximally flat passband. The cutoff frequencies are the half-power (-3dB) frequencies.
+    Butterworth,
+    /// Steeper transition with the given passband ripple, in dB. The cutoff frequencies are the
+    /// edges of the passband.
+    Chebyshev { ripple: f32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Band {
+    LowPass,
+    HighPass,
+    BandPass,
+    BandStop,
+}
+
+impl Band {
+    pub const fn frequency_count(self) -> usize {
+        match self {
+            Band::LowPass | Band::HighPass => 1,
+            Band::BandPass | Band::BandStop => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    Kaiser { beta: f32 },
+}
+
+impl From<Window> for fir_design::Window {
+    fn from(window: Window) -> Self {
+        match window {
+            Window::Rectangular => Self::Rectangular,
+            Window::Hann => Self::Hann,
+            Window::Hamming => Self::Hamming,
+            Window::Blackman => Self::Blackman,
+            Window::Kaiser { beta } => Self::Kaiser { beta: beta as f64 },
+        }
+    }
+}
+
+/// Returns the cutoff frequencies, prewarped for the bilinear transform.
+///
+/// The results are in units of `2 * fs`, so the bilinear transform becomes `s = (z - 1) / (z + 1)`.
+pub(crate) fn prewarp(fs: f32, frequencies: &[f32]) -> Result<[f64; 2], DesignError> {
+    let mut warped = [0.0; 2];
+    let mut previous = 0.0;
+
+    for (warped, &frequency) in warped.iter_mut().zip(frequencies) {
+        if frequency <= previous || frequency >= fs / 2.0 {
+            return Err(DesignError::InvalidFrequency);
+        }
+        previous = frequency;
+
+        *warped = libm::tan(PI * frequency as f64 / fs as f64);
+    }
+
+    Ok(warped)
+}
+
+/// A second-order section of an analog filter: `(n2 s^2 + n1 s + n0) / (d2 s^2 + d1 s + d0)`.
+#[derive(Clone, Copy)]
+pub(crate) struct AnalogSection {
+    pub num: [f64; 3],
+    pub den: [f64; 3],
+    /// First order sections are transformed separately, because doing so as a second-order
+    /// section would place a pole on the unit circle.
+    pub first_order: bool,
+}
+
+/// A second-order section of a digital filter, with `a0` normalized to 1.
+#[derive(Clone, Copy)]
+pub(crate) struct DigitalSection {
+    pub b: [f64; 3],
+    pub a: [f64; 2],
+}
+
+impl AnalogSection {
+    /// Applies the bilinear transform `s = (z - 1) / (z + 1)`.
+    pub fn bilinear(&self) -> DigitalSection {
+        let transform = |[c2, c1, c0]: [f64; 3]| {
+            if self.first_order {
+                // c1 (z - 1) + c0 (z + 1)
+                [c1 + c0, c0 - c1, 0.0]
+            } else {
+                // c2 (z - 1)^2 + c1 (z^2 - 1) + c0 (z + 1)^2
+                [c2 + c1 + c0, 2.0 * (c0 - c2), c2 - c1 + c0]
+            }
+        };
+
+        let b = transform(self.num);
+        let [a0, a1, a2] = transform(self.den);
+
+        DigitalSection {
+            b: b.map(|b| b / a0),
+            a: [a1 / a0, a2 / a0],
+        }
+    }
+}
+
+impl DigitalSection {
+    /// Evaluates the transfer function at `z`.
+    pub fn response(&self, z: Complex<f64>) -> Complex<f64> {
+        let z_inv = z.inv();
+        let [b0, b1, b2] = self.b;
+        let [a1, a2] = self.a;
+
+        let num = (z_inv * b2 + b1) * z_inv + b0;
+        let den = (z_inv * a2 + a1) * z_inv + 1.0;
+
+        num / den
+    }
+
+    /// Scales the section to unity gain at `z`.
+    pub fn normalize(&mut self, z: Complex<f64>) {
+        let gain = self.response(z).norm_sqr();
+        let gain = libm::sqrt(gain);
+        self.b = self.b.map(|b| b / gain);
+    }
+}
+
+/// Returns the poles of the normalized lowpass prototype in the upper half of the s-plane, and
+/// the real pole, if the order is odd. `callback` is called once for each pole.
+pub(crate) fn prototype_poles(
+    prototype: Prototype,
+    order: usize,
+    mut callback: impl FnMut(Complex<f64>),
+) -> Result<(), DesignError> {
+    let (sinh_mu, cosh_mu) = match prototype {
+        Prototype::Butterworth => (1.0, 1.0),
+        Prototype::Chebyshev { ripple } => {
+            if ripple <= 0.0 || ripple.is_nan() {
+                return Err(DesignError::InvalidRipple);
+            }
+
+            let epsilon = libm::sqrt(libm::pow(10.0, ripple as f64 / 10.0) - 1.0);
+            let mu = libm::asinh(1.0 / epsilon) / order as f64;
+            (libm::sinh(mu), libm::cosh(mu))
+        }
+    };
+
+    for k in 0..order.div_ceil(2) {
+        let theta = PI * (2 * k + 1) as f64 / (2 * order) as f64;
+        let pole = if 2 * k + 1 == order {
+            Complex::new(-sinh_mu, 0.0)
+        } else {
+            Complex::new(-sinh_mu * libm::sin(theta), cosh_mu * libm::cos(theta))
+        };
+
+        callback(pole);
+    }
+
+    Ok(())
+}
+
+/// Returns the gain of the prototype at DC. Chebyshev filters of even order start at the bottom
+/// of the passband ripple.
+pub(crate) fn prototype_dc_gain(prototype: Prototype, order: usize) -> f64 {
+    match prototype {
+        Prototype::Chebyshev { ripple } if order % 2 == 0 => libm::pow(10.0, -ripple as f64 / 20.0),
+        _ => 1.0,
+    }
+}
+
+/// Returns the principal square root of `z`.
+pub(crate) fn complex_sqrt(z: Complex<f64>) -> Complex<f64> {
+    let r = libm::hypot(z.re, z.im);
+    let re = libm::sqrt((r + z.re) / 2.0);
+    let im = libm::sqrt((r - z.re) / 2.0);
+
+    Complex::new(re, if z.im < 0.0 { -im } else { im })
+}
+
+fn normalized_cutoff(fs: f32, cutoff: f32) -> Result<f64, DesignError> {
+    if 0.0 < cutoff && cutoff < fs / 2.0 {
+        Ok(cutoff as f64 / fs as f64)
+    } else {
+        Err(DesignError::InvalidFrequency)
+    }
+}
+
+/// Designs a lowpass FIR filter with unity gain at DC. The order of the filter is `N - 1`.
+pub fn fir_lowpass<const N: usize>(
+    fs: f32,
+    cutoff: f32,
+    window: Window,
+) -> Result<[f32; N], DesignError> {
+    if N == 0 {
+        return Err(DesignError::InvalidOrder);
+    }
+
+    let mut coeffs = [0.0; N];
+    fir_design::lowpass(&mut coeffs, normalized_cutoff(fs, cutoff)?, window.into());
+
+    Ok(coeffs.map(|c| c as f32))
+}
+
+/// Designs a highpass FIR filter with unity gain at the Nyquist frequency. The order of the
+/// filter is `N - 1`, which must be even.
+pub fn fir_highpass<const N: usize>(
+    fs: f32,
+    cutoff: f32,
+    window: Window,
+) -> Result<[f32; N], DesignError> {
+    if N % 2 == 0 {
+        return Err(DesignError::InvalidOrder);
+    }
+
+    let mut coeffs = [0.0; N];
+    fir_design::highpass(&mut coeffs, normalized_cutoff(fs, cutoff)?, window.into());
+
+    Ok(coeffs.map(|c| c as f32))
+}
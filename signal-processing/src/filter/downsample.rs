@@ -1,59 +1,24 @@
 use super::{fir::Fir, Filter};
 
-const COEFFS: &[f32; 43] = &[
-    0.001_896_49,
-    0.001_427_698_3,
-    -0.005_418_506_5,
-    -0.014_846_274,
-    -0.014_311_559_5,
-    0.000_110_694_32,
-    0.011_810_528,
-    0.003_197_918,
-    -0.014_473_969,
-    -0.011_425_788_5,
-    0.013_566_333,
-    0.021_809_606,
-    -0.007_811_884_4,
-    -0.033_718_437,
-    -0.005_609_694_4,
-    0.045_531_593,
-    0.031_312_115,
-    -0.055_580_772,
-    -0.084_990_874,
-    0.062_316_805,
-    0.310_974_66,
-    0.435_316_83,
-    0.310_974_66,
-    0.062_316_805,
-    -0.084_990_874,
-    -0.055_580_772,
-    0.031_312_115,
-    0.045_531_593,
-    -0.005_609_694_4,
-    -0.033_718_437,
-    -0.007_811_884_4,
-    0.021_809_606,
-    0.013_566_333,
-    -0.011_425_788_5,
-    -0.014_473_969,
-    0.003_197_918,
-    0.011_810_528,
-    0.000_110_694_32,
-    -0.014_311_559_5,
-    -0.014_846_274,
-    -0.005_418_506_5,
-    0.001_427_698_3,
-    0.001_896_49,
-];
-
 pub struct DownSampler {
     filter: Fir<'static, 43>,
     output_next: bool,
 }
 
+// Only the ratio of the frequencies matters, so the filter works at any input rate. The stopband
+// has to start below half of the output sample rate.
+#[rustfmt::skip]
+pub(crate) const FILTER: Fir<'static, 43> = macros::designfilt!(
+    "lowpassfir",
+    "FilterOrder", 42,
+    "CutoffFrequency", 200,
+    "Window", "hamming",
+    "SampleRate", 1000
+);
+
 impl DownSampler {
     pub const DEFAULT: Self = Self {
-        filter: Fir::from_coeffs(COEFFS),
+        filter: FILTER,
         output_next: false,
     };
 
@@ -90,4 +55,51 @@ impl Filter for DownSampler {
             None
         }
     }
+
+    #[inline]
+    fn process_block(&mut self, input: &[f32], output: &mut [f32]) -> usize {
+        // Skip calculating the outputs that would be dropped.
+        self.filter.process_block_filtered(input, output, || {
+            let output = self.output_next;
+            self.output_next = !output;
+            output
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FILTER;
+
+    /// Returns the magnitude of the filter's frequency response at `frequency`, for 1000Hz input.
+    fn magnitude(frequency: f32) -> f32 {
+        let omega = core::f32::consts::TAU * frequency / 1000.0;
+        let mut re = 0.0;
+        let mut im = 0.0;
+        for (n, coeff) in FILTER.coefficients().iter().enumerate() {
+            let phase = omega * n as f32;
+            re += coeff * phase.cos();
+            im -= coeff * phase.sin();
+        }
+
+        (re * re + im * im).sqrt()
+    }
+
+    #[test]
+    fn passband_is_flat() {
+        // ECG content is below 150Hz
+        for frequency in (0..=150).step_by(5) {
+            let gain = magnitude(frequency as f32);
+            assert!((gain - 1.0).abs() < 0.005, "{frequency}Hz: {gain}");
+        }
+    }
+
+    #[test]
+    fn stopband_prevents_aliasing() {
+        // Everything above the output Nyquist frequency folds back into the signal.
+        for frequency in 250..=500 {
+            let gain = magnitude(frequency as f32);
+            assert!(gain < 0.003, "{frequency}Hz: {gain}"); // -50dB
+        }
+    }
 }
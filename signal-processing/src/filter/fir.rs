@@ -1,10 +1,29 @@
-use crate::sliding::SlidingWindow;
+use crate::{
+    filter::design::{fir_highpass, fir_lowpass, DesignError, Window},
+    sliding::SlidingWindow,
+};
 
 use super::Filter;
 
+#[derive(Clone)]
+enum Coefficients<'a, const N: usize> {
+    Borrowed(&'a [f32; N]),
+    Owned([f32; N]),
+}
+
+impl<const N: usize> Coefficients<'_, N> {
+    #[inline(always)]
+    fn get(&self) -> &[f32; N] {
+        match self {
+            Self::Borrowed(coeffs) => coeffs,
+            Self::Owned(coeffs) => coeffs,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Fir<'a, const N: usize> {
-    coeffs: &'a [f32; N],
+    coeffs: Coefficients<'a, N>,
     buffer: SlidingWindow<N>,
 }
 
@@ -12,12 +31,84 @@ impl<'a, const N: usize> Fir<'a, N> {
     #[inline(always)]
     pub const fn from_coeffs(coeffs: &'a [f32; N]) -> Self {
         Self {
-            coeffs,
+            coeffs: Coefficients::Borrowed(coeffs),
             buffer: SlidingWindow::new(),
         }
     }
 }
 
+impl<const N: usize> Fir<'_, N> {
+    pub(crate) fn coefficients(&self) -> &[f32; N] {
+        self.coeffs.get()
+    }
+}
+
+impl<const N: usize> Fir<'static, N> {
+    #[inline(always)]
+    pub const fn from_owned_coeffs(coeffs: [f32; N]) -> Self {
+        Self {
+            coeffs: Coefficients::Owned(coeffs),
+            buffer: SlidingWindow::new(),
+        }
+    }
+
+    /// Designs a windowed-sinc lowpass filter of order `N - 1` at runtime.
+    pub fn lowpass(fs: f32, cutoff: f32, window: Window) -> Result<Self, DesignError> {
+        fir_lowpass(fs, cutoff, window).map(Self::from_owned_coeffs)
+    }
+
+    /// Designs a windowed-sinc highpass filter of order `N - 1` at runtime. `N` must be odd.
+    pub fn highpass(fs: f32, cutoff: f32, window: Window) -> Result<Self, DesignError> {
+        fir_highpass(fs, cutoff, window).map(Self::from_owned_coeffs)
+    }
+}
+
+impl<const N: usize> Fir<'_, N> {
+    /// Filters a block of samples, but only calculates the outputs for which `keep` returns
+    /// `true`. `keep` is called once for every output sample, in order.
+    pub(crate) fn process_block_filtered(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+        mut keep: impl FnMut() -> bool,
+    ) -> usize {
+        let mut count = 0;
+
+        // The first outputs depend on samples of the previous block.
+        let head = input.len().min(N - 1);
+        for sample in input[..head].iter().copied() {
+            self.buffer.push(sample);
+            if self.buffer.is_full() && keep() {
+                output[count] = dot(self.buffer.iter(), self.coeffs.get());
+                count += 1;
+            }
+        }
+
+        if input.len() < N {
+            return count;
+        }
+
+        // The rest can be calculated from the input directly.
+        for window in input.windows(N) {
+            if keep() {
+                output[count] = dot(window.iter().copied(), self.coeffs.get());
+                count += 1;
+            }
+        }
+
+        for sample in input[input.len() - N..].iter().copied() {
+            self.buffer.push(sample);
+        }
+
+        count
+    }
+}
+
+#[inline(always)]
+fn dot<const N: usize>(samples: impl Iterator<Item = f32>, coeffs: &[f32; N]) -> f32 {
+    samples.zip(coeffs.iter()).map(|(a, b)| a * b).sum()
+}
+
 impl<'a, const N: usize> Filter for Fir<'a, N> {
     fn clear(&mut self) {
         self.buffer.clear()
@@ -26,12 +117,161 @@ impl<'a, const N: usize> Filter for Fir<'a, N> {
     fn update(&mut self, sample: f32) -> Option<f32> {
         self.buffer.push(sample);
 
-        self.buffer.is_full().then(|| {
-            self.buffer
-                .iter()
-                .zip(self.coeffs.iter())
-                .map(|(a, b)| a * b)
-                .sum()
-        })
+        self.buffer
+            .is_full()
+            .then(|| dot(self.buffer.iter(), self.coeffs.get()))
+    }
+
+    fn process_block(&mut self, input: &[f32], output: &mut [f32]) -> usize {
+        self.process_block_filtered(input, output, || true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Filter, Fir};
+    use crate::filter::design::{DesignError, Window};
+
+    #[track_caller]
+    fn assert_float_equals(value: f32, expectation: f32, tolerance: f32) {
+        assert!(
+            (value - expectation).abs() < tolerance,
+            "assertion failed: `(left == right)`\n  left: `{:?}`,\n right: `{:?}`",
+            value,
+            expectation
+        );
+    }
+
+    /// Returns the amplitude of the filter's steady state response to a sine wave.
+    fn response(mut filter: impl Filter, frequency: f32, fs: f32) -> f32 {
+        let output = (0..2000)
+            .filter_map(|n| {
+                let t = n as f32 / fs;
+                filter.update((core::f32::consts::TAU * frequency * t).sin())
+            })
+            .skip(100)
+            .collect::<Vec<_>>();
+
+        let power = output.iter().map(|sample| sample * sample).sum::<f32>() / output.len() as f32;
+        (2.0 * power).sqrt()
+    }
+
+    #[test]
+    fn lowpass_window_design() {
+        let design = || {
+            #[rustfmt::skip]
+            let filter = macros::designfilt!(
+                "lowpassfir",
+                "FilterOrder", 42,
+                "CutoffFrequency", 100,
+                "Window", "hamming",
+                "SampleRate", 1000
+            );
+            filter
+        };
+
+        // -6dB at the cutoff frequency
+        assert_float_equals(response(design(), 10.0, 1000.0), 1.0, 0.01);
+        assert_float_equals(response(design(), 100.0, 1000.0), 0.5, 0.02);
+        assert!(response(design(), 200.0, 1000.0) < 0.003);
+    }
+
+    #[test]
+    fn highpass_window_design() {
+        let design = || {
+            #[rustfmt::skip]
+            let filter = macros::designfilt!(
+                "highpassfir",
+                "FilterOrder", 42,
+                "CutoffFrequency", 200,
+                "Window", "blackman",
+                "SampleRate", 1000
+            );
+            filter
+        };
+
+        assert!(response(design(), 10.0, 1000.0) < 0.001);
+        assert_float_equals(response(design(), 200.0, 1000.0), 0.5, 0.02);
+        assert_float_equals(response(design(), 400.0, 1000.0), 1.0, 0.01);
+    }
+
+    #[test]
+    fn lowpass_kaiser_design() {
+        let design = || {
+            #[rustfmt::skip]
+            let filter = macros::designfilt!(
+                "lowpassfir",
+                "PassbandFrequency", 100,
+                "StopbandFrequency", 150,
+                "StopbandAttenuation", 60,
+                "DesignMethod", "kaiserwin",
+                "SampleRate", 1000
+            );
+            filter
+        };
+
+        assert_float_equals(response(design(), 50.0, 1000.0), 1.0, 0.01);
+        assert!(response(design(), 150.0, 1000.0) < 0.001);
+        assert!(response(design(), 300.0, 1000.0) < 0.001);
+    }
+
+    #[test]
+    fn runtime_lowpass_design() {
+        #[rustfmt::skip]
+        let designed = macros::designfilt!(
+            "lowpassfir",
+            "FilterOrder", 42,
+            "CutoffFrequency", 100,
+            "Window", "hamming",
+            "SampleRate", 1000
+        );
+        let runtime = Fir::<43>::lowpass(1000.0, 100.0, Window::Hamming).unwrap();
+
+        let pairs = runtime.coeffs.get().iter().zip(designed.coeffs.get());
+        for (runtime, designed) in pairs {
+            assert_float_equals(*runtime, *designed, 1e-6);
+        }
+    }
+
+    #[test]
+    fn runtime_highpass_design() {
+        let design = || Fir::<51>::highpass(250.0, 40.0, Window::Blackman).unwrap();
+
+        assert!(response(design(), 5.0, 250.0) < 0.001);
+        assert_float_equals(response(design(), 40.0, 250.0), 0.5, 0.02);
+        assert_float_equals(response(design(), 100.0, 250.0), 1.0, 0.01);
+    }
+
+    #[test]
+    fn block_matches_samples() {
+        let input = (0..200).map(|n| (n as f32 * 0.3).sin()).collect::<Vec<_>>();
+
+        let mut filter = Fir::<43>::lowpass(1000.0, 100.0, Window::Hamming).unwrap();
+        let expected = input
+            .iter()
+            .filter_map(|sample| filter.update(*sample))
+            .collect::<Vec<_>>();
+
+        // Blocks both shorter and longer than the filter
+        let mut filter = Fir::<43>::lowpass(1000.0, 100.0, Window::Hamming).unwrap();
+        let mut output = [0.0; 200];
+        let mut count = 0;
+        for block in [&input[..10], &input[10..30], &input[30..150], &input[150..]] {
+            count += filter.process_block(block, &mut output[count..]);
+        }
+
+        assert_eq!(&output[..count], &expected[..]);
+    }
+
+    #[test]
+    fn runtime_design_errors() {
+        assert_eq!(
+            Fir::<43>::lowpass(1000.0, 500.0, Window::Hann).err(),
+            Some(DesignError::InvalidFrequency)
+        );
+        assert_eq!(
+            Fir::<42>::highpass(1000.0, 100.0, Window::Hann).err(),
+            Some(DesignError::InvalidOrder)
+        );
     }
 }
@@ -0,0 +1,72 @@
+use crate::{
+    filter::{downsample::FILTER, fixed::fir::FixedFir, Filter},
+    fixed::Q31,
+};
+
+/// Fixed-point variant of [`DownSampler`](crate::filter::downsample::DownSampler).
+#[derive(Clone)]
+pub struct FixedDownSampler {
+    filter: FixedFir<43>,
+    output_next: bool,
+}
+
+impl FixedDownSampler {
+    pub fn new() -> Self {
+        Self {
+            filter: FixedFir::from(&FILTER),
+            output_next: false,
+        }
+    }
+}
+
+impl Default for FixedDownSampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filter<Q31> for FixedDownSampler {
+    #[inline]
+    fn clear(&mut self) {
+        self.filter.clear();
+        self.output_next = false;
+    }
+
+    #[inline]
+    fn update(&mut self, sample: Q31) -> Option<Q31> {
+        let filtered = self.filter.update(sample)?;
+
+        let output = self.output_next;
+        self.output_next = !output;
+
+        if output {
+            Some(filtered)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use object_chain::Chain;
+
+    use super::FixedDownSampler;
+    use crate::filter::{downsample::DownSampler, fixed::iir::test::max_error};
+
+    #[test]
+    fn chain_matches_float() {
+        let float = Chain::new(DownSampler::new())
+            .append(DownSampler::new())
+            .append(DownSampler::new());
+        let fixed = Chain::new(FixedDownSampler::new())
+            .append(FixedDownSampler::new())
+            .append(FixedDownSampler::new());
+
+        let error = max_error(float, fixed, 20_000, 0, 1000.0);
+
+        // Each stage can change the gain by 43 * 2^-16, on a signal of at most 63mV.
+        let bound = 0.063 * 3.0 * 43.0 / 65536.0;
+        assert!(error < bound, "{error}");
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{
+    buffer::Buffer,
+    filter::{fir::Fir, Filter},
+    fixed::{Q15, Q31},
+};
+
+/// FIR filter with `Q15` coefficients.
+///
+/// Rounding the coefficients changes the gain by at most `N * 2^-16`.
+#[derive(Clone)]
+pub struct FixedFir<const N: usize> {
+    coeffs: [Q15; N],
+    buffer: Buffer<Q31, N, false>,
+}
+
+impl<const N: usize> FixedFir<N> {
+    pub fn from_coeffs(coeffs: &[f32; N]) -> Self {
+        Self {
+            coeffs: coeffs.map(Q15::from_f32),
+            buffer: Buffer::new(),
+        }
+    }
+}
+
+impl<const N: usize> From<&Fir<'_, N>> for FixedFir<N> {
+    fn from(filter: &Fir<'_, N>) -> Self {
+        Self::from_coeffs(filter.coefficients())
+    }
+}
+
+impl<const N: usize> Filter<Q31> for FixedFir<N> {
+    fn clear(&mut self) {
+        self.buffer.clear()
+    }
+
+    fn update(&mut self, sample: Q31) -> Option<Q31> {
+        self.buffer.push(sample);
+
+        self.buffer.is_full().then(|| {
+            let acc = self
+                .buffer
+                .iter()
+                .zip(self.coeffs.iter())
+                .map(|(sample, coeff)| sample.0 as i64 * coeff.0 as i64)
+                .sum::<i64>();
+
+            Q31::saturate(acc >> Q15::FRACTIONAL_BITS)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FixedFir;
+    use crate::filter::{design::Window, fir::Fir, fixed::iir::test::max_error};
+
+    #[test]
+    fn lowpass_matches_float() {
+        let filter = Fir::<43>::lowpass(1000.0, 40.0, Window::Hamming).unwrap();
+
+        let error = max_error(filter.clone(), FixedFir::from(&filter), 5_000, 0, 1000.0);
+
+        // The signal is at most 63mV, and the coefficients are rounded to 2^-16.
+        let bound = 0.063 * 43.0 / 65536.0;
+        assert!(error < bound, "{error}");
+    }
+}
@@ -0,0 +1,242 @@
+use core::marker::PhantomData;
+
+use crate::{
+    filter::{
+        iir::{BandPass, BandStop, FilterType, HighPass, Iir, LowPass},
+        sos::Sos,
+        Filter,
+    },
+    fixed::Q31,
+};
+
+/// Number of fractional bits of the coefficients. This leaves room for magnitudes up to 8.
+const COEFF_BITS: u32 = 28;
+
+pub trait FixedFilterType: FilterType {
+    /// Whether the first sample is subtracted from the input, see [`FilterType::precondition`].
+    const REMOVES_OFFSET: bool;
+}
+
+impl FixedFilterType for LowPass {
+    const REMOVES_OFFSET: bool = false;
+}
+
+impl FixedFilterType for HighPass {
+    const REMOVES_OFFSET: bool = true;
+}
+
+impl FixedFilterType for BandPass {
+    const REMOVES_OFFSET: bool = true;
+}
+
+impl FixedFilterType for BandStop {
+    const REMOVES_OFFSET: bool = false;
+}
+
+fn coefficient(value: f32) -> i32 {
+    libm::round(value as f64 * (1u64 << COEFF_BITS) as f64) as i32
+}
+
+/// Direct form I IIR filter of order up to `N`.
+///
+/// The quantization error of each output sample is fed back into the next one. This keeps the
+/// rounding noise low even if the poles are close to the unit circle, which is the case for
+/// highpass filters with low cutoff frequencies.
+#[derive(Clone)]
+pub struct FixedIir<T, const N: usize> {
+    b0: i32,
+    /// b1 ..= bN
+    num_coeffs: [i32; N],
+    /// a1 ..= aN
+    denom_coeffs: [i32; N],
+
+    /// x[n - 1] ..= x[n - N]
+    previous_inputs: [i32; N],
+    /// y[n - 1] ..= y[n - N]
+    previous_outputs: [i32; N],
+    error: i64,
+
+    first_sample: Option<Q31>,
+    _filter_kind: PhantomData<T>,
+}
+
+impl<T, const N: usize> FixedIir<T, N>
+where
+    T: FixedFilterType,
+{
+    /// Creates a filter from coefficients in the same format as [`Iir::new`]: the numerator
+    /// coefficients, and the denominator coefficients in reverse order, without `a0`.
+    pub fn new(num: &[f32], denom: &[f32]) -> Self {
+        assert!(num.len() <= N + 1 && denom.len() <= N);
+
+        let mut num_coeffs = [0; N];
+        for (dst, src) in num_coeffs.iter_mut().zip(num.iter().skip(1)) {
+            *dst = coefficient(*src);
+        }
+
+        let mut denom_coeffs = [0; N];
+        for (dst, src) in denom_coeffs.iter_mut().zip(denom.iter().rev()) {
+            *dst = coefficient(*src);
+        }
+
+        Self {
+            b0: num.first().copied().map_or(0, coefficient),
+            num_coeffs,
+            denom_coeffs,
+            previous_inputs: [0; N],
+            previous_outputs: [0; N],
+            error: 0,
+            first_sample: None,
+            _filter_kind: PhantomData,
+        }
+    }
+}
+
+impl<T, const N: usize> From<&Iir<'_, T, N>> for FixedIir<T, N>
+where
+    T: FixedFilterType,
+{
+    fn from(filter: &Iir<'_, T, N>) -> Self {
+        let (num, denom) = filter.coefficients();
+        Self::new(num, denom)
+    }
+}
+
+impl<T> From<&Sos<T, 1>> for FixedIir<T, 2>
+where
+    T: FixedFilterType,
+{
+    fn from(filter: &Sos<T, 1>) -> Self {
+        let (b, [a1, a2]) = unwrap!(filter.sections().next());
+        Self::new(&b, &[a2, a1])
+    }
+}
+
+impl<T, const N: usize> Filter<Q31> for FixedIir<T, N>
+where
+    T: FixedFilterType,
+{
+    fn update(&mut self, sample: Q31) -> Option<Q31> {
+        let sample = if T::REMOVES_OFFSET {
+            sample - *self.first_sample.get_or_insert(sample)
+        } else {
+            sample
+        };
+
+        // Intermediate sums may overflow, but the end result fits, so wrapping is harmless.
+        let mut acc = self.error.wrapping_add(self.b0 as i64 * sample.0 as i64);
+        for k in 0..N {
+            acc = acc
+                .wrapping_add(self.num_coeffs[k] as i64 * self.previous_inputs[k] as i64)
+                .wrapping_sub(self.denom_coeffs[k] as i64 * self.previous_outputs[k] as i64);
+        }
+
+        let output = Q31::saturate(acc >> COEFF_BITS);
+        self.error = if (acc >> COEFF_BITS) == output.0 as i64 {
+            acc & ((1 << COEFF_BITS) - 1)
+        } else {
+            0
+        };
+
+        if N > 0 {
+            self.previous_inputs.copy_within(0..N - 1, 1);
+            self.previous_inputs[0] = sample.0;
+            self.previous_outputs.copy_within(0..N - 1, 1);
+            self.previous_outputs[0] = output.0;
+        }
+
+        Some(output)
+    }
+
+    fn clear(&mut self) {
+        self.previous_inputs = [0; N];
+        self.previous_outputs = [0; N];
+        self.error = 0;
+        self.first_sample = None;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::FixedIir;
+    use crate::{
+        filter::{
+            iir::{HighPass, Iir, LowPass},
+            sos::Sos,
+            Filter,
+        },
+        fixed::Q31,
+    };
+
+    /// A signal with a baseline wander, 50Hz interference and a sharp peak every second.
+    pub(crate) fn test_signal(n: usize, fs: f32) -> f32 {
+        let t = n as f32 / fs;
+        let peak = if n % 1000 < 20 { 0.002 } else { 0.0 };
+
+        0.05 + 0.01 * (0.3 * core::f32::consts::TAU * t).sin()
+            + 0.001 * (50.0 * core::f32::consts::TAU * t).sin()
+            + peak
+    }
+
+    /// Returns the largest difference between the outputs of the two filters, ignoring the first
+    /// `skip` samples.
+    pub(crate) fn max_error(
+        mut float: impl Filter,
+        mut fixed: impl Filter<Q31>,
+        samples: usize,
+        skip: usize,
+        fs: f32,
+    ) -> f32 {
+        let mut max_error = 0.0_f32;
+        for n in 0..samples {
+            let sample = test_signal(n, fs);
+            let expected = float.update(sample);
+            let actual = fixed.update(Q31::from_f32(sample)).map(Q31::to_f32);
+
+            assert_eq!(expected.is_some(), actual.is_some());
+            if let (Some(expected), Some(actual)) = (expected, actual) {
+                if n >= skip {
+                    max_error = max_error.max((expected - actual).abs());
+                }
+            }
+        }
+        max_error
+    }
+
+    #[test]
+    fn lowpass_matches_float() {
+        #[rustfmt::skip]
+        const FILTER: Iir<'static, LowPass, 2> = macros::designfilt!(
+            "lowpassiir",
+            "FilterOrder", 2,
+            "HalfPowerFrequency", 20,
+            "SampleRate", 1000
+        );
+
+        // `Iir` computes its first outputs with partially filled history buffers.
+        let error = max_error(FILTER, FixedIir::from(&FILTER), 10_000, 100, 1000.0);
+
+        // 1 microvolt on a 50 millivolt signal
+        assert!(error < 1e-6, "{error}");
+    }
+
+    #[test]
+    fn highpass_matches_float() {
+        // Highpass filters with a low cutoff frequency are the most sensitive to quantization.
+        let filter = Sos::<HighPass, 1>::butterworth(2, 1000.0, &[0.75]).unwrap();
+
+        let error = max_error(filter.clone(), FixedIir::from(&filter), 20_000, 0, 1000.0);
+
+        // Most of the difference is the rounding error of the single precision float filter.
+        assert!(error < 1e-5, "{error}");
+    }
+
+    #[test]
+    fn all_pass() {
+        let mut filter = FixedIir::from(&Sos::<HighPass, 1>::ALL_PASS);
+
+        for sample in [0, 1, -5, i32::MAX, i32::MIN] {
+            assert_eq!(filter.update(Q31(sample)), Some(Q31(sample)));
+        }
+    }
+}
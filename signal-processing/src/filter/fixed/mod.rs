@@ -0,0 +1,14 @@
+//! Fixed-point variants of the filters, for chips without a floating point unit.
+//!
+//! The filters process [`Q31`](crate::fixed::Q31) samples. Coefficients are designed in floating
+//! point, and converted once when the filter is created.
+
+pub mod downsample;
+pub mod fir;
+pub mod iir;
+pub mod pli;
+
+pub use downsample::FixedDownSampler;
+pub use fir::FixedFir;
+pub use iir::FixedIir;
+pub use pli::FixedPowerLineFilter;
@@ -0,0 +1,503 @@
+//! Fixed-point variant of the [power-line interference filter](crate::filter::pli).
+//!
+//! Phases are represented as integers where 2^32 is one full turn, so the oscillators never lose
+//! precision. The amplitude correction is applied after the signature filters, by dividing with
+//! the estimated amplitude, because its inverse doesn't fit in a `Q31`.
+
+use crate::{
+    buffer::Buffer,
+    filter::{
+        design::DesignError,
+        fixed::iir::FixedIir,
+        iir::{HighPass, IirFilter},
+        pli::{
+            adaptation_blocking::AdaptationBlockingTrait, design_signature_filter, signature_gamma,
+            Constants, ERROR_FILTER, SIGNATURE_FILTER,
+        },
+        Filter,
+    },
+    fixed::{self, phase_from_radians, Q31},
+};
+
+#[derive(Clone)]
+struct FixedFilterCore {
+    // constants
+    phase_step: u32,
+    k_a: Q31,
+    k_phi: i64,
+    k_dw: i64,
+
+    phase_filter: FixedIir<HighPass, 2>,
+    amplitude_filter: FixedIir<HighPass, 2>,
+
+    phase: u32,
+    // oscillator phase of the last estimate, used to derive the harmonics
+    osc_phase: u32,
+
+    // estimated signal parameters
+    theta_phi: i64,
+    theta_a: Q31,
+    theta_dw: i64,
+
+    // signatures
+    y_mod_a: Q31,
+    y_mod_phi: Q31,
+}
+
+impl FixedFilterCore {
+    fn new<F>(fs: f32, frequency: f32, sig_filter: &F) -> Self
+    where
+        F: IirFilter,
+        for<'a> FixedIir<HighPass, 2>: From<&'a F>,
+    {
+        let gamma = signature_gamma(sig_filter, core::f32::consts::TAU * frequency / fs);
+        let sig_filter = FixedIir::from(sig_filter);
+
+        Self {
+            phase_step: (frequency as f64 / fs as f64 * (1u64 << 32) as f64) as u32,
+            k_a: Q31::from_f32(gamma * Constants::K_A / fs),
+            k_phi: phase_from_radians(gamma * Constants::K_PHI),
+            k_dw: phase_from_radians(gamma * Constants::K_DW),
+
+            phase_filter: sig_filter.clone(),
+            amplitude_filter: sig_filter,
+
+            phase: 0,
+            osc_phase: 0,
+
+            theta_phi: 0,
+            theta_a: Q31::ZERO,
+            theta_dw: 0,
+
+            y_mod_a: Q31::ZERO,
+            y_mod_phi: Q31::ZERO,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.phase_filter.clear();
+        self.amplitude_filter.clear();
+
+        self.phase = 0;
+        self.osc_phase = 0;
+        self.theta_phi = 0;
+        self.theta_a = Q31::ZERO;
+        self.theta_dw = 0;
+        self.y_mod_a = Q31::ZERO;
+        self.y_mod_phi = Q31::ZERO;
+    }
+
+    fn estimate(&mut self) -> Q31 {
+        // Truncating theta_phi wraps it around to a single turn
+        let t = self.phase.wrapping_add(self.theta_phi as u32);
+        self.phase = self.phase.wrapping_add(self.phase_step);
+        self.osc_phase = t;
+
+        let osc_i = fixed::sin(t);
+        let osc_q = fixed::cos(t);
+
+        // preserve defaults initially
+        self.y_mod_a = self.amplitude_filter.update(osc_i).unwrap_or(self.y_mod_a);
+        self.y_mod_phi = self.phase_filter.update(osc_q).unwrap_or(self.y_mod_phi);
+
+        // always update the estimated phase based on the
+        // frequency deviation, even when adaptation is blocked
+        self.theta_phi = self.theta_phi.saturating_add(self.theta_dw);
+
+        self.theta_a * osc_i
+    }
+
+    fn adapt(&mut self, theta_dw_update_threshold: i64, ew: Q31) {
+        // Q31 products, without saturation
+        let eta_phi = (ew.0 as i64 * self.y_mod_phi.0 as i64) >> Q31::FRACTIONAL_BITS;
+        let eta_a = (ew.0 as i64 * self.y_mod_a.0 as i64) >> Q31::FRACTIONAL_BITS;
+
+        // The amplitude correction factor, 1.0 until the first amplitude estimate
+        let amplitude = if self.theta_a > Q31::ZERO {
+            self.theta_a.0 as i64
+        } else {
+            Q31::MAX.0 as i64
+        };
+
+        let thetaa_est_new =
+            self.theta_a.0 as i64 + ((self.k_a.0 as i64 * eta_a) >> Q31::FRACTIONAL_BITS);
+        let thetadw_est_new = self
+            .theta_dw
+            .saturating_add(self.k_dw * eta_phi / amplitude);
+        // not a bug: theta_dw added to theta_phi in estimate
+        let thetaphi_est_new = self
+            .theta_phi
+            .saturating_add(self.k_phi * eta_phi / amplitude);
+
+        if thetaa_est_new > 0 {
+            self.theta_a = Q31::saturate(thetaa_est_new);
+        }
+        if thetaphi_est_new.abs() < theta_dw_update_threshold {
+            self.theta_dw = thetadw_est_new;
+        }
+        self.theta_phi = thetaphi_est_new;
+    }
+}
+
+/// Fixed-point variant of the harmonic estimator of the
+/// [`PowerLineFilter`](crate::filter::pli::PowerLineFilter).
+#[derive(Clone)]
+struct FixedHarmonicCore {
+    // constants
+    harmonic: u32,
+    k_a: Q31,
+
+    in_phase_filter: FixedIir<HighPass, 2>,
+    quadrature_filter: FixedIir<HighPass, 2>,
+
+    // estimated signal parameters
+    theta_i: Q31,
+    theta_q: Q31,
+
+    // signatures
+    y_mod_i: Q31,
+    y_mod_q: Q31,
+}
+
+impl FixedHarmonicCore {
+    fn new<F>(fs: f32, fundamental: f32, harmonic: u32, sig_filter: &F) -> Self
+    where
+        F: IirFilter,
+        for<'a> FixedIir<HighPass, 2>: From<&'a F>,
+    {
+        let frequency = core::f32::consts::TAU * fundamental * harmonic as f32 / fs;
+        let gamma = signature_gamma(sig_filter, frequency);
+        let sig_filter = FixedIir::from(sig_filter);
+
+        Self {
+            harmonic,
+            k_a: Q31::from_f32(gamma * Constants::K_A / fs),
+
+            in_phase_filter: sig_filter.clone(),
+            quadrature_filter: sig_filter,
+
+            theta_i: Q31::ZERO,
+            theta_q: Q31::ZERO,
+
+            y_mod_i: Q31::ZERO,
+            y_mod_q: Q31::ZERO,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.in_phase_filter.clear();
+        self.quadrature_filter.clear();
+
+        self.theta_i = Q31::ZERO;
+        self.theta_q = Q31::ZERO;
+        self.y_mod_i = Q31::ZERO;
+        self.y_mod_q = Q31::ZERO;
+    }
+
+    /// Estimates the harmonic, given the oscillator phase of the fundamental.
+    fn estimate(&mut self, fundamental_phase: u32) -> Q31 {
+        let t = fundamental_phase.wrapping_mul(self.harmonic);
+
+        let osc_i = fixed::sin(t);
+        let osc_q = fixed::cos(t);
+
+        // preserve defaults initially
+        self.y_mod_i = self.in_phase_filter.update(osc_i).unwrap_or(self.y_mod_i);
+        self.y_mod_q = self.quadrature_filter.update(osc_q).unwrap_or(self.y_mod_q);
+
+        self.theta_i * osc_i + self.theta_q * osc_q
+    }
+
+    fn adapt(&mut self, ew: Q31) {
+        let step_i = self.k_a * (ew * self.y_mod_i);
+        let step_q = self.k_a * (ew * self.y_mod_q);
+
+        self.theta_i = self.theta_i.saturating_add(step_i);
+        self.theta_q = self.theta_q.saturating_add(step_q);
+    }
+}
+
+/// Fixed-point variant of [`AdaptationBlocking`](crate::filter::pli::adaptation_blocking::AdaptationBlocking),
+/// using an [`EstimatedSum`](crate::moving::sum::EstimatedSum) of `N` samples for the variance.
+#[derive(Clone)]
+pub struct FixedAdaptationBlocking<const N: usize, const L: usize, const C: usize> {
+    delay: Buffer<Q31, L, false>,
+    comb_filter: Buffer<Q31, C, false>,
+    /// Estimated variance of the comb filtered signal, with 62 fractional bits.
+    variance: i64,
+    variance_samples: usize,
+    delay_cnt: usize,
+}
+
+impl<const N: usize, const L: usize, const C: usize> AdaptationBlockingTrait<Q31>
+    for FixedAdaptationBlocking<N, L, C>
+{
+    fn new(_fs: f32) -> Self {
+        Self {
+            delay: Buffer::new(),
+            comb_filter: Buffer::new(),
+            variance: 0,
+            variance_samples: 0,
+            delay_cnt: 0,
+        }
+    }
+
+    fn update(&mut self, sample: Q31) -> Option<(Q31, bool)> {
+        let delayed_sample = self.delay.push(sample);
+        let comb_filtered = sample - self.comb_filter.push(sample)?;
+
+        let squared = comb_filtered.0 as i64 * comb_filtered.0 as i64;
+        if self.variance_samples < N {
+            self.variance += squared / N as i64;
+            self.variance_samples += 1;
+            return None;
+        }
+        self.variance += (squared - self.variance) / N as i64;
+
+        self.delay_cnt = if squared / 2 > self.variance {
+            2 * L
+        } else {
+            self.delay_cnt.saturating_sub(1)
+        };
+
+        delayed_sample.map(|delayed_sample| (delayed_sample, self.delay_cnt > 0))
+    }
+
+    fn clear(&mut self) {
+        self.comb_filter.clear();
+        self.delay.clear();
+        self.delay_cnt = 0;
+        self.variance = 0;
+        self.variance_samples = 0;
+    }
+}
+
+/// Fixed-point variant of [`PowerLineFilter`](crate::filter::pli::PowerLineFilter).
+#[derive(Clone)]
+pub struct FixedPowerLineFilter<ADB, const N_FS: usize, const N_H: usize = 0>
+where
+    ADB: AdaptationBlockingTrait<Q31>,
+{
+    fs: f32,
+    theta_dw_update_threshold: i64,
+    cores: [FixedFilterCore; N_FS],
+    harmonics: [FixedHarmonicCore; N_H],
+    adaptation_blocking: ADB,
+    error_filter: FixedIir<HighPass, 2>,
+}
+
+impl<ADB, const N_FS: usize> FixedPowerLineFilter<ADB, N_FS>
+where
+    ADB: AdaptationBlockingTrait<Q31>,
+{
+    pub fn new_1ksps(frequencies: [f32; N_FS]) -> Self {
+        Self {
+            fs: 1000.0,
+            theta_dw_update_threshold: phase_from_radians(4.0 / 1000.0),
+            cores: frequencies.map(|f| FixedFilterCore::new(1000.0, f, &SIGNATURE_FILTER)),
+            harmonics: [],
+            adaptation_blocking: ADB::new(1000.0),
+            error_filter: FixedIir::from(&ERROR_FILTER),
+        }
+    }
+}
+
+impl<ADB, const N_H: usize> FixedPowerLineFilter<ADB, 1, N_H>
+where
+    ADB: AdaptationBlockingTrait<Q31>,
+{
+    /// See [`PowerLineFilter::new_1ksps_with_harmonics`](crate::filter::pli::PowerLineFilter::new_1ksps_with_harmonics).
+    pub fn new_1ksps_with_harmonics(fundamental: f32) -> Self {
+        Self {
+            fs: 1000.0,
+            theta_dw_update_threshold: phase_from_radians(4.0 / 1000.0),
+            cores: [FixedFilterCore::new(1000.0, fundamental, &SIGNATURE_FILTER)],
+            harmonics: core::array::from_fn(|i| {
+                FixedHarmonicCore::new(1000.0, fundamental, i as u32 + 2, &SIGNATURE_FILTER)
+            }),
+            adaptation_blocking: ADB::new(1000.0),
+            error_filter: FixedIir::from(&ERROR_FILTER),
+        }
+    }
+
+    /// See [`PowerLineFilter::retune_1ksps`](crate::filter::pli::PowerLineFilter::retune_1ksps).
+    pub fn retune_1ksps(&mut self, fundamental: f32) {
+        self.cores = [FixedFilterCore::new(1000.0, fundamental, &SIGNATURE_FILTER)];
+        self.harmonics = core::array::from_fn(|i| {
+            FixedHarmonicCore::new(1000.0, fundamental, i as u32 + 2, &SIGNATURE_FILTER)
+        });
+    }
+
+    /// See [`PowerLineFilter::design_with_harmonics`](crate::filter::pli::PowerLineFilter::design_with_harmonics).
+    pub fn design_with_harmonics(fs: f32, fundamental: f32) -> Result<Self, DesignError> {
+        let sig_filter = design_signature_filter(fs)?;
+
+        Ok(Self {
+            fs,
+            theta_dw_update_threshold: phase_from_radians(4.0 / fs),
+            cores: [FixedFilterCore::new(fs, fundamental, &sig_filter)],
+            harmonics: core::array::from_fn(|i| {
+                FixedHarmonicCore::new(fs, fundamental, i as u32 + 2, &sig_filter)
+            }),
+            adaptation_blocking: ADB::new(fs),
+            error_filter: FixedIir::from(&sig_filter),
+        })
+    }
+
+    /// See [`PowerLineFilter::retune`](crate::filter::pli::PowerLineFilter::retune).
+    pub fn retune(&mut self, fundamental: f32) {
+        let fs = self.fs;
+        // The sample rate has been accepted when the filter was created.
+        let sig_filter = unwrap!(design_signature_filter(fs).ok());
+
+        self.cores = [FixedFilterCore::new(fs, fundamental, &sig_filter)];
+        self.harmonics = core::array::from_fn(|i| {
+            FixedHarmonicCore::new(fs, fundamental, i as u32 + 2, &sig_filter)
+        });
+    }
+}
+
+impl<ADB, const N_FS: usize, const N_H: usize> Filter<Q31> for FixedPowerLineFilter<ADB, N_FS, N_H>
+where
+    ADB: AdaptationBlockingTrait<Q31>,
+{
+    fn clear(&mut self) {
+        self.cores.iter_mut().for_each(FixedFilterCore::clear);
+        self.harmonics.iter_mut().for_each(FixedHarmonicCore::clear);
+        self.error_filter.clear();
+        self.adaptation_blocking.clear();
+    }
+
+    fn update(&mut self, sample: Q31) -> Option<Q31> {
+        let (delayed_sample, adapt_blocked) = self.adaptation_blocking.update(sample)?;
+
+        let x_est = self
+            .cores
+            .iter_mut()
+            .fold(Q31::ZERO, |sum, core| sum + core.estimate());
+
+        let x_est = match self.cores.first() {
+            Some(fundamental) => {
+                let phase = fundamental.osc_phase;
+                self.harmonics
+                    .iter_mut()
+                    .fold(x_est, |sum, harmonic| sum + harmonic.estimate(phase))
+            }
+            None => x_est,
+        };
+
+        let error = delayed_sample - x_est;
+        let filtered_error = self.error_filter.update(error)?;
+
+        if !adapt_blocked {
+            let threshold = self.theta_dw_update_threshold;
+            self.cores
+                .iter_mut()
+                .for_each(|core| core.adapt(threshold, filtered_error));
+            self.harmonics
+                .iter_mut()
+                .for_each(|harmonic| harmonic.adapt(filtered_error));
+        }
+
+        Some(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FixedAdaptationBlocking, FixedPowerLineFilter};
+    use crate::{
+        filter::{
+            fixed::iir::test::{max_error, test_signal},
+            pli::{
+                adaptation_blocking::{AdaptationBlocking, NoAdaptationBlocking},
+                test::{ecg, interference},
+                PowerLineFilter,
+            },
+            Filter,
+        },
+        fixed::Q31,
+        moving::sum::EstimatedSum,
+    };
+
+    #[test]
+    fn matches_float() {
+        let float = PowerLineFilter::<NoAdaptationBlocking, _, 1>::new_1ksps([50.0]);
+        let fixed = FixedPowerLineFilter::<NoAdaptationBlocking, 1>::new_1ksps([50.0]);
+
+        let error = max_error(float, fixed, 20_000, 2000, 1000.0);
+
+        // Without adaptation blocking, the estimates follow the peaks of the signal. The amplitude
+        // correction is applied differently, so the two filters react slightly differently.
+        assert!(error < 5e-5, "{error}");
+    }
+
+    #[test]
+    fn matches_float_with_adaptation_blocking() {
+        type Blocking = AdaptationBlocking<EstimatedSum<1200>, 4, 19>;
+        type FixedBlocking = FixedAdaptationBlocking<1200, 4, 19>;
+
+        let float = PowerLineFilter::<Blocking, _, 1>::new_1ksps([50.0]);
+        let fixed = FixedPowerLineFilter::<FixedBlocking, 1>::new_1ksps([50.0]);
+
+        let error = max_error(float, fixed, 20_000, 5000, 1000.0);
+
+        // Once the filters have converged, the difference is in the order of a microvolt.
+        assert!(error < 5e-6, "{error}");
+    }
+
+    #[test]
+    fn removes_interference() {
+        let mut filter = FixedPowerLineFilter::<NoAdaptationBlocking, 1>::new_1ksps([50.0]);
+
+        let mut residual = 0.0_f32;
+        for n in 0..20_000 {
+            let output = filter.update(Q31::from_f32(test_signal(n, 1000.0)));
+            let interference = 0.001 * (core::f32::consts::TAU * 50.0 * n as f32 / 1000.0).sin();
+
+            if n > 10_000 {
+                let output = output.unwrap().to_f32();
+                let expected = test_signal(n, 1000.0) - interference;
+                residual = residual.max((output - expected).abs());
+            }
+        }
+
+        assert!(residual < 1e-4, "{residual}");
+    }
+
+    #[test]
+    fn designed_filter_matches_float() {
+        type Blocking = AdaptationBlocking<EstimatedSum<600>, 4, 10>;
+        type FixedBlocking = FixedAdaptationBlocking<600, 4, 10>;
+
+        let float = PowerLineFilter::<Blocking, _, 1, 0>::design_with_harmonics(500.0, 50.0);
+        let fixed = FixedPowerLineFilter::<FixedBlocking, 1, 0>::design_with_harmonics(500.0, 50.0);
+
+        let error = max_error(float.unwrap(), fixed.unwrap(), 10_000, 2500, 500.0);
+
+        assert!(error < 5e-6, "{error}");
+    }
+
+    #[test]
+    fn cancels_harmonics() {
+        type FixedBlocking = FixedAdaptationBlocking<1200, 4, 100>;
+
+        let mut filter =
+            FixedPowerLineFilter::<FixedBlocking, 1, 2>::new_1ksps_with_harmonics(50.0);
+
+        let mut sum = 0.0;
+        for n in 0..30_000 {
+            let output = filter.update(Q31::from_f32(ecg(n) + interference(n)));
+
+            if n >= 25_000 {
+                // The output is delayed by the adaptation blocking
+                let error = output.unwrap().to_f32() - ecg(n - 4);
+                sum += error * error;
+            }
+        }
+        let residual = (sum / 5000.0_f32).sqrt();
+
+        // Without the harmonics, the residual is around 260 microvolts
+        assert!(residual < 1e-4, "{residual}");
+    }
+}
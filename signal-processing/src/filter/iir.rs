@@ -25,6 +25,14 @@ pub struct HighPass {
 #[derive(Clone)]
 pub struct LowPass;
 
+#[derive(Clone)]
+pub struct BandPass {
+    first_sample: Option<f32>,
+}
+
+#[derive(Clone)]
+pub struct BandStop;
+
 pub trait FilterType {
     const NEW: Self;
 
@@ -53,6 +61,29 @@ impl FilterType for LowPass {
     }
 }
 
+// Like highpass filters, bandpass filters block DC. Removing the initial offset avoids a long
+// settling time.
+impl FilterType for BandPass {
+    const NEW: Self = Self { first_sample: None };
+
+    fn clear(&mut self) {
+        self.first_sample = None;
+    }
+
+    fn precondition(&mut self, sample: f32) -> f32 {
+        let first_sample = self.first_sample.get_or_insert(sample);
+        sample - *first_sample
+    }
+}
+
+impl FilterType for BandStop {
+    const NEW: Self = Self;
+
+    fn precondition(&mut self, sample: f32) -> f32 {
+        sample
+    }
+}
+
 #[derive(Clone)]
 pub struct Iir<'a, T, const N: usize> {
     previous_inputs: SlidingWindow<N>,
@@ -80,6 +111,13 @@ where
     }
 }
 
+impl<T, const N: usize> Iir<'_, T, N> {
+    /// Returns the numerator and the reversed denominator coefficients.
+    pub(crate) fn coefficients(&self) -> (&[f32], &[f32]) {
+        (self.num_coeffs, self.denom_coeffs)
+    }
+}
+
 impl<'a, T, const N: usize> IirFilter for Iir<'a, T, N> {
     fn transfer_coeff_at(&self, w: f32) -> Complex<f32> {
         let w = w * TAU;
@@ -100,11 +138,12 @@ impl<'a, T, const N: usize> IirFilter for Iir<'a, T, N> {
     }
 }
 
-impl<T, const N: usize> Filter for Iir<'_, T, N>
+impl<T, const N: usize> Iir<'_, T, N>
 where
     T: FilterType,
 {
-    fn update(&mut self, sample: f32) -> Option<f32> {
+    #[inline(always)]
+    fn filter_sample(&mut self, sample: f32) -> f32 {
         let sample = self.filter_kind.precondition(sample);
 
         let mut y_out = sample * self.num_coeffs[0];
@@ -123,7 +162,25 @@ where
         self.previous_inputs.push(sample);
         self.previous_outputs.push(y_out);
 
-        Some(y_out)
+        y_out
+    }
+}
+
+impl<T, const N: usize> Filter for Iir<'_, T, N>
+where
+    T: FilterType,
+{
+    fn update(&mut self, sample: f32) -> Option<f32> {
+        Some(self.filter_sample(sample))
+    }
+
+    fn process_block(&mut self, input: &[f32], output: &mut [f32]) -> usize {
+        // IIR filters produce an output for every input sample.
+        let output = &mut output[..input.len()];
+        for (output, sample) in output.iter_mut().zip(input.iter().copied()) {
+            *output = self.filter_sample(sample);
+        }
+        input.len()
     }
 
     fn clear(&mut self) {
@@ -135,7 +192,10 @@ where
 
 #[cfg(test)]
 mod test {
-    use super::{ComplExt, Filter, HighPass, Iir, IirFilter, LowPass};
+    use super::{BandPass, BandStop, Filter, HighPass, Iir, IirFilter, LowPass};
+
+    #[allow(unused_imports)]
+    use crate::compat::*;
 
     #[track_caller]
     fn assert_float_equals(value: f32, expectation: f32, tolerance: f32) {
@@ -177,6 +237,66 @@ mod test {
         assert_float_equals(filter.transfer_coeff_at(0.1).norm(), 0.5_f32.sqrt(), 0.01);
     }
 
+    #[test]
+    fn transfer_coeff_band_pass() {
+        #[rustfmt::skip]
+        let filter = macros::designfilt!(
+            "bandpassiir",
+            "FilterOrder", 4,
+            "HalfPowerFrequency1", 5,
+            "HalfPowerFrequency2", 15,
+            "SampleRate", 100
+        );
+
+        assert_float_equals(filter.transfer_coeff_at(0.0).norm(), 0.0, 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.05).norm(), 0.5_f32.sqrt(), 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.15).norm(), 0.5_f32.sqrt(), 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.5).norm(), 0.0, 0.01);
+    }
+
+    #[test]
+    fn transfer_coeff_band_stop() {
+        #[rustfmt::skip]
+        let filter = macros::designfilt!(
+            "bandstopiir",
+            "FilterOrder", 4,
+            "HalfPowerFrequency1", 5,
+            "HalfPowerFrequency2", 15,
+            "SampleRate", 100
+        );
+
+        assert_float_equals(filter.transfer_coeff_at(0.0).norm(), 1.0, 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.05).norm(), 0.5_f32.sqrt(), 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.15).norm(), 0.5_f32.sqrt(), 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.5).norm(), 1.0, 0.01);
+    }
+
+    #[test]
+    fn transfer_coeff_notch() {
+        #[rustfmt::skip]
+        let filter = macros::designfilt!(
+            "notchiir",
+            "CenterFrequency", 50,
+            "QualityFactor", 10,
+            "SampleRate", 1000
+        );
+
+        assert_float_equals(filter.transfer_coeff_at(0.0).norm(), 1.0, 0.01);
+        assert_float_equals(filter.transfer_coeff_at(0.05).norm(), 0.0, 0.01);
+        // The -3dB bandwidth is CenterFrequency / QualityFactor
+        assert_float_equals(
+            filter.transfer_coeff_at(0.0475).norm(),
+            0.5_f32.sqrt(),
+            0.02,
+        );
+        assert_float_equals(
+            filter.transfer_coeff_at(0.0525).norm(),
+            0.5_f32.sqrt(),
+            0.02,
+        );
+        assert_float_equals(filter.transfer_coeff_at(0.25).norm(), 1.0, 0.01);
+    }
+
     #[test]
     fn test_iir_no_input() {
         let input = [0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0.];
@@ -49,6 +49,37 @@ impl<const N: usize> Filter for MedianFilter<N> {
             None
         }
     }
+
+    fn process_block(&mut self, input: &[f32], output: &mut [f32]) -> usize {
+        let mut count = 0;
+
+        // The first outputs depend on samples of the previous block.
+        let head = input.len().min(N - 1);
+        for sample in input[..head].iter().copied() {
+            if let Some(filtered) = self.update(sample) {
+                output[count] = filtered;
+                count += 1;
+            }
+        }
+
+        if input.len() < N {
+            return count;
+        }
+
+        // The rest can be copied from the input directly.
+        for window in input.windows(N) {
+            let mut copy: [f32; N] = [0.0; N];
+            copy.copy_from_slice(window);
+            output[count] = Self::nth(&mut copy, N / 2);
+            count += 1;
+        }
+
+        for sample in input[input.len() - N..].iter().copied() {
+            self.buffer.push(sample);
+        }
+
+        count
+    }
 }
 
 #[cfg(test)]
@@ -67,4 +98,22 @@ mod test {
         assert_eq!(2.0, filter.update(2.0).unwrap());
         assert_eq!(3.0, filter.update(5.0).unwrap());
     }
+
+    #[test]
+    fn block_matches_samples() {
+        let input = [0.0, 1.0, 2.0, 3.0, 4.0, 1.0, 2.0, 5.0, -1.0, 0.0, 7.0, 3.0];
+
+        let mut filter: MedianFilter<5> = MedianFilter::new();
+        let expected = input
+            .iter()
+            .filter_map(|sample| filter.update(*sample))
+            .collect::<Vec<_>>();
+
+        let mut filter: MedianFilter<5> = MedianFilter::new();
+        let mut output = [0.0; 12];
+        let mut count = filter.process_block(&input[..3], &mut output);
+        count += filter.process_block(&input[3..], &mut output[count..]);
+
+        assert_eq!(&output[..count], &expected[..]);
+    }
 }
@@ -1,39 +1,72 @@
 use object_chain::{Chain, ChainElement, Link};
 
 pub mod comb;
+pub mod design;
 pub mod downsample;
 
 #[cfg(feature = "dyn_filter")]
 pub mod dyn_iir;
 pub mod fir;
+pub mod fixed;
 pub mod iir;
 pub mod median;
 pub mod pli;
+pub mod sos;
 
-pub trait Filter {
-    fn update(&mut self, sample: f32) -> Option<f32>;
+/// Number of samples a chain passes between its filters at once.
+const CHAIN_BLOCK_SIZE: usize = 32;
+
+/// A filter that processes samples of type `S`.
+pub trait Filter<S = f32> {
+    fn update(&mut self, sample: S) -> Option<S>;
     fn clear(&mut self);
+
+    /// Filters a block of samples, and writes the results to the start of `output`. Returns the
+    /// number of output samples, which is the same as the number of `update` calls that would
+    /// have returned `Some`.
+    ///
+    /// Panics if `output` is shorter than the number of output samples.
+    fn process_block(&mut self, input: &[S], output: &mut [S]) -> usize
+    where
+        S: Copy + Default,
+    {
+        let mut count = 0;
+        for sample in input.iter().copied() {
+            if let Some(filtered) = self.update(sample) {
+                output[count] = filtered;
+                count += 1;
+            }
+        }
+        count
+    }
 }
 
-impl<F> Filter for Chain<F>
+impl<S, F> Filter<S> for Chain<F>
 where
-    F: Filter,
+    F: Filter<S>,
 {
-    fn update(&mut self, sample: f32) -> Option<f32> {
+    fn update(&mut self, sample: S) -> Option<S> {
         self.object.update(sample)
     }
 
     fn clear(&mut self) {
         self.object.clear()
     }
+
+    fn process_block(&mut self, input: &[S], output: &mut [S]) -> usize
+    where
+        S: Copy + Default,
+    {
+        self.object.process_block(input, output)
+    }
 }
 
-impl<F, P> Filter for Link<F, P>
+impl<S, F, P> Filter<S> for Link<F, P>
 where
-    F: Filter,
-    P: ChainElement + Filter,
+    F: Filter<S>,
+    P: ChainElement + Filter<S>,
 {
-    fn update(&mut self, sample: f32) -> Option<f32> {
+    fn update(&mut self, sample: S) -> Option<S> {
         let sample = self.parent.update(sample)?;
         self.object.update(sample)
     }
@@ -42,4 +75,55 @@ where
         self.parent.clear();
         self.object.clear();
     }
+
+    fn process_block(&mut self, input: &[S], output: &mut [S]) -> usize
+    where
+        S: Copy + Default,
+    {
+        let mut scratch = [S::default(); CHAIN_BLOCK_SIZE];
+        let mut count = 0;
+        for chunk in input.chunks(CHAIN_BLOCK_SIZE) {
+            let filtered = self.parent.process_block(chunk, &mut scratch);
+            count += self
+                .object
+                .process_block(&scratch[..filtered], &mut output[count..]);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use object_chain::Chain;
+
+    use crate::filter::{downsample::DownSampler, median::MedianFilter, Filter};
+
+    #[test]
+    fn chain_block_matches_samples() {
+        let new_chain = || {
+            Chain::new(MedianFilter::<5>::new())
+                .append(DownSampler::new())
+                .append(DownSampler::new())
+        };
+
+        let input = (0..1000)
+            .map(|n| ((n * 7919) % 113) as f32 / 113.0)
+            .collect::<Vec<_>>();
+
+        let mut chain = new_chain();
+        let expected = input
+            .iter()
+            .filter_map(|sample| chain.update(*sample))
+            .collect::<Vec<_>>();
+
+        // Uneven block sizes, to split the input at different points
+        let mut chain = new_chain();
+        let mut output = vec![0.0; input.len()];
+        let mut count = 0;
+        for block in input.chunks(77) {
+            count += chain.process_block(block, &mut output[count..]);
+        }
+
+        assert_eq!(&output[..count], &expected[..]);
+    }
 }
@@ -5,13 +5,45 @@
 //! Implementation loosely based on matlab code found in <https://github.com/s-gv/rnicu/blob/master/ecg/adaptive_filter/pll_martens_errorfilt_supp.m>
 
 use crate::filter::{
+    design::DesignError,
     iir::{HighPass, Iir, IirFilter},
+    sos::Sos,
     Filter,
 };
 
 #[allow(unused_imports)]
 use crate::compat::*;
 
+#[rustfmt::skip]
+pub(crate) const SIGNATURE_FILTER: Iir<HighPass, 2> = macros::designfilt!(
+    "highpassiir",
+    "FilterOrder", 2,
+    "HalfPowerFrequency", 50,
+    "SampleRate", 1000
+);
+#[rustfmt::skip]
+pub(crate) const ERROR_FILTER: Iir<HighPass, 2> = macros::designfilt!(
+    "highpassiir",
+    "FilterOrder", 2,
+    "HalfPowerFrequency", 50,
+    "SampleRate", 1000
+);
+
+/// Half-power frequency of the signature and error filters, in Hz.
+pub(crate) const SIGNATURE_CUTOFF: f32 = 50.0;
+
+/// Designs the signature and error filter for `fs` samples per second. Matches
+/// [`SIGNATURE_FILTER`] at 1ksps.
+pub(crate) fn design_signature_filter(fs: f32) -> Result<Sos<HighPass, 1>, DesignError> {
+    Sos::butterworth(2, fs, &[SIGNATURE_CUTOFF])
+}
+
+/// Returns the attenuation correction of the signature filter at `frequency`, with the 2.0
+/// multiplier of the adaptation steps merged in.
+pub(crate) fn signature_gamma(sig_filter: &impl IirFilter, frequency: f32) -> f32 {
+    2.0 / sig_filter.transfer_coeff_at(frequency).norm()
+}
+
 #[derive(Clone)]
 struct FilterCore<F> {
     // constants
@@ -32,6 +64,10 @@ struct FilterCore<F> {
     // signatures
     y_mod_a: f32,
     y_mod_phi: f32,
+
+    // oscillator outputs of the last estimate, used to derive the harmonics
+    osc_sin: f32,
+    osc_cos: f32,
 }
 
 impl<F> FilterCore<F>
@@ -44,7 +80,7 @@ where
 
         Self {
             frequency,
-            gamma: 2.0 / sig_filter.transfer_coeff_at(frequency).norm(),
+            gamma: signature_gamma(&sig_filter, frequency),
 
             phase_filter: sig_filter.clone(),
             amplitude_filter: sig_filter,
@@ -57,6 +93,9 @@ where
 
             y_mod_a: 0.0,
             y_mod_phi: 0.0,
+
+            osc_sin: 0.0,
+            osc_cos: 1.0,
         }
     }
 }
@@ -75,13 +114,18 @@ where
         self.theta_dw = 0.0;
         self.y_mod_a = 0.0;
         self.y_mod_phi = 0.0;
+        self.osc_sin = 0.0;
+        self.osc_cos = 1.0;
     }
 
     fn estimate(&mut self, idx: usize) -> f32 {
         let t = self.frequency * (idx as f32) + self.theta_phi;
 
-        let osc_i = t.sin();
-        let osc_q = self.alpha * t.cos();
+        self.osc_sin = t.sin();
+        self.osc_cos = t.cos();
+
+        let osc_i = self.osc_sin;
+        let osc_q = self.alpha * self.osc_cos;
 
         // preserve defaults initially
         self.y_mod_a = self.amplitude_filter.update(osc_i).unwrap_or(self.y_mod_a);
@@ -115,6 +159,80 @@ where
     }
 }
 
+/// Estimates a harmonic of a [`FilterCore`]'s signal.
+///
+/// The oscillator is derived from the phase of the fundamental, so the harmonic follows the
+/// frequency drift of the fundamental. Only the in-phase and quadrature amplitudes of the harmonic
+/// are adapted.
+#[derive(Clone)]
+struct HarmonicCore<F> {
+    // constants
+    gamma: f32, // error filter attenuation
+
+    in_phase_filter: F,
+    quadrature_filter: F,
+
+    // estimated signal parameters
+    theta_i: f32,
+    theta_q: f32,
+
+    // signatures
+    y_mod_i: f32,
+    y_mod_q: f32,
+}
+
+impl<F> HarmonicCore<F>
+where
+    F: Filter + IirFilter + Clone,
+{
+    #[inline(always)]
+    fn new(fs: f32, fundamental: f32, harmonic: usize, sig_filter: F) -> Self {
+        let frequency = 2.0 * core::f32::consts::PI * fundamental * harmonic as f32 / fs;
+
+        Self {
+            gamma: signature_gamma(&sig_filter, frequency),
+
+            in_phase_filter: sig_filter.clone(),
+            quadrature_filter: sig_filter,
+
+            theta_i: 0.0,
+            theta_q: 0.0,
+
+            y_mod_i: 0.0,
+            y_mod_q: 0.0,
+        }
+    }
+}
+
+impl<F> HarmonicCore<F>
+where
+    F: Filter,
+{
+    fn clear(&mut self) {
+        self.in_phase_filter.clear();
+        self.quadrature_filter.clear();
+
+        self.theta_i = 0.0;
+        self.theta_q = 0.0;
+        self.y_mod_i = 0.0;
+        self.y_mod_q = 0.0;
+    }
+
+    /// Estimates the harmonic, given the sine and cosine of its phase.
+    fn estimate(&mut self, osc_i: f32, osc_q: f32) -> f32 {
+        // preserve defaults initially
+        self.y_mod_i = self.in_phase_filter.update(osc_i).unwrap_or(self.y_mod_i);
+        self.y_mod_q = self.quadrature_filter.update(osc_q).unwrap_or(self.y_mod_q);
+
+        self.theta_i * osc_i + self.theta_q * osc_q
+    }
+
+    fn adapt(&mut self, filter: &Constants, ew: f32) {
+        self.theta_i += filter.k_a * self.gamma * ew * self.y_mod_i;
+        self.theta_q += filter.k_a * self.gamma * ew * self.y_mod_q;
+    }
+}
+
 pub mod adaptation_blocking {
     use crate::{
         filter::{comb::CombFilter, Filter},
@@ -128,9 +246,9 @@ pub mod adaptation_blocking {
     #[allow(unused_imports)]
     use crate::compat::*;
 
-    pub trait AdaptationBlockingTrait {
+    pub trait AdaptationBlockingTrait<S = f32> {
         fn new(fs: f32) -> Self;
-        fn update(&mut self, sample: f32) -> Option<(f32, bool)>;
+        fn update(&mut self, sample: S) -> Option<(S, bool)>;
         fn clear(&mut self);
     }
 
@@ -148,11 +266,11 @@ pub mod adaptation_blocking {
         delay_cnt: usize,
     }
 
-    impl AdaptationBlockingTrait for NoAdaptationBlocking {
+    impl<S> AdaptationBlockingTrait<S> for NoAdaptationBlocking {
         fn new(_fs: f32) -> Self {
             Self
         }
-        fn update(&mut self, sample: f32) -> Option<(f32, bool)> {
+        fn update(&mut self, sample: S) -> Option<(S, bool)> {
             Some((sample, false))
         }
         fn clear(&mut self) {}
@@ -193,32 +311,39 @@ pub mod adaptation_blocking {
 
 #[derive(Clone)]
 pub struct Constants {
+    fs: f32,
     k_a: f32,
     theta_dw_update_threshold: f32,
 }
 
 impl Constants {
-    const K_A: f32 = 1.0 / 0.13;
-    const K_PHI: f32 = 6e-2;
-    const K_DW: f32 = 9e-4;
+    pub(crate) const K_A: f32 = 1.0 / 0.13;
+    pub(crate) const K_PHI: f32 = 6e-2;
+    pub(crate) const K_DW: f32 = 9e-4;
 
     #[inline(always)]
     fn new(fs: f32) -> Self {
         Self {
+            fs,
             k_a: Self::K_A / fs,
             theta_dw_update_threshold: 4.0 / fs,
         }
     }
 }
 
+/// Power-line interference filter that tracks `N_FS` independent frequencies.
+///
+/// If `N_H` is not zero, the filter also cancels the 2nd to `N_H + 1`th harmonics of the first
+/// frequency.
 #[derive(Clone)]
-pub struct PowerLineFilter<ADB, F, const N_FS: usize>
+pub struct PowerLineFilter<ADB, F, const N_FS: usize, const N_H: usize = 0>
 where
     ADB: adaptation_blocking::AdaptationBlockingTrait,
 {
     // configuration
     consts: Constants,
     cores: [FilterCore<F>; N_FS],
+    harmonics: [HarmonicCore<F>; N_H],
     adaptation_blocking: ADB,
     error_filter: F,
     sample_idx: usize,
@@ -230,28 +355,86 @@ where
 {
     #[inline]
     pub fn new_1ksps(frequencies: [f32; N_FS]) -> Self {
-        #[rustfmt::skip]
-        const SIGNATURE_FILTER: Iir<HighPass, 2> = macros::designfilt!(
-            "highpassiir",
-            "FilterOrder", 2,
-            "HalfPowerFrequency", 50,
-            "SampleRate", 1000
-        );
-        #[rustfmt::skip]
-        const ERROR_FILTER: Iir<HighPass, 2> = macros::designfilt!(
-            "highpassiir",
-            "FilterOrder", 2,
-            "HalfPowerFrequency", 50,
-            "SampleRate", 1000
-        );
         Self {
             consts: Constants::new(1000.0),
             cores: frequencies.map(|f| FilterCore::new(1000.0, f, SIGNATURE_FILTER)),
+            harmonics: [],
+            adaptation_blocking: ADB::new(1000.0),
+            error_filter: ERROR_FILTER,
+            sample_idx: 0,
+        }
+    }
+}
+
+impl<ADB, const N_H: usize> PowerLineFilter<ADB, Iir<'static, HighPass, 2>, 1, N_H>
+where
+    ADB: adaptation_blocking::AdaptationBlockingTrait,
+{
+    /// Creates a filter that locks onto the `fundamental` frequency, and cancels its 2nd to
+    /// `N_H + 1`th harmonics.
+    ///
+    /// The comb filter of the adaptation blocking should span a whole number of mains periods.
+    /// Otherwise the distorted interference leaks through it, and adaptation gets blocked at the
+    /// peaks of the interference, which biases the harmonic estimates.
+    #[inline]
+    pub fn new_1ksps_with_harmonics(fundamental: f32) -> Self {
+        Self {
+            consts: Constants::new(1000.0),
+            cores: [FilterCore::new(1000.0, fundamental, SIGNATURE_FILTER)],
+            harmonics: core::array::from_fn(|i| {
+                HarmonicCore::new(1000.0, fundamental, i + 2, SIGNATURE_FILTER)
+            }),
             adaptation_blocking: ADB::new(1000.0),
             error_filter: ERROR_FILTER,
             sample_idx: 0,
         }
     }
+
+    /// Tunes the filter to a different `fundamental` frequency.
+    ///
+    /// Only the estimates are reset. The adaptation blocking and the error filter keep their
+    /// state, so the filter keeps producing an output for every input, with the same delay.
+    pub fn retune_1ksps(&mut self, fundamental: f32) {
+        self.cores = [FilterCore::new(1000.0, fundamental, SIGNATURE_FILTER)];
+        self.harmonics = core::array::from_fn(|i| {
+            HarmonicCore::new(1000.0, fundamental, i + 2, SIGNATURE_FILTER)
+        });
+    }
+}
+
+impl<ADB, const N_H: usize> PowerLineFilter<ADB, Sos<HighPass, 1>, 1, N_H>
+where
+    ADB: adaptation_blocking::AdaptationBlockingTrait,
+{
+    /// Creates a filter for `fs` samples per second, like
+    /// [`new_1ksps_with_harmonics`](PowerLineFilter::new_1ksps_with_harmonics). The signature and
+    /// error filters are designed at runtime.
+    pub fn design_with_harmonics(fs: f32, fundamental: f32) -> Result<Self, DesignError> {
+        let sig_filter = design_signature_filter(fs)?;
+
+        Ok(Self {
+            consts: Constants::new(fs),
+            cores: [FilterCore::new(fs, fundamental, sig_filter.clone())],
+            harmonics: core::array::from_fn(|i| {
+                HarmonicCore::new(fs, fundamental, i + 2, sig_filter.clone())
+            }),
+            adaptation_blocking: ADB::new(fs),
+            error_filter: sig_filter,
+            sample_idx: 0,
+        })
+    }
+
+    /// Tunes the filter to a different `fundamental` frequency, like
+    /// [`retune_1ksps`](PowerLineFilter::retune_1ksps).
+    pub fn retune(&mut self, fundamental: f32) {
+        let fs = self.consts.fs;
+        // The sample rate has been accepted when the filter was created.
+        let sig_filter = unwrap!(design_signature_filter(fs).ok());
+
+        self.cores = [FilterCore::new(fs, fundamental, sig_filter.clone())];
+        self.harmonics =
+            core::array::from_fn(|i| HarmonicCore::new(fs, fundamental, i + 2, sig_filter.clone()));
+    }
 }
 
 #[cfg(feature = "dyn_filter")]
@@ -267,6 +450,7 @@ where
         Self {
             consts: Constants::new(fs),
             cores: frequencies.map(|f| FilterCore::new(fs, f, DynIir::design(fs, f))),
+            harmonics: [],
             adaptation_blocking: ADB::new(fs),
             error_filter: DynIir::design(fs, 50.0),
             sample_idx: 0,
@@ -274,13 +458,41 @@ where
     }
 }
 
-impl<ADB, F, const N_FS: usize> Filter for PowerLineFilter<ADB, F, N_FS>
+impl<ADB, F, const N_FS: usize, const N_H: usize> PowerLineFilter<ADB, F, N_FS, N_H>
+where
+    ADB: adaptation_blocking::AdaptationBlockingTrait,
+    F: Filter,
+{
+    fn estimate_harmonics(&mut self) -> f32 {
+        let Some(fundamental) = self.cores.first() else {
+            return 0.0;
+        };
+
+        // The harmonics are stored in order, so rotating by the fundamental's phase once per
+        // harmonic gives sin(k * t) and cos(k * t) without evaluating the oscillators again.
+        let (sin_t, cos_t) = (fundamental.osc_sin, fundamental.osc_cos);
+        let (mut sin_kt, mut cos_kt) = (sin_t, cos_t);
+
+        let mut x_est = 0.0;
+        for harmonic in self.harmonics.iter_mut() {
+            (sin_kt, cos_kt) = (
+                sin_kt * cos_t + cos_kt * sin_t,
+                cos_kt * cos_t - sin_kt * sin_t,
+            );
+            x_est += harmonic.estimate(sin_kt, cos_kt);
+        }
+        x_est
+    }
+}
+
+impl<ADB, F, const N_FS: usize, const N_H: usize> Filter for PowerLineFilter<ADB, F, N_FS, N_H>
 where
     ADB: adaptation_blocking::AdaptationBlockingTrait,
     F: Filter,
 {
     fn clear(&mut self) {
         self.cores.iter_mut().for_each(FilterCore::clear);
+        self.harmonics.iter_mut().for_each(HarmonicCore::clear);
         self.sample_idx = 0;
         self.error_filter.clear();
         self.adaptation_blocking.clear();
@@ -293,7 +505,8 @@ where
             .cores
             .iter_mut()
             .map(|core| core.estimate(self.sample_idx))
-            .sum::<f32>();
+            .sum::<f32>()
+            + self.estimate_harmonics();
 
         self.sample_idx += 1;
 
@@ -304,8 +517,211 @@ where
             self.cores
                 .iter_mut()
                 .for_each(|core| core.adapt(&self.consts, filtered_error));
+            self.harmonics
+                .iter_mut()
+                .for_each(|harmonic| harmonic.adapt(&self.consts, filtered_error));
         }
 
         Some(error)
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::{
+        adaptation_blocking::{AdaptationBlocking, NoAdaptationBlocking},
+        PowerLineFilter,
+    };
+    use crate::{
+        filter::{comb::CombFilter, Filter},
+        moving::sum::EstimatedSum,
+    };
+
+    const FS: f32 = 1000.0;
+
+    fn gaussian(t: f32, center: f32, width: f32, amplitude: f32) -> f32 {
+        let x = (t - center) / width;
+        amplitude * (-0.5 * x * x).exp()
+    }
+
+    /// A synthetic ECG signal at 72 bpm, with P, QRS and T waves on an electrode offset.
+    pub(crate) fn ecg(n: usize) -> f32 {
+        ecg_at(n as f32 / FS)
+    }
+
+    /// The synthetic ECG signal at `t` seconds.
+    fn ecg_at(t: f32) -> f32 {
+        let t = t % (60.0 / 72.0);
+
+        0.05 + gaussian(t, 0.15, 0.025, 0.00015)
+            + gaussian(t, 0.30, 0.010, 0.0012)
+            + gaussian(t, 0.55, 0.040, 0.0003)
+    }
+
+    /// Off-nominal mains interference, distorted by its 2nd and 3rd harmonics.
+    pub(crate) fn interference(n: usize) -> f32 {
+        let t = core::f32::consts::TAU * 50.2 * n as f32 / FS;
+
+        0.001 * t.sin() + 0.0003 * (2.0 * t + 0.5).sin() + 0.0002 * (3.0 * t + 1.0).sin()
+    }
+
+    /// Returns the RMS of the difference between the filter output and the clean ECG, over the
+    /// last 5 seconds of a 30 second recording. The output lags the input by `delay` samples.
+    fn residual(mut filter: impl Filter, delay: usize) -> f32 {
+        const SAMPLES: usize = 30_000;
+        const SKIP: usize = 25_000;
+
+        let mut sum = 0.0;
+        for n in 0..SAMPLES {
+            let output = filter.update(ecg(n) + interference(n));
+
+            if n >= SKIP {
+                let error = output.unwrap() - ecg(n - delay);
+                sum += error * error;
+            }
+        }
+
+        (sum / (SAMPLES - SKIP) as f32).sqrt()
+    }
+
+    #[test]
+    fn cancels_harmonics() {
+        let fundamental = PowerLineFilter::<NoAdaptationBlocking, _, 1>::new_1ksps([50.0]);
+        let harmonics =
+            PowerLineFilter::<NoAdaptationBlocking, _, 1, 2>::new_1ksps_with_harmonics(50.0);
+
+        let fundamental = residual(fundamental, 0);
+        let harmonics = residual(harmonics, 0);
+
+        // The harmonics are left in the signal
+        assert!(fundamental > 2e-4, "{fundamental}");
+        // The remaining error is mostly the distortion of the ECG waves
+        assert!(harmonics < 5e-5, "{harmonics}");
+    }
+
+    #[test]
+    fn cancels_harmonics_with_adaptation_blocking() {
+        type Blocking = AdaptationBlocking<EstimatedSum<1200>, 4, 100>;
+
+        let fundamental = PowerLineFilter::<Blocking, _, 1>::new_1ksps([50.0]);
+        let harmonics = PowerLineFilter::<Blocking, _, 1, 2>::new_1ksps_with_harmonics(50.0);
+
+        let fundamental = residual(fundamental, 4);
+        let harmonics = residual(harmonics, 4);
+
+        assert!(fundamental > 2e-4, "{fundamental}");
+        assert!(harmonics < 5e-5, "{harmonics}");
+    }
+
+    #[test]
+    fn without_harmonics_matches_fundamental_only() {
+        let mut fundamental = PowerLineFilter::<NoAdaptationBlocking, _, 1>::new_1ksps([50.0]);
+        let mut harmonics =
+            PowerLineFilter::<NoAdaptationBlocking, _, 1, 0>::new_1ksps_with_harmonics(50.0);
+
+        for n in 0..5000 {
+            let sample = ecg(n) + interference(n);
+            assert_eq!(fundamental.update(sample), harmonics.update(sample));
+        }
+    }
+
+    /// Returns the peak output of a comb filter for a unit sine wave at `frequency`.
+    fn comb_gain<const C: usize>(frequency: f32) -> f32 {
+        let mut comb = CombFilter::<C>::new();
+
+        let mut peak = 0.0f32;
+        for n in 0..2000 {
+            let phase = (frequency * n as f32 / FS).fract();
+            if let Some(output) = comb.update((core::f32::consts::TAU * phase).sin()) {
+                peak = peak.max(output.abs());
+            }
+        }
+        peak
+    }
+
+    #[test]
+    fn adaptation_blocking_comb_rejects_mains_harmonics() {
+        for fundamental in [50.0, 60.0] {
+            for harmonic in 1..=3 {
+                let frequency = fundamental * harmonic as f32;
+
+                let gain = comb_gain::<100>(frequency);
+                assert!(gain < 1e-3, "{frequency}Hz: {gain}");
+
+                // A shorter comb leaks the interference
+                let gain = comb_gain::<19>(frequency);
+                assert!(gain > 0.1, "{frequency}Hz: {gain}");
+            }
+        }
+    }
+
+    #[test]
+    fn designed_filter_matches_1ksps() {
+        type Blocking = AdaptationBlocking<EstimatedSum<1200>, 4, 100>;
+
+        let designed =
+            PowerLineFilter::<Blocking, _, 1, 2>::design_with_harmonics(FS, 50.0).unwrap();
+        let builtin = PowerLineFilter::<Blocking, _, 1, 2>::new_1ksps_with_harmonics(50.0);
+
+        let designed = residual(designed, 4);
+        let builtin = residual(builtin, 4);
+
+        // The signature filters start from a different state, so the outputs are not identical
+        assert!(designed < 5e-5, "{designed}");
+        assert!((designed - builtin).abs() < 1e-5, "{designed} vs {builtin}");
+    }
+
+    #[test]
+    fn removes_interference_at_other_sample_rates() {
+        for fs in [500.0, 2000.0] {
+            let window = fs as usize / 10;
+            let mut filter =
+                PowerLineFilter::<NoAdaptationBlocking, _, 1, 2>::design_with_harmonics(fs, 50.0)
+                    .unwrap();
+
+            let signal = |n: usize| ecg_at(n as f32 / fs);
+            let mains = |n: usize| 0.001 * (core::f32::consts::TAU * 50.2 * n as f32 / fs).sin();
+
+            let settle = 20 * fs as usize;
+            let mut sum = 0.0;
+            for n in 0..settle + window {
+                let output = filter.update(signal(n) + mains(n)).unwrap();
+                if n >= settle {
+                    let error = output - signal(n);
+                    sum += error * error;
+                }
+            }
+
+            let residual = (sum / window as f32).sqrt();
+            assert!(residual < 1e-4, "{fs}sps: {residual}");
+        }
+    }
+
+    #[test]
+    fn retuning_keeps_the_output_continuous() {
+        type Blocking = AdaptationBlocking<EstimatedSum<1200>, 4, 100>;
+
+        let mains = |n: usize| 0.001 * (core::f32::consts::TAU * 60.0 * n as f32 / FS).sin();
+        let mut filter = PowerLineFilter::<Blocking, _, 1, 2>::new_1ksps_with_harmonics(50.0);
+
+        for n in 0..5000 {
+            filter.update(ecg(n) + mains(n));
+        }
+
+        filter.retune_1ksps(60.0);
+
+        let mut sum = 0.0;
+        for n in 5000..30_000 {
+            let output = filter.update(ecg(n) + mains(n));
+            assert!(output.is_some(), "no output for sample {n}");
+
+            if n >= 25_000 {
+                let error = output.unwrap() - ecg(n - 4);
+                sum += error * error;
+            }
+        }
+
+        let residual = (sum / 5000.0).sqrt();
+        assert!(residual < 5e-5, "{residual}");
+    }
+}
@@ -0,0 +1,472 @@
+//! IIR filters as a cascade of second-order sections.
+//!
+//! Higher order filters in direct form are very sensitive to coefficient quantization, especially
+//! when the cutoff frequency is low compared to the sample rate. Splitting them into second-order
+//! sections keeps them stable.
+
+use core::f32::consts::TAU;
+
+use num_complex::Complex;
+
+use crate::filter::{
+    design::{
+        complex_sqrt, prewarp, prototype_dc_gain, prototype_poles, AnalogSection, Band,
+        DesignError, DigitalSection, Prototype,
+    },
+    iir::{BandPass, BandStop, FilterType, HighPass, IirFilter, LowPass},
+    Filter,
+};
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+pub trait SosFilterType: FilterType {
+    const BAND: Band;
+}
+
+impl SosFilterType for LowPass {
+    const BAND: Band = Band::LowPass;
+}
+
+impl SosFilterType for HighPass {
+    const BAND: Band = Band::HighPass;
+}
+
+impl SosFilterType for BandPass {
+    const BAND: Band = Band::BandPass;
+}
+
+impl SosFilterType for BandStop {
+    const BAND: Band = Band::BandStop;
+}
+
+/// A second-order section in transposed direct form II.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    state: [f32; 2],
+}
+
+impl Biquad {
+    const PASS_THROUGH: Self = Self {
+        b: [1.0, 0.0, 0.0],
+        a: [0.0, 0.0],
+        state: [0.0, 0.0],
+    };
+
+    fn from_section(section: DigitalSection) -> Self {
+        Self {
+            b: section.b.map(|b| b as f32),
+            a: section.a.map(|a| a as f32),
+            state: [0.0, 0.0],
+        }
+    }
+
+    #[inline(always)]
+    fn update(&mut self, sample: f32) -> f32 {
+        let [b0, b1, b2] = self.b;
+        let [a1, a2] = self.a;
+
+        let output = b0 * sample + self.state[0];
+        self.state[0] = b1 * sample - a1 * output + self.state[1];
+        self.state[1] = b2 * sample - a2 * output;
+
+        output
+    }
+
+    fn transfer_coeff_at(&self, z_inv: Complex<f32>) -> Complex<f32> {
+        let [b0, b1, b2] = self.b;
+        let [a1, a2] = self.a;
+
+        let num = (z_inv * b2 + b1) * z_inv + b0;
+        let den = (z_inv * a2 + a1) * z_inv + 1.0;
+
+        num / den
+    }
+}
+
+/// An IIR filter made of up to `N` second-order sections.
+#[derive(Clone)]
+pub struct Sos<T, const N: usize>
+where
+    T: FilterType,
+{
+    sections: [Biquad; N],
+    filter_kind: T,
+}
+
+impl<T, const N: usize> Sos<T, N>
+where
+    T: FilterType,
+{
+    /// A filter that returns its input unchanged.
+    pub const ALL_PASS: Self = Self {
+        sections: [Biquad::PASS_THROUGH; N],
+        filter_kind: T::NEW,
+    };
+}
+
+impl<T, const N: usize> Sos<T, N>
+where
+    T: FilterType,
+{
+    /// Returns the numerator and denominator coefficients of each section, without `a0`.
+    pub(crate) fn sections(&self) -> impl Iterator<Item = ([f32; 3], [f32; 2])> + '_ {
+        self.sections.iter().map(|section| (section.b, section.a))
+    }
+}
+
+impl<T, const N: usize> Sos<T, N>
+where
+    T: SosFilterType,
+{
+    /// Designs a Butterworth filter. See [`Sos::design`].
+    pub fn butterworth(order: usize, fs: f32, frequencies: &[f32]) -> Result<Self, DesignError> {
+        Self::design(Prototype::Butterworth, order, fs, frequencies)
+    }
+
+    /// Designs a Chebyshev type I filter with `ripple` dB passband ripple. See [`Sos::design`].
+    pub fn chebyshev(
+        order: usize,
+        ripple: f32,
+        fs: f32,
+        frequencies: &[f32],
+    ) -> Result<Self, DesignError> {
+        Self::design(Prototype::Chebyshev { ripple }, order, fs, frequencies)
+    }
+
+    /// Designs a filter at runtime.
+    ///
+    /// Lowpass and highpass filters take one cutoff frequency and need `order / 2` sections,
+    /// rounded up. Bandpass and bandstop filters take the lower and upper edges of the band, and
+    /// their order must be even. Unused sections pass the signal through.
+    pub fn design(
+        prototype: Prototype,
+        order: usize,
+        fs: f32,
+        frequencies: &[f32],
+    ) -> Result<Self, DesignError> {
+        if frequencies.len() != T::BAND.frequency_count() {
+            return Err(DesignError::FrequencyCount);
+        }
+
+        let prototype_order = match T::BAND {
+            Band::LowPass | Band::HighPass => order,
+            Band::BandPass | Band::BandStop if order % 2 == 0 => order / 2,
+            _ => return Err(DesignError::InvalidOrder),
+        };
+
+        if prototype_order == 0 {
+            return Err(DesignError::InvalidOrder);
+        }
+
+        let section_count = match T::BAND {
+            Band::LowPass | Band::HighPass => order.div_ceil(2),
+            Band::BandPass | Band::BandStop => order / 2,
+        };
+
+        if section_count > N {
+            return Err(DesignError::OrderTooHigh);
+        }
+
+        let [w1, w2] = prewarp(fs, frequencies)?;
+
+        // The frequency where the gain of the filter is set to the gain of the prototype at DC.
+        let reference = match T::BAND {
+            Band::LowPass | Band::BandStop => Complex::new(1.0, 0.0),
+            Band::HighPass => Complex::new(-1.0, 0.0),
+            // The center frequency maps to `s = j * sqrt(w1 * w2)`
+            Band::BandPass => {
+                let s = Complex::new(0.0, libm::sqrt(w1 * w2));
+                (Complex::new(1.0, 0.0) + s) / (Complex::new(1.0, 0.0) - s)
+            }
+        };
+
+        let mut filter = Self::ALL_PASS;
+        let mut sections = filter.sections.iter_mut();
+        let mut push = |analog: AnalogSection| {
+            let mut section = analog.bilinear();
+            section.normalize(reference);
+            // `section_count` has been checked above
+            *unwrap!(sections.next()) = Biquad::from_section(section);
+        };
+
+        prototype_poles(prototype, prototype_order, |pole| {
+            for section in transform(T::BAND, pole, w1, w2).into_iter().flatten() {
+                push(section);
+            }
+        })?;
+
+        let gain = prototype_dc_gain(prototype, prototype_order) as f32;
+        filter.sections[0].b.iter_mut().for_each(|b| *b *= gain);
+
+        Ok(filter)
+    }
+}
+
+/// Transforms a pole of the lowpass prototype into analog sections of the requested band type.
+///
+/// `pole` is either real, or the one in the upper half-plane of a complex conjugate pair.
+fn transform(band: Band, pole: Complex<f64>, w1: f64, w2: f64) -> [Option<AnalogSection>; 2] {
+    let real = pole.im == 0.0;
+
+    // Second-order section with the given numerator and a complex conjugate pole pair.
+    let conjugate_pair = |num: [f64; 3], pole: Complex<f64>| AnalogSection {
+        num,
+        den: [1.0, -2.0 * pole.re, pole.norm_sqr()],
+        first_order: false,
+    };
+
+    match band {
+        Band::LowPass | Band::HighPass => {
+            let (pole, num) = if band == Band::LowPass {
+                // Zeros at infinity
+                (pole * w1, [0.0, 0.0, 1.0])
+            } else {
+                // Zeros at DC
+                (w1 / pole, [1.0, 0.0, 0.0])
+            };
+
+            let section = if real {
+                // A single zero at infinity or DC
+                AnalogSection {
+                    num: [0.0, num[0], num[2]],
+                    den: [0.0, 1.0, -pole.re],
+                    first_order: true,
+                }
+            } else {
+                conjugate_pair(num, pole)
+            };
+
+            [Some(section), None]
+        }
+        Band::BandPass | Band::BandStop => {
+            let bandwidth = w2 - w1;
+            let center_sq = w1 * w2;
+
+            let (num, scaled) = if band == Band::BandPass {
+                // One zero at DC, one at infinity
+                ([0.0, 1.0, 0.0], pole * (bandwidth / 2.0))
+            } else {
+                // Zeros at the center frequency
+                ([1.0, 0.0, center_sq], (bandwidth / 2.0) / pole)
+            };
+
+            if real {
+                // The two poles are the roots of `s^2 - 2 * scaled * s + center_sq`
+                let section = AnalogSection {
+                    num,
+                    den: [1.0, -2.0 * scaled.re, center_sq],
+                    first_order: false,
+                };
+
+                [Some(section), None]
+            } else {
+                let root = complex_sqrt(scaled * scaled - center_sq);
+
+                [
+                    Some(conjugate_pair(num, scaled + root)),
+                    Some(conjugate_pair(num, scaled - root)),
+                ]
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> Filter for Sos<T, N>
+where
+    T: FilterType,
+{
+    fn update(&mut self, sample: f32) -> Option<f32> {
+        let sample = self.filter_kind.precondition(sample);
+
+        Some(
+            self.sections
+                .iter_mut()
+                .fold(sample, |sample, section| section.update(sample)),
+        )
+    }
+
+    fn clear(&mut self) {
+        for section in self.sections.iter_mut() {
+            section.state = [0.0, 0.0];
+        }
+        self.filter_kind.clear();
+    }
+}
+
+impl<T, const N: usize> IirFilter for Sos<T, N>
+where
+    T: FilterType,
+{
+    fn transfer_coeff_at(&self, w: f32) -> Complex<f32> {
+        let z_inv = Complex::from_polar(1.0, -w * TAU);
+
+        self.sections
+            .iter()
+            .map(|section| section.transfer_coeff_at(z_inv))
+            .fold(Complex::new(1.0, 0.0), |acc, h| acc * h)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BandPass, BandStop, DesignError, Filter, HighPass, IirFilter, LowPass, Sos};
+
+    #[allow(unused_imports)]
+    use crate::compat::*;
+
+    #[track_caller]
+    fn assert_float_equals(value: f32, expectation: f32, tolerance: f32) {
+        assert!(
+            (value - expectation).abs() < tolerance,
+            "assertion failed: `(left == right)`\n  left: `{:?}`,\n right: `{:?}`",
+            value,
+            expectation
+        );
+    }
+
+    #[track_caller]
+    fn test_filter(mut filter: impl Filter, input: &[f32], expectation: &[f32], epsilon: f32) {
+        for (sample, expectation) in input.iter().zip(expectation.iter()) {
+            let output = filter.update(*sample).unwrap();
+            assert_float_equals(output, *expectation, epsilon);
+        }
+    }
+
+    fn gain(filter: &impl IirFilter, frequency: f32, fs: f32) -> f32 {
+        filter.transfer_coeff_at(frequency / fs).norm()
+    }
+
+    #[test]
+    fn all_pass() {
+        let input = [0., 1., 2., -3., 0.5];
+        test_filter(Sos::<LowPass, 2>::ALL_PASS, &input, &input, 0.0001);
+    }
+
+    // Same expectations as the direct form filters designed by `designfilt!`
+    #[test]
+    fn butterworth_lowpass_impulse_response_order2() {
+        let input = [0., 1., 0., 0., 0., 0., 0., 0.];
+        let expectation = [0.0000, 0.0675, 0.2120, 0.2819, 0.2347, 0.1519, 0.0767];
+
+        let filter = Sos::<LowPass, 1>::butterworth(2, 10.0, &[1.0]).unwrap();
+        test_filter(filter, &input, &expectation, 0.0001);
+    }
+
+    #[test]
+    fn butterworth_highpass_impulse_response_order2() {
+        let input = [0., 1., 0., 0., 0., 0., 0., 0.];
+        let expectation = [0.0000, 0.6389, -0.5476, -0.2507, -0.0605, 0.0343, 0.0642];
+
+        let filter = Sos::<HighPass, 1>::butterworth(2, 10.0, &[1.0]).unwrap();
+        test_filter(filter, &input, &expectation, 0.0001);
+    }
+
+    #[test]
+    fn butterworth_lowpass_step_response_order1() {
+        let input = [0., 1., 1., 1., 1., 1., 1.];
+        let expectation = [0.0000, 0.2452, 0.6154, 0.8041, 0.9002, 0.9491, 0.9741];
+
+        let filter = Sos::<LowPass, 1>::butterworth(1, 10.0, &[1.0]).unwrap();
+        test_filter(filter, &input, &expectation, 0.0001);
+    }
+
+    #[test]
+    fn butterworth_high_order_low_cutoff() {
+        // A direct form filter with these parameters is not usable in single precision.
+        let filter = Sos::<HighPass, 3>::butterworth(5, 1000.0, &[0.5]).unwrap();
+
+        assert_float_equals(gain(&filter, 0.5, 1000.0), 0.5_f32.sqrt(), 0.01);
+        assert_float_equals(gain(&filter, 10.0, 1000.0), 1.0, 0.01);
+        assert!(gain(&filter, 0.1, 1000.0) < 0.001);
+
+        // The impulse response decays
+        let mut filter = filter;
+        let tail = (0..20_000)
+            .map(|n| filter.update(if n == 0 { 1.0 } else { 0.0 }).unwrap())
+            .skip(19_000)
+            .fold(0.0_f32, |max, sample| max.max(sample.abs()));
+        assert!(tail < 1e-5, "{tail}");
+    }
+
+    #[test]
+    fn chebyshev_lowpass() {
+        let filter = Sos::<LowPass, 2>::chebyshev(4, 1.0, 1000.0, &[100.0]).unwrap();
+        let ripple = 10.0_f32.powf(-1.0 / 20.0);
+
+        // Even order Chebyshev filters start at the bottom of the ripple
+        assert_float_equals(gain(&filter, 0.0, 1000.0), ripple, 0.001);
+        assert_float_equals(gain(&filter, 100.0, 1000.0), ripple, 0.001);
+        for f in 1..100 {
+            let gain = gain(&filter, f as f32, 1000.0);
+            assert!(ripple - 0.001 < gain && gain < 1.001, "{f} Hz: {gain}");
+        }
+        // 1 / (epsilon * T4(tan(0.2 pi) / tan(0.1 pi)))
+        assert_float_equals(gain(&filter, 200.0, 1000.0), 0.0122, 0.0005);
+    }
+
+    #[test]
+    fn chebyshev_highpass_odd_order() {
+        let filter = Sos::<HighPass, 2>::chebyshev(3, 0.5, 250.0, &[40.0]).unwrap();
+        let ripple = 10.0_f32.powf(-0.5 / 20.0);
+
+        assert_float_equals(gain(&filter, 125.0, 250.0), 1.0, 0.001);
+        assert_float_equals(gain(&filter, 40.0, 250.0), ripple, 0.001);
+        assert!(gain(&filter, 10.0, 250.0) < 0.03);
+    }
+
+    #[test]
+    fn butterworth_bandpass() {
+        let filter = Sos::<BandPass, 2>::butterworth(4, 100.0, &[5.0, 15.0]).unwrap();
+
+        assert_float_equals(gain(&filter, 0.0, 100.0), 0.0, 0.001);
+        assert_float_equals(gain(&filter, 5.0, 100.0), 0.5_f32.sqrt(), 0.001);
+        assert_float_equals(gain(&filter, 15.0, 100.0), 0.5_f32.sqrt(), 0.001);
+        assert_float_equals(gain(&filter, 50.0, 100.0), 0.0, 0.001);
+    }
+
+    #[test]
+    fn butterworth_bandstop_odd_prototype() {
+        let filter = Sos::<BandStop, 3>::butterworth(6, 1000.0, &[45.0, 55.0]).unwrap();
+
+        assert_float_equals(gain(&filter, 0.0, 1000.0), 1.0, 0.001);
+        assert_float_equals(gain(&filter, 45.0, 1000.0), 0.5_f32.sqrt(), 0.001);
+        assert_float_equals(gain(&filter, 55.0, 1000.0), 0.5_f32.sqrt(), 0.001);
+        assert!(gain(&filter, 50.0, 1000.0) < 0.001);
+        assert_float_equals(gain(&filter, 500.0, 1000.0), 1.0, 0.001);
+    }
+
+    #[test]
+    fn design_errors() {
+        assert_eq!(
+            Sos::<LowPass, 1>::butterworth(3, 1000.0, &[40.0]).err(),
+            Some(DesignError::OrderTooHigh)
+        );
+        assert_eq!(
+            Sos::<LowPass, 1>::butterworth(0, 1000.0, &[40.0]).err(),
+            Some(DesignError::InvalidOrder)
+        );
+        assert_eq!(
+            Sos::<BandPass, 2>::butterworth(3, 1000.0, &[40.0, 60.0]).err(),
+            Some(DesignError::InvalidOrder)
+        );
+        assert_eq!(
+            Sos::<BandPass, 2>::butterworth(4, 1000.0, &[40.0]).err(),
+            Some(DesignError::FrequencyCount)
+        );
+        assert_eq!(
+            Sos::<BandStop, 2>::butterworth(4, 1000.0, &[60.0, 40.0]).err(),
+            Some(DesignError::InvalidFrequency)
+        );
+        assert_eq!(
+            Sos::<HighPass, 2>::butterworth(2, 1000.0, &[500.0]).err(),
+            Some(DesignError::InvalidFrequency)
+        );
+        assert_eq!(
+            Sos::<HighPass, 2>::chebyshev(2, 0.0, 1000.0, &[50.0]).err(),
+            Some(DesignError::InvalidRipple)
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Fixed-point number formats, for chips without a floating point unit.
+//!
+//! `Q31` and `Q15` represent values in `[-1, 1)` with 31 and 15 fractional bits. Arithmetic
+//! saturates instead of wrapping around.
+
+use core::ops::{Add, Mul, Neg, Sub};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Q31(pub i32);
+
+impl Q31 {
+    pub const FRACTIONAL_BITS: u32 = 31;
+
+    pub const ZERO: Self = Self(0);
+    pub const MAX: Self = Self(i32::MAX);
+    pub const MIN: Self = Self(i32::MIN);
+
+    const SCALE: f32 = (1u64 << Self::FRACTIONAL_BITS) as f32;
+
+    #[inline]
+    pub fn from_f32(value: f32) -> Self {
+        // `as` saturates out of range values
+        Self((value * Self::SCALE) as i32)
+    }
+
+    #[inline]
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / Self::SCALE
+    }
+
+    #[inline]
+    pub const fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    #[inline]
+    pub const fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+
+    #[inline]
+    pub const fn saturating_mul(self, other: Self) -> Self {
+        let product = (self.0 as i64 * other.0 as i64) >> Self::FRACTIONAL_BITS;
+        Self::saturate(product)
+    }
+
+    /// Converts an intermediate result with 31 fractional bits.
+    #[inline]
+    pub const fn saturate(value: i64) -> Self {
+        if value > i32::MAX as i64 {
+            Self::MAX
+        } else if value < i32::MIN as i64 {
+            Self::MIN
+        } else {
+            Self(value as i32)
+        }
+    }
+}
+
+impl Add for Q31 {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: Self) -> Self {
+        self.saturating_add(rhs)
+    }
+}
+
+impl Sub for Q31 {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: Self) -> Self {
+        self.saturating_sub(rhs)
+    }
+}
+
+impl Mul for Q31 {
+    type Output = Self;
+
+    #[inline]
+    fn mul(self, rhs: Self) -> Self {
+        self.saturating_mul(rhs)
+    }
+}
+
+impl Neg for Q31 {
+    type Output = Self;
+
+    #[inline]
+    fn neg(self) -> Self {
+        Self(self.0.saturating_neg())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Q15(pub i16);
+
+impl Q15 {
+    pub const FRACTIONAL_BITS: u32 = 15;
+
+    pub const ZERO: Self = Self(0);
+    pub const MAX: Self = Self(i16::MAX);
+    pub const MIN: Self = Self(i16::MIN);
+
+    const SCALE: f32 = (1u32 << Self::FRACTIONAL_BITS) as f32;
+
+    #[inline]
+    pub fn from_f32(value: f32) -> Self {
+        // Round to nearest, `as` saturates out of range values
+        let scaled = value * Self::SCALE;
+        let rounded = if scaled < 0.0 {
+            scaled - 0.5
+        } else {
+            scaled + 0.5
+        };
+        Self(rounded as i16)
+    }
+
+    #[inline]
+    pub fn to_f32(self) -> f32 {
+        self.0 as f32 / Self::SCALE
+    }
+
+    /// Multiplies a `Q31` value, keeping 31 fractional bits.
+    #[inline]
+    pub const fn mul_q31(self, other: Q31) -> Q31 {
+        Q31::saturate((self.0 as i64 * other.0 as i64) >> Self::FRACTIONAL_BITS)
+    }
+}
+
+/// Returns the sine of `phase`, where the full range of `u32` is one full turn.
+///
+/// The error is below 1e-6.
+pub fn sin(phase: u32) -> Q31 {
+    // Map the phase to [-1/2, 1/2) turns, then fold it into [-1/4, 1/4] using sin(x) = sin(pi - x).
+    let mut x = phase as i32 as i64;
+    const QUARTER: i64 = 1 << 30;
+    const HALF: i64 = 1 << 31;
+    if x > QUARTER {
+        x = HALF - x;
+    } else if x < -QUARTER {
+        x = -HALF - x;
+    }
+
+    // x is now the angle in units of quarter turns, with 30 fractional bits. Evaluate a least
+    // squares fit of sin(x * pi / 2) on [-1, 1].
+    const C: [i64; 4] = [1686623980, -693521966, 85291577, -4652396];
+    let x2 = (x * x) >> 30;
+    let mut acc = C[3];
+    for c in C[..3].iter().rev() {
+        acc = c + ((acc * x2) >> 30);
+    }
+
+    Q31::saturate((acc * x) >> 29)
+}
+
+/// Returns the cosine of `phase`, where the full range of `u32` is one full turn.
+pub fn cos(phase: u32) -> Q31 {
+    sin(phase.wrapping_add(1 << 30))
+}
+
+/// Converts an angle in radians to the phase representation used by [`sin`] and [`cos`].
+pub fn phase_from_radians(radians: f32) -> i64 {
+    (radians as f64 * ((1u64 << 32) as f64 / core::f64::consts::TAU)) as i64
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cos, phase_from_radians, sin, Q15, Q31};
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Q31::from_f32(0.5), Q31(1 << 30));
+        assert_eq!(Q31::from_f32(2.0), Q31::MAX);
+        assert_eq!(Q31::from_f32(-1.0), Q31::MIN);
+        assert_eq!(Q31(-(1 << 29)).to_f32(), -0.25);
+
+        assert_eq!(Q15::from_f32(0.25), Q15(1 << 13));
+        assert_eq!(Q15::from_f32(1.0), Q15::MAX);
+    }
+
+    #[test]
+    fn arithmetic_saturates() {
+        assert_eq!(Q31::MAX + Q31(1), Q31::MAX);
+        assert_eq!(Q31::MIN - Q31(1), Q31::MIN);
+        assert_eq!(Q31::MIN * Q31::MIN, Q31::MAX);
+        assert_eq!(-Q31::MIN, Q31::MAX);
+
+        assert_eq!(
+            Q31::from_f32(0.5) * Q31::from_f32(-0.5),
+            Q31::from_f32(-0.25)
+        );
+        assert_eq!(
+            Q15::from_f32(0.5).mul_q31(Q31::from_f32(0.5)),
+            Q31::from_f32(0.25)
+        );
+    }
+
+    #[test]
+    fn trigonometry() {
+        for i in 0..10_000 {
+            let radians = i as f32 * core::f32::consts::TAU / 10_000.0 - 1.0;
+            let phase = phase_from_radians(radians) as u32;
+
+            let error = (sin(phase).to_f32() - radians.sin()).abs();
+            assert!(error < 1e-6, "sin({radians}): {error}");
+
+            let error = (cos(phase).to_f32() - radians.cos()).abs();
+            assert!(error < 1e-6, "cos({radians}): {error}");
+        }
+    }
+}
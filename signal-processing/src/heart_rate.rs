@@ -12,6 +12,48 @@ pub use qrs_detector::{
 #[allow(unused_imports)]
 use crate::compat::*;
 
+/// A detected heart beat.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Beat {
+    /// Position of the R peak, in samples passed to [`HeartRateCalculator::update`] since the
+    /// last [`HeartRateCalculator::clear`].
+    pub sample_index: u32,
+    /// RR interval preceding this beat, in milliseconds.
+    pub rr_interval: Option<f32>,
+    /// Detection confidence between 0 and 1, based on how far the QRS complex exceeded the
+    /// detection threshold.
+    pub confidence: f32,
+}
+
+impl Beat {
+    pub const ENCODED_SIZE: usize = 7;
+
+    /// Encodes the beat as a little-endian sample index, RR interval in milliseconds (0 if
+    /// unknown) and a confidence byte.
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_SIZE] {
+        let rr_interval = self.rr_interval.map_or(0, |rr| rr as u16);
+        let confidence = (self.confidence.clamp(0.0, 1.0) * 255.0) as u8;
+
+        let mut bytes = [0; Self::ENCODED_SIZE];
+        bytes[0..4].copy_from_slice(&self.sample_index.to_le_bytes());
+        bytes[4..6].copy_from_slice(&rr_interval.to_le_bytes());
+        bytes[6] = confidence;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; Self::ENCODED_SIZE]) -> Self {
+        let sample_index = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let rr_interval = u16::from_le_bytes([bytes[4], bytes[5]]);
+
+        Self {
+            sample_index,
+            rr_interval: (rr_interval != 0).then_some(rr_interval as f32),
+            confidence: bytes[6] as f32 / 255.0,
+        }
+    }
+}
+
 pub struct HeartRateCalculator<FMW, FB> {
     fs: SamplingFrequency,
     max_age: usize,
@@ -22,8 +64,12 @@ pub struct HeartRateCalculator<FMW, FB> {
     differentiator: SlidingWindow<2>,
     prev_detection: Option<NonZeroU32>,
     current_hr: Option<NonZeroU8>,
-    is_beat: bool,
+    beat: Option<Beat>,
     age: usize,
+
+    sample_count: u32,
+    detector_start: Option<u32>,
+    peak: f32,
 }
 
 impl HeartRateCalculator<(), ()> {
@@ -66,44 +112,76 @@ where
             differentiator: SlidingWindow::new(),
             prev_detection: None,
             current_hr: None,
-            is_beat: false,
+            beat: None,
             age: max_init,
+
+            sample_count: 0,
+            detector_start: None,
+            peak: 0.0,
         }
     }
 
     pub fn clear(&mut self) {
+        self.reset_detection();
+        self.sample_count = 0;
+    }
+
+    fn reset_detection(&mut self) {
         self.median.clear();
         self.qrs_detector.clear();
         self.differentiator.clear();
         self.prev_detection = None;
         self.current_hr = None;
-        self.is_beat = false;
+        self.beat = None;
         self.age = self.max_init;
+        self.detector_start = None;
+        self.peak = 0.0;
     }
 
     pub fn update(&mut self, sample: f32) -> Option<f32> {
+        let sample_idx = self.sample_count;
+        self.sample_count += 1;
+        self.beat = None;
+
         let Some(old_sample) = self.differentiator.push(sample) else {
             return None;
         };
 
         let complex_lead = (sample - old_sample).abs();
 
+        let detector_start = *self.detector_start.get_or_insert(sample_idx);
+        self.peak = self.peak.max(complex_lead);
+
         if let Some(idx) = self.qrs_detector.update(complex_lead) {
+            let mut rr_interval = None;
             if let Some(prev_idx) = self.prev_detection {
-                let raw = self.fs.s_to_samples(60.0) as f32 / (idx - prev_idx.get()) as f32;
+                let samples_per_minute = self.fs.s_to_samples(60.0) as f32;
+                let rr_samples = (idx - prev_idx.get()) as f32;
+
+                let raw = samples_per_minute / rr_samples;
                 let hr = self.median.update(raw).unwrap_or(raw);
 
                 self.current_hr = NonZeroU8::new(hr as u8);
+                rr_interval = Some(rr_samples * 60_000.0 / samples_per_minute);
             }
 
-            self.is_beat = true;
+            let confidence = match self.qrs_detector.thresholds().total() {
+                Some(threshold) if self.peak > 0.0 => 1.0 - threshold / self.peak,
+                _ => 0.0,
+            };
+
+            self.beat = Some(Beat {
+                sample_index: detector_start + idx,
+                rr_interval,
+                confidence: confidence.clamp(0.0, 1.0),
+            });
             self.prev_detection = NonZeroU32::new(idx);
             self.age = self.max_age;
+            self.peak = 0.0;
         } else if self.age > 0 {
-            self.is_beat = false;
             self.age -= 1;
         } else {
-            self.clear();
+            self.reset_detection();
         }
 
         Some(complex_lead)
@@ -121,6 +199,47 @@ where
 
     #[inline]
     pub fn is_beat(&self) -> bool {
-        self.is_beat
+        self.beat.is_some()
+    }
+
+    /// Returns the beat detected in the last [`update`](Self::update) call.
+    #[inline]
+    pub fn beat(&self) -> Option<Beat> {
+        self.beat
+    }
+
+    /// Returns the RR interval, in milliseconds, that ended with the beat detected in the last
+    /// [`update`](Self::update) call.
+    #[inline]
+    pub fn rr_interval(&self) -> Option<f32> {
+        self.beat.and_then(|beat| beat.rr_interval)
+    }
+
+    /// Returns the number of samples processed since the last [`clear`](Self::clear).
+    #[inline]
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn beat_encoding_roundtrip() {
+        let beat = Beat {
+            sample_index: 123_456,
+            rr_interval: Some(812.0),
+            confidence: 1.0,
+        };
+        assert_eq!(Beat::from_bytes(beat.to_bytes()), beat);
+
+        let first_beat = Beat {
+            sample_index: 7,
+            rr_interval: None,
+            confidence: 0.0,
+        };
+        assert_eq!(Beat::from_bytes(first_beat.to_bytes()), first_beat);
     }
 }
@@ -0,0 +1,294 @@
+//! Heart rate variability analysis
+//!
+//! Collects RR intervals, rejects ectopic beats and artefacts, and computes the usual time-domain
+//! HRV metrics over the accepted (NN) intervals. Everything is computed incrementally, so the
+//! memory use does not depend on the length of the measurement. The metrics are stored in the
+//! [measurement header](crate::measurement::MeasurementHeader).
+
+use crate::filter::{median::MedianFilter, Filter};
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+/// Time-domain HRV metrics. Durations are in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HrvMetrics {
+    /// Mean of the accepted NN intervals.
+    pub mean_nn: f32,
+    /// Standard deviation of the accepted NN intervals.
+    pub sdnn: f32,
+    /// Root mean square of successive NN interval differences.
+    pub rmssd: f32,
+    /// Percentage of successive NN interval differences larger than 50ms.
+    pub pnn50: f32,
+    /// Number of accepted NN intervals.
+    pub nn_count: u32,
+    /// Number of intervals rejected as ectopic or artefact.
+    pub rejected_count: u32,
+}
+
+#[derive(Clone)]
+pub struct HrvCalculator {
+    reference: MedianFilter<5>,
+    reference_value: Option<f32>,
+    previous_nn: Option<f32>,
+    consecutive_rejections: u32,
+
+    nn_count: u32,
+    rejected_count: u32,
+    mean: f32,
+    m2: f32,
+
+    successive_count: u32,
+    successive_sq_sum: f32,
+    nn50_count: u32,
+}
+
+impl Default for HrvCalculator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HrvCalculator {
+    /// Shortest RR interval considered physiological (200 bpm).
+    pub const MIN_RR_MS: f32 = 300.0;
+    /// Longest RR interval considered physiological (30 bpm).
+    pub const MAX_RR_MS: f32 = 2000.0;
+    /// Maximum relative deviation from the recent intervals before a beat is considered ectopic.
+    pub const MAX_DEVIATION: f32 = 0.2;
+    /// Number of consecutive physiological intervals rejected against the reference after which
+    /// the reference is assumed to be wrong (bad first beat, lasting rate change) and re-seeded.
+    pub const RESEED_AFTER: u32 = 5;
+
+    pub const fn new() -> Self {
+        Self {
+            reference: MedianFilter::new(),
+            reference_value: None,
+            previous_nn: None,
+            consecutive_rejections: 0,
+
+            nn_count: 0,
+            rejected_count: 0,
+            mean: 0.0,
+            m2: 0.0,
+
+            successive_count: 0,
+            successive_sq_sum: 0.0,
+            nn50_count: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Processes an RR interval, in milliseconds.
+    ///
+    /// Returns whether the interval was accepted as a normal-to-normal interval.
+    pub fn update(&mut self, rr_ms: f32) -> bool {
+        if !Self::is_physiological(rr_ms) {
+            return self.reject();
+        }
+
+        if !self.matches_reference(rr_ms) {
+            self.consecutive_rejections += 1;
+            if self.consecutive_rejections < Self::RESEED_AFTER {
+                return self.reject();
+            }
+
+            // The rhythm has settled somewhere else, start over from this interval.
+            self.reference.clear();
+            self.reference_value = None;
+        }
+
+        self.consecutive_rejections = 0;
+        self.nn_count += 1;
+
+        // Welford's online algorithm
+        let delta = rr_ms - self.mean;
+        self.mean += delta / self.nn_count as f32;
+        self.m2 += delta * (rr_ms - self.mean);
+
+        if let Some(previous) = self.previous_nn {
+            let diff = rr_ms - previous;
+
+            self.successive_count += 1;
+            self.successive_sq_sum += diff * diff;
+            if diff.abs() > 50.0 {
+                self.nn50_count += 1;
+            }
+        }
+        self.previous_nn = Some(rr_ms);
+
+        self.reference_value = Some(self.reference.update(rr_ms).unwrap_or(rr_ms));
+
+        true
+    }
+
+    fn reject(&mut self) -> bool {
+        self.rejected_count += 1;
+        // An ectopic beat shortens one interval and lengthens the next, so successive
+        // differences must not span a rejected interval.
+        self.previous_nn = None;
+        false
+    }
+
+    fn is_physiological(rr_ms: f32) -> bool {
+        (Self::MIN_RR_MS..=Self::MAX_RR_MS).contains(&rr_ms)
+    }
+
+    fn matches_reference(&self, rr_ms: f32) -> bool {
+        match self.reference_value {
+            Some(reference) => (rr_ms - reference).abs() <= reference * Self::MAX_DEVIATION,
+            None => true,
+        }
+    }
+
+    pub fn nn_count(&self) -> u32 {
+        self.nn_count
+    }
+
+    pub fn rejected_count(&self) -> u32 {
+        self.rejected_count
+    }
+
+    /// Returns the HRV metrics, or `None` if there are not enough intervals to compute them.
+    pub fn metrics(&self) -> Option<HrvMetrics> {
+        if self.nn_count < 2 || self.successive_count == 0 {
+            return None;
+        }
+
+        Some(HrvMetrics {
+            mean_nn: self.mean,
+            sdnn: (self.m2 / (self.nn_count - 1) as f32).sqrt(),
+            rmssd: (self.successive_sq_sum / self.successive_count as f32).sqrt(),
+            pnn50: self.nn50_count as f32 * 100.0 / self.successive_count as f32,
+            nn_count: self.nn_count,
+            rejected_count: self.rejected_count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[track_caller]
+    fn assert_float_equals(value: f32, expectation: f32, tolerance: f32) {
+        assert!(
+            (value - expectation).abs() < tolerance,
+            "assertion failed: `(left == right)`\n  left: `{:?}`,\n right: `{:?}`",
+            value,
+            expectation
+        );
+    }
+
+    fn calculate(intervals: &[f32]) -> HrvCalculator {
+        let mut hrv = HrvCalculator::new();
+        for rr in intervals.iter().copied() {
+            hrv.update(rr);
+        }
+        hrv
+    }
+
+    #[test]
+    fn not_enough_intervals() {
+        assert_eq!(calculate(&[]).metrics(), None);
+        assert_eq!(calculate(&[800.0]).metrics(), None);
+    }
+
+    #[test]
+    fn constant_rhythm_has_no_variability() {
+        let metrics = calculate(&[800.0; 20]).metrics().unwrap();
+
+        assert_float_equals(metrics.mean_nn, 800.0, 0.001);
+        assert_float_equals(metrics.sdnn, 0.0, 0.001);
+        assert_float_equals(metrics.rmssd, 0.0, 0.001);
+        assert_float_equals(metrics.pnn50, 0.0, 0.001);
+        assert_eq!(metrics.nn_count, 20);
+        assert_eq!(metrics.rejected_count, 0);
+    }
+
+    #[test]
+    fn alternating_rhythm() {
+        let intervals = [800.0, 900.0, 800.0, 900.0, 800.0, 900.0];
+        let metrics = calculate(&intervals).metrics().unwrap();
+
+        assert_float_equals(metrics.mean_nn, 850.0, 0.001);
+        assert_float_equals(metrics.sdnn, 54.772, 0.01);
+        assert_float_equals(metrics.rmssd, 100.0, 0.001);
+        assert_float_equals(metrics.pnn50, 100.0, 0.001);
+    }
+
+    #[test]
+    fn small_differences_are_not_counted_in_pnn50() {
+        let intervals = [800.0, 830.0, 800.0, 900.0];
+        let metrics = calculate(&intervals).metrics().unwrap();
+
+        // Differences: 30, -30, 100
+        assert_float_equals(metrics.pnn50, 100.0 / 3.0, 0.001);
+        assert_float_equals(metrics.rmssd, (11800.0f32 / 3.0).sqrt(), 0.001);
+    }
+
+    #[test]
+    fn ectopic_beat_is_rejected() {
+        // A premature beat followed by a compensatory pause
+        let intervals = [800.0, 800.0, 800.0, 800.0, 500.0, 1100.0, 800.0, 800.0];
+        let metrics = calculate(&intervals).metrics().unwrap();
+
+        assert_eq!(metrics.nn_count, 6);
+        assert_eq!(metrics.rejected_count, 2);
+        assert_float_equals(metrics.mean_nn, 800.0, 0.001);
+        assert_float_equals(metrics.rmssd, 0.0, 0.001);
+    }
+
+    #[test]
+    fn non_physiological_intervals_are_rejected() {
+        let mut hrv = HrvCalculator::new();
+
+        assert!(!hrv.update(100.0));
+        assert!(!hrv.update(3000.0));
+        assert!(hrv.update(1000.0));
+
+        assert_eq!(hrv.nn_count(), 1);
+        assert_eq!(hrv.rejected_count(), 2);
+    }
+
+    #[test]
+    fn gradual_changes_are_tracked() {
+        let mut hrv = HrvCalculator::new();
+
+        // 2% change per beat - well within the acceptance range for every step
+        let mut rr = 1000.0;
+        for _ in 0..30 {
+            assert!(hrv.update(rr));
+            rr *= 0.98;
+        }
+
+        assert_eq!(hrv.rejected_count(), 0);
+    }
+
+    #[test]
+    fn reference_is_reseeded_after_lasting_change() {
+        let mut hrv = HrvCalculator::new();
+
+        // A bad first interval must not lock out the real rhythm
+        assert!(hrv.update(1500.0));
+        for _ in 0..HrvCalculator::RESEED_AFTER - 1 {
+            assert!(!hrv.update(700.0));
+        }
+        assert!(hrv.update(700.0));
+        assert!(hrv.update(700.0));
+
+        // A sudden, lasting heart rate change is followed as well
+        for _ in 0..HrvCalculator::RESEED_AFTER - 1 {
+            assert!(!hrv.update(1000.0));
+        }
+        assert!(hrv.update(1000.0));
+        assert!(hrv.update(1000.0));
+
+        assert_eq!(hrv.rejected_count(), 2 * (HrvCalculator::RESEED_AFTER - 1));
+    }
+}
@@ -8,11 +8,20 @@ extern crate logger;
 
 pub mod battery;
 pub mod buffer;
+#[cfg(feature = "alloc")]
+pub mod catalog;
 pub mod compressing_buffer;
 pub mod filter;
+pub mod fixed;
 pub mod heart_rate;
+pub mod hrv;
 pub mod lerp;
+pub mod mains;
+pub mod measurement;
 pub mod moving;
+pub mod quality;
+pub mod retention;
+pub mod rhythm;
 pub mod sliding;
 
 pub use macros::designfilt;
@@ -0,0 +1,182 @@
+//! Mains frequency detection
+//!
+//! Compares the energy of the signal at 50Hz and 60Hz using the Goertzel algorithm. The signal is
+//! differentiated first, so that the electrode offset and the baseline wander don't leak into the
+//! measured frequencies.
+
+use core::f64::consts::PI;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MainsFrequency {
+    Hz50,
+    Hz60,
+}
+
+impl MainsFrequency {
+    pub const fn hz(self) -> f32 {
+        match self {
+            Self::Hz50 => 50.0,
+            Self::Hz60 => 60.0,
+        }
+    }
+}
+
+/// Calculates the power of a single frequency component.
+#[derive(Clone)]
+struct Goertzel {
+    coeff: f32,
+    /// Corrects the gain of the differentiator at the frequency.
+    gain: f32,
+    s1: f32,
+    s2: f32,
+}
+
+impl Goertzel {
+    fn new(fs: f32, frequency: f32) -> Self {
+        let w = 2.0 * PI * frequency as f64 / fs as f64;
+        let differentiator_gain = 4.0 * libm::pow(libm::sin(w / 2.0), 2.0);
+
+        Self {
+            coeff: (2.0 * libm::cos(w)) as f32,
+            gain: (1.0 / differentiator_gain) as f32,
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    fn update(&mut self, sample: f32) {
+        let s0 = sample + self.coeff * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s0;
+    }
+
+    /// Returns the power of the frequency component, and restarts the calculation.
+    fn finish(&mut self) -> f32 {
+        let power = self.s1 * self.s1 + self.s2 * self.s2 - self.coeff * self.s1 * self.s2;
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+        power * self.gain
+    }
+}
+
+#[derive(Clone)]
+pub struct MainsFrequencyDetector {
+    hz50: Goertzel,
+    hz60: Goertzel,
+    previous: Option<f32>,
+    /// Length of a block in samples. One second contains a whole number of periods of both
+    /// frequencies, so they don't leak into each other.
+    block_size: usize,
+    block_samples: usize,
+    blocks: usize,
+    power_50: f32,
+    power_60: f32,
+}
+
+impl MainsFrequencyDetector {
+    /// The power at the detected frequency must be this many times the power at the other one.
+    pub const MIN_POWER_RATIO: f32 = 4.0;
+    /// Number of blocks to collect before the first decision.
+    pub const MIN_BLOCKS: usize = 2;
+
+    pub fn new(fs: f32) -> Self {
+        Self {
+            hz50: Goertzel::new(fs, 50.0),
+            hz60: Goertzel::new(fs, 60.0),
+            previous: None,
+            block_size: fs as usize,
+            block_samples: 0,
+            blocks: 0,
+            power_50: 0.0,
+            power_60: 0.0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.hz50.finish();
+        self.hz60.finish();
+        self.previous = None;
+        self.block_samples = 0;
+        self.blocks = 0;
+        self.power_50 = 0.0;
+        self.power_60 = 0.0;
+    }
+
+    /// Returns the number of samples processed since the detector was created or cleared.
+    pub fn samples(&self) -> usize {
+        self.blocks * self.block_size + self.block_samples
+    }
+
+    /// Processes a sample. Returns the mains frequency at the end of each block, if one of the
+    /// candidates is clearly stronger than the other.
+    pub fn update(&mut self, sample: f32) -> Option<MainsFrequency> {
+        let previous = self.previous.replace(sample)?;
+        let difference = sample - previous;
+
+        self.hz50.update(difference);
+        self.hz60.update(difference);
+
+        self.block_samples += 1;
+        if self.block_samples < self.block_size {
+            return None;
+        }
+
+        self.block_samples = 0;
+        self.blocks += 1;
+        self.power_50 += self.hz50.finish();
+        self.power_60 += self.hz60.finish();
+
+        if self.blocks < Self::MIN_BLOCKS {
+            None
+        } else if self.power_50 > self.power_60 * Self::MIN_POWER_RATIO {
+            Some(MainsFrequency::Hz50)
+        } else if self.power_60 > self.power_50 * Self::MIN_POWER_RATIO {
+            Some(MainsFrequency::Hz60)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MainsFrequency, MainsFrequencyDetector};
+
+    /// Returns a signal with an electrode offset, baseline wander, regular sharp peaks and
+    /// `interference` at `frequency`.
+    fn signal(n: usize, frequency: f32, interference: f32) -> f32 {
+        let t = n as f32 / 1000.0;
+        let peak = if n % 830 < 10 { 0.002 } else { 0.0 };
+
+        0.2 + 0.01 * (0.3 * core::f32::consts::TAU * t).sin()
+            + interference * (frequency * core::f32::consts::TAU * t).sin()
+            + peak
+    }
+
+    fn detect(frequency: f32, interference: f32) -> Option<MainsFrequency> {
+        let mut detector = MainsFrequencyDetector::new(1000.0);
+        (0..5000).find_map(|n| detector.update(signal(n, frequency, interference)))
+    }
+
+    #[test]
+    fn detects_50hz() {
+        assert_eq!(detect(50.0, 0.0002), Some(MainsFrequency::Hz50));
+    }
+
+    #[test]
+    fn detects_60hz() {
+        assert_eq!(detect(60.0, 0.0002), Some(MainsFrequency::Hz60));
+    }
+
+    #[test]
+    fn detects_off_nominal_frequency() {
+        assert_eq!(detect(59.8, 0.0002), Some(MainsFrequency::Hz60));
+        assert_eq!(detect(50.2, 0.0002), Some(MainsFrequency::Hz50));
+    }
+
+    #[test]
+    fn no_interference_is_inconclusive() {
+        assert_eq!(detect(50.0, 0.0), None);
+    }
+}
@@ -0,0 +1,926 @@
+//! Measurement file format
+//!
+//! A measurement consists of a format version, a header describing the recording, the compressed
+//! samples and the beat annotations. The version is stored as a `u8` in files, and as a
+//! little-endian `u32` in uploads. The rest is the same:
+//!
+//! - Version 0: compressed samples only.
+//! - Version 1: the length of the compressed samples as `u32`, the compressed samples, then the
+//!   beat annotations until the end of the data.
+//! - Version 2: same as version 1. Written by firmware that compressed the samples using
+//!   [`RiceFormat`], before the header could say so.
+//! - Version 3: the [header](MeasurementHeader), then the same as version 1. The compression
+//!   format of the samples is stored in the header.
+//! - Version 4: the compressed samples, the header, the beat annotations, then the length of the
+//!   compressed samples as `u32`. Used for files that are written while recording, because the
+//!   header is only complete when the recording ends.
+//!
+//! Uploads use the same layouts. Measurements that are uploaded from memory or converted from
+//! versions 0 to 2 are sent as [`UPLOAD_FORMAT_VERSION`], streamed files as version 4. The upload
+//! version went from 2 to 3 when the header was introduced, so backends that don't know the
+//! header reject these uploads instead of storing data they can't read.
+//!
+//! The format version only describes the layout of the measurement, and is independent of the
+//! version of the sample compression format. Changing how samples are compressed doesn't need a
+//! new format version, only a new value in the header's sample format field.
+//!
+//! The header starts with its own version as `u8` and the length of its fields as `u16`. Each
+//! field is a tag byte, the length of the value as `u16`, then the value. Numbers are
+//! little-endian. Readers skip the fields they don't know, so new fields can be added without
+//! changing the format version.
+
+use core::str;
+
+use embedded_io::Write;
+
+use crate::{
+    compressing_buffer::{RiceFormat, VarintFormat},
+    heart_rate::Beat,
+    hrv::HrvMetrics,
+    quality::QualityReport,
+};
+
+/// The measurement format version written by this crate.
+pub const FORMAT_VERSION: u8 = 3;
+
+/// The format version of measurements uploaded from memory, sent as a little-endian `u32`.
+pub const UPLOAD_FORMAT_VERSION: u32 = FORMAT_VERSION as u32;
+
+/// The format version of measurements that are written while recording.
+pub const STREAMED_FORMAT_VERSION: u8 = 4;
+
+/// The header format version written by this crate.
+pub const HEADER_VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FormatError {
+    /// The measurement or header format version is not supported.
+    UnsupportedVersion(u32),
+    /// The data ended unexpectedly.
+    Truncated,
+    /// A known header field has an invalid value.
+    InvalidField(u8),
+}
+
+/// Header field tags.
+mod tag {
+    pub const SAMPLE_FORMAT: u8 = 1;
+    pub const SAMPLE_RATE: u8 = 2;
+    pub const ADC_GAIN: u8 = 3;
+    pub const REFERENCE_VOLTAGE: u8 = 4;
+    pub const FIRMWARE: u8 = 5;
+    pub const HARDWARE: u8 = 6;
+    pub const SERIAL_NUMBER: u8 = 7;
+    pub const START_TIME: u8 = 8;
+    pub const FILTER: u8 = 9;
+    pub const LEAD_OFF: u8 = 10;
+    pub const HEART_RATE: u8 = 11;
+    pub const SEGMENT: u8 = 12;
+    pub const SAMPLE_COUNT: u8 = 13;
+    pub const QUALITY: u8 = 14;
+    pub const HRV: u8 = 15;
+}
+
+const QUALITY_ENCODED_SIZE: usize = 12;
+
+fn quality_to_bytes(quality: QualityReport) -> [u8; QUALITY_ENCODED_SIZE] {
+    let mut bytes = [0; QUALITY_ENCODED_SIZE];
+    bytes[0..4].copy_from_slice(&quality.score.to_le_bytes());
+    bytes[4..8].copy_from_slice(&quality.seconds.to_le_bytes());
+    bytes[8..12].copy_from_slice(&quality.usable_seconds.to_le_bytes());
+    bytes
+}
+
+fn quality_from_bytes(bytes: &[u8]) -> Option<QualityReport> {
+    let bytes: [u8; QUALITY_ENCODED_SIZE] = bytes.try_into().ok()?;
+
+    Some(QualityReport {
+        score: f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        seconds: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        usable_seconds: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+    })
+}
+
+const HRV_ENCODED_SIZE: usize = 24;
+
+fn hrv_to_bytes(hrv: HrvMetrics) -> [u8; HRV_ENCODED_SIZE] {
+    let mut bytes = [0; HRV_ENCODED_SIZE];
+    bytes[0..4].copy_from_slice(&hrv.mean_nn.to_le_bytes());
+    bytes[4..8].copy_from_slice(&hrv.sdnn.to_le_bytes());
+    bytes[8..12].copy_from_slice(&hrv.rmssd.to_le_bytes());
+    bytes[12..16].copy_from_slice(&hrv.pnn50.to_le_bytes());
+    bytes[16..20].copy_from_slice(&hrv.nn_count.to_le_bytes());
+    bytes[20..24].copy_from_slice(&hrv.rejected_count.to_le_bytes());
+    bytes
+}
+
+fn hrv_from_bytes(bytes: &[u8]) -> Option<HrvMetrics> {
+    let bytes: [u8; HRV_ENCODED_SIZE] = bytes.try_into().ok()?;
+    let f32_at =
+        |i: usize| f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    let u32_at =
+        |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+    Some(HrvMetrics {
+        mean_nn: f32_at(0),
+        sdnn: f32_at(4),
+        rmssd: f32_at(8),
+        pnn50: f32_at(12),
+        nn_count: u32_at(16),
+        rejected_count: u32_at(20),
+    })
+}
+
+/// Settings of the filters the device used for display and analysis. The recorded samples are
+/// not filtered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FilterSettings {
+    /// Cutoff frequency of the baseline wander filter in Hz, or `None` if it was disabled.
+    pub high_pass_cutoff: Option<f32>,
+    /// Frequency of the power line interference filter in Hz.
+    pub mains_frequency: u8,
+    /// Number of mains harmonics cancelled by the power line interference filter.
+    pub mains_harmonics: u8,
+}
+
+impl FilterSettings {
+    const ENCODED_SIZE: usize = 6;
+
+    fn to_bytes(self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0; Self::ENCODED_SIZE];
+        bytes[0..4].copy_from_slice(&self.high_pass_cutoff.unwrap_or(0.0).to_le_bytes());
+        bytes[4] = self.mains_frequency;
+        bytes[5] = self.mains_harmonics;
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: [u8; Self::ENCODED_SIZE] = bytes.try_into().ok()?;
+        let cutoff = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+        Some(Self {
+            high_pass_cutoff: (cutoff > 0.0).then_some(cutoff),
+            mains_frequency: bytes[4],
+            mains_harmonics: bytes[5],
+        })
+    }
+}
+
+/// A period when a lead was disconnected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LeadOff {
+    /// Index of the first sample recorded with a disconnected lead.
+    pub start: u32,
+    /// Index of the first sample after the lead was reconnected.
+    pub end: u32,
+}
+
+impl LeadOff {
+    pub const ENCODED_SIZE: usize = 8;
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0; Self::ENCODED_SIZE];
+        bytes[0..4].copy_from_slice(&self.start.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.end.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; Self::ENCODED_SIZE]) -> Self {
+        Self {
+            start: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            end: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+}
+
+/// Lead-off intervals, either as values or as they are stored in a header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LeadOffIntervals<'a> {
+    Decoded(&'a [LeadOff]),
+    Encoded(&'a [u8]),
+}
+
+impl<'a> LeadOffIntervals<'a> {
+    pub const EMPTY: Self = Self::Decoded(&[]);
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Decoded(intervals) => intervals.len(),
+            Self::Encoded(bytes) => bytes.len() / LeadOff::ENCODED_SIZE,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = LeadOff> + 'a {
+        let (decoded, encoded): (&[LeadOff], &[u8]) = match *self {
+            Self::Decoded(intervals) => (intervals, &[]),
+            Self::Encoded(bytes) => (&[], bytes),
+        };
+
+        decoded.iter().copied().chain(
+            encoded
+                .chunks_exact(LeadOff::ENCODED_SIZE)
+                .map(|chunk| LeadOff::from_bytes(unwrap!(chunk.try_into().ok()))),
+        )
+    }
+}
+
+/// Heart rate statistics of a recording.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HeartRateSummary {
+    /// Number of detected beats.
+    pub beats: u32,
+    /// Lowest heart rate in beats per minute.
+    pub min: u8,
+    /// Mean heart rate in beats per minute.
+    pub mean: u8,
+    /// Highest heart rate in beats per minute.
+    pub max: u8,
+}
+
+impl HeartRateSummary {
+    const ENCODED_SIZE: usize = 7;
+
+    /// Calculates the summary from the RR intervals of the beats. Returns `None` if no beat has
+    /// a known RR interval.
+    pub fn from_beats(beats: &[Beat]) -> Option<Self> {
+        let mut count = 0;
+        let mut sum = 0.0;
+        let mut shortest = f32::MAX;
+        let mut longest = 0.0_f32;
+
+        for rr_interval in beats.iter().filter_map(|beat| beat.rr_interval) {
+            count += 1;
+            sum += rr_interval;
+            shortest = shortest.min(rr_interval);
+            longest = longest.max(rr_interval);
+        }
+
+        if count == 0 {
+            return None;
+        }
+
+        let bpm = |rr_interval: f32| (60_000.0 / rr_interval).clamp(0.0, 255.0) as u8;
+
+        Some(Self {
+            beats: beats.len() as u32,
+            min: bpm(longest),
+            mean: bpm(sum / count as f32),
+            max: bpm(shortest),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0; Self::ENCODED_SIZE];
+        bytes[0..4].copy_from_slice(&self.beats.to_le_bytes());
+        bytes[4] = self.min;
+        bytes[5] = self.mean;
+        bytes[6] = self.max;
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: [u8; Self::ENCODED_SIZE] = bytes.try_into().ok()?;
+
+        Some(Self {
+            beats: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            min: bytes[4],
+            mean: bytes[5],
+            max: bytes[6],
+        })
+    }
+}
+
+/// Identifies a part of a long recording that is stored in multiple measurements.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Segment {
+    /// Random number shared by the segments of a recording.
+    pub session: u32,
+    /// Index of the segment in the recording, starting from 0.
+    pub sequence: u16,
+    /// Samples lost between the previous segment and this one, because the recording buffers
+    /// overran.
+    pub lost_samples: u32,
+}
+
+impl Segment {
+    const ENCODED_SIZE: usize = 10;
+
+    fn to_bytes(self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0; Self::ENCODED_SIZE];
+        bytes[0..4].copy_from_slice(&self.session.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[6..10].copy_from_slice(&self.lost_samples.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: [u8; Self::ENCODED_SIZE] = bytes.try_into().ok()?;
+
+        Some(Self {
+            session: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            sequence: u16::from_le_bytes([bytes[4], bytes[5]]),
+            lost_samples: u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]),
+        })
+    }
+}
+
+/// Describes a recording. Fields that are `None` are not stored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeasurementHeader<'a> {
+    /// Version of the sample compression format, see
+    /// [`EkgDecoder`](crate::compressing_buffer::EkgDecoder).
+    pub sample_format: u8,
+    /// Samples per second.
+    pub sample_rate: Option<u16>,
+    /// Gain of the ADC's amplifier.
+    pub adc_gain: Option<u8>,
+    /// Reference voltage of the ADC, in volts. The full scale of the ADC is
+    /// `±reference_voltage / adc_gain`.
+    pub reference_voltage: Option<f32>,
+    /// Firmware version, including the commit hash.
+    pub firmware: Option<&'a str>,
+    /// Hardware version.
+    pub hardware: Option<&'a str>,
+    pub serial_number: Option<[u8; 6]>,
+    /// Start of the recording, in seconds since the Unix epoch.
+    pub start_time: Option<u64>,
+    pub filter: Option<FilterSettings>,
+    /// Periods with a disconnected lead, indexed from the first sample.
+    pub lead_off: LeadOffIntervals<'a>,
+    pub heart_rate: Option<HeartRateSummary>,
+    pub segment: Option<Segment>,
+    /// Number of recorded samples.
+    pub sample_count: Option<u32>,
+    pub quality: Option<QualityReport>,
+    /// Heart rate variability of the recording.
+    pub hrv: Option<HrvMetrics>,
+}
+
+impl MeasurementHeader<'_> {
+    /// The header of measurements stored before the header was introduced. These were all recorded
+    /// with the same ADC settings.
+    pub const LEGACY: Self = Self {
+        sample_format: VarintFormat::VERSION,
+        sample_rate: Some(1000),
+        adc_gain: Some(1),
+        reference_voltage: Some(2.42),
+        firmware: None,
+        hardware: None,
+        serial_number: None,
+        start_time: None,
+        filter: None,
+        lead_off: LeadOffIntervals::EMPTY,
+        heart_rate: None,
+        segment: None,
+        sample_count: None,
+        quality: None,
+        hrv: None,
+    };
+
+    /// Returns the header of a measurement stored in format `version`, if that format has no
+    /// header.
+    pub fn legacy(version: u32) -> Option<Self> {
+        match version {
+            0 | 1 => Some(Self::LEGACY),
+            2 => Some(Self {
+                sample_format: RiceFormat::VERSION,
+                ..Self::LEGACY
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the length of the recording in seconds, if known.
+    pub fn duration_secs(&self) -> Option<u32> {
+        let sample_rate = self.sample_rate.filter(|rate| *rate > 0)?;
+        Some(self.sample_count? / sample_rate as u32)
+    }
+
+    /// Returns the voltage of an ADC code, if the ADC settings are known.
+    pub fn volts_per_lsb(&self) -> Option<f32> {
+        let reference = self.reference_voltage?;
+        let gain = self.adc_gain?;
+
+        // The ADC has a 24 bit resolution
+        Some(reference / gain as f32 / (1 << 23) as f32)
+    }
+
+    fn fields_len(&self) -> usize {
+        let field = |present: bool, len: usize| if present { 3 + len } else { 0 };
+
+        field(true, 1)
+            + field(self.sample_rate.is_some(), 2)
+            + field(self.adc_gain.is_some(), 1)
+            + field(self.reference_voltage.is_some(), 4)
+            + field(self.firmware.is_some(), self.firmware.map_or(0, str::len))
+            + field(self.hardware.is_some(), self.hardware.map_or(0, str::len))
+            + field(self.serial_number.is_some(), 6)
+            + field(self.start_time.is_some(), 8)
+            + field(self.filter.is_some(), FilterSettings::ENCODED_SIZE)
+            + field(
+                !self.lead_off.is_empty(),
+                self.lead_off.len() * LeadOff::ENCODED_SIZE,
+            )
+            + field(self.heart_rate.is_some(), HeartRateSummary::ENCODED_SIZE)
+            + field(self.segment.is_some(), Segment::ENCODED_SIZE)
+            + field(self.sample_count.is_some(), 4)
+            + field(self.quality.is_some(), QUALITY_ENCODED_SIZE)
+            + field(self.hrv.is_some(), HRV_ENCODED_SIZE)
+    }
+
+    /// Returns the size of the encoded header, in bytes.
+    pub fn encoded_len(&self) -> usize {
+        3 + self.fields_len()
+    }
+
+    pub fn encode<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        fn write_field<W: Write>(writer: &mut W, tag: u8, value: &[u8]) -> Result<(), W::Error> {
+            writer.write_all(&[tag])?;
+            writer.write_all(&(value.len() as u16).to_le_bytes())?;
+            writer.write_all(value)
+        }
+
+        writer.write_all(&[HEADER_VERSION])?;
+        writer.write_all(&(self.fields_len() as u16).to_le_bytes())?;
+
+        write_field(writer, tag::SAMPLE_FORMAT, &[self.sample_format])?;
+        if let Some(sample_rate) = self.sample_rate {
+            write_field(writer, tag::SAMPLE_RATE, &sample_rate.to_le_bytes())?;
+        }
+        if let Some(gain) = self.adc_gain {
+            write_field(writer, tag::ADC_GAIN, &[gain])?;
+        }
+        if let Some(reference) = self.reference_voltage {
+            write_field(writer, tag::REFERENCE_VOLTAGE, &reference.to_le_bytes())?;
+        }
+        if let Some(firmware) = self.firmware {
+            write_field(writer, tag::FIRMWARE, firmware.as_bytes())?;
+        }
+        if let Some(hardware) = self.hardware {
+            write_field(writer, tag::HARDWARE, hardware.as_bytes())?;
+        }
+        if let Some(serial) = self.serial_number {
+            write_field(writer, tag::SERIAL_NUMBER, &serial)?;
+        }
+        if let Some(start_time) = self.start_time {
+            write_field(writer, tag::START_TIME, &start_time.to_le_bytes())?;
+        }
+        if let Some(filter) = self.filter {
+            write_field(writer, tag::FILTER, &filter.to_bytes())?;
+        }
+        if !self.lead_off.is_empty() {
+            let len = self.lead_off.len() * LeadOff::ENCODED_SIZE;
+            writer.write_all(&[tag::LEAD_OFF])?;
+            writer.write_all(&(len as u16).to_le_bytes())?;
+            for interval in self.lead_off.iter() {
+                writer.write_all(&interval.to_bytes())?;
+            }
+        }
+        if let Some(heart_rate) = self.heart_rate {
+            write_field(writer, tag::HEART_RATE, &heart_rate.to_bytes())?;
+        }
+        if let Some(segment) = self.segment {
+            write_field(writer, tag::SEGMENT, &segment.to_bytes())?;
+        }
+        if let Some(sample_count) = self.sample_count {
+            write_field(writer, tag::SAMPLE_COUNT, &sample_count.to_le_bytes())?;
+        }
+        if let Some(quality) = self.quality {
+            write_field(writer, tag::QUALITY, &quality_to_bytes(quality))?;
+        }
+        if let Some(hrv) = self.hrv {
+            write_field(writer, tag::HRV, &hrv_to_bytes(hrv))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> MeasurementHeader<'a> {
+    /// Parses a header, and returns the data following it.
+    pub fn parse(bytes: &'a [u8]) -> Result<(Self, &'a [u8]), FormatError> {
+        let [version, len_lo, len_hi, rest @ ..] = bytes else {
+            return Err(FormatError::Truncated);
+        };
+        if *version != HEADER_VERSION {
+            return Err(FormatError::UnsupportedVersion(*version as u32));
+        }
+
+        let len = u16::from_le_bytes([*len_lo, *len_hi]) as usize;
+        if rest.len() < len {
+            return Err(FormatError::Truncated);
+        }
+        let (mut fields, rest) = rest.split_at(len);
+
+        let mut header = Self {
+            sample_format: 0,
+            sample_rate: None,
+            adc_gain: None,
+            reference_voltage: None,
+            firmware: None,
+            hardware: None,
+            serial_number: None,
+            start_time: None,
+            filter: None,
+            lead_off: LeadOffIntervals::EMPTY,
+            heart_rate: None,
+            segment: None,
+            sample_count: None,
+            quality: None,
+            hrv: None,
+        };
+        let mut sample_format = None;
+
+        while let [tag, len_lo, len_hi, field @ ..] = fields {
+            let len = u16::from_le_bytes([*len_lo, *len_hi]) as usize;
+            if field.len() < len {
+                return Err(FormatError::Truncated);
+            }
+            let (value, next) = field.split_at(len);
+            fields = next;
+
+            let invalid = FormatError::InvalidField(*tag);
+            match *tag {
+                tag::SAMPLE_FORMAT => {
+                    let [format] = value else { return Err(invalid) };
+                    sample_format = Some(*format);
+                }
+                tag::SAMPLE_RATE => {
+                    let value = value.try_into().map_err(|_| invalid)?;
+                    header.sample_rate = Some(u16::from_le_bytes(value));
+                }
+                tag::ADC_GAIN => {
+                    let [gain] = value else { return Err(invalid) };
+                    header.adc_gain = Some(*gain);
+                }
+                tag::REFERENCE_VOLTAGE => {
+                    let value = value.try_into().map_err(|_| invalid)?;
+                    header.reference_voltage = Some(f32::from_le_bytes(value));
+                }
+                tag::FIRMWARE => {
+                    header.firmware = Some(str::from_utf8(value).map_err(|_| invalid)?);
+                }
+                tag::HARDWARE => {
+                    header.hardware = Some(str::from_utf8(value).map_err(|_| invalid)?);
+                }
+                tag::SERIAL_NUMBER => {
+                    header.serial_number = Some(value.try_into().map_err(|_| invalid)?);
+                }
+                tag::START_TIME => {
+                    let value = value.try_into().map_err(|_| invalid)?;
+                    header.start_time = Some(u64::from_le_bytes(value));
+                }
+                tag::FILTER => {
+                    header.filter = Some(FilterSettings::from_bytes(value).ok_or(invalid)?);
+                }
+                tag::LEAD_OFF => {
+                    if value.len() % LeadOff::ENCODED_SIZE != 0 {
+                        return Err(invalid);
+                    }
+                    header.lead_off = LeadOffIntervals::Encoded(value);
+                }
+                tag::HEART_RATE => {
+                    header.heart_rate = Some(HeartRateSummary::from_bytes(value).ok_or(invalid)?);
+                }
+                tag::SEGMENT => {
+                    header.segment = Some(Segment::from_bytes(value).ok_or(invalid)?);
+                }
+                tag::SAMPLE_COUNT => {
+                    let value = value.try_into().map_err(|_| invalid)?;
+                    header.sample_count = Some(u32::from_le_bytes(value));
+                }
+                tag::QUALITY => {
+                    header.quality = Some(quality_from_bytes(value).ok_or(invalid)?);
+                }
+                tag::HRV => {
+                    header.hrv = Some(hrv_from_bytes(value).ok_or(invalid)?);
+                }
+                _ => {
+                    // Unknown field, added by a later firmware version
+                }
+            }
+        }
+
+        if !fields.is_empty() {
+            return Err(FormatError::Truncated);
+        }
+
+        let Some(sample_format) = sample_format else {
+            return Err(FormatError::InvalidField(tag::SAMPLE_FORMAT));
+        };
+        header.sample_format = sample_format;
+
+        Ok((header, rest))
+    }
+}
+
+/// A parsed measurement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Measurement<'a> {
+    pub header: MeasurementHeader<'a>,
+    /// Samples, compressed using the format in the header.
+    pub samples: &'a [u8],
+    /// Encoded beat annotations, see [`Measurement::beats`].
+    pub beat_bytes: &'a [u8],
+}
+
+impl<'a> Measurement<'a> {
+    /// Parses the data following the format version of a measurement. Measurements stored in
+    /// older formats get a [legacy header](MeasurementHeader::LEGACY).
+    pub fn parse(version: u32, bytes: &'a [u8]) -> Result<Self, FormatError> {
+        let (header, rest) = match version {
+            0 => {
+                return Ok(Self {
+                    header: MeasurementHeader::LEGACY,
+                    samples: bytes,
+                    beat_bytes: &[],
+                })
+            }
+            3 => MeasurementHeader::parse(bytes)?,
+            4 => return Self::parse_streamed(bytes),
+            _ => match MeasurementHeader::legacy(version) {
+                Some(header) => (header, bytes),
+                None => return Err(FormatError::UnsupportedVersion(version)),
+            },
+        };
+
+        let [l0, l1, l2, l3, rest @ ..] = rest else {
+            return Err(FormatError::Truncated);
+        };
+        let samples_len = u32::from_le_bytes([*l0, *l1, *l2, *l3]) as usize;
+        if rest.len() < samples_len {
+            return Err(FormatError::Truncated);
+        }
+        let (samples, beat_bytes) = rest.split_at(samples_len);
+
+        Ok(Self {
+            header,
+            samples,
+            beat_bytes,
+        })
+    }
+
+    fn parse_streamed(bytes: &'a [u8]) -> Result<Self, FormatError> {
+        let Some(rest_len) = bytes.len().checked_sub(4) else {
+            return Err(FormatError::Truncated);
+        };
+        let (rest, samples_len) = bytes.split_at(rest_len);
+        let samples_len = u32::from_le_bytes(unwrap!(samples_len.try_into().ok())) as usize;
+        if rest.len() < samples_len {
+            return Err(FormatError::Truncated);
+        }
+        let (samples, rest) = rest.split_at(samples_len);
+        let (header, beat_bytes) = MeasurementHeader::parse(rest)?;
+
+        Ok(Self {
+            header,
+            samples,
+            beat_bytes,
+        })
+    }
+
+    /// Returns the length of the data following the format version, as written by
+    /// [`Measurement::encode`].
+    pub fn encoded_len(&self) -> usize {
+        self.header.encoded_len() + 4 + self.samples.len() + self.beat_bytes.len()
+    }
+
+    /// Writes the data following the format version, in the current format.
+    pub fn encode<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        self.header.encode(writer)?;
+        writer.write_all(&(self.samples.len() as u32).to_le_bytes())?;
+        writer.write_all(self.samples)?;
+        writer.write_all(self.beat_bytes)
+    }
+
+    pub fn beats(&self) -> impl Iterator<Item = Beat> + 'a {
+        self.beat_bytes
+            .chunks_exact(Beat::ENCODED_SIZE)
+            .map(|chunk| Beat::from_bytes(unwrap!(chunk.try_into().ok())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(lead_off: &[LeadOff]) -> MeasurementHeader<'_> {
+        MeasurementHeader {
+            sample_format: 1,
+            firmware: Some("0.1.0-abcdef"),
+            hardware: Some("v6s3"),
+            serial_number: Some([1, 2, 3, 4, 5, 6]),
+            start_time: Some(1_700_000_000),
+            filter: Some(FilterSettings {
+                high_pass_cutoff: Some(0.75),
+                mains_frequency: 50,
+                mains_harmonics: 2,
+            }),
+            lead_off: LeadOffIntervals::Decoded(lead_off),
+            heart_rate: Some(HeartRateSummary {
+                beats: 90,
+                min: 58,
+                mean: 63,
+                max: 70,
+            }),
+            segment: Some(Segment {
+                session: 0xDEADBEEF,
+                sequence: 3,
+                lost_samples: 1200,
+            }),
+            sample_count: Some(65_000),
+            quality: Some(QualityReport {
+                score: 0.8,
+                seconds: 65,
+                usable_seconds: 60,
+            }),
+            hrv: Some(HrvMetrics {
+                mean_nn: 950.0,
+                sdnn: 42.5,
+                rmssd: 31.0,
+                pnn50: 12.5,
+                nn_count: 85,
+                rejected_count: 4,
+            }),
+            ..MeasurementHeader::LEGACY
+        }
+    }
+
+    fn encode(measurement: &Measurement) -> Vec<u8> {
+        let mut bytes = vec![0; measurement.encoded_len()];
+        measurement.encode(&mut &mut bytes[..]).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let lead_off = [
+            LeadOff { start: 0, end: 10 },
+            LeadOff {
+                start: 500,
+                end: 1500,
+            },
+        ];
+        let beat = Beat {
+            sample_index: 1234,
+            rr_interval: Some(950.0),
+            confidence: 1.0,
+        };
+
+        let measurement = Measurement {
+            header: header(&lead_off),
+            samples: &[1, 2, 3, 4, 5],
+            beat_bytes: &beat.to_bytes(),
+        };
+
+        let bytes = encode(&measurement);
+        let parsed = Measurement::parse(FORMAT_VERSION as u32, &bytes).unwrap();
+
+        assert_eq!(parsed.samples, measurement.samples);
+        assert_eq!(parsed.beats().collect::<Vec<_>>(), [beat]);
+        assert_eq!(parsed.header.lead_off.iter().collect::<Vec<_>>(), lead_off);
+        assert_eq!(parsed.header.duration_secs(), Some(65));
+        assert_eq!(
+            parsed.header,
+            MeasurementHeader {
+                lead_off: parsed.header.lead_off,
+                ..header(&[])
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_skipped() {
+        let measurement = Measurement {
+            header: header(&[]),
+            samples: &[1, 2, 3],
+            beat_bytes: &[],
+        };
+
+        // Insert an unknown field before the others
+        let mut bytes = encode(&measurement);
+        let fields_len = u16::from_le_bytes([bytes[1], bytes[2]]) + 5;
+        bytes[1..3].copy_from_slice(&fields_len.to_le_bytes());
+        bytes.splice(3..3, [200, 2, 0, 0xAA, 0xBB]);
+
+        let parsed = Measurement::parse(FORMAT_VERSION as u32, &bytes).unwrap();
+
+        assert_eq!(parsed, measurement);
+    }
+
+    #[test]
+    fn legacy_measurements_can_be_parsed() {
+        let v0 = Measurement::parse(0, &[1, 2, 3]).unwrap();
+        assert_eq!(v0.header, MeasurementHeader::LEGACY);
+        assert_eq!(v0.samples, [1, 2, 3]);
+        assert_eq!(v0.beats().count(), 0);
+
+        let beat = Beat {
+            sample_index: 1,
+            rr_interval: None,
+            confidence: 0.0,
+        };
+        let mut v1 = vec![2, 0, 0, 0, 5, 6];
+        v1.extend_from_slice(&beat.to_bytes());
+
+        let v1 = Measurement::parse(1, &v1).unwrap();
+        assert_eq!(v1.header.sample_format, 0);
+        assert_eq!(v1.header.sample_rate, Some(1000));
+        assert_eq!(v1.samples, [5, 6]);
+        assert_eq!(v1.beats().collect::<Vec<_>>(), [beat]);
+
+        let v2 = Measurement::parse(2, &[0, 0, 0, 0]).unwrap();
+        assert_eq!(v2.header.sample_format, 1);
+    }
+
+    #[test]
+    fn legacy_measurements_are_converted_by_prepending_the_header() {
+        let v1 = [2, 0, 0, 0, 5, 6];
+        let header = MeasurementHeader::legacy(1).unwrap();
+
+        let mut converted = vec![0; header.encoded_len()];
+        header.encode(&mut &mut converted[..]).unwrap();
+        converted.extend_from_slice(&v1);
+
+        assert_eq!(converted, encode(&Measurement::parse(1, &v1).unwrap()));
+        assert_eq!(MeasurementHeader::legacy(FORMAT_VERSION as u32), None);
+    }
+
+    #[test]
+    fn streamed_measurement_can_be_parsed() {
+        let beat = Beat {
+            sample_index: 2,
+            rr_interval: Some(800.0),
+            confidence: 1.0,
+        };
+        let header = header(&[]);
+
+        let mut bytes = vec![1, 2, 3];
+        let header_start = bytes.len();
+        bytes.resize(header_start + header.encoded_len(), 0);
+        header.encode(&mut &mut bytes[header_start..]).unwrap();
+        bytes.extend_from_slice(&beat.to_bytes());
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+
+        let parsed = Measurement::parse(STREAMED_FORMAT_VERSION as u32, &bytes).unwrap();
+
+        assert_eq!(parsed.header, header);
+        assert_eq!(parsed.samples, [1, 2, 3]);
+        assert_eq!(parsed.beats().collect::<Vec<_>>(), [beat]);
+
+        assert_eq!(
+            Measurement::parse(STREAMED_FORMAT_VERSION as u32, &bytes[..3]),
+            Err(FormatError::Truncated)
+        );
+    }
+
+    #[test]
+    fn truncated_measurement_is_rejected() {
+        let measurement = Measurement {
+            header: header(&[]),
+            samples: &[1, 2, 3],
+            beat_bytes: &[],
+        };
+        let bytes = encode(&measurement);
+
+        for len in 0..bytes.len() - 3 {
+            assert_eq!(
+                Measurement::parse(FORMAT_VERSION as u32, &bytes[..len]),
+                Err(FormatError::Truncated),
+                "{len}"
+            );
+        }
+    }
+
+    #[test]
+    fn heart_rate_summary() {
+        let beat = |rr_interval| Beat {
+            sample_index: 0,
+            rr_interval,
+            confidence: 1.0,
+        };
+
+        let summary =
+            HeartRateSummary::from_beats(&[beat(None), beat(Some(1000.0)), beat(Some(500.0))]);
+
+        assert_eq!(
+            summary,
+            Some(HeartRateSummary {
+                beats: 3,
+                min: 60,
+                mean: 80,
+                max: 120,
+            })
+        );
+        assert_eq!(HeartRateSummary::from_beats(&[beat(None)]), None);
+    }
+}
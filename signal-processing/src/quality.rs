@@ -0,0 +1,454 @@
+//! Signal quality estimation
+//!
+//! Scores every second of a recording between 0 (unusable) and 1 (clean) based on
+//!  - baseline wander: how much the mean of the raw signal moved since the previous second,
+//!  - high frequency noise: the RMS of the second difference of the filtered signal, which is
+//!    dominated by muscle noise as the ECG itself is smooth at this scale,
+//!  - saturation: the portion of samples close to the ADC's full scale,
+//!  - QRS consistency: whether beats are detected and their RR intervals agree with each other,
+//!  - lead-off: the portion of samples recorded while a lead was disconnected.
+//!
+//! The score of a second is the score of its worst component.
+
+use crate::filter::{median::MedianFilter, Filter};
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+/// Quality of a single second of signal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SecondQuality {
+    /// Overall score between 0 (unusable) and 1 (clean).
+    pub score: f32,
+    /// Change of the signal's mean since the previous second, in volts.
+    pub baseline_wander: f32,
+    /// RMS of the second difference of the filtered signal, in volts.
+    pub noise: f32,
+    /// Portion of saturated samples.
+    pub saturation: f32,
+    /// Portion of consistent QRS detections, or 0 if the beats were lost.
+    pub qrs_consistency: f32,
+    /// Portion of samples recorded with a disconnected lead.
+    pub lead_off: f32,
+}
+
+/// Quality summary of a recording.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QualityReport {
+    /// Mean of the per-second scores.
+    pub score: f32,
+    /// Number of seconds scored.
+    pub seconds: u32,
+    /// Number of seconds with a score of at least [`SignalQuality::USABLE_SCORE`].
+    pub usable_seconds: u32,
+}
+
+impl QualityReport {
+    /// Returns whether enough of the recording is usable for analysis.
+    pub fn is_usable(&self) -> bool {
+        self.usable_seconds as f32 >= self.seconds as f32 * SignalQuality::USABLE_PORTION
+    }
+}
+
+#[derive(Clone)]
+pub struct SignalQuality {
+    samples_per_second: usize,
+    full_scale: f32,
+
+    // Current second
+    count: usize,
+    raw_sum: f32,
+    previous_mean: Option<f32>,
+    previous_sample: Option<f32>,
+    older_sample: Option<f32>,
+    noise_sq_sum: f32,
+    noise_count: usize,
+    saturated: usize,
+    lead_off: usize,
+    beats: u32,
+    consistent_beats: u32,
+
+    // Beat tracking
+    rr_reference: MedianFilter<5>,
+    rr_reference_value: Option<f32>,
+    samples_since_beat: usize,
+
+    // Whole recording
+    seconds: u32,
+    usable_seconds: u32,
+    score_sum: f32,
+}
+
+impl SignalQuality {
+    /// Baseline wander below this is not penalized, in volts.
+    pub const GOOD_WANDER: f32 = 0.3e-3;
+    /// Baseline wander above this makes a second unusable, in volts.
+    pub const BAD_WANDER: f32 = 2.0e-3;
+    /// Noise below this is not penalized, in volts.
+    pub const GOOD_NOISE: f32 = 10.0e-6;
+    /// Noise above this makes a second unusable, in volts.
+    pub const BAD_NOISE: f32 = 50.0e-6;
+    /// Samples above this portion of the full scale are considered saturated.
+    pub const SATURATION_LEVEL: f32 = 0.95;
+    /// Portion of saturated samples that makes a second unusable.
+    pub const BAD_SATURATION: f32 = 0.1;
+    /// Maximum relative difference between an RR interval and the recent ones.
+    pub const MAX_RR_DEVIATION: f32 = 0.3;
+    /// Seconds without a detected beat before the QRS detection is considered lost.
+    pub const MAX_BEAT_GAP: f32 = 2.0;
+
+    /// Seconds with at least this score are considered usable.
+    pub const USABLE_SCORE: f32 = 0.5;
+    /// Portion of usable seconds needed for a recording to be usable.
+    pub const USABLE_PORTION: f32 = 0.6;
+
+    /// Creates a new estimator for the given sample rate and ADC full scale voltage.
+    pub fn new(fs: f32, full_scale: f32) -> Self {
+        Self {
+            samples_per_second: fs as usize,
+            full_scale,
+
+            count: 0,
+            raw_sum: 0.0,
+            previous_mean: None,
+            previous_sample: None,
+            older_sample: None,
+            noise_sq_sum: 0.0,
+            noise_count: 0,
+            saturated: 0,
+            lead_off: 0,
+            beats: 0,
+            consistent_beats: 0,
+
+            rr_reference: MedianFilter::new(),
+            rr_reference_value: None,
+            samples_since_beat: 0,
+
+            seconds: 0,
+            usable_seconds: 0,
+            score_sum: 0.0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new(self.samples_per_second as f32, self.full_scale);
+    }
+
+    /// Processes a sample.
+    ///
+    /// `raw` is the unfiltered sample, `filtered` is the same sample with the baseline and power
+    /// line interference removed. Returns the quality of the last second, once it is complete.
+    pub fn update(
+        &mut self,
+        raw: f32,
+        filtered: f32,
+        leads_connected: bool,
+    ) -> Option<SecondQuality> {
+        self.count += 1;
+        self.samples_since_beat += 1;
+        self.raw_sum += raw;
+
+        if raw.abs() >= self.full_scale * Self::SATURATION_LEVEL {
+            self.saturated += 1;
+        }
+        if !leads_connected {
+            self.lead_off += 1;
+        }
+
+        if let (Some(older), Some(previous)) = (self.older_sample, self.previous_sample) {
+            let second_diff = filtered - 2.0 * previous + older;
+            self.noise_sq_sum += second_diff * second_diff;
+            self.noise_count += 1;
+        }
+        self.older_sample = self.previous_sample;
+        self.previous_sample = Some(filtered);
+
+        if self.count < self.samples_per_second {
+            return None;
+        }
+
+        let quality = self.finish_second();
+        self.record(quality);
+
+        Some(quality)
+    }
+
+    /// Notifies the estimator of a detected beat and the RR interval, in milliseconds, that
+    /// ended with it.
+    pub fn beat(&mut self, rr_interval: Option<f32>) {
+        self.samples_since_beat = 0;
+        self.beats += 1;
+
+        let Some(rr) = rr_interval else {
+            // The first beat after a detector reset can not be checked.
+            self.consistent_beats += 1;
+            return;
+        };
+
+        let consistent = match self.rr_reference_value {
+            Some(reference) => (rr - reference).abs() <= reference * Self::MAX_RR_DEVIATION,
+            None => true,
+        };
+        if consistent {
+            self.consistent_beats += 1;
+        }
+
+        self.rr_reference_value = Some(self.rr_reference.update(rr).unwrap_or(rr));
+    }
+
+    fn finish_second(&mut self) -> SecondQuality {
+        let count = self.count as f32;
+
+        let mean = self.raw_sum / count;
+        let baseline_wander = self
+            .previous_mean
+            .map_or(0.0, |previous| (mean - previous).abs());
+        self.previous_mean = Some(mean);
+
+        let noise = if self.noise_count > 0 {
+            (self.noise_sq_sum / self.noise_count as f32).sqrt()
+        } else {
+            0.0
+        };
+
+        let saturation = self.saturated as f32 / count;
+        let lead_off = self.lead_off as f32 / count;
+
+        let beats_lost = self.samples_since_beat as f32 > Self::MAX_BEAT_GAP * count;
+        let qrs_consistency = if beats_lost {
+            0.0
+        } else if self.beats == 0 {
+            // Slow heart rate, nothing to check in this second.
+            1.0
+        } else {
+            self.consistent_beats as f32 / self.beats as f32
+        };
+
+        let score = [
+            1.0 - Self::grade(baseline_wander, Self::GOOD_WANDER, Self::BAD_WANDER),
+            1.0 - Self::grade(noise, Self::GOOD_NOISE, Self::BAD_NOISE),
+            1.0 - Self::grade(saturation, 0.0, Self::BAD_SATURATION),
+            qrs_consistency,
+            1.0 - lead_off,
+        ]
+        .into_iter()
+        .fold(1.0, f32::min);
+
+        self.count = 0;
+        self.raw_sum = 0.0;
+        self.noise_sq_sum = 0.0;
+        self.noise_count = 0;
+        self.saturated = 0;
+        self.lead_off = 0;
+        self.beats = 0;
+        self.consistent_beats = 0;
+
+        SecondQuality {
+            score,
+            baseline_wander,
+            noise,
+            saturation,
+            qrs_consistency,
+            lead_off,
+        }
+    }
+
+    /// Maps `value` to 0 at or below `good` and 1 at or above `bad`, linearly in between.
+    fn grade(value: f32, good: f32, bad: f32) -> f32 {
+        ((value - good) / (bad - good)).clamp(0.0, 1.0)
+    }
+
+    fn record(&mut self, quality: SecondQuality) {
+        self.seconds += 1;
+        self.score_sum += quality.score;
+        if quality.score >= Self::USABLE_SCORE {
+            self.usable_seconds += 1;
+        }
+    }
+
+    /// Returns the quality summary of the seconds processed so far, or `None` if no second has
+    /// been completed yet.
+    pub fn report(&self) -> Option<QualityReport> {
+        (self.seconds > 0).then(|| QualityReport {
+            score: self.score_sum / self.seconds as f32,
+            seconds: self.seconds,
+            usable_seconds: self.usable_seconds,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FS: usize = 1000;
+    const FULL_SCALE: f32 = 2.42;
+
+    /// Deterministic pseudo-random numbers in `-0.5..0.5`.
+    struct Lcg(u32);
+
+    impl Lcg {
+        fn next(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (self.0 >> 8) as f32 / (1 << 24) as f32 - 0.5
+        }
+    }
+
+    /// Synthetic ECG at 75 bpm: 1 mV triangular QRS complexes on a flat line.
+    fn ecg(n: usize) -> f32 {
+        match n % 800 {
+            i @ 0..=20 => i as f32 * 0.05e-3,
+            i @ 21..=40 => (40 - i) as f32 * 0.05e-3,
+            _ => 0.0,
+        }
+    }
+
+    struct Recording {
+        seconds: usize,
+        noise: f32,
+        wander: f32,
+        offset: f32,
+        lead_off_after: Option<usize>,
+        beats: bool,
+    }
+
+    impl Default for Recording {
+        fn default() -> Self {
+            Self {
+                seconds: 30,
+                noise: 0.0,
+                wander: 0.0,
+                offset: 0.0,
+                lead_off_after: None,
+                beats: true,
+            }
+        }
+    }
+
+    impl Recording {
+        fn process(&self) -> (SignalQuality, Vec<SecondQuality>) {
+            let mut rng = Lcg(1);
+            let mut quality = SignalQuality::new(FS as f32, FULL_SCALE);
+            let mut seconds = Vec::new();
+
+            for n in 0..self.seconds * FS {
+                let t = n as f32 / FS as f32;
+                let signal = ecg(n) + rng.next() * self.noise;
+                let baseline = self.offset + self.wander * (t * 0.5 * core::f32::consts::TAU).sin();
+                let leads_connected = !matches!(self.lead_off_after, Some(s) if n >= s * FS);
+
+                if self.beats && n % 800 == 20 {
+                    quality.beat((n >= 800).then_some(800.0));
+                }
+
+                if let Some(second) = quality.update(signal + baseline, signal, leads_connected) {
+                    seconds.push(second);
+                }
+            }
+
+            (quality, seconds)
+        }
+    }
+
+    #[test]
+    fn no_report_before_first_second() {
+        let mut quality = SignalQuality::new(FS as f32, FULL_SCALE);
+        for _ in 0..FS - 1 {
+            assert_eq!(quality.update(0.0, 0.0, true), None);
+        }
+        assert_eq!(quality.report(), None);
+    }
+
+    #[test]
+    fn clean_signal() {
+        let (quality, seconds) = Recording::default().process();
+
+        assert_eq!(seconds.len(), 30);
+        assert!(seconds.iter().all(|s| s.score > 0.99), "{seconds:?}");
+
+        let report = quality.report().unwrap();
+        assert_eq!(report.usable_seconds, 30);
+        assert!(report.is_usable());
+    }
+
+    #[test]
+    fn muscle_noise() {
+        let (quality, _) = Recording {
+            noise: 100.0e-6,
+            ..Default::default()
+        }
+        .process();
+
+        let report = quality.report().unwrap();
+        assert!(report.score < 0.5, "{report:?}");
+        assert!(!report.is_usable());
+    }
+
+    #[test]
+    fn baseline_wander() {
+        let (quality, seconds) = Recording {
+            wander: 5.0e-3,
+            ..Default::default()
+        }
+        .process();
+
+        assert!(seconds
+            .iter()
+            .any(|s| s.baseline_wander > SignalQuality::BAD_WANDER));
+        assert!(!quality.report().unwrap().is_usable());
+    }
+
+    #[test]
+    fn saturation() {
+        let (quality, seconds) = Recording {
+            offset: FULL_SCALE,
+            ..Default::default()
+        }
+        .process();
+
+        assert!(seconds.iter().all(|s| s.saturation == 1.0));
+        assert_eq!(quality.report().unwrap().usable_seconds, 0);
+    }
+
+    #[test]
+    fn lead_off() {
+        let (quality, seconds) = Recording {
+            lead_off_after: Some(10),
+            ..Default::default()
+        }
+        .process();
+
+        assert!(seconds[..10].iter().all(|s| s.lead_off == 0.0));
+        assert!(seconds[10..].iter().all(|s| s.lead_off == 1.0));
+        assert_eq!(quality.report().unwrap().usable_seconds, 10);
+    }
+
+    #[test]
+    fn lost_beats() {
+        let (quality, seconds) = Recording {
+            beats: false,
+            ..Default::default()
+        }
+        .process();
+
+        assert!(seconds[2..].iter().all(|s| s.qrs_consistency == 0.0));
+        assert!(!quality.report().unwrap().is_usable());
+    }
+
+    #[test]
+    fn inconsistent_beats() {
+        let mut quality = SignalQuality::new(FS as f32, FULL_SCALE);
+        for rr in [800.0, 800.0, 800.0, 400.0] {
+            quality.beat(Some(rr));
+        }
+
+        let mut second = None;
+        for _ in 0..FS {
+            second = quality.update(0.0, 0.0, true);
+        }
+
+        assert_eq!(second.unwrap().qrs_consistency, 0.75);
+    }
+}
@@ -0,0 +1,286 @@
+//! Retention policy for stored measurements
+//!
+//! When a new measurement needs space, older ones are deleted: uploaded measurements first, then,
+//! if the policy allows it, measurements that haven't been uploaded yet. Measurements are always
+//! deleted oldest first. Some space is never used for measurements, so that the configuration
+//! can still be saved when the storage is full.
+//!
+//! The policy only decides what to delete, one measurement at a time. The caller deletes it and
+//! asks again until there is enough space.
+
+/// A stored measurement, as far as the retention policy is concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StoredItem {
+    /// Size of the measurement, in bytes.
+    pub size: usize,
+    pub uploaded: bool,
+}
+
+/// What to do before storing a new measurement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Eviction {
+    /// The new measurement can be stored.
+    Done,
+    /// Delete the item at this index, then ask again.
+    Delete(usize),
+    /// The new measurement doesn't fit, and nothing else can be deleted.
+    Full,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RetentionPolicy {
+    /// The most measurements to keep, including the new one.
+    pub keep_newest: Option<usize>,
+    /// Whether measurements that haven't been uploaded may be deleted.
+    pub delete_pending: bool,
+    /// Space that measurements may not use, in bytes.
+    pub reserved_bytes: usize,
+}
+
+impl RetentionPolicy {
+    /// Decides what to delete before storing a measurement of `needed` bytes. `items` are the
+    /// stored measurements, oldest first.
+    pub fn next_eviction(
+        &self,
+        items: &[StoredItem],
+        free_bytes: usize,
+        needed: usize,
+    ) -> Eviction {
+        let too_many = self
+            .keep_newest
+            .is_some_and(|keep| items.len() >= keep.max(1));
+        let too_large = free_bytes < needed.saturating_add(self.reserved_bytes);
+
+        if !too_many && !too_large {
+            return Eviction::Done;
+        }
+
+        // Don't delete anything if the new measurement won't fit anyway.
+        if too_large && self.available_bytes(items, free_bytes) < needed {
+            return Eviction::Full;
+        }
+
+        match self.oldest_deletable(items) {
+            Some(index) => Eviction::Delete(index),
+            // Measurements that can't be deleted are kept, even if there are too many of them.
+            None if too_large => Eviction::Full,
+            None => Eviction::Done,
+        }
+    }
+
+    /// Returns the space measurements can use, if every measurement the policy allows is deleted.
+    pub fn available_bytes(&self, items: &[StoredItem], free_bytes: usize) -> usize {
+        let deletable = items
+            .iter()
+            .filter(|item| self.can_delete(item))
+            .map(|item| item.size)
+            .sum::<usize>();
+
+        (free_bytes + deletable).saturating_sub(self.reserved_bytes)
+    }
+
+    fn can_delete(&self, item: &StoredItem) -> bool {
+        item.uploaded || self.delete_pending
+    }
+
+    fn oldest_deletable(&self, items: &[StoredItem]) -> Option<usize> {
+        items
+            .iter()
+            .position(|item| item.uploaded)
+            .or_else(|| items.iter().position(|item| self.can_delete(item)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CAPACITY: usize = 1000;
+
+    struct Storage {
+        items: Vec<(u32, StoredItem)>,
+    }
+
+    impl Storage {
+        fn new() -> Self {
+            Self { items: Vec::new() }
+        }
+
+        fn free_bytes(&self) -> usize {
+            CAPACITY - self.items.iter().map(|(_, item)| item.size).sum::<usize>()
+        }
+
+        fn stored(&self) -> Vec<StoredItem> {
+            self.items.iter().map(|(_, item)| *item).collect()
+        }
+
+        fn ids(&self) -> Vec<u32> {
+            self.items.iter().map(|(id, _)| *id).collect()
+        }
+
+        fn upload(&mut self, id: u32) {
+            for (item_id, item) in self.items.iter_mut() {
+                if *item_id == id {
+                    item.uploaded = true;
+                }
+            }
+        }
+
+        /// Stores a measurement the way the firmware does, evicting until it fits.
+        fn store(&mut self, policy: &RetentionPolicy, id: u32, size: usize) -> bool {
+            loop {
+                match policy.next_eviction(&self.stored(), self.free_bytes(), size) {
+                    Eviction::Done => break,
+                    Eviction::Delete(index) => {
+                        self.items.remove(index);
+                    }
+                    Eviction::Full => return false,
+                }
+            }
+
+            self.items.push((
+                id,
+                StoredItem {
+                    size,
+                    uploaded: false,
+                },
+            ));
+            true
+        }
+    }
+
+    const POLICY: RetentionPolicy = RetentionPolicy {
+        keep_newest: None,
+        delete_pending: false,
+        reserved_bytes: 100,
+    };
+
+    #[test]
+    fn stores_until_reserved_space() {
+        let mut storage = Storage::new();
+
+        for id in 0..9 {
+            assert!(storage.store(&POLICY, id, 100), "failed to store {id}");
+        }
+        assert_eq!(storage.free_bytes(), 100);
+
+        assert!(!storage.store(&POLICY, 9, 1));
+        assert_eq!(storage.ids(), (0..9).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn evicts_oldest_uploaded_first() {
+        let mut storage = Storage::new();
+        for id in 0..9 {
+            assert!(storage.store(&POLICY, id, 100));
+        }
+
+        storage.upload(5);
+        storage.upload(2);
+
+        assert!(storage.store(&POLICY, 9, 100));
+        assert_eq!(storage.ids(), [0, 1, 3, 4, 5, 6, 7, 8, 9]);
+
+        assert!(storage.store(&POLICY, 10, 100));
+        assert_eq!(storage.ids(), [0, 1, 3, 4, 6, 7, 8, 9, 10]);
+
+        // Nothing left that has been uploaded.
+        assert!(!storage.store(&POLICY, 11, 100));
+    }
+
+    #[test]
+    fn evicts_as_many_as_needed() {
+        let mut storage = Storage::new();
+        for id in 0..9 {
+            assert!(storage.store(&POLICY, id, 100));
+            storage.upload(id);
+        }
+
+        assert!(storage.store(&POLICY, 9, 350));
+        assert_eq!(storage.ids(), [4, 5, 6, 7, 8, 9]);
+        assert!(storage.free_bytes() >= POLICY.reserved_bytes);
+    }
+
+    #[test]
+    fn evicts_pending_if_allowed() {
+        let policy = RetentionPolicy {
+            delete_pending: true,
+            ..POLICY
+        };
+
+        let mut storage = Storage::new();
+        for id in 0..9 {
+            assert!(storage.store(&policy, id, 100));
+        }
+        storage.upload(7);
+
+        // The uploaded one goes first, even though it is newer.
+        assert!(storage.store(&policy, 9, 100));
+        assert_eq!(storage.ids(), [0, 1, 2, 3, 4, 5, 6, 8, 9]);
+
+        assert!(storage.store(&policy, 10, 100));
+        assert_eq!(storage.ids(), [1, 2, 3, 4, 5, 6, 8, 9, 10]);
+    }
+
+    #[test]
+    fn keeps_newest() {
+        let policy = RetentionPolicy {
+            keep_newest: Some(3),
+            ..POLICY
+        };
+
+        let mut storage = Storage::new();
+        for id in 0..5 {
+            assert!(storage.store(&policy, id, 10));
+            storage.upload(id);
+        }
+        assert_eq!(storage.ids(), [2, 3, 4]);
+
+        // Measurements that haven't been uploaded are kept, even if there are too many.
+        for id in 5..8 {
+            assert!(storage.store(&policy, id, 10));
+        }
+        assert_eq!(storage.ids(), [5, 6, 7]);
+        assert!(storage.store(&policy, 8, 10));
+        assert_eq!(storage.ids(), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn measurement_larger_than_storage_is_rejected() {
+        let policy = RetentionPolicy {
+            delete_pending: true,
+            ..POLICY
+        };
+
+        let mut storage = Storage::new();
+        assert!(storage.store(&policy, 0, 100));
+        assert!(!storage.store(&policy, 1, CAPACITY));
+        assert_eq!(storage.ids(), [0]);
+    }
+
+    #[test]
+    fn available_bytes_counts_deletable_items() {
+        let items = [
+            StoredItem {
+                size: 100,
+                uploaded: true,
+            },
+            StoredItem {
+                size: 200,
+                uploaded: false,
+            },
+        ];
+
+        assert_eq!(POLICY.available_bytes(&items, 50), 50);
+        assert_eq!(POLICY.available_bytes(&items, 500), 500);
+
+        let policy = RetentionPolicy {
+            delete_pending: true,
+            ..POLICY
+        };
+        assert_eq!(policy.available_bytes(&items, 500), 700);
+    }
+}
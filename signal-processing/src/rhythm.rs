@@ -0,0 +1,323 @@
+//! Rhythm irregularity screening
+//!
+//! Atrial fibrillation shows up on a single lead as an irregularly irregular rhythm: the RR
+//! intervals vary a lot, and they do so randomly. The classifier looks at a sliding window of RR
+//! intervals and flags a window as irregular if both
+//!  - the normalized RMSSD (RMSSD divided by the mean RR interval, without the largest successive
+//!    differences) is high, and
+//!  - the turning point ratio is close to what a random sequence would produce.
+//!
+//! The second condition keeps sinus arrhythmia, where the RR intervals change smoothly with
+//! breathing, from being flagged. The result is a screening aid, not a diagnosis.
+//!
+//! The thresholds have only been checked against synthetic RR intervals, not against annotated
+//! recordings of real patients.
+
+use crate::buffer::Buffer;
+
+#[allow(unused_imports)]
+use crate::compat::*;
+
+/// The outcome of the rhythm screening.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Rhythm {
+    /// Not enough beats were detected to make a decision.
+    Inconclusive,
+    Regular,
+    Irregular,
+}
+
+const WINDOW: usize = 32;
+
+#[derive(Clone)]
+pub struct RhythmClassifier {
+    window: Buffer<f32, WINDOW, false>,
+    evaluated_windows: u32,
+    irregular_windows: u32,
+}
+
+impl Default for RhythmClassifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RhythmClassifier {
+    /// Number of RR intervals evaluated together.
+    pub const WINDOW: usize = WINDOW;
+    /// Shortest RR interval considered physiological (200 bpm).
+    pub const MIN_RR_MS: f32 = 300.0;
+    /// Longest RR interval considered physiological (30 bpm).
+    pub const MAX_RR_MS: f32 = 2000.0;
+    /// Number of the largest successive differences left out of the RMSSD.
+    pub const IGNORED_DIFFERENCES: usize = 8;
+    /// Normalized RMSSD above which a window may be irregular.
+    pub const NRMSSD_THRESHOLD: f32 = 0.1;
+    /// Turning point ratios expected from a random sequence of [`Self::WINDOW`] intervals.
+    pub const TPR_RANGE: core::ops::RangeInclusive<f32> = 0.54..=0.77;
+    /// Portion of the evaluated windows that must be irregular to flag the recording.
+    pub const IRREGULAR_PORTION: f32 = 0.5;
+
+    pub const fn new() -> Self {
+        Self {
+            window: Buffer::new(),
+            evaluated_windows: 0,
+            irregular_windows: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Processes an RR interval, in milliseconds.
+    pub fn update(&mut self, rr_ms: f32) {
+        if !(Self::MIN_RR_MS..=Self::MAX_RR_MS).contains(&rr_ms) {
+            // Missed or false detections would look like irregularity, so start over.
+            self.window.clear();
+            return;
+        }
+
+        self.window.push(rr_ms);
+
+        if self.window.is_full() {
+            self.evaluated_windows += 1;
+            if self.window_is_irregular() {
+                self.irregular_windows += 1;
+            }
+        }
+    }
+
+    fn window_is_irregular(&self) -> bool {
+        let mut diffs = [0.0; WINDOW - 1];
+        let mut sum = 0.0;
+        let mut turning_points = 0;
+
+        let mut iter = self.window.iter();
+        let Some(mut prev) = iter.next() else {
+            return false;
+        };
+        sum += prev;
+
+        let mut prev_diff: f32 = 0.0;
+        for (rr, slot) in iter.zip(diffs.iter_mut()) {
+            sum += rr;
+
+            let diff = rr - prev;
+            *slot = diff.abs();
+
+            if prev_diff * diff < 0.0 {
+                turning_points += 1;
+            }
+
+            if diff != 0.0 {
+                prev_diff = diff;
+            }
+            prev = rr;
+        }
+
+        // Each ectopic beat causes a few large differences in an otherwise regular rhythm. AF
+        // is irregular throughout, so ignoring the largest differences barely affects it.
+        diffs.sort_unstable_by(|a, b| a.total_cmp(b));
+        let kept = &diffs[..diffs.len() - Self::IGNORED_DIFFERENCES];
+        let successive_sq_sum = kept.iter().map(|diff| diff * diff).sum::<f32>();
+
+        let mean = sum / WINDOW as f32;
+        let rmssd = (successive_sq_sum / kept.len() as f32).sqrt();
+        let tpr = turning_points as f32 / (WINDOW - 2) as f32;
+
+        rmssd / mean > Self::NRMSSD_THRESHOLD && Self::TPR_RANGE.contains(&tpr)
+    }
+
+    /// Returns the portion of the evaluated windows that were irregular, or `None` if no window
+    /// could be evaluated yet.
+    pub fn irregularity(&self) -> Option<f32> {
+        (self.evaluated_windows > 0)
+            .then(|| self.irregular_windows as f32 / self.evaluated_windows as f32)
+    }
+
+    pub fn rhythm(&self) -> Rhythm {
+        match self.irregularity() {
+            None => Rhythm::Inconclusive,
+            Some(irregularity) if irregularity > Self::IRREGULAR_PORTION => Rhythm::Irregular,
+            Some(_) => Rhythm::Regular,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Deterministic pseudo-random numbers in `0.0..1.0`.
+    struct Lcg(u32);
+
+    impl Lcg {
+        fn next(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+    }
+
+    fn classify(intervals: impl IntoIterator<Item = f32>) -> RhythmClassifier {
+        let mut classifier = RhythmClassifier::new();
+        for rr in intervals {
+            classifier.update(rr);
+        }
+        classifier
+    }
+
+    /// Sinus rhythm around `mean` with a small amount of beat-to-beat jitter.
+    fn sinus(rng: &mut Lcg, mean: f32, count: usize) -> impl Iterator<Item = f32> + '_ {
+        (0..count).map(move |_| mean + (rng.next() - 0.5) * 20.0)
+    }
+
+    /// Atrial fibrillation: random intervals with a high variance.
+    fn fibrillation(rng: &mut Lcg, count: usize) -> impl Iterator<Item = f32> + '_ {
+        (0..count).map(move |_| 400.0 + rng.next() * 600.0)
+    }
+
+    #[test]
+    fn short_recording_is_inconclusive() {
+        let mut rng = Lcg(1);
+        let classifier = classify(sinus(&mut rng, 800.0, RhythmClassifier::WINDOW - 1));
+
+        assert_eq!(classifier.rhythm(), Rhythm::Inconclusive);
+    }
+
+    #[test]
+    fn sinus_rhythm_is_regular() {
+        let mut rng = Lcg(2);
+        let classifier = classify(sinus(&mut rng, 800.0, 100));
+
+        assert_eq!(classifier.rhythm(), Rhythm::Regular);
+        assert_eq!(classifier.irregularity(), Some(0.0));
+    }
+
+    #[test]
+    fn sinus_arrhythmia_is_regular() {
+        // Breathing every 5 beats modulates the heart rate by +/-15%
+        let intervals =
+            (0..100).map(|i| 800.0 + 120.0 * (i as f32 * 0.4 * core::f32::consts::PI).sin());
+        let classifier = classify(intervals);
+
+        assert_eq!(classifier.rhythm(), Rhythm::Regular);
+    }
+
+    #[test]
+    fn sinus_rhythm_with_ectopic_beats_is_regular() {
+        let mut rng = Lcg(3);
+        let intervals = sinus(&mut rng, 800.0, 100)
+            .enumerate()
+            .map(|(i, rr)| match i % 20 {
+                10 => 500.0,
+                11 => 1100.0,
+                _ => rr,
+            });
+        let classifier = classify(intervals);
+
+        assert_eq!(classifier.rhythm(), Rhythm::Regular);
+    }
+
+    #[test]
+    fn fibrillation_is_irregular() {
+        let mut rng = Lcg(4);
+        let classifier = classify(fibrillation(&mut rng, 100));
+
+        assert_eq!(classifier.rhythm(), Rhythm::Irregular);
+    }
+
+    #[test]
+    fn paroxysmal_fibrillation() {
+        let mut rng = Lcg(5);
+        let intervals = sinus(&mut rng, 800.0, 20)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .chain(fibrillation(&mut rng, 150).collect::<Vec<_>>());
+        let classifier = classify(intervals);
+
+        assert_eq!(classifier.rhythm(), Rhythm::Irregular);
+    }
+
+    /// An episode of an annotated recording.
+    struct Episode {
+        rhythm: Rhythm,
+        intervals: Vec<f32>,
+    }
+
+    /// Parses an annotated RR interval fixture, see `testdata/rhythm`.
+    fn parse_fixture(fixture: &str) -> Vec<Episode> {
+        let mut episodes = Vec::new();
+        for (line_idx, line) in fixture.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(annotation) = line.strip_prefix('(') {
+                let rhythm = match annotation {
+                    "N" => Rhythm::Regular,
+                    "AFIB" => Rhythm::Irregular,
+                    _ => panic!("Unknown rhythm annotation on line {}", line_idx + 1),
+                };
+                episodes.push(Episode {
+                    rhythm,
+                    intervals: Vec::new(),
+                });
+                continue;
+            }
+
+            // The beat label is only informative, the classifier has to cope with ectopic beats.
+            let rr = line.split_whitespace().next().unwrap();
+            let rr = rr
+                .parse()
+                .unwrap_or_else(|_| panic!("Invalid RR interval on line {}", line_idx + 1));
+            episodes
+                .last_mut()
+                .expect("RR interval before the first annotation")
+                .intervals
+                .push(rr);
+        }
+        episodes
+    }
+
+    /// The fixture is synthetic, so passing this test doesn't mean that the classifier works on
+    /// real recordings. It has not been validated against a database like the MIT-BIH Atrial
+    /// Fibrillation Database yet. Fixtures with real RR intervals can be added to `FIXTURES`.
+    #[test]
+    fn synthetic_episodes() {
+        const FIXTURES: &[(&str, &str)] = &[(
+            "episodes.txt",
+            include_str!("../testdata/rhythm/episodes.txt"),
+        )];
+
+        for (name, fixture) in FIXTURES {
+            let episodes = parse_fixture(fixture);
+            assert!(!episodes.is_empty(), "{name} has no episodes");
+
+            for (idx, episode) in episodes.iter().enumerate() {
+                let classifier = classify(episode.intervals.iter().copied());
+
+                assert_eq!(
+                    classifier.rhythm(),
+                    episode.rhythm,
+                    "{name}, episode {idx}: irregularity {:?}",
+                    classifier.irregularity()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn missed_beats_restart_the_window() {
+        let mut classifier = RhythmClassifier::new();
+        for _ in 0..RhythmClassifier::WINDOW - 1 {
+            classifier.update(800.0);
+        }
+        classifier.update(2500.0);
+        classifier.update(800.0);
+
+        assert_eq!(classifier.rhythm(), Rhythm::Inconclusive);
+    }
+}
@@ -0,0 +1,161 @@
+//! SNTP (RFC 4330) client packets.
+//!
+//! The client sends a [`request`] over UDP and passes the answer to [`parse_response`]. Sending,
+//! receiving and retrying are left to the caller.
+
+#![cfg_attr(not(test), no_std)]
+
+pub const PACKET_SIZE: usize = 48;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_TO_UNIX_SECONDS: u64 = 2_208_988_800;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResponseError {
+    /// The response is not an SNTP packet.
+    InvalidLength,
+    /// The response belongs to a different request.
+    Mismatch,
+    /// The packet is not a server response, or the server's clock is not synchronized.
+    Unsynchronized,
+    /// The time is before the Unix epoch.
+    InvalidTime,
+}
+
+/// Creates a request. The server returns `transmit_timestamp` in its response, which tells that
+/// the response belongs to this request. The value doesn't need to be the current time.
+pub fn request(transmit_timestamp: u64) -> [u8; PACKET_SIZE] {
+    let mut request = [0; PACKET_SIZE];
+
+    // LI = 0 (no warning), VN = 4, Mode = 3 (client)
+    request[0] = 0b00_100_011;
+    request[40..48].copy_from_slice(&transmit_timestamp.to_be_bytes());
+
+    request
+}
+
+/// Returns the transmit timestamp of an SNTP response to `request`, in microseconds since the
+/// Unix epoch.
+pub fn parse_response(response: &[u8], request: &[u8; PACKET_SIZE]) -> Result<u64, ResponseError> {
+    let Ok(response) = <[u8; PACKET_SIZE]>::try_from(response) else {
+        return Err(ResponseError::InvalidLength);
+    };
+
+    if response[24..32] != request[40..48] {
+        return Err(ResponseError::Mismatch);
+    }
+
+    let leap_indicator = response[0] >> 6;
+    let mode = response[0] & 0b111;
+    let stratum = response[1];
+    // Mode 4 is a server response. Stratum 0 is a "kiss-o'-death" message, and a leap indicator
+    // of 3 means that the server's clock is not synchronized.
+    if mode != 4 || stratum == 0 || leap_indicator == 3 {
+        return Err(ResponseError::Unsynchronized);
+    }
+
+    let seconds = u32::from_be_bytes([response[40], response[41], response[42], response[43]]);
+    let fraction = u32::from_be_bytes([response[44], response[45], response[46], response[47]]);
+
+    let Some(seconds) = (seconds as u64).checked_sub(NTP_TO_UNIX_SECONDS) else {
+        return Err(ResponseError::InvalidTime);
+    };
+    let micros = (fraction as u64 * 1_000_000) >> 32;
+
+    Ok(seconds * 1_000_000 + micros)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(request: &[u8; PACKET_SIZE]) -> [u8; PACKET_SIZE] {
+        let mut response = [0; PACKET_SIZE];
+        // LI = 0, VN = 4, Mode = 4 (server)
+        response[0] = 0b00_100_100;
+        response[1] = 2;
+        response[24..32].copy_from_slice(&request[40..48]);
+        let seconds = (NTP_TO_UNIX_SECONDS + 1_700_000_000) as u32;
+        response[40..44].copy_from_slice(&seconds.to_be_bytes());
+        response[44..48].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+        response
+    }
+
+    #[test]
+    fn request_is_a_client_packet() {
+        let request = request(123_456_789);
+
+        assert_eq!(request[0], 0x23);
+        assert_eq!(request[40..48], 123_456_789u64.to_be_bytes());
+        assert!(request[1..40].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn parses_transmit_timestamp() {
+        let request = request(123_456_789);
+        assert_eq!(
+            parse_response(&response(&request), &request),
+            Ok(1_700_000_000_500_000)
+        );
+    }
+
+    #[test]
+    fn rejects_response_to_other_request() {
+        let request = request(123_456_789);
+        let mut response = response(&request);
+        response[31] ^= 1;
+
+        assert_eq!(
+            parse_response(&response, &request),
+            Err(ResponseError::Mismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_unsynchronized_server() {
+        let request = request(123_456_789);
+
+        let mut kiss_of_death = response(&request);
+        kiss_of_death[1] = 0;
+        assert_eq!(
+            parse_response(&kiss_of_death, &request),
+            Err(ResponseError::Unsynchronized)
+        );
+
+        let mut unsynchronized = response(&request);
+        unsynchronized[0] |= 0b11 << 6;
+        assert_eq!(
+            parse_response(&unsynchronized, &request),
+            Err(ResponseError::Unsynchronized)
+        );
+
+        let mut client = response(&request);
+        client[0] = request[0];
+        assert_eq!(
+            parse_response(&client, &request),
+            Err(ResponseError::Unsynchronized)
+        );
+    }
+
+    #[test]
+    fn rejects_time_before_unix_epoch() {
+        let request = request(123_456_789);
+        let mut response = response(&request);
+        response[40..44].copy_from_slice(&1000u32.to_be_bytes());
+
+        assert_eq!(
+            parse_response(&response, &request),
+            Err(ResponseError::InvalidTime)
+        );
+    }
+
+    #[test]
+    fn rejects_short_response() {
+        let request = request(123_456_789);
+        assert_eq!(
+            parse_response(&response(&request)[..47], &request),
+            Err(ResponseError::InvalidLength)
+        );
+    }
+}
@@ -0,0 +1,506 @@
+//! Index of the stored measurements.
+//!
+//! The catalog keeps a record of every `meas.<id>` file. The file format and the bookkeeping are
+//! in [`signal_processing::catalog`], this module reads and writes the catalog file.
+//!
+//! A catalog that is missing or corrupted is rebuilt from the directory. A catalog can also be
+//! out of date if the device stops between writing a measurement file and recording it, so the
+//! directory is checked once after boot. Records of missing files are dropped, and files without
+//! a record are added as measurements that haven't been uploaded. After that, the catalog is
+//! trusted.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::vec::Vec;
+use norfs::{
+    medium::StorageMedium, read_dir::DirEntry, writer::FileDataWriter, OnCollision, Storage,
+    StorageError,
+};
+use signal_processing::{
+    catalog::{self, StoredFile},
+    measurement::{MeasurementHeader, FORMAT_VERSION, STREAMED_FORMAT_VERSION},
+    retention::{Eviction, RetentionPolicy, StoredItem},
+};
+
+pub use signal_processing::catalog::{
+    CatalogEntry, MeasurementInfo, UploadState, MAX_LOAD_FAILURES,
+};
+
+use crate::uformat;
+
+const CATALOG_FILE: &str = "catalog";
+
+/// Whether the catalog has been checked against the directory since boot.
+static RECONCILED: AtomicBool = AtomicBool::new(false);
+
+/// Returns the name of the file that holds a measurement.
+pub fn measurement_file_name(id: u32) -> heapless::String<16> {
+    uformat!(16, "meas.{}", id)
+}
+
+fn parse_measurement_file_name(name: &str) -> Option<u32> {
+    name.strip_prefix("meas.")?.parse().ok()
+}
+
+pub struct Catalog {
+    entries: Vec<CatalogEntry>,
+}
+
+impl Catalog {
+    /// Loads the catalog. The first time after boot, or if the catalog can't be read, it is
+    /// checked against the directory.
+    pub async fn load<M>(storage: &mut Storage<M>) -> Self
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        let mut catalog = match Self::read(storage).await {
+            Ok(catalog) if RECONCILED.load(Ordering::Relaxed) => return catalog,
+            Ok(catalog) => catalog,
+            Err(()) => {
+                info!("Rebuilding measurement catalog");
+                Self {
+                    entries: Vec::new(),
+                }
+            }
+        };
+
+        // Only tried once, so that a directory that can't be listed is not listed on every load.
+        RECONCILED.store(true, Ordering::Relaxed);
+
+        match list_measurement_files(storage).await {
+            Ok(files) => catalog.reconcile(storage, &files).await,
+            Err(e) => warn!("Failed to list measurements: {:?}", e),
+        }
+
+        catalog
+    }
+
+    /// Drops the records of missing files, and records the files that are not in the catalog.
+    async fn reconcile<M>(&mut self, storage: &mut Storage<M>, files: &[StoredFile])
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        let reconciliation = catalog::reconcile(&mut self.entries, files);
+        if reconciliation.is_empty() {
+            return;
+        }
+
+        if reconciliation.missing > 0 {
+            warn!("{} measurement files are missing", reconciliation.missing);
+        }
+
+        for file in reconciliation.unrecorded {
+            info!("Adding measurement {} to the catalog", file.id);
+            // We don't know if it has been uploaded, so we upload it again.
+            let info = read_info(storage, file).await;
+            catalog::insert(&mut self.entries, CatalogEntry::pending(file.id, info));
+        }
+
+        if let Err(e) = self.save(storage).await {
+            warn!("Failed to save catalog: {:?}", e);
+        }
+    }
+
+    async fn read<M>(storage: &mut Storage<M>) -> Result<Self, ()>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        let mut reader = match storage.read(CATALOG_FILE).await {
+            Ok(reader) => reader,
+            Err(e) => {
+                warn!("Failed to open catalog: {:?}", e);
+                return Err(());
+            }
+        };
+
+        let mut header = [0; catalog::HEADER_SIZE];
+        if let Err(e) = reader.read_all(storage, &mut header).await {
+            warn!("Failed to read catalog: {:?}", e);
+            return Err(());
+        }
+        let body_len = match catalog::body_len(header) {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("Failed to read catalog: {:?}", e);
+                return Err(());
+            }
+        };
+
+        let mut body = Vec::new();
+        if body.try_reserve_exact(body_len).is_err() {
+            warn!("Failed to allocate catalog");
+            return Err(());
+        }
+        body.resize(body_len, 0);
+        if let Err(e) = reader.read_all(storage, &mut body).await {
+            warn!("Failed to read catalog: {:?}", e);
+            return Err(());
+        }
+
+        match catalog::decode(header, &body) {
+            Ok(entries) => Ok(Self { entries }),
+            Err(e) => {
+                warn!("Failed to read catalog: {:?}", e);
+                Err(())
+            }
+        }
+    }
+
+    async fn save<M>(&self, storage: &mut Storage<M>) -> Result<(), StorageError>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        storage
+            .store_writer(CATALOG_FILE, &CatalogWriter(self), OnCollision::Overwrite)
+            .await
+    }
+
+    /// Returns the measurements, oldest first.
+    pub fn entries(&self) -> &[CatalogEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, id: u32) -> Option<&CatalogEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    /// Returns the id of the next measurement.
+    pub fn next_id(&self) -> u32 {
+        self.entries.last().map_or(0, |entry| entry.id + 1)
+    }
+
+    pub fn has_pending_uploads(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.upload == UploadState::Pending)
+    }
+
+    /// Returns the measurements that haven't been uploaded yet, oldest first.
+    pub fn pending_uploads(&self) -> impl Iterator<Item = &CatalogEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.upload == UploadState::Pending)
+    }
+
+    /// Records a new measurement, after its file has been written.
+    pub async fn add<M>(
+        &mut self,
+        storage: &mut Storage<M>,
+        id: u32,
+        info: MeasurementInfo,
+    ) -> Result<(), StorageError>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        catalog::insert(&mut self.entries, CatalogEntry::pending(id, info));
+
+        self.save(storage).await
+    }
+
+    pub async fn mark_uploaded<M>(
+        &mut self,
+        storage: &mut Storage<M>,
+        id: u32,
+    ) -> Result<(), StorageError>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == id) else {
+            return Ok(());
+        };
+        entry.upload = UploadState::Uploaded;
+        entry.failures = 0;
+
+        self.save(storage).await
+    }
+
+    /// Records a failed upload attempt.
+    pub async fn mark_failed<M>(
+        &mut self,
+        storage: &mut Storage<M>,
+        id: u32,
+    ) -> Result<(), StorageError>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == id) else {
+            return Ok(());
+        };
+        entry.failures = entry.failures.saturating_add(1);
+
+        self.save(storage).await
+    }
+
+    /// Records an upload attempt that failed because the measurement couldn't be read. The
+    /// measurement is marked as failed after [`MAX_LOAD_FAILURES`] attempts.
+    pub async fn mark_unreadable<M>(
+        &mut self,
+        storage: &mut Storage<M>,
+        id: u32,
+    ) -> Result<(), StorageError>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.id == id) else {
+            return Ok(());
+        };
+        entry.failures = entry.failures.saturating_add(1);
+        if entry.failures >= MAX_LOAD_FAILURES {
+            warn!("Giving up on uploading measurement {}", id);
+            entry.upload = UploadState::Failed;
+        }
+
+        self.save(storage).await
+    }
+
+    /// Deletes a measurement file and its record.
+    pub async fn delete<M>(&mut self, storage: &mut Storage<M>, id: u32) -> Result<(), StorageError>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        match storage.delete(&measurement_file_name(id)).await {
+            Ok(()) | Err(StorageError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+
+        self.entries.retain(|entry| entry.id != id);
+        self.save(storage).await
+    }
+
+    /// Deletes measurements, as far as the retention policy allows, until a new measurement of
+    /// `bytes` fits. Returns whether it does.
+    pub async fn make_room<M>(
+        &mut self,
+        storage: &mut Storage<M>,
+        policy: &RetentionPolicy,
+        bytes: usize,
+    ) -> Result<bool, StorageError>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        loop {
+            let items = self.stored_items();
+            match policy.next_eviction(&items, storage.free_bytes(), bytes) {
+                Eviction::Done => return Ok(true),
+                Eviction::Full => return Ok(false),
+                Eviction::Delete(index) => {
+                    let id = self.entries[index].id;
+                    info!("Deleting measurement {} to free space", id);
+                    self.delete(storage, id).await?;
+                }
+            }
+        }
+    }
+
+    /// Returns the space new measurements can use, if the retention policy deletes every
+    /// measurement it can.
+    pub fn available_bytes<M>(&self, storage: &Storage<M>, policy: &RetentionPolicy) -> usize
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        policy.available_bytes(&self.stored_items(), storage.free_bytes())
+    }
+
+    fn stored_items(&self) -> Vec<StoredItem> {
+        self.entries
+            .iter()
+            .map(|entry| StoredItem {
+                size: entry.info.size as usize,
+                uploaded: entry.upload == UploadState::Uploaded,
+            })
+            .collect()
+    }
+}
+
+/// Lists the measurement files, ordered by id.
+async fn list_measurement_files<M>(
+    storage: &mut Storage<M>,
+) -> Result<Vec<StoredFile>, StorageError>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    let mut files = Vec::new();
+    let mut dir = storage.read_dir().await?;
+
+    let mut buffer = [0; 64];
+    while let Some(file) = dir.next(storage).await? {
+        let id = match file.name(storage, &mut buffer).await {
+            Ok(name) => parse_measurement_file_name(name),
+            Err(StorageError::InsufficientBuffer) => {
+                // not a measurement file, ignore
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        let Some(id) = id else {
+            continue;
+        };
+
+        let size = file.size(storage).await?;
+        files.push(StoredFile { id, size });
+    }
+
+    files.sort_unstable_by_key(|file| file.id);
+
+    Ok(files)
+}
+
+struct CatalogWriter<'a>(&'a Catalog);
+
+impl FileDataWriter for CatalogWriter<'_> {
+    async fn write<M>(
+        &self,
+        writer: &mut norfs::writer::Writer<M>,
+        storage: &mut Storage<M>,
+    ) -> Result<(), StorageError>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        let mut writer = writer.bind(storage);
+        writer.write_all(&catalog::encode(&self.0.entries)).await
+    }
+
+    fn estimate_length(&self) -> usize {
+        catalog::encoded_len(self.0.entries.len())
+    }
+}
+
+/// Describes a measurement file from its header.
+async fn read_info<M>(storage: &mut Storage<M>, file: StoredFile) -> MeasurementInfo
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    match read_layout(storage, file.id, file.size).await {
+        Ok(Some(layout)) => match MeasurementHeader::parse(&layout.header) {
+            Ok((header, _)) => return MeasurementInfo::from_header(file.size, &header),
+            Err(e) => warn!("Failed to parse header of {}: {:?}", file.id, e),
+        },
+        Ok(None) => {}
+        Err(e) => warn!("Failed to read header of {}: {:?}", file.id, e),
+    }
+
+    MeasurementInfo::unknown(file.size)
+}
+
+/// Where the parts of a stored measurement are.
+pub struct MeasurementLayout {
+    /// The encoded header.
+    pub header: Vec<u8>,
+    /// Position of the compressed samples in the file, in bytes.
+    pub samples_offset: usize,
+    /// Length of the compressed samples, in bytes.
+    pub samples_len: usize,
+}
+
+/// Reads the header of a measurement file, and finds its samples. Returns `None` for formats
+/// without a header.
+pub async fn read_layout<M>(
+    storage: &mut Storage<M>,
+    id: u32,
+    size: usize,
+) -> Result<Option<MeasurementLayout>, StorageError>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    let name = measurement_file_name(id);
+    let mut reader = storage.read(&name).await?;
+
+    let mut version = [0];
+    reader.read_all(storage, &mut version).await?;
+
+    let streamed_samples_len = match version[0] {
+        FORMAT_VERSION => None,
+        STREAMED_FORMAT_VERSION => {
+            // The header follows the samples. Their length is stored at the end of the file.
+            let Some(skipped) = size.checked_sub(1 + 4) else {
+                return Ok(None);
+            };
+            reader = skip(DirEntry::from_reader(reader), storage, skipped)
+                .await?
+                .open();
+
+            let mut samples_len = [0; 4];
+            reader.read_all(storage, &mut samples_len).await?;
+
+            let file = DirEntry::from_reader(storage.read(&name).await?);
+            let samples_len = u32::from_le_bytes(samples_len) as usize;
+            reader = skip(file, storage, 1 + samples_len).await?.open();
+
+            Some(samples_len)
+        }
+        _ => return Ok(None),
+    };
+
+    let mut prefix = [0; 3];
+    reader.read_all(storage, &mut prefix).await?;
+    let fields_len = u16::from_le_bytes([prefix[1], prefix[2]]) as usize;
+
+    let mut header = Vec::new();
+    if header.try_reserve_exact(prefix.len() + fields_len).is_err() {
+        warn!("Failed to allocate header");
+        return Ok(None);
+    }
+    header.extend_from_slice(&prefix);
+    header.resize(prefix.len() + fields_len, 0);
+    reader
+        .read_all(storage, &mut header[prefix.len()..])
+        .await?;
+
+    let layout = match streamed_samples_len {
+        Some(samples_len) => MeasurementLayout {
+            header,
+            samples_offset: 1,
+            samples_len,
+        },
+        None => {
+            // The length of the samples follows the header.
+            let mut samples_len = [0; 4];
+            reader.read_all(storage, &mut samples_len).await?;
+
+            MeasurementLayout {
+                samples_offset: 1 + header.len() + 4,
+                header,
+                samples_len: u32::from_le_bytes(samples_len) as usize,
+            }
+        }
+    };
+
+    Ok(Some(layout))
+}
+
+/// Reads and drops the next `len` bytes of a file.
+pub async fn skip<M>(
+    file: DirEntry<M>,
+    storage: &mut Storage<M>,
+    mut len: usize,
+) -> Result<DirEntry<M>, StorageError>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    let mut reader = file.open();
+
+    let mut buffer = [0; 256];
+    while len > 0 {
+        let chunk = &mut buffer[..len.min(256)];
+        reader.read_all(storage, chunk).await?;
+        len -= chunk.len();
+    }
+
+    Ok(DirEntry::from_reader(reader))
+}
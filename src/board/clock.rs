@@ -0,0 +1,140 @@
+//! Wall-clock time.
+//!
+//! The RTC timer keeps counting in deep sleep, so the wall-clock time is kept as an offset from
+//! it. The offset is set over SNTP when the device connects to a network, and it is saved in RTC
+//! memory. RTC memory survives deep sleep, but not a reset or a power loss, after which the time
+//! is unknown until the next synchronization.
+
+use core::mem;
+
+use embassy_time::Instant;
+use gui::widgets::clock::TimeOfDay;
+
+use crate::board::{
+    hal::{macros::ram, Rtc},
+    wifi::sta::Sta,
+};
+
+/// Marks a valid [`SavedOffset`]. RTC memory holds garbage after a power loss.
+const MAGIC: u32 = 0x7153_C10C;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SavedOffset {
+    magic: u32,
+    /// Unix time at RTC time 0, in microseconds.
+    offset_us: u64,
+    /// Bitwise inverse of `offset_us`.
+    check: u64,
+}
+
+impl SavedOffset {
+    fn new(offset_us: u64) -> Self {
+        Self {
+            magic: MAGIC,
+            offset_us,
+            check: !offset_us,
+        }
+    }
+
+    fn offset_us(&self) -> Option<u64> {
+        (self.magic == MAGIC && self.check == !self.offset_us).then_some(self.offset_us)
+    }
+}
+
+#[ram(rtc_fast, uninitialized)]
+static mut SAVED_OFFSET: SavedOffset = SavedOffset {
+    magic: 0,
+    offset_us: 0,
+    check: 0,
+};
+
+pub struct Clock {
+    rtc: Rtc<'static>,
+    /// Whether synchronization has been attempted since the device woke up.
+    sync_attempted: bool,
+}
+
+impl Clock {
+    pub fn new(rtc: Rtc<'static>) -> Self {
+        Self {
+            rtc,
+            sync_attempted: false,
+        }
+    }
+
+    /// Returns the current time in microseconds since the Unix epoch, if it is known.
+    pub fn now_us(&self) -> Option<u64> {
+        let offset = unsafe { SAVED_OFFSET }.offset_us()?;
+        Some(offset + self.rtc.get_time_us())
+    }
+
+    /// Returns the current time in seconds since the Unix epoch, if it is known.
+    pub fn now(&self) -> Option<u64> {
+        self.now_us().map(|us| us / 1_000_000)
+    }
+
+    /// Returns the time since the device was powered on, in microseconds. Unlike
+    /// [`embassy_time::Instant`], this keeps counting in deep sleep.
+    pub fn rtc_time_us(&self) -> u64 {
+        self.rtc.get_time_us()
+    }
+
+    /// Returns the current time of day, in UTC.
+    pub fn time_of_day(&self) -> Option<TimeOfDay> {
+        self.now().map(TimeOfDay::from_timestamp)
+    }
+
+    /// Sets the current time, in microseconds since the Unix epoch.
+    pub fn set(&mut self, unix_us: u64) {
+        let offset_us = unix_us.saturating_sub(self.rtc.get_time_us());
+        unsafe { SAVED_OFFSET = SavedOffset::new(offset_us) };
+    }
+
+    /// Sets the clock over SNTP. Only the first attempt after waking up is made, so that an
+    /// unreachable NTP server doesn't delay every connection.
+    pub async fn synchronize(&mut self, sta: &Sta) {
+        if mem::replace(&mut self.sync_attempted, true) {
+            return;
+        }
+
+        match sta.sntp_time().await {
+            Ok(unix_us) => {
+                self.set(unix_us);
+                info!("Clock synchronized: {}", unix_us / 1_000_000);
+            }
+            Err(e) => warn!("Failed to synchronize clock: {:?}", e),
+        }
+    }
+
+    /// Returns a reader of the time that doesn't borrow the clock.
+    pub fn reader(&self) -> ClockReader {
+        let instant_us = Instant::now().as_micros();
+        ClockReader {
+            rtc_at_instant_0_us: self.rtc.get_time_us().saturating_sub(instant_us),
+        }
+    }
+
+    /// Returns the RTC, which is needed to enter deep sleep.
+    pub fn into_rtc(self) -> Rtc<'static> {
+        self.rtc
+    }
+}
+
+/// Reads the wall-clock time, for tasks that run while the [`Clock`] is borrowed elsewhere. The
+/// time is known as soon as the clock is synchronized, even if that happens after the reader was
+/// created.
+#[derive(Clone, Copy)]
+pub struct ClockReader {
+    /// RTC time at `Instant` 0, in microseconds.
+    rtc_at_instant_0_us: u64,
+}
+
+impl ClockReader {
+    /// Returns the current time in seconds since the Unix epoch, if it is known.
+    pub fn now(&self) -> Option<u64> {
+        let offset = unsafe { SAVED_OFFSET }.offset_us()?;
+        let rtc_us = self.rtc_at_instant_0_us + Instant::now().as_micros();
+        Some((offset + rtc_us) / 1_000_000)
+    }
+}
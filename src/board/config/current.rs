@@ -4,10 +4,16 @@ use gui::widgets::battery_small::BatteryStyle;
 use norfs::storable::{LoadError, Loadable, Storable};
 use ssd1306::prelude::Brightness;
 
+use signal_processing::retention::RetentionPolicy;
+use tls_pinning::KeyPin;
+
 use crate::board::DEFAULT_BACKEND_URL;
 
 use super::{
-    types::{DisplayBrightness, FilterStrength, MeasurementAction},
+    types::{
+        DisplayBrightness, FilterStrength, KeepMeasurements, MainsFrequency, MeasurementAction,
+        MeasurementEviction, PoorSignalAction,
+    },
     CURRENT_VERSION,
 };
 
@@ -19,21 +25,33 @@ pub struct Config {
     pub filter_strength: FilterStrength,
     pub backend_url: heapless::String<64>,
     pub measurement_action: MeasurementAction,
+    pub poor_signal_action: PoorSignalAction,
+    pub mains_frequency: MainsFrequency,
+    pub keep_measurements: KeepMeasurements,
+    pub measurement_eviction: MeasurementEviction,
+    /// Credential issued by the backend when the device is paired. Empty if the device is not
+    /// registered.
+    pub device_token: heapless::String<64>,
+    /// Hex encoded SHA-256 hash of the backend's public key, see the `tls_pinning` crate. Empty if
+    /// the backend is not verified.
+    pub server_key_pin: heapless::String<64>,
 }
 
-impl From<super::v4::Config> for Config {
-    fn from(value: super::v4::Config) -> Self {
+impl From<super::v9::Config> for Config {
+    fn from(value: super::v9::Config) -> Self {
         Self {
             battery_display_style: value.battery_display_style,
             display_brightness: value.display_brightness,
             known_networks: value.known_networks,
             filter_strength: value.filter_strength,
             backend_url: value.backend_url,
-            measurement_action: if value.store_measurement {
-                MeasurementAction::Auto
-            } else {
-                MeasurementAction::Upload
-            },
+            measurement_action: value.measurement_action,
+            poor_signal_action: value.poor_signal_action,
+            mains_frequency: value.mains_frequency,
+            keep_measurements: value.keep_measurements,
+            measurement_eviction: value.measurement_eviction,
+            device_token: value.device_token,
+            server_key_pin: heapless::String::new(),
         }
     }
 }
@@ -48,6 +66,12 @@ impl Default for Config {
             filter_strength: FilterStrength::Weak,
             backend_url: heapless::String::try_from(DEFAULT_BACKEND_URL).unwrap(),
             measurement_action: MeasurementAction::Auto,
+            poor_signal_action: PoorSignalAction::Warn,
+            mains_frequency: MainsFrequency::Auto,
+            keep_measurements: KeepMeasurements::All,
+            measurement_eviction: MeasurementEviction::Uploaded,
+            device_token: heapless::String::new(),
+            server_key_pin: heapless::String::new(),
         }
     }
 }
@@ -70,8 +94,34 @@ impl Config {
     pub fn filter_strength(&self) -> FilterStrength {
         self.filter_strength
     }
+
+    /// Whether the device has been paired with the backend.
+    pub fn is_registered(&self) -> bool {
+        !self.device_token.is_empty()
+    }
+
+    /// The pinned key of the backend, or `None` if the backend is not verified.
+    pub fn server_key_pin(&self) -> Option<KeyPin> {
+        KeyPin::parse(&self.server_key_pin)
+    }
+
+    pub fn retention_policy(&self) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_newest: match self.keep_measurements {
+                KeepMeasurements::All => None,
+                KeepMeasurements::Newest10 => Some(10),
+                KeepMeasurements::Newest50 => Some(50),
+                KeepMeasurements::Newest100 => Some(100),
+            },
+            delete_pending: self.measurement_eviction == MeasurementEviction::Oldest,
+            reserved_bytes: RESERVED_SPACE,
+        }
+    }
 }
 
+/// Space kept free for the configuration and the measurement catalog, in bytes.
+const RESERVED_SPACE: usize = 16 * 1024;
+
 impl Loadable for Config {
     async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
         let data = Self {
@@ -81,6 +131,12 @@ impl Loadable for Config {
             filter_strength: FilterStrength::load(reader).await?,
             backend_url: heapless::String::load(reader).await?,
             measurement_action: MeasurementAction::load(reader).await?,
+            poor_signal_action: PoorSignalAction::load(reader).await?,
+            mains_frequency: MainsFrequency::load(reader).await?,
+            keep_measurements: KeepMeasurements::load(reader).await?,
+            measurement_eviction: MeasurementEviction::load(reader).await?,
+            device_token: heapless::String::load(reader).await?,
+            server_key_pin: heapless::String::load(reader).await?,
         };
 
         Ok(data)
@@ -97,6 +153,12 @@ impl Storable for Config {
         self.filter_strength.store(writer).await?;
         self.backend_url.store(writer).await?;
         self.measurement_action.store(writer).await?;
+        self.poor_signal_action.store(writer).await?;
+        self.mains_frequency.store(writer).await?;
+        self.keep_measurements.store(writer).await?;
+        self.measurement_eviction.store(writer).await?;
+        self.device_token.store(writer).await?;
+        self.server_key_pin.store(writer).await?;
 
         Ok(())
     }
@@ -87,3 +87,146 @@ impl Storable for MeasurementAction {
         writer.write_all(&[*self as u8]).await
     }
 }
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, SelectValue)]
+pub enum PoorSignalAction {
+    Warn = 0,
+    Discard = 1,
+}
+
+impl Loadable for PoorSignalAction {
+    async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        let data = match u8::load(reader).await? {
+            0 => Self::Warn,
+            1 => Self::Discard,
+            _ => return Err(LoadError::InvalidValue),
+        };
+
+        Ok(data)
+    }
+}
+
+impl Storable for PoorSignalAction {
+    async fn store<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        writer.write_all(&[*self as u8]).await
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MainsFrequency {
+    Auto = 0,
+    Hz50 = 1,
+    Hz60 = 2,
+}
+
+impl embedded_menu::items::menu_item::SelectValue for MainsFrequency {
+    fn next(&mut self) {
+        *self = match self {
+            Self::Auto => Self::Hz50,
+            Self::Hz50 => Self::Hz60,
+            Self::Hz60 => Self::Auto,
+        };
+    }
+
+    fn marker(&self) -> &'static str {
+        match self {
+            Self::Auto => "Auto",
+            Self::Hz50 => "50 Hz",
+            Self::Hz60 => "60 Hz",
+        }
+    }
+}
+
+impl Loadable for MainsFrequency {
+    async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        let data = match u8::load(reader).await? {
+            0 => Self::Auto,
+            1 => Self::Hz50,
+            2 => Self::Hz60,
+            _ => return Err(LoadError::InvalidValue),
+        };
+
+        Ok(data)
+    }
+}
+
+impl Storable for MainsFrequency {
+    async fn store<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        writer.write_all(&[*self as u8]).await
+    }
+}
+
+/// How many measurements to keep on the device.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum KeepMeasurements {
+    All = 0,
+    Newest10 = 1,
+    Newest50 = 2,
+    Newest100 = 3,
+}
+
+impl embedded_menu::items::menu_item::SelectValue for KeepMeasurements {
+    fn next(&mut self) {
+        *self = match self {
+            Self::All => Self::Newest10,
+            Self::Newest10 => Self::Newest50,
+            Self::Newest50 => Self::Newest100,
+            Self::Newest100 => Self::All,
+        };
+    }
+
+    fn marker(&self) -> &'static str {
+        match self {
+            Self::All => "All",
+            Self::Newest10 => "10",
+            Self::Newest50 => "50",
+            Self::Newest100 => "100",
+        }
+    }
+}
+
+impl Loadable for KeepMeasurements {
+    async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        let data = match u8::load(reader).await? {
+            0 => Self::All,
+            1 => Self::Newest10,
+            2 => Self::Newest50,
+            3 => Self::Newest100,
+            _ => return Err(LoadError::InvalidValue),
+        };
+
+        Ok(data)
+    }
+}
+
+impl Storable for KeepMeasurements {
+    async fn store<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        writer.write_all(&[*self as u8]).await
+    }
+}
+
+/// Which measurements may be deleted to make room for new ones. Uploaded measurements are always
+/// deleted first.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, SelectValue)]
+pub enum MeasurementEviction {
+    Uploaded = 0,
+    Oldest = 1,
+}
+
+impl Loadable for MeasurementEviction {
+    async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        let data = match u8::load(reader).await? {
+            0 => Self::Uploaded,
+            1 => Self::Oldest,
+            _ => return Err(LoadError::InvalidValue),
+        };
+
+        Ok(data)
+    }
+}
+
+impl Storable for MeasurementEviction {
+    async fn store<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        writer.write_all(&[*self as u8]).await
+    }
+}
@@ -0,0 +1,59 @@
+use config_site::data::network::WifiNetwork;
+use embedded_io_async::Read;
+use gui::widgets::battery_small::BatteryStyle;
+use norfs::storable::{LoadError, Loadable};
+
+use super::types::{
+    DisplayBrightness, FilterStrength, KeepMeasurements, MainsFrequency, MeasurementAction,
+    MeasurementEviction, PoorSignalAction,
+};
+
+#[derive(Clone)]
+pub struct Config {
+    pub battery_display_style: BatteryStyle,
+    pub display_brightness: DisplayBrightness,
+    pub known_networks: heapless::Vec<WifiNetwork, 8>,
+    pub filter_strength: FilterStrength,
+    pub backend_url: heapless::String<64>,
+    pub measurement_action: MeasurementAction,
+    pub poor_signal_action: PoorSignalAction,
+    pub mains_frequency: MainsFrequency,
+    pub keep_measurements: KeepMeasurements,
+    pub measurement_eviction: MeasurementEviction,
+}
+
+impl From<super::v7::Config> for Config {
+    fn from(value: super::v7::Config) -> Self {
+        Self {
+            battery_display_style: value.battery_display_style,
+            display_brightness: value.display_brightness,
+            known_networks: value.known_networks,
+            filter_strength: value.filter_strength,
+            backend_url: value.backend_url,
+            measurement_action: value.measurement_action,
+            poor_signal_action: value.poor_signal_action,
+            mains_frequency: value.mains_frequency,
+            keep_measurements: KeepMeasurements::All,
+            measurement_eviction: MeasurementEviction::Uploaded,
+        }
+    }
+}
+
+impl Loadable for Config {
+    async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        let data = Self {
+            battery_display_style: BatteryStyle::load(reader).await?,
+            display_brightness: DisplayBrightness::load(reader).await?,
+            known_networks: heapless::Vec::load(reader).await?,
+            filter_strength: FilterStrength::load(reader).await?,
+            backend_url: heapless::String::load(reader).await?,
+            measurement_action: MeasurementAction::load(reader).await?,
+            poor_signal_action: PoorSignalAction::load(reader).await?,
+            mains_frequency: MainsFrequency::load(reader).await?,
+            keep_measurements: KeepMeasurements::load(reader).await?,
+            measurement_eviction: MeasurementEviction::load(reader).await?,
+        };
+
+        Ok(data)
+    }
+}
@@ -5,6 +5,52 @@ use embedded_hal::digital::{InputPin, OutputPin};
 use embedded_hal_async::{digital::Wait, spi::SpiDevice as AsyncSpiDevice};
 use register_access::AsyncRegisterAccess;
 
+/// The data rate the ADC is configured for.
+pub const DATA_RATE: DataRate = DataRate::_1ksps;
+
+/// The number of samples per second at [`DATA_RATE`].
+pub const SAMPLE_RATE: f32 = match DATA_RATE {
+    DataRate::_125sps => 125.0,
+    DataRate::_250sps => 250.0,
+    DataRate::_500sps => 500.0,
+    DataRate::_1ksps => 1000.0,
+    DataRate::_2ksps => 2000.0,
+    DataRate::_4ksps => 4000.0,
+    DataRate::_8ksps => 8000.0,
+};
+
+/// The reference voltage the ADC is configured for.
+pub const REFERENCE_VOLTAGE: ReferenceVoltage = ReferenceVoltage::_2_42V;
+
+/// [`REFERENCE_VOLTAGE`] in volts.
+pub const REFERENCE_VOLTS: f32 = match REFERENCE_VOLTAGE {
+    ReferenceVoltage::_2_42V => 2.42,
+    ReferenceVoltage::_4_033V => 4.033,
+    ReferenceVoltage::External => panic!("the external reference voltage is not known"),
+};
+
+/// The gain of the ECG channel.
+pub const GAIN: Gain = Gain::X1;
+
+/// The amplification at [`GAIN`].
+pub const GAIN_FACTOR: u8 = match GAIN {
+    Gain::X1 => 1,
+    Gain::X2 => 2,
+    Gain::X3 => 3,
+    Gain::X4 => 4,
+    Gain::X6 => 6,
+    Gain::X8 => 8,
+    Gain::X12 => 12,
+};
+
+/// The input voltage at the full scale of the ADC.
+pub const FULL_SCALE_VOLTS: f32 = REFERENCE_VOLTS / GAIN_FACTOR as f32;
+
+const _: () = assert!(
+    Sample::VOLTS_PER_LSB == FULL_SCALE_VOLTS / (1 << 23) as f32,
+    "Sample::voltage doesn't match the ADC configuration"
+);
+
 pub struct Frontend<S, DRDY, RESET, CLKEN, TOUCH> {
     adc: Ads129x<S>,
     drdy: DRDY,
@@ -47,14 +93,14 @@ where
         ConfigRegisters {
             config1: Config1::new(|r| {
                 r
-                .data_rate().write(DataRate::_1ksps)
+                .data_rate().write(DATA_RATE)
                 .sampling().write(Sampling::Continuous)
             }),
 
             config2: Config2::new(|r| {
                 r
                 .pdb_loff_comp().write(Buffer::Enabled)
-                .ref_voltage().write(ReferenceVoltage::_2_42V)
+                .ref_voltage().write(REFERENCE_VOLTAGE)
                 .clock_pin().write(ClockPin::Disabled)
                 .test_signal().write(TestSignal::Disabled)
             }),
@@ -69,7 +115,7 @@ where
             ch1set: Ch1Set::new(|r| {
                 r
                 .enabled().write(Channel::Enabled)
-                .gain().write(Gain::X1)
+                .gain().write(GAIN)
                 .mux().write(Ch1Mux::Normal)
             }),
 
@@ -1,27 +1,38 @@
-use core::ops::{Deref, DerefMut};
+use core::{
+    num::NonZeroU32,
+    ops::{Deref, DerefMut},
+};
 
 use crate::{
     board::{
+        catalog::Catalog,
+        clock::Clock,
         config::Config,
         drivers::battery_monitor::BatteryMonitor,
         hal::clock::Clocks,
         storage::FileSystem,
+        upload_schedule::UploadSchedule,
         wifi::{ap::Ap, sta::Sta, WifiDriver},
         ChargerStatus, Display, EcgFrontend, VbusDetect,
     },
-    saved_measurement_exists,
     states::MESSAGE_MIN_DURATION,
+    uformat,
 };
 use display_interface::DisplayError;
 use embassy_executor::SendSpawner;
 use embassy_net::{Config as NetConfig, Ipv4Address, Ipv4Cidr, StaticConfigV4};
 use embassy_time::{Duration, Instant, Timer};
-use embedded_graphics::{pixelcolor::BinaryColor, prelude::DrawTarget, Drawable};
+use embedded_graphics::{
+    pixelcolor::BinaryColor,
+    prelude::{DrawTarget, DrawTargetExt, Point, Size},
+    primitives::Rectangle,
+    Drawable,
+};
 use gui::{
     screens::message::MessageScreen,
     widgets::{
-        battery_small::Battery, status_bar::StatusBar, wifi_access_point::WifiAccessPointStateView,
-        wifi_client::WifiClientStateView,
+        battery_small::Battery, clock::ClockView, progress_bar::ProgressBar, status_bar::StatusBar,
+        wifi_access_point::WifiAccessPointStateView, wifi_client::WifiClientStateView,
     },
 };
 use norfs::OnCollision;
@@ -38,9 +49,10 @@ pub struct InnerContext {
     pub high_prio_spawner: SendSpawner,
     pub battery_monitor: BatteryMonitor<VbusDetect, ChargerStatus>,
     pub wifi: &'static mut WifiDriver,
+    pub clock: Clock,
     pub config: &'static mut Config,
     pub config_changed: bool,
-    pub sta_work_available: Option<bool>,
+    pub upload_schedule: UploadSchedule,
     pub message_displayed_at: Option<Instant>,
 }
 
@@ -91,15 +103,24 @@ impl Context {
         // communication, so we can keep wifi on. Question is: when/how do we disable wifi if
         // it is in on-demand mode?
 
-        if self.inner.sta_work_available.is_none() {
+        if self.inner.upload_schedule.pending().is_none() {
             if let Some(storage) = self.storage.as_mut() {
-                if saved_measurement_exists(storage).await {
-                    self.inner.sta_work_available = Some(true);
+                if Catalog::load(storage).await.has_pending_uploads() {
+                    self.inner.upload_schedule.set_pending(true);
                 }
             }
         }
 
-        self.inner.sta_work_available.unwrap_or(false)
+        self.inner.upload_schedule.pending().unwrap_or(false)
+    }
+
+    /// Returns whether there are measurements that can be uploaded without the user, see
+    /// [`UploadSchedule`].
+    pub async fn can_upload_in_background(&mut self) -> bool {
+        !self.config.backend_url.is_empty()
+            && self.config.is_registered()
+            && !self.config.known_networks.is_empty()
+            && self.sta_has_work().await
     }
 
     pub async fn enable_wifi_sta(&mut self, mode: StaMode) -> Option<Sta> {
@@ -160,6 +181,26 @@ impl InnerContext {
             .await;
     }
 
+    /// Displays a message above a progress bar. `current` and `total` can be in any unit.
+    pub async fn display_progress(&mut self, message: &str, current: usize, total: usize) {
+        let percent = (current as u64 * 100 / total.max(1) as u64).min(100) as u32;
+        let label = uformat!(8, "{}%", percent);
+
+        self.with_status_bar(|display| {
+            // The progress bar is at the bottom of the screen.
+            let message_area = Rectangle::new(Point::zero(), Size::new(128, 51));
+            MessageScreen { message }.draw(&mut display.cropped(&message_area))?;
+
+            ProgressBar {
+                label: &label,
+                progress: percent,
+                max_progress: unwrap!(NonZeroU32::new(100)),
+            }
+            .draw(display)
+        })
+        .await;
+    }
+
     async fn enable_sta(&mut self, can_enable: bool) -> Option<Sta> {
         if !can_enable {
             warn!("Not enabling STA");
@@ -232,10 +273,6 @@ impl InnerContext {
             .unwrap_or(false)
     }
 
-    pub fn signal_sta_work_available(&mut self, available: bool) {
-        self.sta_work_available = Some(available);
-    }
-
     pub fn update_config(&mut self, cb: impl FnOnce(&mut Config)) {
         struct ConfigWriter<'a> {
             config: &'a mut Config,
@@ -272,6 +309,7 @@ impl InnerContext {
         let ap_connection_state = self.wifi.ap_state();
 
         StatusBar {
+            clock: ClockView::new(self.clock.time_of_day()),
             battery: Battery::with_style(battery_data, self.config.battery_style()),
             wifi_sta: WifiClientStateView::new(sta_connection_state),
             wifi_ap: WifiAccessPointStateView::new(ap_connection_state),
@@ -15,12 +15,15 @@
 )]
 pub mod hardware;
 
+pub mod catalog;
+pub mod clock;
 pub mod config;
 pub mod drivers;
 pub mod initialized;
 pub mod ota;
 pub mod startup;
 pub mod storage;
+pub mod upload_schedule;
 pub mod utils;
 pub mod wifi;
 
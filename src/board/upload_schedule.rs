@@ -0,0 +1,88 @@
+//! When to upload stored measurements in the background.
+//!
+//! While the device is charging, measurements that haven't been uploaded are uploaded without
+//! asking the user. After a failed attempt, the next one waits according to [`BACKOFF`]. The
+//! schedule is kept in RTC memory, so that it survives deep sleep, and the device can wake up
+//! for the next attempt. After a power loss, the next attempt is made right away.
+//!
+//! How many times each measurement failed to upload is saved in the catalog, so that a
+//! measurement that keeps failing doesn't hold back the others.
+
+use ::upload_schedule::{Backoff, SavedSchedule};
+use embassy_time::Duration;
+
+use crate::board::{clock::Clock, hal::macros::ram};
+
+/// Retry after 5 minutes at first, but at least every 6 hours.
+const BACKOFF: Backoff = Backoff {
+    first_delay: 5 * 60,
+    max_delay: 6 * 60 * 60,
+};
+
+#[ram(rtc_fast, uninitialized)]
+static mut SAVED_SCHEDULE: SavedSchedule = SavedSchedule::INVALID;
+
+fn load_schedule() -> SavedSchedule {
+    unsafe { SAVED_SCHEDULE }.validated()
+}
+
+fn save_schedule(schedule: SavedSchedule) {
+    unsafe { SAVED_SCHEDULE = schedule };
+}
+
+pub struct UploadSchedule {
+    /// Whether there are measurements to upload. `None` until the catalog has been checked.
+    pending: Option<bool>,
+}
+
+impl UploadSchedule {
+    pub const fn new() -> Self {
+        Self { pending: None }
+    }
+
+    pub fn pending(&self) -> Option<bool> {
+        self.pending
+    }
+
+    /// Records whether there are measurements to upload, e.g. after a new one has been stored.
+    pub fn set_pending(&mut self, pending: bool) {
+        self.pending = Some(pending);
+    }
+
+    /// Returns how long until the next background upload may start.
+    pub fn time_until_due(&self, clock: &Clock) -> Duration {
+        let wait_us = load_schedule().wait_us(&BACKOFF, clock.rtc_time_us());
+        Duration::from_micros(wait_us)
+    }
+
+    pub fn is_due(&self, clock: &Clock) -> bool {
+        self.time_until_due(clock) == Duration::MIN
+    }
+
+    /// Returns how long the device may sleep before waking up for the next upload. An upload that
+    /// is already due waits a little, so that the device doesn't wake up right after going to
+    /// sleep.
+    pub fn time_until_wakeup(&self, clock: &Clock) -> Duration {
+        const MIN_SLEEP: Duration = Duration::from_secs(60);
+
+        self.time_until_due(clock).max(MIN_SLEEP)
+    }
+
+    /// Records an attempt that uploaded every measurement it tried.
+    pub fn succeeded(&mut self, pending: bool) {
+        save_schedule(SavedSchedule::EMPTY);
+        self.pending = Some(pending);
+    }
+
+    /// Records a failed attempt, and delays the next one.
+    pub fn failed(&mut self, clock: &Clock) {
+        let schedule = load_schedule().failed(&BACKOFF, clock.rtc_time_us());
+        info!(
+            "Next upload attempt in {} seconds",
+            BACKOFF.delay(schedule.failures())
+        );
+
+        save_schedule(schedule);
+        self.pending = Some(true);
+    }
+}
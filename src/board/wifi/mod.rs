@@ -36,7 +36,8 @@ pub unsafe fn as_static_mut<T>(what: &mut T) -> &'static mut T {
     mem::transmute(what)
 }
 
-const STACK_SOCKET_COUNT: usize = 3;
+// HTTP, DNS and SNTP, with one to spare.
+const STACK_SOCKET_COUNT: usize = 4;
 
 struct StackWrapper<MODE: WifiDeviceMode>(
     NonNull<StackResources<STACK_SOCKET_COUNT>>,
@@ -81,7 +82,9 @@ impl<MODE: WifiDeviceMode> Drop for StackWrapper<MODE> {
 
 pub mod ap;
 pub mod ap_sta;
+pub mod sntp;
 pub mod sta;
+pub mod tls;
 
 pub struct WifiDriver {
     wifi: WIFI,
@@ -234,6 +237,11 @@ impl WifiDriver {
         }
     }
 
+    /// Returns a random number from the hardware RNG.
+    pub fn random(&mut self) -> u32 {
+        self.rng.random()
+    }
+
     pub fn ap_handle(&self) -> Option<&Ap> {
         match &self.state {
             WifiDriverState::Ap(ap) => Some(ap.handle()),
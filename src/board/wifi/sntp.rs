@@ -0,0 +1,118 @@
+//! A minimal SNTP client (RFC 4330).
+
+use embassy_net::{
+    dns::DnsQueryType,
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint,
+};
+use embassy_time::{with_timeout, Duration, Instant};
+use sntp_packet::PACKET_SIZE;
+
+use crate::board::wifi::sta::Sta;
+
+const NTP_SERVER: &str = "pool.ntp.org";
+const NTP_PORT: u16 = 123;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests are sent over UDP, so a lost request or response is retried.
+const ATTEMPTS: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SntpError {
+    Dns,
+    Network,
+    Timeout,
+    InvalidResponse,
+}
+
+impl Sta {
+    /// Asks an NTP server for the current time. Returns the time in microseconds since the Unix
+    /// epoch.
+    pub async fn sntp_time(&self) -> Result<u64, SntpError> {
+        let addresses = match with_timeout(
+            TIMEOUT,
+            self.sta_stack.dns_query(NTP_SERVER, DnsQueryType::A),
+        )
+        .await
+        {
+            Ok(Ok(addresses)) => addresses,
+            Ok(Err(e)) => {
+                warn!("Failed to resolve {}: {:?}", NTP_SERVER, e);
+                return Err(SntpError::Dns);
+            }
+            Err(_) => return Err(SntpError::Timeout),
+        };
+        let Some(address) = addresses.first() else {
+            return Err(SntpError::Dns);
+        };
+        let server = IpEndpoint::new(*address, NTP_PORT);
+
+        let mut rx_meta = [PacketMetadata::EMPTY; 1];
+        let mut rx_buffer = [0; PACKET_SIZE];
+        let mut tx_meta = [PacketMetadata::EMPTY; 1];
+        let mut tx_buffer = [0; PACKET_SIZE];
+        let mut socket = UdpSocket::new(
+            &**self.sta_stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        if let Err(e) = socket.bind(0) {
+            warn!("Failed to bind SNTP socket: {:?}", e);
+            return Err(SntpError::Network);
+        }
+
+        let mut attempt = 1;
+        loop {
+            match request_time(&mut socket, server).await {
+                Err(e @ (SntpError::Timeout | SntpError::InvalidResponse))
+                    if attempt < ATTEMPTS =>
+                {
+                    warn!("SNTP request failed: {:?}, retrying", e);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Sends a single SNTP request, and waits for the response.
+async fn request_time(socket: &mut UdpSocket<'_>, server: IpEndpoint) -> Result<u64, SntpError> {
+    // The server returns the transmit timestamp of the request as the originate timestamp of
+    // its response, which tells us that the response belongs to this request. The clock is not
+    // set, so the time since boot is used.
+    let sent_at = Instant::now();
+    let request = sntp_packet::request(sent_at.as_micros());
+
+    if let Err(e) = socket.send_to(&request, server).await {
+        warn!("Failed to send SNTP request: {:?}", e);
+        return Err(SntpError::Network);
+    }
+
+    let mut response = [0; PACKET_SIZE];
+    let len = loop {
+        match with_timeout(TIMEOUT, socket.recv_from(&mut response)).await {
+            Ok(Ok((len, from))) if from == server => break len,
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                warn!("Failed to receive SNTP response: {:?}", e);
+                return Err(SntpError::Network);
+            }
+            Err(_) => return Err(SntpError::Timeout),
+        }
+    };
+    let round_trip = sent_at.elapsed();
+
+    match sntp_packet::parse_response(&response[..len], &request) {
+        // The server sent its time about halfway through the round trip.
+        Ok(server_time) => Ok(server_time + round_trip.as_micros() / 2),
+        Err(e) => {
+            warn!("Invalid SNTP response: {:?}", e);
+            Err(SntpError::InvalidResponse)
+        }
+    }
+}
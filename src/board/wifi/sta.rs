@@ -1,10 +1,18 @@
-use core::{alloc::AllocError, ptr::addr_of, sync::atomic::Ordering};
+use core::{
+    alloc::AllocError,
+    ptr::{addr_of, addr_of_mut},
+    sync::atomic::Ordering,
+};
 
 use crate::{
     board::{
         hal::{peripherals::WIFI, Rng},
         initialized::Context,
-        wifi::{sta_net_task, StackWrapper},
+        wifi::{
+            sta_net_task,
+            tls::{Server, TlsBuffers, TlsConnector},
+            StackWrapper,
+        },
     },
     task_control::{TaskControlToken, TaskController},
     Shared,
@@ -34,7 +42,8 @@ use esp_wifi::{
 };
 use gui::widgets::wifi_client::WifiClientState;
 use macros as cardio;
-use reqwless::client::{HttpClient, TlsConfig, TlsVerify};
+use reqwless::client::HttpClient;
+use tls_pinning::KeyPin;
 
 pub(super) const SCAN_RESULTS: usize = 20;
 
@@ -170,6 +179,7 @@ impl Sta {
         }
 
         if self.connection_state() == WifiClientState::Connected {
+            context.clock.synchronize(self).await;
             true
         } else {
             debug!("No network connection");
@@ -177,17 +187,29 @@ impl Sta {
         }
     }
 
-    /// Allocates resources for an HTTPS capable [`HttpClient`].
-    pub fn https_client_resources(&self) -> Result<HttpsClientResources<'_>, AllocError> {
+    /// Allocates resources for an HTTPS capable [`HttpClient`] that connects to the server of
+    /// `url`. If `key_pin` is set, the server must present the pinned key.
+    pub fn https_client_resources(
+        &self,
+        url: &str,
+        key_pin: Option<KeyPin>,
+    ) -> Result<HttpsClientResources<'_>, HttpsClientError> {
+        let Some(server) = Server::new(url, key_pin) else {
+            warn!("Invalid server URL: {}", url);
+            return Err(HttpsClientError::InvalidUrl);
+        };
+
         // The client state must be heap allocated, because we take a reference to it.
-        let resources = Box::try_new(TlsClientState::EMPTY)?;
+        let mut resources = Box::try_new(TlsClientState::EMPTY)?;
         let client_state = unsafe { unwrap!(addr_of!(resources.tcp_state).as_ref()) };
+        let tls_buffers = addr_of_mut!(resources.tls_buffers);
+
+        let tcp_client = TcpClient::new(&self.sta_stack, client_state);
 
         Ok(HttpsClientResources {
-            resources,
-            tcp_client: TcpClient::new(&self.sta_stack, client_state),
+            _resources: resources,
+            connector: TlsConnector::new(tcp_client, tls_buffers, server, self.rng),
             dns_client: DnsSocket::new(&self.sta_stack),
-            rng: self.rng,
         })
     }
 
@@ -206,16 +228,13 @@ impl Sta {
     }
 }
 
-const SOCKET_COUNT: usize = 1;
-const SOCKET_TX_BUFFER: usize = 8 * 1024;
-const SOCKET_RX_BUFFER: usize = 16 * 1024;
-
-const TLS_READ_BUFFER: usize = 16 * 1024 + 256;
-const TLS_WRITE_BUFFER: usize = 4096;
+pub(super) const SOCKET_COUNT: usize = 1;
+pub(super) const SOCKET_TX_BUFFER: usize = 8 * 1024;
+pub(super) const SOCKET_RX_BUFFER: usize = 16 * 1024;
 
 type TcpClientState =
     embassy_net::tcp::client::TcpClientState<SOCKET_COUNT, SOCKET_TX_BUFFER, SOCKET_RX_BUFFER>;
-type TcpClient<'a> = embassy_net::tcp::client::TcpClient<
+pub(super) type TcpClient<'a> = embassy_net::tcp::client::TcpClient<
     'a,
     WifiDevice<'static, WifiStaDevice>,
     SOCKET_COUNT,
@@ -225,43 +244,44 @@ type TcpClient<'a> = embassy_net::tcp::client::TcpClient<
 
 struct TlsClientState {
     tcp_state: TcpClientState,
-    tls_read_buffer: [u8; TLS_READ_BUFFER], // must be 16K
-    tls_write_buffer: [u8; TLS_WRITE_BUFFER],
+    tls_buffers: TlsBuffers,
 }
 
 impl TlsClientState {
     pub const EMPTY: Self = Self {
         tcp_state: TcpClientState::new(),
-        tls_read_buffer: [0; TLS_READ_BUFFER],
-        tls_write_buffer: [0; TLS_WRITE_BUFFER],
+        tls_buffers: TlsBuffers::EMPTY,
     };
 }
 
+pub enum HttpsClientError {
+    InvalidUrl,
+    OutOfMemory,
+}
+
+impl From<AllocError> for HttpsClientError {
+    fn from(_: AllocError) -> Self {
+        Self::OutOfMemory
+    }
+}
+
 pub struct HttpsClientResources<'a> {
-    resources: Box<TlsClientState>,
-    tcp_client: TcpClient<'a>,
+    /// Referenced by the connector.
+    _resources: Box<TlsClientState>,
+    connector: TlsConnector<'a>,
     dns_client: DnsSocket<'a, WifiDevice<'static, WifiStaDevice>>,
-    rng: Rng,
 }
 
 impl<'a> HttpsClientResources<'a> {
     pub fn client(
         &mut self,
-    ) -> HttpClient<'_, TcpClient<'a>, DnsSocket<'a, WifiDevice<'static, WifiStaDevice>>> {
-        let upper = self.rng.random() as u64;
-        let lower = self.rng.random() as u64;
-        let seed = (upper << 32) | lower;
-
-        HttpClient::new_with_tls(
-            &self.tcp_client,
-            &self.dns_client,
-            TlsConfig::new(
-                seed,
-                &mut self.resources.tls_read_buffer,
-                &mut self.resources.tls_write_buffer,
-                TlsVerify::None,
-            ),
-        )
+    ) -> HttpClient<'_, TlsConnector<'a>, DnsSocket<'a, WifiDevice<'static, WifiStaDevice>>> {
+        HttpClient::new(&self.connector, &self.dns_client)
+    }
+
+    /// Whether the client talks to a server that has been verified by its pinned key.
+    pub fn is_verified(&self) -> bool {
+        self.connector.server().is_verified()
     }
 }
 
@@ -0,0 +1,209 @@
+//! HTTPS connections to the backend, verified by the pinned key of the server.
+//!
+//! `reqwless` can only open TLS connections without verifying the server, so the connector opens
+//! the TLS connection itself and hands it to `reqwless` as a plain connection.
+
+use embassy_net::tcp::{client::TcpConnection, Error as TcpError};
+use embedded_io::ErrorKind;
+use embedded_io_async::{ErrorType, Read, Write};
+use embedded_nal_async::{SocketAddr, TcpConnect};
+use embedded_tls::{
+    Aes128GcmSha256, TlsConfig, TlsConnection, TlsContext, TlsError, UnsecureProvider,
+};
+use rand_chacha::{rand_core::SeedableRng, ChaCha8Rng};
+use tls_pinning::{KeyPin, PinningProvider};
+
+use crate::board::{
+    hal::Rng,
+    wifi::sta::{TcpClient, SOCKET_COUNT, SOCKET_RX_BUFFER, SOCKET_TX_BUFFER},
+};
+
+const TLS_READ_BUFFER: usize = 16 * 1024 + 256;
+const TLS_WRITE_BUFFER: usize = 4096;
+
+// The connector shares one set of buffers between the connections of the TCP client.
+const _: () = assert!(SOCKET_COUNT == 1);
+
+pub(super) struct TlsBuffers {
+    read: [u8; TLS_READ_BUFFER], // must be 16K
+    write: [u8; TLS_WRITE_BUFFER],
+}
+
+impl TlsBuffers {
+    pub const EMPTY: Self = Self {
+        read: [0; TLS_READ_BUFFER],
+        write: [0; TLS_WRITE_BUFFER],
+    };
+}
+
+/// The server that the connector talks to.
+pub struct Server {
+    /// Whether the server is accessed over HTTPS.
+    tls: bool,
+    /// The host name, sent to the server so that it can pick its certificate.
+    name: heapless::String<64>,
+    key_pin: Option<KeyPin>,
+}
+
+impl Server {
+    /// Returns the server of `url`, or `None` if the URL is not valid.
+    pub fn new(url: &str, key_pin: Option<KeyPin>) -> Option<Self> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return None;
+        };
+
+        let name = rest.split([':', '/']).next().unwrap_or(rest);
+
+        Some(Self {
+            tls,
+            name: heapless::String::try_from(name).ok()?,
+            key_pin,
+        })
+    }
+
+    /// Whether the connection is encrypted and the server's key is checked.
+    pub fn is_verified(&self) -> bool {
+        self.tls && self.key_pin.is_some()
+    }
+}
+
+pub struct TlsConnector<'a> {
+    tcp_client: TcpClient<'a>,
+    /// Owned by `HttpsClientResources`. Only used by the open connection, and the TCP client can
+    /// only have one.
+    buffers: *mut TlsBuffers,
+    server: Server,
+    rng: Rng,
+}
+
+impl<'a> TlsConnector<'a> {
+    pub(super) fn new(
+        tcp_client: TcpClient<'a>,
+        buffers: *mut TlsBuffers,
+        server: Server,
+        rng: Rng,
+    ) -> Self {
+        Self {
+            tcp_client,
+            buffers,
+            server,
+            rng,
+        }
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    async fn open_tls<'m>(
+        &'m self,
+        connection: TcpConnection<'m, SOCKET_COUNT, SOCKET_TX_BUFFER, SOCKET_RX_BUFFER>,
+    ) -> Result<Connection<'m>, TlsError> {
+        let buffers = unsafe { &mut *self.buffers };
+
+        let mut connection = TlsConnection::new(connection, &mut buffers.read, &mut buffers.write);
+
+        let mut rng = self.rng;
+        let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+        let rng = ChaCha8Rng::seed_from_u64(seed);
+
+        let config = TlsConfig::new().with_server_name(&self.server.name);
+        match self.server.key_pin {
+            Some(pin) => {
+                let provider = PinningProvider::new(rng, pin);
+                connection.open(TlsContext::new(&config, provider)).await?
+            }
+            None => {
+                let provider = UnsecureProvider::new::<Aes128GcmSha256>(rng);
+                connection.open(TlsContext::new(&config, provider)).await?
+            }
+        }
+
+        Ok(Connection::Tls(connection))
+    }
+}
+
+impl TcpConnect for TlsConnector<'_> {
+    type Error = ConnectionError;
+    type Connection<'m>
+        = Connection<'m>
+    where
+        Self: 'm;
+
+    async fn connect<'m>(&'m self, remote: SocketAddr) -> Result<Connection<'m>, ConnectionError> {
+        let connection = self
+            .tcp_client
+            .connect(remote)
+            .await
+            .map_err(ConnectionError::Tcp)?;
+
+        if !self.server.tls {
+            return Ok(Connection::Plain(connection));
+        }
+
+        self.open_tls(connection).await.map_err(|e| {
+            warn!("TLS handshake failed: {:?}", e);
+            ConnectionError::Tls(e)
+        })
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConnectionError {
+    Tcp(TcpError),
+    Tls(TlsError),
+}
+
+impl embedded_io::Error for ConnectionError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Tcp(e) => e.kind(),
+            Self::Tls(e) => e.kind(),
+        }
+    }
+}
+
+pub enum Connection<'a> {
+    Plain(TcpConnection<'a, SOCKET_COUNT, SOCKET_TX_BUFFER, SOCKET_RX_BUFFER>),
+    Tls(
+        TlsConnection<
+            'a,
+            TcpConnection<'a, SOCKET_COUNT, SOCKET_TX_BUFFER, SOCKET_RX_BUFFER>,
+            Aes128GcmSha256,
+        >,
+    ),
+}
+
+impl ErrorType for Connection<'_> {
+    type Error = ConnectionError;
+}
+
+impl Read for Connection<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self {
+            Self::Plain(connection) => connection.read(buf).await.map_err(ConnectionError::Tcp),
+            Self::Tls(connection) => connection.read(buf).await.map_err(ConnectionError::Tls),
+        }
+    }
+}
+
+impl Write for Connection<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        match self {
+            Self::Plain(connection) => connection.write(buf).await.map_err(ConnectionError::Tcp),
+            Self::Tls(connection) => connection.write(buf).await.map_err(ConnectionError::Tls),
+        }
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        match self {
+            Self::Plain(connection) => connection.flush().await.map_err(ConnectionError::Tcp),
+            Self::Tls(connection) => connection.flush().await.map_err(ConnectionError::Tls),
+        }
+    }
+}
@@ -66,6 +66,81 @@ impl defmt::Format for Throughput {
     }
 }
 
+/// A length of time, in seconds.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Seconds(pub u32);
+
+impl uDisplay for Seconds {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        let (hours, minutes, seconds) = (self.0 / 3600, self.0 / 60 % 60, self.0 % 60);
+        if hours > 0 {
+            uwrite!(f, "{}h {}m", hours, minutes)
+        } else if minutes > 0 {
+            uwrite!(f, "{}m {}s", minutes, seconds)
+        } else {
+            uwrite!(f, "{}s", seconds)
+        }
+    }
+}
+
+/// Converts days since the Unix epoch to a (year, month, day) date.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    (year, month, day)
+}
+
+fn write_two_digits<W>(f: &mut ufmt::Formatter<'_, W>, value: u64) -> Result<(), W::Error>
+where
+    W: ufmt::uWrite + ?Sized,
+{
+    if value < 10 {
+        f.write_str("0")?;
+    }
+    uwrite!(f, "{}", value)
+}
+
+/// A point in time, in seconds since the Unix epoch, displayed in UTC. With `with_year` unset,
+/// only the month and the day of the date are shown.
+#[derive(Clone, Copy, PartialEq)]
+pub struct DateTime {
+    pub timestamp: u64,
+    pub with_year: bool,
+}
+
+impl uDisplay for DateTime {
+    fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: ufmt::uWrite + ?Sized,
+    {
+        let (year, month, day) = civil_from_days(self.timestamp / 86_400);
+        let seconds = self.timestamp % 86_400;
+
+        if self.with_year {
+            uwrite!(f, "{}-", year)?;
+        }
+        write_two_digits(f, month)?;
+        f.write_str("-")?;
+        write_two_digits(f, day)?;
+        f.write_str(" ")?;
+        write_two_digits(f, seconds / 3600)?;
+        f.write_str(":")?;
+        write_two_digits(f, seconds / 60 % 60)
+    }
+}
+
 #[allow(clippy::collapsible_else_if)]
 const fn digits(value: u32) -> usize {
     if value < 10_000 {
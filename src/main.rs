@@ -22,8 +22,7 @@ use embassy_sync::{
     mutex::{Mutex, MutexGuard},
 };
 use embassy_time::{Duration, Timer};
-use norfs::{medium::StorageMedium, Storage, StorageError};
-use signal_processing::compressing_buffer::CompressingBuffer;
+use norfs::{medium::StorageMedium, Storage};
 use static_cell::StaticCell;
 
 #[cfg(feature = "battery_max17055")]
@@ -31,6 +30,7 @@ pub use crate::states::menu::battery_info::battery_info_menu;
 
 use crate::{
     board::{
+        clock::Clock,
         config::{Config, ConfigFile},
         hal::{
             self,
@@ -43,17 +43,26 @@ use crate::{
         initialized::{Context, InnerContext},
         startup::StartupResources,
         storage::FileSystem,
+        upload_schedule::UploadSchedule,
     },
     states::{
         charging::charging,
         display_serial::display_serial,
         firmware_update::firmware_update,
+        holter::holter,
         init::initialize,
-        measure::{measure, ECG_BUFFER_SIZE},
+        measure::{measure, EcgRecording},
         menu::{
-            about::about_menu, display::display_menu, main::main_menu, storage::storage_menu,
-            wifi_ap::wifi_ap, wifi_sta::wifi_sta, AppMenu,
+            about::about_menu,
+            display::display_menu,
+            main::main_menu,
+            measurements::{measurement_menu, measurements_menu},
+            storage::storage_menu,
+            wifi_ap::wifi_ap,
+            wifi_sta::wifi_sta,
+            AppMenu,
         },
+        pairing::pairing,
         throughput::throughput,
         upload_or_store_measurement::{upload_or_store_measurement, upload_stored_measurements},
         MESSAGE_DURATION,
@@ -125,14 +134,16 @@ pub enum AppState {
     PreInitialize,
     Initialize,
     Measure,
+    Holter,
     Charging,
     Menu(AppMenu),
     DisplaySerial,
     FirmwareUpdate,
     Throughput,
+    Pairing,
     Shutdown,
     UploadStored(AppMenu),
-    UploadOrStore(Box<CompressingBuffer<ECG_BUFFER_SIZE>>),
+    UploadOrStore(EcgRecording),
 }
 
 static INT_EXECUTOR: InterruptExecutor<FromCpu1> = InterruptExecutor::new();
@@ -172,50 +183,6 @@ where
     CONFIG.init(Config::default())
 }
 
-async fn saved_measurement_exists<M>(storage: &mut Storage<M>) -> bool
-where
-    M: StorageMedium,
-    [(); M::BLOCK_COUNT]:,
-{
-    let mut dir = match storage.read_dir().await {
-        Ok(dir) => dir,
-        Err(e) => {
-            warn!("Failed to open directory: {:?}", e);
-            return false;
-        }
-    };
-
-    let mut buffer = [0; 64];
-    loop {
-        match dir.next(storage).await {
-            Ok(file) => {
-                let Some(file) = file else {
-                    return false;
-                };
-
-                match file.name(storage, &mut buffer).await {
-                    Ok(name) => {
-                        if name.starts_with("meas.") {
-                            return true;
-                        }
-                    }
-                    Err(StorageError::InsufficientBuffer) => {
-                        // not a measurement file, ignore
-                    }
-                    Err(e) => {
-                        warn!("Failed to read file name: {:?}", e);
-                        return false;
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("Failed to read directory: {:?}", e);
-                return false;
-            }
-        }
-    }
-}
-
 #[main]
 async fn main(_spawner: Spawner) {
     let resources = StartupResources::initialize().await;
@@ -248,9 +215,10 @@ async fn main(_spawner: Spawner) {
             high_prio_spawner: INT_EXECUTOR.start(Priority::Priority3),
             battery_monitor: resources.battery_monitor,
             wifi: resources.wifi,
+            clock: Clock::new(resources.rtc),
             config,
             config_changed: true,
-            sta_work_available: None,
+            upload_schedule: UploadSchedule::new(),
             message_displayed_at: None,
         },
     });
@@ -281,9 +249,12 @@ async fn main(_spawner: Spawner) {
             AppState::Initialize => initialize(&mut board).await,
             AppState::Charging => charging(&mut board).await,
             AppState::Measure => measure(&mut board).await,
+            AppState::Holter => holter(&mut board).await,
             AppState::Menu(AppMenu::Main) => main_menu(&mut board).await,
             AppState::Menu(AppMenu::Display) => display_menu(&mut board).await,
             AppState::Menu(AppMenu::Storage) => storage_menu(&mut board).await,
+            AppState::Menu(AppMenu::Measurements) => measurements_menu(&mut board).await,
+            AppState::Menu(AppMenu::Measurement(id)) => measurement_menu(&mut board, id).await,
             AppState::Menu(AppMenu::DeviceInfo) => about_menu(&mut board).await,
             AppState::Menu(AppMenu::WifiAP) => wifi_ap(&mut board).await,
             AppState::Menu(AppMenu::WifiListVisible) => wifi_sta(&mut board).await,
@@ -292,11 +263,12 @@ async fn main(_spawner: Spawner) {
             AppState::DisplaySerial => display_serial(&mut board).await,
             AppState::FirmwareUpdate => firmware_update(&mut board).await,
             AppState::Throughput => throughput(&mut board).await,
+            AppState::Pairing => pairing(&mut board).await,
             AppState::UploadStored(next_state) => {
                 upload_stored_measurements(&mut board, AppState::Menu(next_state)).await
             }
-            AppState::UploadOrStore(buffer) => {
-                upload_or_store_measurement(&mut board, buffer, AppState::Shutdown).await
+            AppState::UploadOrStore(recording) => {
+                upload_or_store_measurement(&mut board, recording, AppState::Shutdown).await
             }
             AppState::Shutdown => break,
         };
@@ -309,10 +281,21 @@ async fn main(_spawner: Spawner) {
     board.frontend.wait_for_release().await;
     Timer::after(Duration::from_millis(100)).await;
 
+    // Background uploads only run while charging.
+    let is_charging = board.battery_monitor.is_plugged();
+    let upload_wakeup = if is_charging && board.can_upload_in_background().await {
+        let sleep_time = board.upload_schedule.time_until_wakeup(&board.clock);
+        info!("Waking up for upload in {} s", sleep_time.as_secs());
+        Some(sleep::TimerWakeupSource::new(
+            core::time::Duration::from_micros(sleep_time.as_micros()),
+        ))
+    } else {
+        None
+    };
+
     let mut battery_monitor = board.inner.battery_monitor;
 
-    let mut rtc = resources.rtc;
-    let is_charging = battery_monitor.is_plugged();
+    let mut rtc = board.inner.clock.into_rtc();
 
     #[cfg(feature = "hw_v1")]
     let (_, mut charger_pin) = battery_monitor.stop().await;
@@ -325,7 +308,13 @@ async fn main(_spawner: Spawner) {
     let mut wakeup_pins = heapless::Vec::<(&mut dyn RtcWakeupPin, WakeupLevel), 2>::new();
     let wakeup_source =
         setup_wakeup_pins(&mut wakeup_pins, &mut touch, &mut charger_pin, is_charging);
-    rtc.sleep_deep(&[&wakeup_source], &mut delay);
+
+    let mut wakeup_sources = heapless::Vec::<&dyn sleep::WakeSource, 2>::new();
+    unwrap!(wakeup_sources.push(&wakeup_source).ok());
+    if let Some(upload_wakeup) = upload_wakeup.as_ref() {
+        unwrap!(wakeup_sources.push(upload_wakeup).ok());
+    }
+    rtc.sleep_deep(&wakeup_sources, &mut delay);
 
     // Shouldn't reach this. If we do, we just exit the task, which means the executor
     // will have nothing else to do. Not ideal, but again, we shouldn't reach this.
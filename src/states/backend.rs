@@ -0,0 +1,357 @@
+//! Credentials for talking to the backend, and sending requests to it.
+//!
+//! Requests are signed with the device token, see the `request_signing` crate. The token itself
+//! is never sent.
+
+use core::{cell::Cell, convert::Infallible};
+
+use embassy_time::{with_timeout, Duration};
+use embedded_io_async::{BufRead, ErrorType, Write};
+use embedded_nal_async::{Dns, TcpConnect};
+use request_signing::{
+    BodyHasher, Digest, HexDigest, SignedRequest, NONCE_HEADER, REQUEST_TIME_HEADER,
+    SIGNATURE_HEADER,
+};
+use reqwless::{
+    client::HttpClient,
+    request::{Method, RequestBody, RequestBuilder},
+    response::Status,
+};
+use tls_pinning::KeyPin;
+use ufmt::uwrite;
+
+use crate::{
+    board::{
+        clock::ClockReader,
+        initialized::InnerContext,
+        wifi::sta::{HttpsClientError, HttpsClientResources, Sta},
+    },
+    uformat,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The backend of a registered device.
+pub struct Backend {
+    url: heapless::String<64>,
+    /// `None` while pairing, when requests are not signed.
+    token: Option<heapless::String<64>>,
+    key_pin: Option<KeyPin>,
+    /// Requests are timestamped when they are signed.
+    clock: ClockReader,
+    next_nonce: Cell<u64>,
+}
+
+impl Backend {
+    /// Returns the configured backend, or `None` if the device has not been paired with it.
+    pub fn new(context: &mut InnerContext) -> Option<Self> {
+        if !context.config.is_registered() {
+            return None;
+        }
+
+        // A random start makes nonces unique across restarts.
+        let nonce = (context.wifi.random() as u64) << 32 | context.wifi.random() as u64;
+
+        Some(Self {
+            url: context.config.backend_url.clone(),
+            token: Some(context.config.device_token.clone()),
+            key_pin: context.config.server_key_pin(),
+            clock: context.clock.reader(),
+            next_nonce: Cell::new(nonce),
+        })
+    }
+
+    /// Returns the configured backend, for pairing with it. Requests are not signed.
+    pub fn for_pairing(context: &InnerContext) -> Self {
+        Self {
+            url: context.config.backend_url.clone(),
+            token: None,
+            key_pin: context.config.server_key_pin(),
+            clock: context.clock.reader(),
+            next_nonce: Cell::new(0),
+        }
+    }
+
+    /// Allocates an HTTP client for talking to the backend.
+    pub fn client_resources<'a>(
+        &self,
+        sta: &'a Sta,
+    ) -> Result<HttpsClientResources<'a>, HttpsClientError> {
+        sta.https_client_resources(&self.url, self.key_pin)
+    }
+
+    /// Returns the URL of an endpoint. `path` is the part after the backend URL.
+    fn url(&self, path: &str) -> Result<heapless::String<160>, ()> {
+        let mut url = heapless::String::new();
+        if uwrite!(&mut url, "{}{}", self.url.as_str(), path).is_err() {
+            warn!("URL too long");
+            return Err(());
+        }
+
+        Ok(url)
+    }
+
+    /// Sends a request, and passes the response to `sink`. Returns the status of the response.
+    pub async fn request<T, DNS, B, S>(
+        &self,
+        client: &mut HttpClient<'_, T, DNS>,
+        request: Request<'_, B>,
+        rx_buffer: &mut [u8],
+        sink: &mut S,
+    ) -> Result<Status, BackendError<S::Error>>
+    where
+        T: TcpConnect,
+        DNS: Dns,
+        B: RequestBody,
+        S: ResponseSink,
+    {
+        let Ok(url) = self.url(request.path) else {
+            return Err(BackendError::Internal);
+        };
+
+        let signature = match self.token.as_ref() {
+            Some(token) => {
+                // The backend rejects requests that are not timestamped.
+                let Some(time) = self.clock.now() else {
+                    warn!("Clock not set, not sending request");
+                    return Err(BackendError::TimeUnknown);
+                };
+                let method = request.method;
+                Some(self.sign(token, time, method, request.path, request.body_hash))
+            }
+            None => None,
+        };
+        let mut headers = heapless::Vec::<_, 6>::new();
+        if let Some(signature) = signature.as_ref() {
+            unwrap!(headers.extend_from_slice(&signature.headers()).ok());
+        }
+        if headers.extend_from_slice(request.headers).is_err() {
+            warn!("Too many headers");
+            return Err(BackendError::Internal);
+        }
+
+        debug!("{} {}", request.method.as_str(), url.as_str());
+
+        let mut handle =
+            match with_timeout(CONNECT_TIMEOUT, client.request(request.method, &url)).await {
+                Ok(Ok(handle)) => handle.headers(&headers).body(request.body),
+                Ok(Err(e)) => {
+                    warn!("HTTP connect error: {:?}", e);
+                    return Err(BackendError::Connect);
+                }
+                Err(_) => {
+                    warn!("Connect timeout");
+                    return Err(BackendError::ConnectTimeout);
+                }
+            };
+
+        let response = match with_timeout(request.timeout, handle.send(rx_buffer)).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => {
+                warn!("HTTP request error: {:?}", e);
+                return Err(BackendError::Request);
+            }
+            Err(_) => {
+                warn!("Request timeout");
+                return Err(BackendError::RequestTimeout);
+            }
+        };
+
+        let status = response.status;
+        let read_body = sink
+            .start(status, response.content_length)
+            .await
+            .map_err(BackendError::Sink)?;
+        if !read_body {
+            return Ok(status);
+        }
+
+        let mut reader = response.body().reader();
+        loop {
+            let data = match with_timeout(request.timeout, reader.fill_buf()).await {
+                Ok(Ok(&[])) => return Ok(status),
+                Ok(Ok(data)) => data,
+                Ok(Err(e)) => {
+                    warn!("HTTP read error: {:?}", e);
+                    return Err(BackendError::Read);
+                }
+                Err(_) => {
+                    warn!("Read timeout");
+                    return Err(BackendError::ReadTimeout);
+                }
+            };
+
+            sink.write(data).await.map_err(BackendError::Sink)?;
+
+            let len = data.len();
+            reader.consume(len);
+        }
+    }
+
+    fn sign(
+        &self,
+        token: &str,
+        time: u64,
+        method: Method,
+        path: &str,
+        body_hash: Digest,
+    ) -> RequestSignature {
+        let nonce = self.next_nonce.get();
+        self.next_nonce.set(nonce.wrapping_add(1));
+
+        let request = SignedRequest {
+            method: method.as_str(),
+            path,
+            time,
+            nonce,
+            body_hash,
+        };
+
+        RequestSignature {
+            time: uformat!(20, "{}", time),
+            nonce: uformat!(20, "{}", nonce),
+            signature: HexDigest::new(&request.signature(token.as_bytes())),
+        }
+    }
+}
+
+/// A request to the backend.
+pub struct Request<'a, B> {
+    pub method: Method,
+    /// The part of the URL after the backend URL.
+    pub path: &'a str,
+    /// Headers that are sent in addition to the signature, if there is one.
+    pub headers: &'a [(&'a str, &'a str)],
+    pub body: B,
+    /// Hash of `body`, see [`request_signing::body_hash`] and [`hash_body`].
+    pub body_hash: Digest,
+    /// Time limit for the response, and for each part of its body.
+    pub timeout: Duration,
+}
+
+impl<'a> Request<'a, ()> {
+    /// A request without a body.
+    pub fn new(method: Method, path: &'a str, timeout: Duration) -> Self {
+        Self {
+            method,
+            path,
+            headers: &[],
+            body: (),
+            body_hash: request_signing::body_hash(&[]),
+            timeout,
+        }
+    }
+}
+
+/// Why a request to the backend failed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BackendError<E> {
+    /// The URL or the headers don't fit into their buffers.
+    Internal,
+    /// The clock is not set, so the request can't be signed.
+    TimeUnknown,
+    Connect,
+    ConnectTimeout,
+    Request,
+    RequestTimeout,
+    /// The response body could not be read.
+    Read,
+    ReadTimeout,
+    /// The sink rejected the response.
+    Sink(E),
+}
+
+/// Receives the response to a request.
+pub trait ResponseSink {
+    type Error;
+
+    /// Called with the status and the length of the body, if the server sent it. Returns whether
+    /// the body should be read.
+    async fn start(
+        &mut self,
+        status: Status,
+        content_length: Option<usize>,
+    ) -> Result<bool, Self::Error>;
+
+    /// Receives the next part of the body.
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Doesn't read the body.
+pub struct IgnoreBody;
+
+impl ResponseSink for IgnoreBody {
+    type Error = Infallible;
+
+    async fn start(&mut self, _: Status, _: Option<usize>) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    async fn write(&mut self, _: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Keeps the body of a `200 OK` or `201 Created` response. Longer bodies are rejected.
+pub struct ResponseBuffer<const N: usize>(pub heapless::Vec<u8, N>);
+
+impl<const N: usize> ResponseBuffer<N> {
+    pub const fn new() -> Self {
+        Self(heapless::Vec::new())
+    }
+}
+
+impl<const N: usize> ResponseSink for ResponseBuffer<N> {
+    type Error = ();
+
+    async fn start(&mut self, status: Status, _: Option<usize>) -> Result<bool, Self::Error> {
+        Ok(matches!(status, Status::Ok | Status::Created))
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        if self.0.extend_from_slice(data).is_err() {
+            warn!("Response too long");
+            return Err(());
+        }
+
+        Ok(())
+    }
+}
+
+/// The signature headers of a request.
+struct RequestSignature {
+    time: heapless::String<20>,
+    nonce: heapless::String<20>,
+    signature: HexDigest,
+}
+
+impl RequestSignature {
+    fn headers(&self) -> [(&str, &str); 3] {
+        [
+            (REQUEST_TIME_HEADER, self.time.as_str()),
+            (NONCE_HEADER, self.nonce.as_str()),
+            (SIGNATURE_HEADER, self.signature.as_str()),
+        ]
+    }
+}
+
+/// Computes the hash of a request body that is written in parts, by writing it an extra time.
+pub async fn hash_body(body: &impl RequestBody) -> Digest {
+    struct HashWriter(BodyHasher);
+
+    impl ErrorType for HashWriter {
+        type Error = Infallible;
+    }
+
+    impl Write for HashWriter {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.update(buf);
+            Ok(buf.len())
+        }
+    }
+
+    let mut writer = HashWriter(BodyHasher::new());
+    unwrap!(body.write(&mut writer).await.ok());
+    writer.0.finalize()
+}
@@ -1,51 +1,198 @@
+//! The charging screen.
+//!
+//! While the device is charging, stored measurements are uploaded in the background, when the
+//! [`UploadSchedule`] allows it. The upload is stopped when the charger is unplugged, or when the
+//! screen is touched, because the user may need the radio for something else. The measurement
+//! being uploaded is finished first, so that the catalog is not left half written.
+//!
+//! [`UploadSchedule`]: crate::board::upload_schedule::UploadSchedule
+
 use crate::{
-    board::initialized::Context,
-    states::{menu::AppMenu, TouchInputShaper, MIN_FRAME_TIME, TARGET_FPS},
+    board::{
+        initialized::{Context, InnerContext, StaMode},
+        wifi::sta::Sta,
+        EcgFrontend,
+    },
+    states::{
+        backend::Backend, menu::AppMenu, upload_or_store_measurement::upload_pending,
+        TouchInputShaper, MIN_FRAME_TIME, TARGET_FPS,
+    },
     timeout::Timeout,
     AppState,
 };
-use embassy_time::{Duration, Ticker};
+use core::{cell::Cell, pin::pin};
+use embassy_futures::select::{select, Either};
+use embassy_time::{with_timeout, Duration, Ticker};
 use embedded_graphics::Drawable;
-use gui::screens::charging::ChargingScreen;
+use gui::{screens::charging::ChargingScreen, widgets::wifi_client::WifiClientState};
 
 pub async fn charging(context: &mut Context) -> AppState {
     const DISPLAY_TIME: Duration = Duration::from_secs(10);
 
-    let mut ticker = Ticker::every(MIN_FRAME_TIME);
-    let mut exit_timer = Timeout::new(DISPLAY_TIME);
+    let mut screen = Screen::new(context);
 
-    let mut charging_screen = ChargingScreen {
-        battery_data: context.battery_monitor.battery_data(),
-        is_charging: context.battery_monitor.is_charging(),
-        frames: 0,
-        fps: TARGET_FPS,
-        progress: 0,
-    };
+    if let Some(next_state) = upload_in_background(context, &mut screen).await {
+        return next_state;
+    }
+
+    screen
+        .show(&mut context.frontend, &mut context.inner, DISPLAY_TIME)
+        .await
+}
 
-    let mut input = TouchInputShaper::new();
-    while context.battery_monitor.is_plugged() && !exit_timer.is_elapsed() {
-        input.update(&mut context.frontend);
+struct Screen {
+    charging_screen: ChargingScreen,
+    input: TouchInputShaper,
+}
 
-        let is_touched = input.is_touched();
-        if is_touched {
-            exit_timer.reset();
+impl Screen {
+    fn new(context: &mut InnerContext) -> Self {
+        Self {
+            charging_screen: ChargingScreen {
+                battery_data: context.battery_monitor.battery_data(),
+                is_charging: context.battery_monitor.is_charging(),
+                frames: 0,
+                fps: TARGET_FPS,
+                progress: 0,
+            },
+            input: TouchInputShaper::new(),
         }
+    }
+
+    /// Draws the charging screen until it is touched, the charger is unplugged, or the screen
+    /// hasn't been touched for `display_time`.
+    async fn show(
+        &mut self,
+        frontend: &mut EcgFrontend,
+        context: &mut InnerContext,
+        display_time: Duration,
+    ) -> AppState {
+        let mut ticker = Ticker::every(MIN_FRAME_TIME);
+        let mut exit_timer = Timeout::new(display_time);
+
+        while context.battery_monitor.is_plugged() && !exit_timer.is_elapsed() {
+            self.input.update(frontend);
+
+            let is_touched = self.input.is_touched();
+            if is_touched {
+                exit_timer.reset();
+            }
 
-        if charging_screen.update_touched(input.is_touched()) {
-            return AppState::Menu(AppMenu::Main);
+            if self.charging_screen.update_touched(self.input.is_touched()) {
+                return AppState::Menu(AppMenu::Main);
+            }
+
+            self.charging_screen.is_charging = context.battery_monitor.is_charging();
+            self.charging_screen.battery_data = context.battery_monitor.battery_data();
+            self.charging_screen.frames += 1;
+
+            context
+                .display
+                .frame(|display| self.charging_screen.draw(display))
+                .await;
+
+            ticker.next().await;
         }
 
-        charging_screen.is_charging = context.battery_monitor.is_charging();
-        charging_screen.battery_data = context.battery_monitor.battery_data();
-        charging_screen.frames += 1;
+        AppState::Shutdown
+    }
+}
+
+/// Uploads the stored measurements, if an upload is due, while showing the charging screen.
+/// Returns the next state if the screen exits before the upload is finished.
+async fn upload_in_background(context: &mut Context, screen: &mut Screen) -> Option<AppState> {
+    if !context.can_upload_in_background().await || !context.upload_schedule.is_due(&context.clock)
+    {
+        return None;
+    }
+
+    let sta = context.enable_wifi_sta(StaMode::Enable).await?;
 
-        context
-            .display
-            .frame(|display| charging_screen.draw(display))
-            .await;
+    info!("Starting background upload");
+    let result = upload(context, screen, &sta).await;
 
-        ticker.next().await;
+    drop(sta);
+    context.disable_wifi().await;
+
+    let inner = &mut context.inner;
+    match result {
+        Either::First(Ok(())) => {
+            info!("Background upload finished");
+            inner.upload_schedule.succeeded(false);
+            None
+        }
+        Either::First(Err(())) => {
+            warn!("Background upload failed");
+            inner.upload_schedule.failed(&inner.clock);
+            None
+        }
+        Either::Second(next_state) => {
+            // Nothing has failed, the upload continues the next time.
+            info!("Background upload interrupted");
+            Some(next_state)
+        }
+    }
+}
+
+async fn upload(
+    context: &mut Context,
+    screen: &mut Screen,
+    sta: &Sta,
+) -> Either<Result<(), ()>, AppState> {
+    let connected = select(
+        wait_for_connection(sta),
+        screen.show(&mut context.frontend, &mut context.inner, Duration::MAX),
+    )
+    .await;
+    match connected {
+        Either::First(true) => {}
+        Either::First(false) => {
+            warn!("No network connection");
+            return Either::First(Err(()));
+        }
+        Either::Second(next_state) => return Either::Second(next_state),
     }
 
-    AppState::Shutdown
+    context.clock.synchronize(sta).await;
+
+    let backend = unwrap!(Backend::new(context));
+    let Ok(mut client_resources) = backend.client_resources(sta) else {
+        warn!("Failed to allocate HTTP client");
+        return Either::First(Err(()));
+    };
+    let mut client = client_resources.client();
+
+    // `can_upload_in_background` has checked that there are measurements in the storage.
+    let storage = unwrap!(context.storage.as_mut());
+
+    let stop = Cell::new(false);
+    let mut upload = pin!(upload_pending(&mut client, &backend, storage, &stop));
+    let shown = select(
+        upload.as_mut(),
+        screen.show(&mut context.frontend, &mut context.inner, Duration::MAX),
+    )
+    .await;
+
+    match shown {
+        Either::First(result) => Either::First(result),
+        Either::Second(next_state) => {
+            stop.set(true);
+            context.inner.display_message("Finishing upload...").await;
+            let _ = upload.await;
+            Either::Second(next_state)
+        }
+    }
+}
+
+/// Waits for the network connection without using the display.
+async fn wait_for_connection(sta: &Sta) -> bool {
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    let connected = async {
+        while sta.connection_state() != WifiClientState::Connected {
+            sta.wait_for_state_change().await;
+        }
+    };
+
+    with_timeout(CONNECT_TIMEOUT, connected).await.is_ok()
 }
@@ -0,0 +1,490 @@
+//! Resumable upload of large measurements.
+//!
+//! Sending a long recording in a single request often fails on a weak connection, and then the
+//! whole recording has to be sent again. Instead, large measurements are sent in chunks:
+//!
+//! 1. `POST {backend}/upload_session/{serial}` starts a session. `X-Upload-Size` is the length of
+//!    the measurement. The response body is the session id.
+//! 2. `PUT {backend}/upload_session/{serial}/{session}/{index}` sends a chunk. `X-Offset` is its
+//!    position in the measurement and `X-Crc32` is its CRC-32 checksum.
+//! 3. `POST {backend}/upload_session/{serial}/{session}/finish` completes the upload.
+//!
+//! Every request is signed, see [`Backend::request`].
+//!
+//! The upload is the same measurement that a single `upload_data` request would send. After each
+//! chunk, the progress is saved on flash, so an interrupted upload continues with the next chunk,
+//! even after a restart. Only one upload is in progress at a time.
+
+use core::str;
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use embassy_time::{Duration, Instant};
+use embedded_io_async::{Read, Write};
+use embedded_nal_async::{Dns, TcpConnect};
+use norfs::{
+    medium::StorageMedium,
+    read_dir::DirEntry,
+    storable::{LoadError, Loadable, Storable},
+    OnCollision, Storage, StorageError,
+};
+use request_signing::body_hash;
+use reqwless::{
+    client::HttpClient,
+    request::{Method, RequestBody},
+    response::Status,
+};
+use ufmt::uwrite;
+
+use crate::{
+    board::{
+        catalog::{measurement_file_name, skip},
+        initialized::InnerContext,
+    },
+    human_readable::BinarySize,
+    states::{
+        backend::{Backend, IgnoreBody, Request, ResponseBuffer},
+        upload_or_store_measurement::{buffer_with_capacity, UploadError},
+    },
+    uformat, SerialNumber,
+};
+
+/// Measurements larger than this are uploaded in chunks, in bytes.
+pub(super) const CHUNKED_UPLOAD_THRESHOLD: usize = 64 * 1024;
+
+/// Size of the uploaded chunks, in bytes. Every chunk is read into memory before it is sent.
+const UPLOAD_CHUNK_SIZE: usize = 8 * 1024;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+const PROGRESS_FILE: &str = "upload";
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// An upload that has been started, but not finished.
+#[derive(Clone, PartialEq, Debug)]
+struct UploadProgress {
+    /// The measurement being uploaded.
+    id: u32,
+    /// Length of the upload, in bytes. Used to detect that the measurement has been replaced.
+    size: u32,
+    session: heapless::String<32>,
+    /// The chunks before this one have been received by the server.
+    next_chunk: u32,
+}
+
+impl UploadProgress {
+    async fn load<M>(storage: &mut Storage<M>) -> Option<Self>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        let mut reader = storage.read(PROGRESS_FILE).await.ok()?;
+        match reader.read_loadable::<Self>(storage).await {
+            Ok(progress) => Some(progress),
+            Err(e) => {
+                warn!("Failed to read upload progress: {:?}", e);
+                None
+            }
+        }
+    }
+
+    async fn save<M>(&self, storage: &mut Storage<M>)
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        if let Err(e) = storage
+            .store_writer(PROGRESS_FILE, self, OnCollision::Overwrite)
+            .await
+        {
+            // The upload can continue, but it will start over if it is interrupted.
+            warn!("Failed to save upload progress: {:?}", e);
+        }
+    }
+
+    async fn clear<M>(storage: &mut Storage<M>)
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        match storage.delete(PROGRESS_FILE).await {
+            Ok(()) | Err(StorageError::NotFound) => {}
+            Err(e) => warn!("Failed to delete upload progress: {:?}", e),
+        }
+    }
+}
+
+impl Loadable for UploadProgress {
+    async fn load<R: Read>(reader: &mut R) -> Result<Self, LoadError<R::Error>> {
+        Ok(Self {
+            id: u32::load(reader).await?,
+            size: u32::load(reader).await?,
+            session: heapless::String::load(reader).await?,
+            next_chunk: u32::load(reader).await?,
+        })
+    }
+}
+
+impl Storable for UploadProgress {
+    async fn store<W: Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        self.id.store(writer).await?;
+        self.size.store(writer).await?;
+        self.session.store(writer).await?;
+        self.next_chunk.store(writer).await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum RequestError {
+    /// The server doesn't know the session. It has expired, or it has been completed already.
+    UnknownSession,
+    Failed,
+}
+
+/// A stored measurement to upload in chunks.
+pub(super) struct ChunkedUpload {
+    pub id: u32,
+    /// The format version of the file.
+    pub version: u8,
+    /// The length of the data following the format version.
+    pub size: usize,
+    /// Start of the measurement, in seconds since the Unix epoch. 0 if unknown.
+    pub timestamp: u64,
+    /// No chunks are sent after this time. The upload continues with the next chunk when it is
+    /// tried again.
+    pub deadline: Option<Instant>,
+}
+
+/// Uploads a stored measurement in chunks, continuing a previous upload if there is one.
+pub(super) async fn upload_chunked<T, DNS, M>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    upload: ChunkedUpload,
+    storage: &mut Storage<M>,
+    mut display: Option<&mut InnerContext>,
+) -> Result<(), UploadError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    let ChunkedUpload {
+        id,
+        version,
+        size,
+        timestamp,
+        deadline,
+    } = upload;
+
+    // The format version is uploaded as 4 bytes.
+    let total = size + 4;
+    let chunk_count = total.div_ceil(UPLOAD_CHUNK_SIZE) as u32;
+
+    let progress = UploadProgress::load(storage).await.filter(|progress| {
+        progress.id == id && progress.size == total as u32 && progress.next_chunk <= chunk_count
+    });
+    let mut progress = match progress {
+        Some(progress) => {
+            info!(
+                "Resuming upload of {} at chunk {}/{}",
+                id, progress.next_chunk, chunk_count
+            );
+            progress
+        }
+        None => {
+            let session = start_session(client, backend, total, timestamp)
+                .await
+                .map_err(|_| UploadError::Send)?;
+            let progress = UploadProgress {
+                id,
+                size: total as u32,
+                session,
+                next_chunk: 0,
+            };
+            progress.save(storage).await;
+            progress
+        }
+    };
+
+    let Ok(mut buffer) = buffer_with_capacity(UPLOAD_CHUNK_SIZE, 0u8) else {
+        warn!("Failed to allocate {} bytes", UPLOAD_CHUNK_SIZE);
+        return Err(UploadError::Send);
+    };
+
+    let offset = progress.next_chunk as usize * UPLOAD_CHUNK_SIZE;
+    let mut source = match ChunkSource::open(storage, id, version, offset).await {
+        Ok(source) => source,
+        Err(e) => {
+            warn!("Failed to open {}: {:?}", id, e);
+            return Err(UploadError::Load);
+        }
+    };
+
+    while progress.next_chunk < chunk_count {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            debug!(
+                "Pausing upload of {} at chunk {}/{}",
+                id, progress.next_chunk, chunk_count
+            );
+            return Err(UploadError::Paused);
+        }
+
+        let offset = progress.next_chunk as usize * UPLOAD_CHUNK_SIZE;
+        let chunk = &mut buffer[..UPLOAD_CHUNK_SIZE.min(total - offset)];
+
+        if let Some(display) = display.as_deref_mut() {
+            let message = uformat!(32, "Uploading measurement: {}", BinarySize(total));
+            display.display_progress(&message, offset, total).await;
+        }
+
+        if let Err(e) = source.read(storage, chunk).await {
+            warn!("Failed to read {}: {:?}", id, e);
+            return Err(UploadError::Load);
+        }
+
+        let sent = send_chunk(
+            client,
+            backend,
+            &progress.session,
+            progress.next_chunk,
+            offset,
+            chunk,
+        )
+        .await;
+        match sent {
+            Ok(()) => {}
+            Err(RequestError::UnknownSession) => {
+                // The next attempt starts a new session.
+                UploadProgress::clear(storage).await;
+                return Err(UploadError::Send);
+            }
+            Err(RequestError::Failed) => return Err(UploadError::Send),
+        }
+
+        progress.next_chunk += 1;
+        progress.save(storage).await;
+    }
+
+    let finished = finish_session(client, backend, &progress.session).await;
+    if finished != Err(RequestError::Failed) {
+        // Either the upload is complete, or it has to start over.
+        UploadProgress::clear(storage).await;
+    }
+
+    finished.map_err(|_| UploadError::Send)
+}
+
+fn session_path(session: &str, suffix: &str) -> Result<heapless::String<96>, ()> {
+    let mut path = heapless::String::new();
+    if uwrite!(
+        &mut path,
+        "/upload_session/{}/{}{}",
+        SerialNumber,
+        session,
+        suffix
+    )
+    .is_err()
+    {
+        warn!("URL too long");
+        return Err(());
+    }
+
+    Ok(path)
+}
+
+async fn start_session<T, DNS>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    total: usize,
+    timestamp: u64,
+) -> Result<heapless::String<32>, ()>
+where
+    T: TcpConnect,
+    DNS: Dns,
+{
+    let path = uformat!(48, "/upload_session/{}", SerialNumber);
+    let timestamp = uformat!(24, "{}", timestamp);
+    let size = uformat!(16, "{}", total);
+    let headers = [
+        ("X-Timestamp", timestamp.as_str()),
+        ("X-Upload-Size", size.as_str()),
+    ];
+    let request = Request {
+        headers: &headers,
+        ..Request::new(Method::POST, &path, REQUEST_TIMEOUT)
+    };
+
+    let mut rx_buffer = [0; 512];
+    let mut body = ResponseBuffer::<64>::new();
+    match backend
+        .request(client, request, &mut rx_buffer, &mut body)
+        .await
+    {
+        Ok(Status::Ok | Status::Created) => {}
+        Ok(status) => {
+            warn!("Failed to start upload session: {:?}", status);
+            return Err(());
+        }
+        Err(_) => return Err(()),
+    }
+
+    let session = str::from_utf8(&body.0).map(str::trim).unwrap_or("");
+    let valid = !session.is_empty()
+        && session
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_');
+    match heapless::String::try_from(session) {
+        Ok(session) if valid => Ok(session),
+        _ => {
+            warn!("Invalid upload session id");
+            Err(())
+        }
+    }
+}
+
+async fn send_chunk<T, DNS>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    session: &str,
+    index: u32,
+    offset: usize,
+    chunk: &[u8],
+) -> Result<(), RequestError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+{
+    let Ok(path) = session_path(session, &uformat!(12, "/{}", index)) else {
+        return Err(RequestError::Failed);
+    };
+
+    let offset = uformat!(16, "{}", offset);
+    let crc = uformat!(16, "{}", CRC.checksum(chunk));
+    let headers = [("X-Offset", offset.as_str()), ("X-Crc32", crc.as_str())];
+
+    debug!("Uploading chunk {}", index);
+
+    let request = Request {
+        method: Method::PUT,
+        path: &path,
+        headers: &headers,
+        body: chunk,
+        body_hash: body_hash(chunk),
+        timeout: REQUEST_TIMEOUT,
+    };
+    send_request(client, backend, request).await
+}
+
+async fn finish_session<T, DNS>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    session: &str,
+) -> Result<(), RequestError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+{
+    let Ok(path) = session_path(session, "/finish") else {
+        return Err(RequestError::Failed);
+    };
+
+    debug!("Finishing upload");
+
+    let request = Request::new(Method::POST, &path, REQUEST_TIMEOUT);
+    send_request(client, backend, request).await
+}
+
+async fn send_request<T, DNS, B>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    request: Request<'_, B>,
+) -> Result<(), RequestError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+    B: RequestBody,
+{
+    let mut rx_buffer = [0; 512];
+    match backend
+        .request(client, request, &mut rx_buffer, &mut IgnoreBody)
+        .await
+    {
+        Ok(Status::Ok | Status::Created | Status::NoContent) => Ok(()),
+        Ok(Status::NotFound) => {
+            warn!("Upload session not found");
+            Err(RequestError::UnknownSession)
+        }
+        Ok(status) => {
+            warn!("HTTP upload failed: {:?}", status);
+            Err(RequestError::Failed)
+        }
+        Err(_) => Err(RequestError::Failed),
+    }
+}
+
+/// Reads a measurement file the way it is uploaded: the format version is sent as 4 bytes.
+struct ChunkSource<M>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    file: Option<DirEntry<M>>,
+    version: u8,
+    /// Position in the upload, in bytes.
+    position: usize,
+}
+
+impl<M> ChunkSource<M>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    async fn open(
+        storage: &mut Storage<M>,
+        id: u32,
+        version: u8,
+        position: usize,
+    ) -> Result<Self, StorageError> {
+        let file = DirEntry::from_reader(storage.read(&measurement_file_name(id)).await?);
+        // The file starts with a single byte format version.
+        let file = skip(file, storage, 1 + position.saturating_sub(4)).await?;
+
+        Ok(Self {
+            file: Some(file),
+            version,
+            position,
+        })
+    }
+
+    async fn read(
+        &mut self,
+        storage: &mut Storage<M>,
+        buffer: &mut [u8],
+    ) -> Result<(), StorageError> {
+        let mut buffer = buffer;
+
+        if self.position < 4 {
+            let version = (self.version as u32).to_le_bytes();
+            let len = (4 - self.position).min(buffer.len());
+            buffer[..len].copy_from_slice(&version[self.position..self.position + len]);
+
+            self.position += len;
+            buffer = &mut buffer[len..];
+        }
+
+        let Some(file) = self.file.take() else {
+            return Err(StorageError::NotFound);
+        };
+        let mut reader = file.open();
+        reader.read_all(storage, buffer).await?;
+        self.file = Some(DirEntry::from_reader(reader));
+
+        self.position += buffer.len();
+
+        Ok(())
+    }
+}
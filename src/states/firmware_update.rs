@@ -1,8 +1,7 @@
 use core::cell::Cell;
 
 use embassy_futures::select::{select, Either};
-use embassy_time::{with_timeout, Duration, Instant, Timer};
-use embedded_io_async::BufRead;
+use embassy_time::{Duration, Instant, Timer};
 use reqwless::{request::Method, response::Status};
 use ufmt::uwrite;
 
@@ -12,18 +11,23 @@ use crate::{
         ota::{Ota0Partition, Ota1Partition, OtaClient, OtaDataPartition},
     },
     human_readable::{BinarySize, Throughput},
-    states::menu::AppMenu,
+    states::{
+        backend::{Backend, BackendError, Request, ResponseSink},
+        menu::AppMenu,
+    },
     AppState, SerialNumber,
 };
 
-const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
 const READ_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Clone, Copy, PartialEq)]
 enum UpdateError {
+    NotRegistered,
     WifiNotEnabled,
     WifiNotConnected,
     InternalError,
+    TimeUnknown,
+    ServerNotVerified,
     HttpConnectionFailed,
     HttpConnectionTimeout,
     HttpRequestTimeout,
@@ -49,9 +53,12 @@ pub async fn firmware_update(context: &mut Context) -> AppState {
         UpdateResult::Success => "Update complete",
         UpdateResult::AlreadyUpToDate => "Already up to date",
         UpdateResult::Failed(e) => match e {
+            UpdateError::NotRegistered => "Device not registered",
             UpdateError::WifiNotEnabled => "WiFi not enabled",
             UpdateError::WifiNotConnected => "Could not connect to WiFi",
             UpdateError::InternalError => "Update failed: internal error",
+            UpdateError::TimeUnknown => "Update failed: time unknown",
+            UpdateError::ServerNotVerified => "Update server not verified",
             UpdateError::HttpConnectionFailed => "Failed to connect to update server",
             UpdateError::HttpConnectionTimeout => "Connection to update server timed out",
             UpdateError::HttpRequestTimeout => "Update request timed out",
@@ -74,6 +81,10 @@ pub async fn firmware_update(context: &mut Context) -> AppState {
 }
 
 async fn do_update(context: &mut Context) -> UpdateResult {
+    if !context.config.is_registered() {
+        return UpdateResult::Failed(UpdateError::NotRegistered);
+    }
+
     let sta = if let Some(sta) = context.enable_wifi_sta(StaMode::Enable).await {
         if sta.wait_for_connection(context).await {
             sta
@@ -84,18 +95,24 @@ async fn do_update(context: &mut Context) -> UpdateResult {
         return UpdateResult::Failed(UpdateError::WifiNotEnabled);
     };
 
+    let backend = unwrap!(Backend::new(context));
+
     context.display_message("Looking for updates").await;
 
-    let Ok(mut client_resources) = sta.https_client_resources() else {
+    let Ok(mut client_resources) = backend.client_resources(&sta) else {
         return UpdateResult::Failed(UpdateError::InternalError);
     };
+    // Firmware is only installed from a server that we know is ours.
+    if !client_resources.is_verified() {
+        warn!("Refusing to update over an unverified connection");
+        return UpdateResult::Failed(UpdateError::ServerNotVerified);
+    }
     let mut client = client_resources.client();
 
-    let mut url = heapless::String::<128>::new();
+    let mut path = heapless::String::<96>::new();
     if uwrite!(
-        &mut url,
-        "{}/firmware/{}/{}/{}",
-        context.config.backend_url.as_str(),
+        &mut path,
+        "/firmware/{}/{}/{}",
         env!("HW_VERSION"),
         SerialNumber,
         env!("COMMIT_HASH")
@@ -106,37 +123,7 @@ async fn do_update(context: &mut Context) -> UpdateResult {
         return UpdateResult::Failed(UpdateError::InternalError);
     }
 
-    debug!("Looking for update at {}", url.as_str());
-
-    let mut request = match with_timeout(CONNECT_TIMEOUT, client.request(Method::GET, &url)).await {
-        Ok(Ok(request)) => request,
-        Ok(Err(e)) => {
-            warn!("HTTP connect error: {:?}", e);
-            return UpdateResult::Failed(UpdateError::HttpConnectionFailed);
-        }
-        Err(_) => return UpdateResult::Failed(UpdateError::HttpConnectionTimeout),
-    };
-
-    let mut rx_buffer = [0; 4096];
-    let result = match with_timeout(READ_TIMEOUT, request.send(&mut rx_buffer)).await {
-        Ok(result) => result,
-        _ => return UpdateResult::Failed(UpdateError::HttpRequestTimeout),
-    };
-
-    let response = match result {
-        Ok(response) => match response.status {
-            Status::Ok => response,
-            Status::NotModified => return UpdateResult::AlreadyUpToDate,
-            _ => {
-                warn!("HTTP response error: {:?}", response.status);
-                return UpdateResult::Failed(UpdateError::HttpRequestFailed);
-            }
-        },
-        Err(e) => {
-            warn!("HTTP response error: {:?}", e);
-            return UpdateResult::Failed(UpdateError::HttpRequestFailed);
-        }
-    };
+    debug!("Looking for update at {}", path.as_str());
 
     let mut ota = match OtaClient::initialize(OtaDataPartition, Ota0Partition, Ota1Partition).await
     {
@@ -147,61 +134,45 @@ async fn do_update(context: &mut Context) -> UpdateResult {
         }
     };
 
-    let size = response.content_length;
-    print_progress(context, 0, size, None).await;
-
-    if let Err(e) = ota.erase().await {
-        warn!("Failed to erase OTA: {:?}", e);
-        return UpdateResult::Failed(UpdateError::EraseFailed);
+    let started = Cell::new(None);
+    let size = Cell::new(None);
+    let received_since = Cell::new(0);
+    let mut sink = UpdateSink {
+        ota: &mut ota,
+        started: &started,
+        size: &size,
+        received: &received_since,
     };
 
-    let mut reader = response.body().reader();
-
-    let started = Instant::now();
-    let received_since = Cell::new(0);
+    let request = Request::new(Method::GET, &path, READ_TIMEOUT);
+    let mut rx_buffer = [0; 4096];
     let mut received_total = 0;
     let result = select(
-        async {
-            loop {
-                let received_buffer = match with_timeout(READ_TIMEOUT, reader.fill_buf()).await {
-                    Ok(result) => match result {
-                        Ok(&[]) => break None,
-                        Ok(read) => read,
-                        Err(e) => {
-                            warn!("HTTP read error: {:?}", e);
-                            break Some(UpdateError::DownloadFailed);
-                        }
-                    },
-                    _ => break Some(UpdateError::DownloadTimeout),
-                };
-
-                if let Err(e) = ota.write(received_buffer).await {
-                    warn!("Failed to write OTA: {:?}", e);
-                    break Some(UpdateError::WriteError);
-                }
-
-                let received_len = received_buffer.len();
-                received_since.set(received_since.get() + received_len);
-                reader.consume(received_len);
-            }
-        },
+        backend.request(&mut client, request, &mut rx_buffer, &mut sink),
         async {
             loop {
                 Timer::after(Duration::from_millis(500)).await;
+                let Some(started) = started.get() else {
+                    continue;
+                };
                 let received = received_since.take();
                 received_total += received;
 
                 let avg_speed = Throughput(received_total, started.elapsed());
 
-                print_progress(context, received_total, size, Some(avg_speed)).await;
+                print_progress(context, received_total, size.get(), Some(avg_speed)).await;
             }
         },
     )
     .await;
 
+    let result = match result {
+        Either::First(result) => result,
+        Either::Second(_) => unreachable!(),
+    };
+
     match result {
-        Either::First(Some(error)) => UpdateResult::Failed(error),
-        Either::First(None) => {
+        Ok(Status::Ok) => {
             if let Err(e) = ota.activate().await {
                 warn!("Failed to activate OTA: {:?}", e);
                 UpdateResult::Failed(UpdateError::ActivateFailed)
@@ -209,7 +180,67 @@ async fn do_update(context: &mut Context) -> UpdateResult {
                 UpdateResult::Success
             }
         }
-        Either::Second(_) => unreachable!(),
+        Ok(Status::NotModified) => UpdateResult::AlreadyUpToDate,
+        Ok(status) => {
+            warn!("HTTP response error: {:?}", status);
+            UpdateResult::Failed(UpdateError::HttpRequestFailed)
+        }
+        Err(e) => UpdateResult::Failed(match e {
+            BackendError::Internal => UpdateError::InternalError,
+            BackendError::TimeUnknown => UpdateError::TimeUnknown,
+            BackendError::Connect => UpdateError::HttpConnectionFailed,
+            BackendError::ConnectTimeout => UpdateError::HttpConnectionTimeout,
+            BackendError::Request => UpdateError::HttpRequestFailed,
+            BackendError::RequestTimeout => UpdateError::HttpRequestTimeout,
+            BackendError::Read => UpdateError::DownloadFailed,
+            BackendError::ReadTimeout => UpdateError::DownloadTimeout,
+            BackendError::Sink(e) => e,
+        }),
+    }
+}
+
+/// Writes the update into the inactive partition.
+struct UpdateSink<'a> {
+    ota: &'a mut OtaClient<OtaDataPartition, Ota0Partition, Ota1Partition>,
+    /// Set when the download starts.
+    started: &'a Cell<Option<Instant>>,
+    size: &'a Cell<Option<usize>>,
+    /// Bytes received since the progress was last displayed.
+    received: &'a Cell<usize>,
+}
+
+impl ResponseSink for UpdateSink<'_> {
+    type Error = UpdateError;
+
+    async fn start(
+        &mut self,
+        status: Status,
+        content_length: Option<usize>,
+    ) -> Result<bool, Self::Error> {
+        if status != Status::Ok {
+            return Ok(false);
+        }
+
+        if let Err(e) = self.ota.erase().await {
+            warn!("Failed to erase OTA: {:?}", e);
+            return Err(UpdateError::EraseFailed);
+        }
+
+        self.size.set(content_length);
+        self.started.set(Some(Instant::now()));
+
+        Ok(true)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        if let Err(e) = self.ota.write(data).await {
+            warn!("Failed to write OTA: {:?}", e);
+            return Err(UpdateError::WriteError);
+        }
+
+        self.received.set(self.received.get() + data.len());
+
+        Ok(())
     }
 }
 
@@ -0,0 +1,226 @@
+//! Long-term recording with the display turned off.
+//!
+//! The recording is split into segments of about 5 minutes. Each segment is written into its own
+//! measurement file while recording, so a recording that ends unexpectedly only loses its last
+//! segment. If a network is available, completed segments are uploaded in chunks between
+//! segments. Each break only leaves time for a part of a segment, so the upload continues in the
+//! next break, and whatever is left is uploaded after the recording.
+
+use embassy_futures::join::join;
+use embassy_time::{with_timeout, Duration, Instant};
+use gui::widgets::wifi_client::WifiClientState;
+use norfs::{medium::StorageMedium, Storage, StorageError};
+use signal_processing::retention::RetentionPolicy;
+
+use crate::{
+    board::{
+        catalog::{measurement_file_name, Catalog, MeasurementInfo},
+        initialized::{Context, StaMode},
+        wifi::sta::Sta,
+    },
+    states::{
+        backend::Backend,
+        measure::{create_ecg_objects, holter_impl, SegmentInfoQueue, SEGMENT_SIZE},
+        menu::AppMenu,
+        recorder::{RecordingPipe, MAX_BUFFERS},
+        upload_or_store_measurement::upload_one_stored,
+        MESSAGE_DURATION,
+    },
+    AppState,
+};
+
+/// While uploading, the samples are kept in the recording buffers. All of them hold about 27
+/// seconds of samples, so uploads are cancelled after this time.
+const UPLOAD_TIME_LIMIT: Duration = Duration::from_secs(20);
+
+/// New chunks of a segment are only sent during this time, so that the last one usually finishes
+/// before [`UPLOAD_TIME_LIMIT`].
+const UPLOAD_CHUNK_TIME: Duration = Duration::from_secs(15);
+
+pub async fn holter(context: &mut Context) -> AppState {
+    let policy = context.config.retention_policy();
+    let Some(storage) = context.storage.as_mut() else {
+        context.display_message("Storage not available").await;
+        return AppState::Menu(AppMenu::Main);
+    };
+
+    // Measurements are deleted as the segments are stored.
+    let catalog = Catalog::load(storage).await;
+    if catalog.available_bytes(storage, &policy) < SEGMENT_SIZE {
+        context.display_message("Storage full").await;
+        return AppState::Menu(AppMenu::Main);
+    }
+
+    let Ok(pipe) = RecordingPipe::new(MAX_BUFFERS) else {
+        context.display_message("Out of memory").await;
+        return AppState::Menu(AppMenu::Main);
+    };
+
+    let uploader = if context.config.backend_url.is_empty() {
+        None
+    } else if context.config.is_registered() {
+        connect_uploader(context).await
+    } else {
+        // Segments are only stored, they can be uploaded after pairing.
+        context.display_message("Device not registered").await;
+        context.wait_for_message(MESSAGE_DURATION).await;
+        None
+    };
+
+    context.display_message("Holter recording").await;
+    context.wait_for_message(MESSAGE_DURATION).await;
+    context.display.shut_down();
+
+    let mut ecg = create_ecg_objects(context);
+    let session = context.wifi.random();
+    let segments = SegmentInfoQueue::new();
+
+    let frontend = unsafe { core::ptr::read(&context.frontend) };
+    let storage = unwrap!(context.storage.as_mut());
+
+    let ((next_state, frontend), stored) = join(
+        holter_impl(
+            &mut context.inner,
+            frontend,
+            &mut ecg,
+            &pipe,
+            &segments,
+            session,
+        ),
+        store_segments(&pipe, &segments, storage, &policy, uploader),
+    )
+    .await;
+
+    unsafe { core::ptr::write(&mut context.frontend, frontend) };
+
+    context.upload_schedule.set_pending(true);
+
+    let message = match stored {
+        Ok(()) => "Holter recording finished",
+        Err(e) => {
+            error!("Failed to store Holter recording: {:?}", e);
+            "Could not store recording"
+        }
+    };
+
+    if context.display.enable().await.is_err() {
+        // The segments are stored, but nothing can be shown without the display.
+        error!("Failed to enable display");
+        return AppState::Shutdown;
+    }
+    let brightness = context.config.display_brightness();
+    let _ = context.display.update_brightness_async(brightness).await;
+
+    context.display_message(message).await;
+
+    next_state
+}
+
+/// Writes the segments into new measurement files until the recording ends.
+async fn store_segments<M>(
+    pipe: &RecordingPipe,
+    segments: &SegmentInfoQueue,
+    storage: &mut Storage<M>,
+    policy: &RetentionPolicy,
+    uploader: Option<BackgroundUpload>,
+) -> Result<(), StorageError>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    while pipe.wait_for_recording().await {
+        if let Err(e) = store_segment(pipe, segments, storage, policy).await {
+            pipe.fail();
+            return Err(e);
+        }
+
+        if let Some(uploader) = uploader.as_ref() {
+            uploader.upload(storage).await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn store_segment<M>(
+    pipe: &RecordingPipe,
+    segments: &SegmentInfoQueue,
+    storage: &mut Storage<M>,
+    policy: &RetentionPolicy,
+) -> Result<(), StorageError>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    let mut catalog = Catalog::load(storage).await;
+    let id = catalog.next_id();
+    let filename = measurement_file_name(id);
+
+    // The segment is written while it is recorded, so its final size is not known yet.
+    if !catalog.make_room(storage, policy, SEGMENT_SIZE).await? {
+        // The samples are already recorded, so we try to store them anyway.
+        warn!("Not enough space for {}", filename);
+    }
+
+    pipe.store(storage, &filename).await?;
+
+    // The segment is described when it ends, before the end of the file is written.
+    let info = match segments.try_receive() {
+        Ok(info) => info,
+        Err(_) => {
+            warn!("Segment info missing for {}", filename);
+            MeasurementInfo::unknown(0)
+        }
+    };
+    catalog.add(storage, id, info).await?;
+
+    info!("Holter segment saved to {}", filename);
+
+    Ok(())
+}
+
+/// Connects to the network, which also synchronizes the clock that requests are signed with.
+async fn connect_uploader(context: &mut Context) -> Option<BackgroundUpload> {
+    let sta = context.enable_wifi_sta(StaMode::Enable).await?;
+    if !sta.wait_for_connection(context).await {
+        // The connection may come up while recording. Requests are only sent once the clock is
+        // set, which may have to wait until after the recording.
+        warn!("No network connection");
+    }
+
+    let backend = unwrap!(Backend::new(context));
+    Some(BackgroundUpload { sta, backend })
+}
+
+struct BackgroundUpload {
+    sta: Sta,
+    backend: Backend,
+}
+
+impl BackgroundUpload {
+    /// Uploads a stored measurement, or a part of it, if the network is connected.
+    async fn upload<M>(&self, storage: &mut Storage<M>)
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        if self.sta.connection_state() != WifiClientState::Connected {
+            return;
+        }
+
+        let Ok(mut client_resources) = self.backend.client_resources(&self.sta) else {
+            warn!("Failed to allocate HTTP client");
+            return;
+        };
+        let mut client = client_resources.client();
+
+        let deadline = Instant::now() + UPLOAD_CHUNK_TIME;
+        let upload = upload_one_stored(&mut client, &self.backend, storage, deadline);
+        match with_timeout(UPLOAD_TIME_LIMIT, upload).await {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) => debug!("Nothing to upload"),
+            Ok(Err(())) => warn!("Background upload failed"),
+            Err(_) => warn!("Background upload timed out"),
+        }
+    }
+}
@@ -1,69 +1,213 @@
 use crate::{
     board::{
-        config::types::FilterStrength,
+        catalog::{measurement_file_name, Catalog, MeasurementInfo},
+        config::types::{FilterStrength, MainsFrequency, MeasurementAction},
+        drivers::frontend,
         hal::prelude::*,
         initialized::{Context, InnerContext},
+        storage::FileSystem,
         AdcSpi, EcgFrontend, PoweredEcgFrontend,
     },
-    states::{menu::AppMenu, to_progress, INIT_MENU_THRESHOLD, INIT_TIME, MIN_FRAME_TIME},
+    states::{
+        menu::AppMenu,
+        recorder::{
+            recording_size, RecordedFile, RecordingPipe, RecordingStream, MAX_RECORDING_SAMPLES,
+        },
+        to_progress, INIT_MENU_THRESHOLD, INIT_TIME, MESSAGE_DURATION, MIN_FRAME_TIME,
+    },
     task_control::{TaskControlToken, TaskController},
     timeout::Timeout,
-    AppState,
+    AppState, SerialNumber,
 };
 use ads129x::{Error, Sample};
-use alloc::{boxed::Box, sync::Arc};
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use embassy_futures::join::join;
+use embassy_sync::{
+    blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex},
+    channel::Channel,
+};
 use embassy_time::{Duration, Instant, Ticker};
 use embedded_graphics::Drawable;
 use embedded_hal::spi::ErrorType;
 use gui::screens::{init::StartupScreen, measure::EcgScreen};
 use macros as cardio;
+use norfs::StorageError;
 use object_chain::{chain, Chain, ChainElement, Link};
 use signal_processing::{
-    compressing_buffer::CompressingBuffer,
+    compressing_buffer::{CompressingBuffer, EkgFormat},
     filter::{
-        iir::{precomputed::ALL_PASS, HighPass, Iir, LowPass},
-        pli::{adaptation_blocking::AdaptationBlocking, PowerLineFilter},
+        iir::{HighPass, LowPass},
+        sos::Sos,
         Filter,
     },
-    heart_rate::HeartRateCalculator,
+    heart_rate::{Beat, HeartRateCalculator},
+    hrv::{HrvCalculator, HrvMetrics},
+    mains::{self, MainsFrequencyDetector},
+    measurement::{
+        FilterSettings, HeartRateSummary, LeadOff, LeadOffIntervals, MeasurementHeader, Segment,
+        STREAMED_FORMAT_VERSION,
+    },
+    quality::{QualityReport, SignalQuality},
+    rhythm::{Rhythm, RhythmClassifier},
+};
+
+#[cfg(not(feature = "fixed-point"))]
+use signal_processing::{
+    filter::pli::{adaptation_blocking::AdaptationBlocking, PowerLineFilter},
     moving::sum::EstimatedSum,
 };
 
-#[cfg(not(feature = "downsampler-light"))]
+#[cfg(feature = "fixed-point")]
+use signal_processing::{
+    filter::fixed::{pli::FixedAdaptationBlocking, FixedIir, FixedPowerLineFilter},
+    fixed::Q31,
+};
+
+#[cfg(all(not(feature = "downsampler-light"), not(feature = "fixed-point")))]
 use signal_processing::filter::downsample::DownSampler;
 
-type MessageQueue = Channel<CriticalSectionRawMutex, Sample, 32>;
+#[cfg(all(not(feature = "downsampler-light"), feature = "fixed-point"))]
+use signal_processing::filter::fixed::FixedDownSampler as DownSampler;
+
+#[derive(Clone, Copy)]
+struct EcgSample {
+    sample: Sample,
+    leads_connected: bool,
+}
+
+/// Number of samples processed at once.
+const BATCH_SIZE: usize = 32;
+
+/// Number of samples the reader task can queue. Large enough to not lose samples while the
+/// recording is written to flash.
+const QUEUE_SIZE: usize = 256;
+
+type MessageQueue = Channel<CriticalSectionRawMutex, EcgSample, QUEUE_SIZE>;
 
 // FIXME: avoid this allow
 #[allow(suspicious_auto_trait_impls)] // SAFETY: yolo
 unsafe impl Send for PoweredEcgFrontend {}
 
+type ReaderResult = Result<(), Error<<AdcSpi as ErrorType>::Error>>;
+
 struct EcgTaskParams {
-    token: TaskControlToken<Result<(), Error<<AdcSpi as ErrorType>::Error>>, PoweredEcgFrontend>,
+    token: TaskControlToken<ReaderResult, PoweredEcgFrontend>,
     sender: Arc<MessageQueue>,
+    /// Stop reading when the device is no longer touched.
+    stop_on_release: bool,
+}
+
+/// The sample rate the ADC is configured for.
+const SAMPLE_RATE: f32 = frontend::SAMPLE_RATE;
+
+/// Cutoff frequency of the noise filter in front of the heart rate calculator.
+const HR_NOISE_CUTOFF: f32 = 20.0;
+
+/// The sample type of the ECG filter and the downsampler.
+#[cfg(not(feature = "fixed-point"))]
+type FilterSample = f32;
+
+#[cfg(not(feature = "fixed-point"))]
+fn to_filter_sample(sample: Sample) -> FilterSample {
+    sample.voltage()
 }
 
+#[cfg(not(feature = "fixed-point"))]
+fn to_voltage(sample: FilterSample) -> f32 {
+    sample
+}
+
+// Chips without an FPU would spend most of their time in soft-float routines.
+#[cfg(feature = "fixed-point")]
+type FilterSample = Q31;
+
+#[cfg(feature = "fixed-point")]
+fn to_filter_sample(sample: Sample) -> FilterSample {
+    // The ADC returns 24 bit samples
+    Q31(sample.raw() << 8)
+}
+
+#[cfg(feature = "fixed-point")]
+fn to_voltage(sample: FilterSample) -> f32 {
+    sample.0 as f32 * (Sample::VOLTS_PER_LSB / 256.0)
+}
+
+/// Number of mains harmonics cancelled by the power line filter, on top of the fundamental.
+const MAINS_HARMONICS: usize = 2;
+
+/// Length of the adaptation blocking comb filter. Its zeros are at multiples of 10Hz, so 100ms
+/// spans a whole number of periods of both 50Hz and 60Hz mains, and the interference and its
+/// harmonics don't block adaptation.
+const COMB_LENGTH: usize = SAMPLE_RATE as usize / 10;
+
+/// Length of the adaptation blocking variance window, 1.2 seconds.
+const VARIANCE_WINDOW: usize = SAMPLE_RATE as usize * 6 / 5;
+
 // PLI filtering algo is probably overkill for displaying, but it's fancy
+#[cfg(not(feature = "fixed-point"))]
+type EcgPowerLineFilter = PowerLineFilter<
+    AdaptationBlocking<EstimatedSum<VARIANCE_WINDOW>, 4, COMB_LENGTH>,
+    Sos<HighPass, 1>,
+    1,
+    MAINS_HARMONICS,
+>;
+
+#[cfg(not(feature = "fixed-point"))]
+pub type EcgFilter = chain! {
+    EcgPowerLineFilter,
+    Sos<HighPass, 1>
+};
+
+#[cfg(not(feature = "fixed-point"))]
+fn create_filter(mains: mains::MainsFrequency, hpf: Sos<HighPass, 1>) -> EcgFilter {
+    Chain::new(create_power_line_filter(mains)).append(hpf)
+}
+
+#[cfg(feature = "fixed-point")]
+type EcgPowerLineFilter = FixedPowerLineFilter<
+    FixedAdaptationBlocking<VARIANCE_WINDOW, 4, COMB_LENGTH>,
+    1,
+    MAINS_HARMONICS,
+>;
+
+#[cfg(feature = "fixed-point")]
 pub type EcgFilter = chain! {
-    PowerLineFilter<AdaptationBlocking<EstimatedSum<1200>, 4, 19>, Iir<'static, HighPass, 2>, 1>,
-    Iir<'static, HighPass, 2>
+    EcgPowerLineFilter,
+    FixedIir<HighPass, 2>
 };
 
+#[cfg(feature = "fixed-point")]
+fn create_filter(mains: mains::MainsFrequency, hpf: Sos<HighPass, 1>) -> EcgFilter {
+    Chain::new(create_power_line_filter(mains)).append(FixedIir::from(&hpf))
+}
+
+fn create_power_line_filter(mains: mains::MainsFrequency) -> EcgPowerLineFilter {
+    unwrap!(EcgPowerLineFilter::design_with_harmonics(
+        SAMPLE_RATE,
+        mains.hz()
+    ))
+}
+
+/// Give up detecting the mains frequency after 10 seconds.
+const MAINS_DETECTION_TIMEOUT: usize = 10 * SAMPLE_RATE as usize;
+
 #[cfg(feature = "downsampler-light")]
 pub struct DownsamplerLight {
-    filter: Iir<'static, LowPass, 2>,
+    #[cfg(not(feature = "fixed-point"))]
+    filter: Sos<LowPass, 1>,
+    #[cfg(feature = "fixed-point")]
+    filter: FixedIir<LowPass, 2>,
     counter: u8,
 }
 
 #[cfg(feature = "downsampler-light")]
-impl Filter for DownsamplerLight {
+impl Filter<FilterSample> for DownsamplerLight {
     fn clear(&mut self) {
         self.filter.clear();
         self.counter = 0;
     }
 
-    fn update(&mut self, sample: f32) -> Option<f32> {
+    fn update(&mut self, sample: FilterSample) -> Option<FilterSample> {
         let filtered = self.filter.update(sample)?;
         if self.counter == 0 {
             self.counter = 7;
@@ -98,99 +242,667 @@ type EcgDownsampler = DownsamplerLight;
 
 #[cfg(feature = "downsampler-light")]
 fn create_downsampler() -> DownsamplerLight {
+    let filter = unwrap!(Sos::<LowPass, 1>::butterworth(2, SAMPLE_RATE, &[35.0]));
+
     DownsamplerLight {
-        #[rustfmt::skip]
-        filter: macros::designfilt!(
-            "lowpassiir",
-            "FilterOrder", 2,
-            "HalfPowerFrequency", 35,
-            "SampleRate", 1000
-        ),
+        #[cfg(not(feature = "fixed-point"))]
+        filter,
+        #[cfg(feature = "fixed-point")]
+        filter: FixedIir::from(&filter),
         counter: 7,
     }
 }
 
 pub const ECG_BUFFER_SIZE: usize = 90_000;
 
+// Enough for the buffer length at 300 bpm.
+const MAX_BEATS: usize = 512;
+
+// Enough for the longest recording at 300 bpm.
+const MAX_RECORDED_BEATS: usize = MAX_RECORDING_SAMPLES / 200;
+
+/// Length of a Holter segment, in samples.
+const SEGMENT_SAMPLES: usize = 5 * 60 * SAMPLE_RATE as usize;
+
+/// Space to reserve for a Holter segment, in bytes.
+pub(super) const SEGMENT_SIZE: usize = recording_size(SEGMENT_SAMPLES);
+
+/// Space to reserve for recording a measurement, in bytes.
+const MAX_RECORDING_SIZE: usize = recording_size(MAX_RECORDING_SAMPLES);
+
+// Enough for a Holter segment at 300 bpm.
+const MAX_SEGMENT_BEATS: usize = SEGMENT_SAMPLES / 200;
+
+/// Holter recordings end when the leads are disconnected for this long, in samples.
+const HOLTER_LEAD_OFF_TIMEOUT: usize = 60 * SAMPLE_RATE as usize;
+
+/// Holter recordings don't draw anything, so the samples are processed less often.
+const HOLTER_FRAME_TIME: Duration = Duration::from_millis(50);
+
+/// Describes the finished Holter segments to the task that stores them. Segments are finished
+/// much less often than they are written, so a few entries are plenty.
+pub(super) type SegmentInfoQueue = Channel<NoopRawMutex, MeasurementInfo, 4>;
+
+/// Lead-off periods stored with a recording. If there are more, the oldest ones are dropped.
+const MAX_LEAD_OFF_INTERVALS: usize = 32;
+
+/// Where the samples of a measurement are recorded.
+enum Recorder<'a> {
+    /// The last samples are kept in memory. Used when the measurement is not stored, or the
+    /// storage is not available or fails.
+    Memory(Box<CompressingBuffer<ECG_BUFFER_SIZE>>),
+    /// Samples are streamed into a file.
+    File(RecordingStream<'a>),
+}
+
+impl Recorder<'_> {
+    /// Records a sample. Returns `false` if the sample was not recorded.
+    fn push(&mut self, sample: i32) -> bool {
+        match self {
+            Self::Memory(buffer) => {
+                buffer.push(sample);
+                true
+            }
+            Self::File(stream) => stream.push(sample),
+        }
+    }
+
+    /// Returns the number of samples available in the recording.
+    fn len(&self) -> usize {
+        match self {
+            Self::Memory(buffer) => buffer.len(),
+            Self::File(stream) => stream.len(),
+        }
+    }
+}
+
+/// The compressed samples of a finished measurement.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecordedSamples {
+    Memory(Box<CompressingBuffer<ECG_BUFFER_SIZE>>),
+    /// The measurement is written into the file of measurement `id`, in the
+    /// [streamed format](STREAMED_FORMAT_VERSION), but it is not in the catalog yet.
+    File {
+        id: u32,
+        file: RecordedFile,
+    },
+}
+
+impl RecordedSamples {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Memory(buffer) => buffer.len(),
+            Self::File { file, .. } => file.samples,
+        }
+    }
+}
+
+/// A finished measurement: the compressed samples and the beats detected in them.
+pub struct EcgRecording {
+    pub samples: RecordedSamples,
+    /// Beat annotations. Sample indices are relative to the first sample in `samples`.
+    pub beats: Vec<Beat>,
+    pub rhythm: Rhythm,
+    pub quality: Option<QualityReport>,
+    pub hrv: Option<HrvMetrics>,
+    /// Periods with a disconnected lead. Sample indices are relative to the first sample in
+    /// `samples`.
+    pub lead_off: Vec<LeadOff>,
+    pub filter: FilterSettings,
+    /// Start of the recording, in seconds since the Unix epoch.
+    pub start_time: Option<u64>,
+}
+
+impl EcgRecording {
+    /// Returns the header that describes the recording.
+    pub fn header(&self) -> MeasurementHeader<'_> {
+        recording_header(
+            &self.beats,
+            &self.lead_off,
+            self.filter,
+            self.samples.len(),
+            self.quality,
+            self.hrv,
+            self.start_time,
+        )
+    }
+}
+
+fn recording_header<'a>(
+    beats: &[Beat],
+    lead_off: &'a [LeadOff],
+    filter: FilterSettings,
+    sample_count: usize,
+    quality: Option<QualityReport>,
+    hrv: Option<HrvMetrics>,
+    start_time: Option<u64>,
+) -> MeasurementHeader<'a> {
+    MeasurementHeader {
+        sample_format: EkgFormat::VERSION,
+        sample_rate: Some(SAMPLE_RATE as u16),
+        adc_gain: Some(frontend::GAIN_FACTOR),
+        reference_voltage: Some(frontend::REFERENCE_VOLTS),
+        firmware: Some(env!("FW_VERSION")),
+        hardware: Some(env!("HW_VERSION")),
+        serial_number: Some(SerialNumber::bytes()),
+        start_time,
+        filter: Some(filter),
+        lead_off: LeadOffIntervals::Decoded(lead_off),
+        heart_rate: HeartRateSummary::from_beats(beats),
+        segment: None,
+        sample_count: Some(sample_count as u32),
+        quality,
+        hrv,
+    }
+}
+
+impl core::fmt::Debug for EcgRecording {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EcgRecording")
+            .field("samples", &self.samples)
+            .field("beats", &self.beats.len())
+            .field("rhythm", &self.rhythm)
+            .field("quality", &self.quality)
+            .field("lead_off", &self.lead_off.len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for EcgRecording {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "EcgRecording {{ samples: {}, beats: {}, rhythm: {}, quality: {}, lead_off: {} }}",
+            self.samples,
+            self.beats.len(),
+            self.rhythm,
+            self.quality,
+            self.lead_off.len()
+        )
+    }
+}
+
+/// Delay of the detected beats behind the recorded samples, in samples. The heart rate noise
+/// filter (2nd order Butterworth) delays the QRS complex by about `√2 / (2π · fc)`, and the heart
+/// rate calculator's differentiator by one more sample.
+const BEAT_DELAY: i64 = (SAMPLE_RATE * core::f32::consts::SQRT_2
+    / (core::f32::consts::TAU * HR_NOISE_CUTOFF)) as i64
+    + 1;
+
+/// Collects beat annotations for the samples stored in the ECG buffer.
+struct BeatLog {
+    beats: Vec<Beat>,
+    /// Number of samples pushed into the ECG buffer since it was last cleared.
+    recorded: u32,
+}
+
+impl BeatLog {
+    fn new(capacity: usize) -> Self {
+        let mut beats = Vec::new();
+        if beats.try_reserve_exact(capacity).is_err() {
+            warn!("Failed to allocate beat buffer");
+        }
+
+        Self { beats, recorded: 0 }
+    }
+
+    fn clear(&mut self) {
+        self.beats.clear();
+        self.recorded = 0;
+    }
+
+    fn sample_recorded(&mut self) {
+        self.recorded += 1;
+    }
+
+    /// Records a beat, translating the calculator's sample index into the recording's.
+    fn push(&mut self, mut beat: Beat, calculator_samples: u32, buffered_samples: usize) {
+        // The heart rate calculator and the ECG buffer count samples from different starting
+        // points, but their last samples are aligned, apart from the filter delay.
+        let index = beat.sample_index as i64 + self.recorded as i64
+            - calculator_samples as i64
+            - BEAT_DELAY;
+        let Ok(index) = u32::try_from(index) else {
+            // The beat was detected before the recording started.
+            return;
+        };
+        beat.sample_index = index;
+
+        if self.beats.len() == self.beats.capacity() {
+            let first_sample = self.first_buffered_sample(buffered_samples);
+            self.beats.retain(|beat| beat.sample_index >= first_sample);
+        }
+
+        if self.beats.len() < self.beats.capacity() {
+            self.beats.push(beat);
+        }
+    }
+
+    fn first_buffered_sample(&self, buffered_samples: usize) -> u32 {
+        self.recorded.saturating_sub(buffered_samples as u32)
+    }
+
+    /// Returns the beats that belong to the buffered samples, indexed from the first one.
+    fn into_beats(mut self, buffered_samples: usize) -> Vec<Beat> {
+        let first_sample = self.first_buffered_sample(buffered_samples);
+        self.beats.retain(|beat| beat.sample_index >= first_sample);
+        for beat in self.beats.iter_mut() {
+            beat.sample_index -= first_sample;
+        }
+        self.beats
+    }
+}
+
+/// Collects the periods with a disconnected lead, using the sample indices of the [`BeatLog`].
+struct LeadOffLog {
+    intervals: heapless::Deque<LeadOff, MAX_LEAD_OFF_INTERVALS>,
+    /// Start of the current lead-off period.
+    start: Option<u32>,
+}
+
+impl LeadOffLog {
+    const fn new() -> Self {
+        Self {
+            intervals: heapless::Deque::new(),
+            start: None,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.intervals.clear();
+        self.start = None;
+    }
+
+    fn update(&mut self, leads_connected: bool, sample_index: u32) {
+        match self.start {
+            None if !leads_connected => self.start = Some(sample_index),
+            Some(start) if leads_connected => {
+                self.start = None;
+                self.push(LeadOff {
+                    start,
+                    end: sample_index,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, interval: LeadOff) {
+        if self.intervals.is_full() {
+            self.intervals.pop_front();
+        }
+        unwrap!(self.intervals.push_back(interval).ok());
+    }
+
+    /// Returns the periods of a finished Holter segment. A period that is still ongoing continues
+    /// in the next segment.
+    fn split(&mut self, beat_log: &BeatLog) -> Vec<LeadOff> {
+        let ongoing = self.start.is_some();
+        let intervals = core::mem::replace(self, Self::new())
+            .into_intervals(beat_log, beat_log.recorded as usize);
+        if ongoing {
+            self.start = Some(0);
+        }
+
+        intervals
+    }
+
+    /// Returns the periods that overlap the buffered samples, indexed from the first one.
+    fn into_intervals(mut self, beat_log: &BeatLog, buffered_samples: usize) -> Vec<LeadOff> {
+        if let Some(start) = self.start.take() {
+            self.push(LeadOff {
+                start,
+                end: beat_log.recorded,
+            });
+        }
+
+        let first_sample = beat_log.first_buffered_sample(buffered_samples);
+        self.intervals
+            .iter()
+            .filter(|interval| interval.end > first_sample)
+            .map(|interval| LeadOff {
+                start: interval.start.max(first_sample) - first_sample,
+                end: interval.end - first_sample,
+            })
+            .collect()
+    }
+}
+
 // Two filter chains:
 // - PLI -> IIR HPF -> FIR Downsample -> display
 // - PLI -> IIR HPF -> FIR LPF in HR calculator -> HR calculator
-struct EcgObjects {
+pub(super) struct EcgObjects {
     pub filter: EcgFilter,
     pub downsampler: EcgDownsampler,
     pub heart_rate_calculator: HeartRateCalculator<[f32; 300], [f32; 50]>,
-    pub hr_noise_filter: Iir<'static, LowPass, 2>,
+    pub hr_noise_filter: Sos<LowPass, 1>,
+    pub hrv: HrvCalculator,
+    pub rhythm: RhythmClassifier,
+    pub quality: SignalQuality,
+    pub mains_frequency: mains::MainsFrequency,
+    pub mains_detector: Option<MainsFrequencyDetector>,
+    pub high_pass_cutoff: Option<f32>,
 }
 
 impl EcgObjects {
     #[inline(always)]
-    fn new(hpf: Iir<'static, HighPass, 2>) -> Self {
+    fn new(hpf: Sos<HighPass, 1>, high_pass_cutoff: Option<f32>, mains: MainsFrequency) -> Self {
+        // Until detected, we assume 50Hz mains.
+        let mains_frequency = match mains {
+            MainsFrequency::Auto | MainsFrequency::Hz50 => mains::MainsFrequency::Hz50,
+            MainsFrequency::Hz60 => mains::MainsFrequency::Hz60,
+        };
+        let mains_detector =
+            (mains == MainsFrequency::Auto).then(|| MainsFrequencyDetector::new(SAMPLE_RATE));
+
         Self {
-            filter: Chain::new(PowerLineFilter::new_1ksps([50.0])).append(hpf),
+            filter: create_filter(mains_frequency, hpf),
             downsampler: create_downsampler(),
-            heart_rate_calculator: HeartRateCalculator::new(1000.0),
+            heart_rate_calculator: HeartRateCalculator::new(SAMPLE_RATE),
+            hr_noise_filter: unwrap!(Sos::butterworth(2, SAMPLE_RATE, &[HR_NOISE_CUTOFF])),
+            hrv: HrvCalculator::new(),
+            rhythm: RhythmClassifier::new(),
+            quality: SignalQuality::new(SAMPLE_RATE, frontend::FULL_SCALE_VOLTS),
+            mains_frequency,
+            mains_detector,
+            high_pass_cutoff,
+        }
+    }
 
-            #[rustfmt::skip]
-            hr_noise_filter: macros::designfilt!(
-                "lowpassiir",
-                "FilterOrder", 2,
-                "HalfPowerFrequency", 20,
-                "SampleRate", 1000
-            ),
+    /// Forgets the analysis of the previous measurement.
+    fn clear_analysis(&mut self) {
+        self.heart_rate_calculator.clear();
+        self.hrv.clear();
+        self.rhythm.clear();
+        self.quality.clear();
+    }
+
+    fn filter_settings(&self) -> FilterSettings {
+        FilterSettings {
+            high_pass_cutoff: self.high_pass_cutoff,
+            mains_frequency: self.mains_frequency.hz() as u8,
+            mains_harmonics: MAINS_HARMONICS as u8,
         }
     }
 }
 
+impl EcgObjects {
+    /// Retunes the power line filter if the detected mains frequency differs from the assumed one.
+    fn detect_mains_frequency(&mut self, batch: &[EcgSample]) {
+        let Some(detector) = self.mains_detector.as_mut() else {
+            return;
+        };
+
+        let detected = batch
+            .iter()
+            .find_map(|ecg_sample| detector.update(ecg_sample.sample.voltage()));
+
+        match detected {
+            Some(frequency) => {
+                info!("Detected {}Hz mains", frequency.hz());
+                self.mains_detector = None;
+
+                if frequency != self.mains_frequency {
+                    // Rebuilding the filter would restart its adaptation blocking delay, and drop
+                    // samples from the middle of the recording.
+                    self.mains_frequency = frequency;
+                    self.filter.parent.object.retune(frequency.hz());
+                }
+            }
+            None if detector.samples() > MAINS_DETECTION_TIMEOUT => {
+                warn!("Failed to detect mains frequency");
+                self.mains_detector = None;
+            }
+            None => {}
+        }
+    }
+
+    /// Filters a batch of samples, and feeds the results to the analysis and the display.
+    fn process_batch(
+        &mut self,
+        batch: &[EcgSample],
+        mut recorder: Option<&mut Recorder<'_>>,
+        beat_log: &mut BeatLog,
+        lead_off_log: &mut LeadOffLog,
+        mut screen: Option<&mut EcgScreen>,
+    ) {
+        self.detect_mains_frequency(batch);
+
+        let mut input = [FilterSample::default(); BATCH_SIZE];
+        for (input, ecg_sample) in input.iter_mut().zip(batch) {
+            *input = to_filter_sample(ecg_sample.sample);
+        }
+
+        let mut filtered = [FilterSample::default(); BATCH_SIZE];
+        let filtered_count = self
+            .filter
+            .process_block(&input[..batch.len()], &mut filtered);
+        let filtered = &filtered[..filtered_count];
+
+        let mut voltages = [0.0; BATCH_SIZE];
+        for (voltage, sample) in voltages.iter_mut().zip(filtered) {
+            *voltage = to_voltage(*sample);
+        }
+        let voltages = &voltages[..filtered_count];
+
+        let mut hr_input = [0.0; BATCH_SIZE];
+        let hr_count = self.hr_noise_filter.process_block(voltages, &mut hr_input);
+
+        // The filters only drop samples while they start up, so their outputs belong to the last
+        // samples of the batch.
+        let filtered_offset = batch.len() - filtered_count;
+        let hr_offset = batch.len() - hr_count;
+
+        for (i, ecg_sample) in batch.iter().enumerate() {
+            if let Some(recorder) = recorder.as_deref_mut() {
+                if recorder.push(ecg_sample.sample.raw()) {
+                    lead_off_log.update(ecg_sample.leads_connected, beat_log.recorded);
+                    beat_log.sample_recorded();
+                }
+            }
+
+            let Some(filtered) = i.checked_sub(filtered_offset).map(|i| voltages[i]) else {
+                continue;
+            };
+
+            if let Some(second) = self.quality.update(
+                ecg_sample.sample.voltage(),
+                filtered,
+                ecg_sample.leads_connected,
+            ) {
+                if let Some(screen) = screen.as_deref_mut() {
+                    screen.poor_signal = second.score < SignalQuality::USABLE_SCORE;
+                }
+            }
+
+            let Some(hr_sample) = i.checked_sub(hr_offset).map(|i| hr_input[i]) else {
+                continue;
+            };
+
+            self.heart_rate_calculator.update(hr_sample);
+
+            if let Some(beat) = self.heart_rate_calculator.beat() {
+                self.quality.beat(beat.rr_interval);
+                if let Some(rr_interval) = beat.rr_interval {
+                    self.hrv.update(rr_interval);
+                    self.rhythm.update(rr_interval);
+                }
+                if let Some(recorder) = recorder.as_deref() {
+                    beat_log.push(
+                        beat,
+                        self.heart_rate_calculator.sample_count(),
+                        recorder.len(),
+                    );
+                }
+            }
+        }
+
+        let Some(screen) = screen else {
+            return;
+        };
+
+        let mut downsampled = [FilterSample::default(); BATCH_SIZE];
+        let downsampled_count = self.downsampler.process_block(filtered, &mut downsampled);
+        for sample in downsampled[..downsampled_count].iter().copied() {
+            screen.push(to_voltage(sample));
+        }
+    }
+
+    /// Processes recorded samples for the display, as if they were being measured.
+    pub(super) fn process_recorded(&mut self, samples: &[i32], screen: &mut EcgScreen) {
+        let mut beat_log = BeatLog::new(0);
+        let mut lead_off_log = LeadOffLog::new();
+
+        for chunk in samples.chunks(BATCH_SIZE) {
+            let batch = chunk
+                .iter()
+                .map(|raw| EcgSample {
+                    sample: Sample::new(*raw),
+                    leads_connected: true,
+                })
+                .collect::<heapless::Vec<_, BATCH_SIZE>>();
+
+            self.process_batch(
+                &batch,
+                None,
+                &mut beat_log,
+                &mut lead_off_log,
+                Some(&mut *screen),
+            );
+        }
+    }
+}
+
+/// Creates the filters and the analysis, as configured.
+pub(super) fn create_ecg_objects(context: &Context) -> Box<EcgObjects> {
+    let cutoff = match context.config.filter_strength() {
+        FilterStrength::None => None,
+        FilterStrength::Weak => Some(0.75),
+        FilterStrength::Strong => Some(1.5),
+    };
+    let filter = match cutoff {
+        Some(cutoff) => unwrap!(Sos::butterworth(2, SAMPLE_RATE, &[cutoff])),
+        None => Sos::ALL_PASS,
+    };
+
+    Box::new(EcgObjects::new(
+        filter,
+        cutoff,
+        context.config.mains_frequency,
+    ))
+}
+
 pub async fn measure(context: &mut Context) -> AppState {
-    let filter = match context.config.filter_strength() {
-        FilterStrength::None => ALL_PASS,
-        #[rustfmt::skip]
-        FilterStrength::Weak => macros::designfilt!(
-            "highpassiir",
-            "FilterOrder", 2,
-            "HalfPowerFrequency", 0.75,
-            "SampleRate", 1000
-        ),
-        #[rustfmt::skip]
-        FilterStrength::Strong => macros::designfilt!(
-            "highpassiir",
-            "FilterOrder", 2,
-            "HalfPowerFrequency", 1.5,
-            "SampleRate", 1000
-        ),
+    let mut ecg = create_ecg_objects(context);
+
+    // If the measurement may be stored, we stream the recording into its file. Otherwise, we
+    // keep the last samples in memory.
+    let file_id = match context.config.measurement_action {
+        MeasurementAction::Upload | MeasurementAction::Discard => None,
+        _ => recording_file_id(context).await,
     };
+    let pipe = file_id.and_then(|_| RecordingPipe::new(2).ok());
 
-    // We allocate two different objects because the filters don't need to outlive this app state.
-    let ecg_buffer = Box::try_new(CompressingBuffer::EMPTY).ok();
-    let mut ecg = Box::new(EcgObjects::new(filter));
+    let frontend = unsafe { core::ptr::read(&context.frontend) };
 
-    if ecg_buffer.is_none() {
-        warn!("Failed to allocate ECG buffer");
-    }
+    let (next_state, frontend) = match (file_id, pipe.as_ref(), context.storage.as_mut()) {
+        (Some(id), Some(pipe), Some(storage)) => {
+            let filename = measurement_file_name(id);
+            let measurement = measure_impl(
+                &mut context.inner,
+                frontend,
+                &mut ecg,
+                pipe.try_stream(&[STREAMED_FORMAT_VERSION])
+                    .map(Recorder::File),
+                file_id,
+            );
+
+            let ((next_state, frontend), stored) =
+                join(measurement, pipe.store(storage, &filename)).await;
+
+            if let Err(e) = stored.as_ref() {
+                error!("Failed to record measurement: {:?}", e);
+            }
+
+            // If the file failed while measuring, the recording continued in memory.
+            let recorded_to_file = matches!(
+                &next_state,
+                AppState::UploadOrStore(recording)
+                    if matches!(recording.samples, RecordedSamples::File { .. })
+            );
+            if !recorded_to_file || stored.is_err() {
+                match storage.delete(&filename).await {
+                    Ok(()) | Err(StorageError::NotFound) => {}
+                    Err(e) => warn!("Failed to delete recording: {:?}", e),
+                }
+            }
+
+            let next_state = if recorded_to_file && stored.is_err() {
+                // The end of the recording was lost after the measurement finished.
+                context.display_message("Could not store measurement").await;
+                context.wait_for_message(MESSAGE_DURATION).await;
+                AppState::Shutdown
+            } else {
+                next_state
+            };
 
-    unsafe {
-        let frontend = core::ptr::read(&context.frontend);
+            (next_state, frontend)
+        }
+        _ => {
+            let ecg_buffer = Box::try_new(CompressingBuffer::EMPTY).ok();
+            if ecg_buffer.is_none() {
+                warn!("Failed to allocate ECG buffer");
+            }
+
+            measure_impl(
+                &mut context.inner,
+                frontend,
+                &mut ecg,
+                ecg_buffer.map(Recorder::Memory),
+                None,
+            )
+            .await
+        }
+    };
 
-        let (next_state, frontend) =
-            measure_impl(&mut context.inner, frontend, &mut ecg, ecg_buffer).await;
+    unsafe { core::ptr::write(&mut context.frontend, frontend) };
 
-        core::ptr::write(&mut context.frontend, frontend);
-        next_state
+    next_state
+}
+
+/// Returns the id of the measurement to record into, if the longest recording fits into the free
+/// space. Nothing is deleted here, measurements only make room for the size of a recording when
+/// it is stored.
+async fn recording_file_id(context: &mut Context) -> Option<u32> {
+    let policy = context.config.retention_policy();
+    let storage = context.storage.as_mut()?;
+
+    let catalog = Catalog::load(storage).await;
+    if catalog.available_bytes(storage, &policy) < MAX_RECORDING_SIZE {
+        context.display_message("Storage full").await;
+        context.wait_for_message(MESSAGE_DURATION).await;
+        return None;
     }
+
+    let fits = storage.free_bytes() >= MAX_RECORDING_SIZE + policy.reserved_bytes;
+    fits.then_some(catalog.next_id())
 }
 
-async fn measure_impl(
+/// Powers up the ADC, and starts reading samples into `queue`.
+async fn start_reader(
     context: &mut InnerContext,
     frontend: EcgFrontend,
-    ecg: &mut EcgObjects,
-    mut ecg_buffer: Option<Box<CompressingBuffer<ECG_BUFFER_SIZE>>>,
-) -> (AppState, EcgFrontend) {
+    queue: &Arc<MessageQueue>,
+    stop_on_release: bool,
+) -> Result<TaskController<ReaderResult, PoweredEcgFrontend>, EcgFrontend> {
     let mut frontend = match frontend.enable_async().await {
         Ok(frontend) => frontend,
         Err((fe, _err)) => {
             context.display_message("ADC error").await;
 
-            return (AppState::Shutdown, fe);
+            return Err(fe);
         }
     };
 
@@ -205,14 +917,12 @@ async fn measure_impl(
         Err(_e) => {
             context.display_message("ADC error").await;
 
-            return (AppState::Shutdown, frontend.shut_down().await);
+            return Err(frontend.shut_down().await);
         }
 
         _ => {}
     }
 
-    let queue = Arc::new(MessageQueue::new());
-
     let task_control = TaskController::from_resources(frontend);
 
     context
@@ -220,9 +930,33 @@ async fn measure_impl(
         .must_spawn(reader_task(EcgTaskParams {
             token: task_control.token(),
             sender: queue.clone(),
+            stop_on_release,
         }));
 
-    ecg.heart_rate_calculator.clear();
+    Ok(task_control)
+}
+
+async fn measure_impl(
+    context: &mut InnerContext,
+    frontend: EcgFrontend,
+    ecg: &mut EcgObjects,
+    mut recorder: Option<Recorder<'_>>,
+    // The measurement that a `Recorder::File` is written into.
+    file_id: Option<u32>,
+) -> (AppState, EcgFrontend) {
+    let queue = Arc::new(MessageQueue::new());
+
+    let task_control = match start_reader(context, frontend, &queue, true).await {
+        Ok(task_control) => task_control,
+        Err(frontend) => return (AppState::Shutdown, frontend),
+    };
+
+    ecg.clear_analysis();
+    let mut beat_log = BeatLog::new(match recorder {
+        Some(Recorder::File(_)) => MAX_RECORDED_BEATS,
+        _ => MAX_BEATS,
+    });
+    let mut lead_off_log = LeadOffLog::new();
 
     let mut screen = EcgScreen::new();
 
@@ -236,34 +970,49 @@ async fn measure_impl(
 
     while !task_control.has_exited() && !context.battery_monitor.is_low() {
         let display_full = screen.buffer_full();
+        // Recording starts when the display is full, and the signal has settled.
+        let mut active_recorder = recorder.as_mut().filter(|_| display_full);
+        let mut batch = heapless::Vec::<EcgSample, BATCH_SIZE>::new();
         while let Ok(sample) = queue.try_receive() {
             samples += 1;
 
             if drop_samples == 0 {
-                if let Some(ecg_buffer) = ecg_buffer.as_deref_mut() {
-                    ecg_buffer.push(sample.raw());
-                }
-                if let Some(filtered) = ecg.filter.update(sample.voltage()) {
-                    if let Some(filtered) = ecg.hr_noise_filter.update(filtered) {
-                        ecg.heart_rate_calculator.update(filtered);
-                    }
-
-                    if let Some(downsampled) = ecg.downsampler.update(filtered) {
-                        screen.push(downsampled);
-                    }
+                unwrap!(batch.push(sample).ok());
+                if batch.is_full() {
+                    ecg.process_batch(
+                        &batch,
+                        active_recorder.as_deref_mut(),
+                        &mut beat_log,
+                        &mut lead_off_log,
+                        Some(&mut screen),
+                    );
+                    batch.clear();
                 }
             } else {
                 drop_samples -= 1;
             }
         }
+        ecg.process_batch(
+            &batch,
+            active_recorder.as_deref_mut(),
+            &mut beat_log,
+            &mut lead_off_log,
+            Some(&mut screen),
+        );
+
+        if matches!(&recorder, Some(Recorder::File(stream)) if stream.is_failed()) {
+            recorder = continue_in_memory(&mut beat_log, &mut lead_off_log);
+        }
 
         if !display_full {
             if screen.buffer_full() {
                 entered = Instant::now();
             }
-            if let Some(ecg_buffer) = ecg_buffer.as_deref_mut() {
-                ecg_buffer.clear();
-            }
+            ecg.hrv.clear();
+            ecg.rhythm.clear();
+            ecg.quality.clear();
+            beat_log.clear();
+            lead_off_log.clear();
         }
 
         if debug_print_timer.is_elapsed() {
@@ -297,6 +1046,26 @@ async fn measure_impl(
     }
 
     let result = task_control.stop().await;
+
+    if let Some(metrics) = ecg.hrv.metrics() {
+        info!(
+            "HRV: mean NN {}ms, SDNN {}ms, RMSSD {}ms, pNN50 {}% ({} NN, {} rejected)",
+            metrics.mean_nn,
+            metrics.sdnn,
+            metrics.rmssd,
+            metrics.pnn50,
+            metrics.nn_count,
+            metrics.rejected_count
+        );
+    }
+
+    if let Some(quality) = ecg.quality.report() {
+        info!(
+            "Signal quality: {} ({}/{} usable seconds)",
+            quality.score, quality.usable_seconds, quality.seconds
+        );
+    }
+
     let next_state = match result {
         Ok(result) => {
             // task stopped itself
@@ -305,8 +1074,63 @@ async fn measure_impl(
             }
             if result.is_ok() && !exit_timer.is_elapsed() {
                 AppState::Menu(AppMenu::Main)
-            } else if let Some(ecg_buffer) = ecg_buffer {
-                AppState::UploadOrStore(ecg_buffer)
+            } else if let Some(mut recorder) = recorder {
+                let buffered = match &mut recorder {
+                    Recorder::Memory(samples) => {
+                        // Flushing may drop the oldest samples, so do it before collecting the
+                        // beats.
+                        samples.flush();
+                        samples.len()
+                    }
+                    Recorder::File(_) => beat_log.recorded as usize,
+                };
+
+                let mut lead_off = lead_off_log.into_intervals(&beat_log, buffered);
+                let mut beats = beat_log.into_beats(buffered);
+
+                // If the recording was cut short, the file may end before the last samples.
+                let recorded = recorder.len() as u32;
+                beats.retain(|beat| beat.sample_index < recorded);
+                lead_off.retain(|interval| interval.start < recorded);
+                for interval in lead_off.iter_mut() {
+                    interval.end = interval.end.min(recorded);
+                }
+
+                let start_time = recording_start(context.clock.now(), recorded as usize);
+                let quality = ecg.quality.report();
+                let hrv = ecg.hrv.metrics();
+                let filter = ecg.filter_settings();
+
+                let samples = match recorder {
+                    Recorder::Memory(samples) => RecordedSamples::Memory(samples),
+                    Recorder::File(stream) => {
+                        let header = recording_header(
+                            &beats,
+                            &lead_off,
+                            filter,
+                            recorded as usize,
+                            quality,
+                            hrv,
+                            start_time,
+                        );
+                        let trailer = encode_trailer(&header, &mut beats);
+                        RecordedSamples::File {
+                            id: unwrap!(file_id),
+                            file: stream.finish_with_trailer(trailer),
+                        }
+                    }
+                };
+
+                AppState::UploadOrStore(EcgRecording {
+                    samples,
+                    beats,
+                    rhythm: ecg.rhythm.rhythm(),
+                    quality,
+                    hrv,
+                    lead_off,
+                    filter,
+                    start_time,
+                })
             } else {
                 AppState::Shutdown
             }
@@ -322,12 +1146,255 @@ async fn measure_impl(
     (next_state, frontend.shut_down().await)
 }
 
+/// Records consecutive Holter segments until the leads are disconnected for a minute, the
+/// battery runs low, or a segment can't be stored. Each segment is written as a separate
+/// measurement in the [streamed format](STREAMED_FORMAT_VERSION).
+pub(super) async fn holter_impl(
+    context: &mut InnerContext,
+    frontend: EcgFrontend,
+    ecg: &mut EcgObjects,
+    pipe: &RecordingPipe,
+    segments: &SegmentInfoQueue,
+    session: u32,
+) -> (AppState, EcgFrontend) {
+    let queue = Arc::new(MessageQueue::new());
+
+    // The electrodes are attached to the body, so we don't stop when the device is released.
+    let task_control = match start_reader(context, frontend, &queue, false).await {
+        Ok(task_control) => task_control,
+        Err(frontend) => return (AppState::Shutdown, frontend),
+    };
+
+    ecg.clear_analysis();
+    let mut beat_log = BeatLog::new(MAX_SEGMENT_BEATS);
+    let mut lead_off_log = LeadOffLog::new();
+
+    let mut segment = Segment {
+        session,
+        sequence: 0,
+        lost_samples: 0,
+    };
+    let mut recorder = None;
+
+    // Sample counts since the start of the recording, to find the samples lost to overruns.
+    let mut processed = 0;
+    let mut segment_start = 0;
+    let mut previous_end = 0;
+    let mut lead_off_samples = 0;
+
+    let mut ticker = Ticker::every(HOLTER_FRAME_TIME);
+    let mut drop_samples = 1500; // Slight delay for the input to settle
+
+    while !task_control.has_exited()
+        && !context.battery_monitor.is_low()
+        && !pipe.is_failed()
+        && lead_off_samples < HOLTER_LEAD_OFF_TIMEOUT
+    {
+        // After an overrun, the next segment starts when a buffer is written.
+        if recorder.is_none() {
+            recorder = pipe
+                .try_stream(&[STREAMED_FORMAT_VERSION])
+                .map(Recorder::File);
+            if recorder.is_some() {
+                segment.lost_samples = (processed - previous_end) as u32;
+                segment_start = processed;
+            }
+        }
+
+        let mut batch = heapless::Vec::<EcgSample, BATCH_SIZE>::new();
+        while let Ok(sample) = queue.try_receive() {
+            if drop_samples > 0 {
+                drop_samples -= 1;
+                continue;
+            }
+
+            if sample.leads_connected {
+                lead_off_samples = 0;
+            } else {
+                lead_off_samples += 1;
+            }
+
+            unwrap!(batch.push(sample).ok());
+            if batch.is_full() {
+                ecg.process_batch(
+                    &batch,
+                    recorder.as_mut(),
+                    &mut beat_log,
+                    &mut lead_off_log,
+                    None,
+                );
+                processed += batch.len();
+                batch.clear();
+            }
+        }
+        ecg.process_batch(
+            &batch,
+            recorder.as_mut(),
+            &mut beat_log,
+            &mut lead_off_log,
+            None,
+        );
+        processed += batch.len();
+
+        let segment_ended = matches!(
+            &recorder,
+            Some(Recorder::File(stream)) if !stream.is_recording() || stream.len() >= SEGMENT_SAMPLES
+        );
+        if segment_ended {
+            if let Some(Recorder::File(stream)) = recorder.take() {
+                previous_end = segment_start + stream.len();
+                let now = context.clock.now();
+                let info =
+                    finish_segment(stream, &mut beat_log, &mut lead_off_log, ecg, segment, now);
+                send_segment_info(segments, info);
+            }
+            segment.sequence = segment.sequence.wrapping_add(1);
+        }
+
+        ticker.next().await;
+    }
+
+    // Close the last segment, so that it can be read.
+    if let Some(Recorder::File(stream)) = recorder.take() {
+        let now = context.clock.now();
+        let info = finish_segment(stream, &mut beat_log, &mut lead_off_log, ecg, segment, now);
+        send_segment_info(segments, info);
+    }
+    pipe.close();
+
+    info!("Holter recording stopped");
+
+    if let Ok(Err(_e)) = task_control.stop().await {
+        warn!("Measurement task error"); // TODO: print error once supported
+    }
+
+    let next_state = if context.battery_monitor.is_low() {
+        AppState::Shutdown
+    } else {
+        AppState::Menu(AppMenu::Main)
+    };
+
+    let frontend = task_control.unwrap();
+
+    (next_state, frontend.shut_down().await)
+}
+
+/// Replaces a recording whose file can't be written with one that keeps the last samples in
+/// memory. The samples recorded into the file are lost. Returns `None` if there is not enough
+/// memory.
+fn continue_in_memory(
+    beat_log: &mut BeatLog,
+    lead_off_log: &mut LeadOffLog,
+) -> Option<Recorder<'static>> {
+    warn!("Failed to write recording, continuing in memory");
+
+    // The beat log of a file recording is larger than needed for the buffer, free it first.
+    *beat_log = BeatLog::new(0);
+    lead_off_log.clear();
+
+    let buffer = Box::try_new(CompressingBuffer::EMPTY).ok();
+    *beat_log = BeatLog::new(MAX_BEATS);
+
+    match buffer {
+        Some(buffer) => Some(Recorder::Memory(buffer)),
+        None => {
+            warn!("Failed to allocate ECG buffer");
+            None
+        }
+    }
+}
+
+/// Returns the start of a recording of `samples` that has just ended.
+fn recording_start(now: Option<u64>, samples: usize) -> Option<u64> {
+    now.map(|now| now.saturating_sub((samples as f32 / SAMPLE_RATE) as u64))
+}
+
+fn send_segment_info(segments: &SegmentInfoQueue, info: MeasurementInfo) {
+    if segments.try_send(info).is_err() {
+        warn!("Segment info queue full, segment will not be cataloged correctly");
+    }
+}
+
+/// Ends a Holter segment, writing its header and beats after the samples.
+fn finish_segment(
+    stream: RecordingStream<'_>,
+    beat_log: &mut BeatLog,
+    lead_off_log: &mut LeadOffLog,
+    ecg: &mut EcgObjects,
+    segment: Segment,
+    now: Option<u64>,
+) -> MeasurementInfo {
+    let recorded = stream.len() as u32;
+
+    let mut lead_off = lead_off_log.split(beat_log);
+    lead_off.retain(|interval| interval.start < recorded);
+    for interval in lead_off.iter_mut() {
+        interval.end = interval.end.min(recorded);
+    }
+    beat_log.beats.retain(|beat| beat.sample_index < recorded);
+
+    // Every segment is scored separately.
+    let quality = ecg.quality.report();
+    ecg.quality.clear();
+    let hrv = ecg.hrv.metrics();
+    ecg.hrv.clear();
+
+    let header = MeasurementHeader {
+        segment: Some(segment),
+        ..recording_header(
+            &beat_log.beats,
+            &lead_off,
+            ecg.filter_settings(),
+            recorded as usize,
+            quality,
+            hrv,
+            recording_start(now, recorded as usize),
+        )
+    };
+    let mut info = MeasurementInfo::from_header(0, &header);
+
+    let trailer = encode_trailer(&header, &mut beat_log.beats);
+    beat_log.clear();
+
+    // The file holds the format version, the samples, the trailer and the length of the samples.
+    let overhead = 1 + trailer.len() + 4;
+    let file = stream.finish_with_trailer(trailer);
+    debug!("Holter segment {} finished: {:?}", segment.sequence, file);
+
+    info.size = (file.bytes + overhead) as u32;
+    info
+}
+
+/// Encodes the header and the beats that follow the samples in the
+/// [streamed format](STREAMED_FORMAT_VERSION). If there is not enough memory, the beats are
+/// dropped.
+fn encode_trailer(header: &MeasurementHeader<'_>, beats: &mut Vec<Beat>) -> Vec<u8> {
+    let mut trailer = Vec::new();
+    let trailer_len = header.encoded_len() + beats.len() * Beat::ENCODED_SIZE + 4;
+    if trailer.try_reserve_exact(trailer_len).is_err() {
+        warn!("Failed to allocate recording trailer, dropping beats");
+        beats.clear();
+    }
+
+    trailer.resize(header.encoded_len(), 0);
+    unwrap!(header.encode(&mut &mut trailer[..]).ok());
+    for beat in beats.iter() {
+        trailer.extend_from_slice(&beat.to_bytes());
+    }
+
+    trailer
+}
+
 #[cardio::task]
 async fn reader_task(params: EcgTaskParams) {
-    let EcgTaskParams { mut token, sender } = params;
+    let EcgTaskParams {
+        mut token,
+        sender,
+        stop_on_release,
+    } = params;
 
     token
-        .run_cancellable(|frontend| read_ecg(sender.as_ref(), frontend))
+        .run_cancellable(|frontend| read_ecg(sender.as_ref(), frontend, stop_on_release))
         .await;
     info!("Measurement task stopped");
 }
@@ -335,16 +1402,21 @@ async fn reader_task(params: EcgTaskParams) {
 async fn read_ecg(
     queue: &MessageQueue,
     frontend: &mut PoweredEcgFrontend,
-) -> Result<(), Error<<AdcSpi as ErrorType>::Error>> {
+    stop_on_release: bool,
+) -> ReaderResult {
     loop {
         match frontend.read().await {
             Ok(sample) => {
-                if !frontend.is_touched() {
+                if stop_on_release && !frontend.is_touched() {
                     info!("Not touched, stopping");
                     return Ok(());
                 }
 
-                if queue.try_send(sample.ch1_sample()).is_err() {
+                let sample = EcgSample {
+                    sample: sample.ch1_sample(),
+                    leads_connected: sample.ch1_leads_connected(),
+                };
+                if queue.try_send(sample).is_err() {
                     warn!("Sample lost");
                 }
             }
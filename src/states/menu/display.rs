@@ -1,6 +1,6 @@
 use crate::{
     board::{
-        config::types::{DisplayBrightness, FilterStrength},
+        config::types::{DisplayBrightness, FilterStrength, MainsFrequency},
         initialized::Context,
     },
     states::menu::{AppMenu, AppMenuBuilder, MenuScreen},
@@ -24,6 +24,7 @@ pub enum DisplayMenuEvents {
     ChangeBrigtness(DisplayBrightness),
     ChangeBatteryStyle(BatteryStyle),
     ChangeFilterStrength(FilterStrength),
+    ChangeMainsFrequency(MainsFrequency),
     Back,
 }
 
@@ -47,6 +48,11 @@ fn display_menu_builder(context: &mut Context) -> DisplayMenuBuilder {
             context.config.filter_strength,
             DisplayMenuEvents::ChangeFilterStrength,
         )
+        .add_item(
+            "Mains",
+            context.config.mains_frequency,
+            DisplayMenuEvents::ChangeMainsFrequency,
+        )
         .add_item("Back", "<-", |_| DisplayMenuEvents::Back)
 }
 
@@ -75,6 +81,9 @@ impl MenuScreen for DisplayMenu {
             DisplayMenuEvents::ChangeFilterStrength(strength) => {
                 context.update_config(|config| config.filter_strength = strength);
             }
+            DisplayMenuEvents::ChangeMainsFrequency(frequency) => {
+                context.update_config(|config| config.mains_frequency = frequency);
+            }
             DisplayMenuEvents::Back => return Some(AppState::Menu(AppMenu::Main)),
         }
 
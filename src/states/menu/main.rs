@@ -12,12 +12,14 @@ use super::AppMenuBuilder;
 #[derive(Clone, Copy, PartialEq)]
 pub enum MainMenuEvents {
     Measure,
+    Holter,
     Display,
     About,
     WifiSetup,
     WifiListVisible,
     FirmwareUpdate,
     Throughput,
+    Pairing,
     Storage,
     Shutdown,
 }
@@ -41,7 +43,7 @@ struct MainMenu;
 type MainMenuBuilder = impl AppMenuBuilder<MainMenuEvents>;
 
 fn main_menu_builder(context: &mut Context) -> MainMenuBuilder {
-    let mut optional_items = heapless::Vec::<_, 4>::new();
+    let mut optional_items = heapless::Vec::<_, 5>::new();
 
     if context.can_enable_wifi() {
         let mut optional_item = |label, event| {
@@ -56,13 +58,30 @@ fn main_menu_builder(context: &mut Context) -> MainMenuBuilder {
         optional_item("Wifi networks", MainMenuEvents::WifiListVisible);
 
         if network_configured {
+            let label = if context.config.is_registered() {
+                "Pair again"
+            } else {
+                "Pair device"
+            };
+            optional_item(label, MainMenuEvents::Pairing);
             optional_item("Firmware update", MainMenuEvents::FirmwareUpdate);
             optional_item("Speed test", MainMenuEvents::Throughput);
         }
     }
 
+    let mut storage_items = heapless::Vec::<_, 1>::new();
+    if context.storage.is_some() {
+        unwrap!(storage_items
+            .push(
+                MenuItem::new("Holter recording", MainMenuEvents::Holter)
+                    .with_value_converter(|evt| evt)
+            )
+            .ok());
+    }
+
     create_menu("Main menu")
         .add_item("Measure", MainMenuEvents::Measure, |evt| evt)
+        .add_menu_items(storage_items)
         .add_item("Display", MainMenuEvents::Display, |evt| evt)
         .add_item("Storage", MainMenuEvents::Storage, |evt| evt)
         .add_item("Device info", MainMenuEvents::About, |evt| evt)
@@ -86,6 +105,7 @@ impl MenuScreen for MainMenu {
     ) -> Option<Self::Result> {
         let event = match event {
             MainMenuEvents::Measure => AppState::Initialize,
+            MainMenuEvents::Holter => AppState::Holter,
             MainMenuEvents::Display => AppState::Menu(AppMenu::Display),
             MainMenuEvents::About => AppState::Menu(AppMenu::DeviceInfo),
             MainMenuEvents::WifiSetup => AppState::Menu(AppMenu::WifiAP),
@@ -93,6 +113,7 @@ impl MenuScreen for MainMenu {
             MainMenuEvents::Storage => AppState::Menu(AppMenu::Storage),
             MainMenuEvents::FirmwareUpdate => AppState::FirmwareUpdate,
             MainMenuEvents::Throughput => AppState::Throughput,
+            MainMenuEvents::Pairing => AppState::Pairing,
             MainMenuEvents::Shutdown => AppState::Shutdown,
         };
 
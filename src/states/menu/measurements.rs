@@ -0,0 +1,266 @@
+use crate::{
+    board::{
+        catalog::{Catalog, CatalogEntry, UploadState},
+        initialized::Context,
+    },
+    human_readable::{BinarySize, DateTime, Seconds},
+    states::{
+        menu::{AppMenu, AppMenuBuilder, MenuScreen},
+        preview::preview,
+        upload_or_store_measurement::upload_stored_measurement,
+    },
+    uformat, AppState,
+};
+use embedded_menu::items::menu_item::{MenuItem, SelectValue};
+use gui::screens::create_menu;
+
+/// The most measurements listed, newest first.
+const MAX_LISTED: usize = 32;
+
+#[derive(Clone, PartialEq)]
+struct Label(heapless::String<24>);
+
+impl SelectValue for Label {
+    fn marker(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+#[derive(Clone, PartialEq)]
+struct ListedMeasurement {
+    id: u32,
+    duration: Label,
+}
+
+impl SelectValue for ListedMeasurement {
+    fn marker(&self) -> &str {
+        self.duration.marker()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum MeasurementsMenuEvents {
+    Open(u32),
+    Back,
+}
+
+pub async fn measurements_menu(context: &mut Context) -> AppState {
+    MeasurementsMenu
+        .display(context)
+        .await
+        .unwrap_or(AppState::Shutdown)
+}
+
+async fn load_catalog(context: &mut Context) -> Option<Catalog> {
+    let storage = context.storage.as_mut()?;
+    Some(Catalog::load(storage).await)
+}
+
+struct MeasurementsMenu;
+type MeasurementsMenuBuilder = impl AppMenuBuilder<MeasurementsMenuEvents>;
+
+async fn measurements_menu_builder(context: &mut Context) -> MeasurementsMenuBuilder {
+    let mut items = heapless::Vec::<_, MAX_LISTED>::new();
+
+    if let Some(catalog) = load_catalog(context).await {
+        for entry in catalog.entries().iter().rev().take(MAX_LISTED) {
+            let label = match entry.info.start_time {
+                Some(timestamp) => uformat!(
+                    16,
+                    "{}",
+                    DateTime {
+                        timestamp,
+                        with_year: false,
+                    }
+                ),
+                None => uformat!(16, "#{}", entry.id),
+            };
+            let duration = match entry.info.duration {
+                Some(duration) => uformat!(24, "{}", Seconds(duration)),
+                None => uformat!(24, "{}", BinarySize(entry.info.size as usize)),
+            };
+
+            let item = ListedMeasurement {
+                id: entry.id,
+                duration: Label(duration),
+            };
+            unwrap!(items
+                .push(
+                    MenuItem::new(label, item)
+                        .with_value_converter(|item| MeasurementsMenuEvents::Open(item.id))
+                )
+                .ok());
+        }
+    }
+
+    create_menu("Measurements")
+        .add_menu_items(items)
+        .add_item("Back", "<-", |_| MeasurementsMenuEvents::Back)
+}
+
+impl MenuScreen for MeasurementsMenu {
+    type Event = MeasurementsMenuEvents;
+    type Result = AppState;
+    type MenuBuilder = MeasurementsMenuBuilder;
+
+    async fn menu(&mut self, context: &mut Context) -> Self::MenuBuilder {
+        measurements_menu_builder(context).await
+    }
+
+    async fn handle_event(
+        &mut self,
+        event: Self::Event,
+        _context: &mut Context,
+    ) -> Option<Self::Result> {
+        match event {
+            MeasurementsMenuEvents::Open(id) => Some(AppState::Menu(AppMenu::Measurement(id))),
+            MeasurementsMenuEvents::Back => Some(AppState::Menu(AppMenu::Storage)),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum MeasurementMenuEvents {
+    Preview,
+    Upload,
+    Delete,
+    Nothing,
+    Back,
+}
+
+pub async fn measurement_menu(context: &mut Context, id: u32) -> AppState {
+    let entry = match load_catalog(context).await {
+        Some(catalog) => catalog.get(id).copied(),
+        None => None,
+    };
+    let Some(entry) = entry else {
+        context.display_message("Measurement not found").await;
+        return AppState::Menu(AppMenu::Measurements);
+    };
+
+    MeasurementMenu { entry }
+        .display(context)
+        .await
+        .unwrap_or(AppState::Shutdown)
+}
+
+struct MeasurementMenu {
+    entry: CatalogEntry,
+}
+type MeasurementMenuBuilder = impl AppMenuBuilder<MeasurementMenuEvents>;
+
+fn measurement_menu_builder(context: &mut Context, entry: &CatalogEntry) -> MeasurementMenuBuilder {
+    let info = entry.info;
+
+    let date = match info.start_time {
+        Some(timestamp) => uformat!(
+            24,
+            "{}",
+            DateTime {
+                timestamp,
+                with_year: true,
+            }
+        ),
+        None => uformat!(24, "Unknown"),
+    };
+    let duration = match info.duration {
+        Some(duration) => uformat!(24, "{}", Seconds(duration)),
+        None => uformat!(24, "Unknown"),
+    };
+    let quality = match info.quality {
+        Some(quality) => uformat!(24, "{}%", quality),
+        None => uformat!(24, "Unknown"),
+    };
+    let uploaded = match entry.upload {
+        UploadState::Pending => uformat!(24, "No"),
+        UploadState::Uploaded => uformat!(24, "Yes"),
+        UploadState::Failed => uformat!(24, "Failed"),
+    };
+
+    let mut details = heapless::Vec::<_, 5>::new();
+    for (title, value) in [
+        ("Date", date),
+        ("Length", duration),
+        ("Size", uformat!(24, "{}", BinarySize(info.size as usize))),
+        ("Quality", quality),
+        ("Uploaded", uploaded),
+    ] {
+        unwrap!(details
+            .push(
+                MenuItem::new(title, Label(value))
+                    .with_value_converter(|_| MeasurementMenuEvents::Nothing)
+            )
+            .ok());
+    }
+
+    let mut upload_item = heapless::Vec::<_, 1>::new();
+    if context.can_enable_wifi()
+        && !context.config.known_networks.is_empty()
+        && !context.config.backend_url.is_empty()
+    {
+        unwrap!(upload_item
+            .push(
+                MenuItem::new("Upload", "->")
+                    .with_value_converter(|_| MeasurementMenuEvents::Upload)
+            )
+            .ok());
+    }
+
+    create_menu("Measurement")
+        .add_menu_items(details)
+        .add_item("Preview", "->", |_| MeasurementMenuEvents::Preview)
+        .add_menu_items(upload_item)
+        .add_item("Delete", "->", |_| MeasurementMenuEvents::Delete)
+        .add_item("Back", "<-", |_| MeasurementMenuEvents::Back)
+}
+
+impl MenuScreen for MeasurementMenu {
+    type Event = MeasurementMenuEvents;
+    type Result = AppState;
+    type MenuBuilder = MeasurementMenuBuilder;
+
+    async fn menu(&mut self, context: &mut Context) -> Self::MenuBuilder {
+        measurement_menu_builder(context, &self.entry)
+    }
+
+    async fn handle_event(
+        &mut self,
+        event: Self::Event,
+        context: &mut Context,
+    ) -> Option<Self::Result> {
+        let id = self.entry.id;
+        match event {
+            MeasurementMenuEvents::Preview => {
+                preview(context, id).await;
+                // Redraw the details.
+                return Some(AppState::Menu(AppMenu::Measurement(id)));
+            }
+            MeasurementMenuEvents::Upload => {
+                upload_stored_measurement(context, id).await;
+                // Show the new upload state.
+                return Some(AppState::Menu(AppMenu::Measurement(id)));
+            }
+            MeasurementMenuEvents::Delete => {
+                let Some(storage) = context.storage.as_mut() else {
+                    return Some(AppState::Menu(AppMenu::Storage));
+                };
+
+                let mut catalog = Catalog::load(storage).await;
+                let message = match catalog.delete(storage, id).await {
+                    Ok(()) => "Measurement deleted",
+                    Err(e) => {
+                        warn!("Failed to delete measurement {}: {:?}", id, e);
+                        "Could not delete measurement"
+                    }
+                };
+                context.display_message(message).await;
+
+                return Some(AppState::Menu(AppMenu::Measurements));
+            }
+            MeasurementMenuEvents::Back => return Some(AppState::Menu(AppMenu::Measurements)),
+            MeasurementMenuEvents::Nothing => {}
+        }
+
+        None
+    }
+}
@@ -20,6 +20,7 @@ pub mod about;
 pub mod battery_info;
 pub mod display;
 pub mod main;
+pub mod measurements;
 pub mod storage;
 pub mod wifi_ap;
 pub mod wifi_sta;
@@ -30,6 +31,8 @@ pub enum AppMenu {
     Main,
     Display,
     Storage,
+    Measurements,
+    Measurement(u32),
     DeviceInfo,
     #[cfg(feature = "battery_max17055")]
     BatteryInfo,
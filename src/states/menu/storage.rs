@@ -1,11 +1,18 @@
 use crate::{
     board::{
-        config::{types::MeasurementAction, Config},
+        catalog::Catalog,
+        config::{
+            types::{KeepMeasurements, MeasurementAction, MeasurementEviction, PoorSignalAction},
+            Config,
+        },
         initialized::Context,
         storage::FileSystem,
     },
-    human_readable::BinarySize,
-    states::menu::{AppMenu, MenuScreen},
+    human_readable::{BinarySize, Seconds},
+    states::{
+        menu::{AppMenu, MenuScreen},
+        recorder::BYTES_PER_SECOND,
+    },
     uformat, AppState,
 };
 use embedded_menu::items::menu_item::{MenuItem, SelectValue};
@@ -16,8 +23,12 @@ use super::AppMenuBuilder;
 #[derive(Clone, Copy)]
 pub enum StorageMenuEvents {
     ChangeMeasurementAction(MeasurementAction),
+    ChangePoorSignalAction(PoorSignalAction),
+    ChangeKeepMeasurements(KeepMeasurements),
+    ChangeMeasurementEviction(MeasurementEviction),
     Format,
     Upload,
+    Browse,
     Nothing,
     Back,
 }
@@ -46,9 +57,10 @@ struct StorageMenu;
 type StorageMenuBuilder = impl AppMenuBuilder<StorageMenuEvents>;
 
 async fn storage_menu_builder(context: &mut Context) -> StorageMenuBuilder {
-    let mut used_item = heapless::Vec::<_, 2>::new();
+    let mut used_item = heapless::Vec::<_, 3>::new();
     let mut items = heapless::Vec::<_, 2>::new();
 
+    let policy = context.config.retention_policy();
     if let Some(storage) = context.storage.as_mut() {
         if let Ok(used) = storage.used_bytes().await {
             let used_str = UsedStorage(uformat!(
@@ -65,6 +77,29 @@ async fn storage_menu_builder(context: &mut Context) -> StorageMenuBuilder {
                 )
                 .ok());
         }
+
+        let catalog = Catalog::load(storage).await;
+        let measurements = UsedStorage(uformat!(
+            32,
+            "{} ({} new)",
+            catalog.entries().len(),
+            catalog.pending_uploads().count()
+        ));
+        unwrap!(used_item
+            .push(
+                MenuItem::new("Measurements", measurements)
+                    .with_value_converter(|_| StorageMenuEvents::Browse)
+            )
+            .ok());
+
+        let free_time = catalog.available_bytes(storage, &policy) / BYTES_PER_SECOND;
+        let free_time = UsedStorage(uformat!(32, "{}", Seconds(free_time as u32)));
+        unwrap!(used_item
+            .push(
+                MenuItem::new("Free time", free_time)
+                    .with_value_converter(|_| StorageMenuEvents::Nothing)
+            )
+            .ok());
     }
 
     if context.can_enable_wifi()
@@ -86,6 +121,21 @@ async fn storage_menu_builder(context: &mut Context) -> StorageMenuBuilder {
             context.config.measurement_action,
             StorageMenuEvents::ChangeMeasurementAction,
         )
+        .add_item(
+            "Poor signal",
+            context.config.poor_signal_action,
+            StorageMenuEvents::ChangePoorSignalAction,
+        )
+        .add_item(
+            "Keep",
+            context.config.keep_measurements,
+            StorageMenuEvents::ChangeKeepMeasurements,
+        )
+        .add_item(
+            "Delete",
+            context.config.measurement_eviction,
+            StorageMenuEvents::ChangeMeasurementEviction,
+        )
         .add_menu_items(used_item)
         .add_menu_items(items)
         .add_item("Format storage", "->", |_| StorageMenuEvents::Format)
@@ -112,6 +162,21 @@ impl MenuScreen for StorageMenu {
 
                 context.update_config(|config| config.measurement_action = action);
             }
+            StorageMenuEvents::ChangePoorSignalAction(action) => {
+                debug!("Settings changed");
+
+                context.update_config(|config| config.poor_signal_action = action);
+            }
+            StorageMenuEvents::ChangeKeepMeasurements(keep) => {
+                debug!("Settings changed");
+
+                context.update_config(|config| config.keep_measurements = keep);
+            }
+            StorageMenuEvents::ChangeMeasurementEviction(eviction) => {
+                debug!("Settings changed");
+
+                context.update_config(|config| config.measurement_eviction = eviction);
+            }
             StorageMenuEvents::Format => {
                 info!("Format requested");
                 context.display_message("Formatting storage...").await;
@@ -127,6 +192,7 @@ impl MenuScreen for StorageMenu {
                 return Some(AppState::Menu(AppMenu::Main));
             }
             StorageMenuEvents::Upload => return Some(AppState::UploadStored(AppMenu::Storage)),
+            StorageMenuEvents::Browse => return Some(AppState::Menu(AppMenu::Measurements)),
             StorageMenuEvents::Back => return Some(AppState::Menu(AppMenu::Main)),
             StorageMenuEvents::Nothing => {}
         }
@@ -44,6 +44,7 @@ pub async fn wifi_ap(context: &mut Context) -> AppState {
     let web_context = Rc::new(SharedWebContext::new(WebContext {
         known_networks: context.config.known_networks.clone(),
         backend_url: context.config.backend_url.clone(),
+        server_key_pin: context.config.server_key_pin.clone(),
     }));
 
     let webserver_task_control = [(); WEBSERVER_TASKS].map(|_| TaskController::new());
@@ -118,6 +119,13 @@ pub async fn wifi_ap(context: &mut Context) -> AppState {
             }
             if web_context.backend_url != config.backend_url {
                 config.backend_url.clone_from(&web_context.backend_url);
+                // The token was issued by the previous backend.
+                config.device_token.clear();
+            }
+            if web_context.server_key_pin != config.server_key_pin {
+                config
+                    .server_key_pin
+                    .clone_from(&web_context.server_key_pin);
             }
         });
     }
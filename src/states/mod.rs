@@ -1,11 +1,17 @@
 #[cfg(feature = "hw_v1")]
 pub mod adc_setup;
+pub mod backend;
 pub mod charging;
+pub mod chunked_upload;
 pub mod display_serial;
 pub mod firmware_update;
+pub mod holter;
 pub mod init;
 pub mod measure;
 pub mod menu;
+pub mod pairing;
+pub mod preview;
+pub mod recorder;
 pub mod throughput;
 pub mod upload_or_store_measurement;
 
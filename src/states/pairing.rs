@@ -0,0 +1,217 @@
+//! Registering the device with the backend.
+//!
+//! 1. `POST {backend}/pairing/{serial}` returns a short pairing code.
+//! 2. The code is displayed as a QR code, which the user scans with the app to claim the device.
+//! 3. `GET {backend}/pairing/{serial}/{code}` is polled until the device has been claimed. The
+//!    backend responds with `202 Accepted` while waiting, and with the device token once the
+//!    device is claimed. `404 Not Found` means that the code has expired.
+//!
+//! The token is stored in the configuration. It is never sent again, later requests are signed
+//! with it (see [`request_signing`]).
+
+use core::str;
+
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_graphics::Drawable;
+use embedded_nal_async::{Dns, TcpConnect};
+use gui::screens::qr::QrCodeScreen;
+use reqwless::{client::HttpClient, request::Method, response::Status};
+
+use crate::{
+    board::initialized::{Context, StaMode},
+    states::{
+        backend::{Backend, BackendError, Request, ResponseBuffer},
+        menu::AppMenu,
+        MIN_FRAME_TIME,
+    },
+    timeout::Timeout,
+    uformat, AppState, SerialNumber,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the pairing code is displayed.
+const PAIRING_TIME: Duration = Duration::from_secs(5 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, PartialEq)]
+enum PairingError {
+    WifiNotEnabled,
+    WifiNotConnected,
+    InternalError,
+    RequestFailed,
+    InvalidResponse,
+    Expired,
+    Cancelled,
+}
+
+enum PollResult {
+    Waiting,
+    Claimed(heapless::String<64>),
+}
+
+pub async fn pairing(context: &mut Context) -> AppState {
+    let message = match pair(context).await {
+        Ok(()) => "Device registered",
+        Err(PairingError::WifiNotEnabled) => "WiFi not enabled",
+        Err(PairingError::WifiNotConnected) => "Could not connect to WiFi",
+        Err(PairingError::InternalError) => "Pairing failed: internal error",
+        Err(PairingError::RequestFailed) => "Failed to reach server",
+        Err(PairingError::InvalidResponse) => "Pairing failed: invalid response",
+        Err(PairingError::Expired) => "Pairing code expired",
+        Err(PairingError::Cancelled) => "Pairing cancelled",
+    };
+
+    context.display_message(message).await;
+
+    AppState::Menu(AppMenu::Main)
+}
+
+async fn pair(context: &mut Context) -> Result<(), PairingError> {
+    let sta = if let Some(sta) = context.enable_wifi_sta(StaMode::Enable).await {
+        if sta.wait_for_connection(context).await {
+            sta
+        } else {
+            return Err(PairingError::WifiNotConnected);
+        }
+    } else {
+        return Err(PairingError::WifiNotEnabled);
+    };
+
+    context.display_message("Requesting pairing code").await;
+
+    let backend = Backend::for_pairing(context);
+    let Ok(mut client_resources) = backend.client_resources(&sta) else {
+        return Err(PairingError::InternalError);
+    };
+    let mut client = client_resources.client();
+
+    let code = request_code(&mut client, &backend).await?;
+
+    let qr_content = uformat!(48, "Card/IO:{}:{}", SerialNumber, code.as_str());
+
+    let mut ticker = Ticker::every(MIN_FRAME_TIME);
+    let timeout = Timeout::new(PAIRING_TIME);
+    let mut next_poll = Instant::now() + POLL_INTERVAL;
+    // The touch that opened this screen does not cancel pairing.
+    let mut was_touched = true;
+
+    while !timeout.is_elapsed() {
+        let touched = context.frontend.is_touched();
+        if touched && !was_touched {
+            return Err(PairingError::Cancelled);
+        }
+        was_touched = touched;
+
+        if context.battery_monitor.is_low() {
+            return Err(PairingError::Cancelled);
+        }
+
+        context
+            .with_status_bar(|display| {
+                QrCodeScreen {
+                    message: qr_content.as_str(),
+                    countdown: Some(timeout.remaining().as_secs() as usize),
+                    invert: false,
+                }
+                .draw(display)
+            })
+            .await;
+
+        if Instant::now() >= next_poll {
+            match poll(&mut client, &backend, &code).await {
+                Ok(PollResult::Waiting) => {}
+                // A missed poll is retried.
+                Err(PairingError::RequestFailed) => {}
+                Ok(PollResult::Claimed(token)) => {
+                    context.update_config(|config| config.device_token = token);
+                    context.save_config().await;
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+            next_poll = Instant::now() + POLL_INTERVAL;
+        }
+
+        ticker.next().await;
+    }
+
+    Err(PairingError::Expired)
+}
+
+/// Returns the trimmed response body if it only contains characters that are safe to put in a URL
+/// or a header.
+fn parse_token<const N: usize>(body: &[u8]) -> Result<heapless::String<N>, PairingError> {
+    let token = str::from_utf8(body).map(str::trim).unwrap_or("");
+    let valid = !token.is_empty()
+        && token
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_' || c == b'.');
+
+    match heapless::String::try_from(token) {
+        Ok(token) if valid => Ok(token),
+        _ => {
+            warn!("Invalid response: {:?}", body);
+            Err(PairingError::InvalidResponse)
+        }
+    }
+}
+
+async fn request_code<T, DNS>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+) -> Result<heapless::String<16>, PairingError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+{
+    let path = uformat!(48, "/pairing/{}", SerialNumber);
+    let request = Request::new(Method::POST, &path, REQUEST_TIMEOUT);
+
+    debug!("Requesting pairing code");
+
+    let mut rx_buffer = [0; 512];
+    let mut body = ResponseBuffer::<64>::new();
+    match backend
+        .request(client, request, &mut rx_buffer, &mut body)
+        .await
+    {
+        Ok(Status::Ok | Status::Created) => parse_token(&body.0),
+        Ok(status) => {
+            warn!("Failed to request pairing code: {:?}", status);
+            Err(PairingError::RequestFailed)
+        }
+        Err(BackendError::Internal) => Err(PairingError::InternalError),
+        Err(_) => Err(PairingError::RequestFailed),
+    }
+}
+
+async fn poll<T, DNS>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    code: &str,
+) -> Result<PollResult, PairingError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+{
+    let path = uformat!(64, "/pairing/{}/{}", SerialNumber, code);
+    let request = Request::new(Method::GET, &path, REQUEST_TIMEOUT);
+
+    let mut rx_buffer = [0; 512];
+    let mut body = ResponseBuffer::<128>::new();
+    match backend
+        .request(client, request, &mut rx_buffer, &mut body)
+        .await
+    {
+        Ok(Status::Ok) => parse_token(&body.0).map(PollResult::Claimed),
+        Ok(Status::Accepted) => Ok(PollResult::Waiting),
+        Ok(Status::NotFound) => Err(PairingError::Expired),
+        Ok(status) => {
+            warn!("Pairing poll failed: {:?}", status);
+            Err(PairingError::RequestFailed)
+        }
+        Err(BackendError::Internal) => Err(PairingError::InternalError),
+        Err(_) => Err(PairingError::RequestFailed),
+    }
+}
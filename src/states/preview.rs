@@ -0,0 +1,196 @@
+//! Plays back a stored measurement on the ECG screen, at the speed it was recorded.
+
+use embassy_time::{Instant, Ticker};
+use embedded_graphics::Drawable;
+use gui::screens::measure::EcgScreen;
+use norfs::{medium::StorageMedium, read_dir::DirEntry, Storage, StorageError};
+use signal_processing::{
+    compressing_buffer::{EkgDecoder, EkgFormat},
+    measurement::MeasurementHeader,
+};
+
+use crate::{
+    board::{
+        catalog::{measurement_file_name, read_layout, skip},
+        initialized::Context,
+    },
+    states::{measure::create_ecg_objects, TouchInputShaper, MIN_FRAME_TIME},
+};
+
+/// Samples decoded at once.
+const CHUNK_SAMPLES: usize = 32;
+
+/// Plays back the measurement `id` until it ends or the screen is touched.
+pub async fn preview(context: &mut Context, id: u32) {
+    context.display_message("Loading...").await;
+
+    let mut ecg = create_ecg_objects(context);
+
+    let Some(storage) = context.storage.as_mut() else {
+        context.display_message("Storage not available").await;
+        return;
+    };
+
+    let mut samples = match SampleReader::open(storage, id).await {
+        Ok(Some(samples)) => samples,
+        Ok(None) => {
+            context.display_message("Preview not available").await;
+            return;
+        }
+        Err(e) => {
+            warn!("Failed to open measurement {}: {:?}", id, e);
+            context.display_message("Could not read measurement").await;
+            return;
+        }
+    };
+
+    let mut screen = EcgScreen::new();
+    let mut input = TouchInputShaper::new();
+    let mut ticker = Ticker::every(MIN_FRAME_TIME);
+
+    let started = Instant::now();
+    let mut played = 0;
+    let mut finished = false;
+
+    while !finished && !context.inner.battery_monitor.is_low() {
+        input.update(&mut context.frontend);
+        if input.is_touched() {
+            break;
+        }
+
+        // Catch up with the time that has passed since the playback started.
+        let due = started.elapsed().as_millis() as usize * samples.sample_rate / 1000;
+        while played < due {
+            let mut chunk = [0; CHUNK_SAMPLES];
+            let wanted = (due - played).min(CHUNK_SAMPLES);
+
+            let mut count = 0;
+            while count < wanted {
+                match samples.next(storage).await {
+                    Ok(Some(sample)) => {
+                        chunk[count] = sample;
+                        count += 1;
+                    }
+                    Ok(None) => {
+                        finished = true;
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("Failed to read measurement {}: {:?}", id, e);
+                        finished = true;
+                        break;
+                    }
+                }
+            }
+
+            ecg.process_recorded(&chunk[..count], &mut screen);
+            played += count;
+
+            if finished {
+                break;
+            }
+        }
+
+        screen.update_heart_rate(ecg.heart_rate_calculator.current_hr());
+        screen.elapsed_secs = played / samples.sample_rate;
+
+        context
+            .inner
+            .with_status_bar(|display| screen.draw(display))
+            .await;
+
+        ticker.next().await;
+    }
+}
+
+/// Decodes the samples of a stored measurement.
+struct SampleReader<M>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    file: Option<DirEntry<M>>,
+    decoder: EkgDecoder,
+    sample_rate: usize,
+    /// Compressed samples read from the file. `buffer[start..end]` are not decoded yet.
+    buffer: [u8; 2 * EkgFormat::MAX_BLOCK_SIZE],
+    start: usize,
+    end: usize,
+    /// Compressed bytes left in the file.
+    remaining: usize,
+}
+
+impl<M> SampleReader<M>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    /// Opens a measurement. Returns `None` for formats that can't be played back.
+    async fn open(storage: &mut Storage<M>, id: u32) -> Result<Option<Self>, StorageError> {
+        let name = measurement_file_name(id);
+        let file = DirEntry::from_reader(storage.read(&name).await?);
+        let size = file.size(storage).await?;
+
+        let Some(layout) = read_layout(storage, id, size).await? else {
+            return Ok(None);
+        };
+        let Ok((header, _)) = MeasurementHeader::parse(&layout.header) else {
+            return Ok(None);
+        };
+        let Some(decoder) = EkgDecoder::new(header.sample_format) else {
+            return Ok(None);
+        };
+
+        let file = DirEntry::from_reader(storage.read(&name).await?);
+        let file = skip(file, storage, layout.samples_offset).await?;
+
+        Ok(Some(Self {
+            file: Some(file),
+            decoder,
+            sample_rate: header.sample_rate.filter(|rate| *rate > 0).unwrap_or(1000) as usize,
+            buffer: [0; 2 * EkgFormat::MAX_BLOCK_SIZE],
+            start: 0,
+            end: 0,
+            remaining: layout.samples_len,
+        }))
+    }
+
+    /// Returns the next sample, or `None` at the end of the measurement.
+    async fn next(&mut self, storage: &mut Storage<M>) -> Result<Option<i32>, StorageError> {
+        // A whole block must be available to decode the next sample.
+        if self.end - self.start < EkgFormat::MAX_BLOCK_SIZE && self.remaining > 0 {
+            self.refill(storage).await?;
+        }
+
+        let mut unread = &self.buffer[self.start..self.end];
+        let sample = self.decoder.read(&mut unread).unwrap_or_else(|_| {
+            warn!("Failed to decode samples");
+            None
+        });
+        self.start = self.end - unread.len();
+
+        Ok(sample)
+    }
+
+    async fn refill(&mut self, storage: &mut Storage<M>) -> Result<(), StorageError> {
+        self.buffer.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+
+        let len = self.remaining.min(self.buffer.len() - self.end);
+        let Some(file) = self.file.take() else {
+            return Ok(());
+        };
+
+        let mut reader = file.open();
+        reader
+            .read_all(storage, &mut self.buffer[self.end..self.end + len])
+            .await?;
+        self.file = Some(DirEntry::from_reader(reader));
+
+        self.end += len;
+        self.remaining -= len;
+
+        Ok(())
+    }
+}
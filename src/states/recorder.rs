@@ -0,0 +1,333 @@
+//! Streams compressed ECG samples into a file while measuring.
+//!
+//! The measurement loop pushes samples into a [`RecordingStream`], which compresses them into a
+//! buffer. Full buffers are handed over to the [`RecordingPipe::store`] future, which writes them
+//! into a file while the other buffers are being filled. This way flash erase and write stalls
+//! don't block the measurement.
+
+use core::cell::Cell;
+
+use alloc::vec::Vec;
+use embassy_futures::yield_now;
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use norfs::{medium::StorageMedium, writer::FileDataWriter, OnCollision, Storage, StorageError};
+use signal_processing::compressing_buffer::EkgFormat;
+
+/// Size of a single buffer. At about 1.2 bytes per sample, a buffer lasts for more than 3 seconds.
+const BUFFER_SIZE: usize = 4096;
+
+/// The longest recording we store, in samples.
+pub const MAX_RECORDING_SAMPLES: usize = 10 * 60 * 1000;
+
+/// Typical size of a second of compressed samples, in bytes.
+pub const BYTES_PER_SECOND: usize = 1200;
+
+/// Returns the space to reserve for recording `samples` samples. Noisy signals compress worse
+/// than usual, so this leaves some headroom.
+pub const fn recording_size(samples: usize) -> usize {
+    samples / 1000 * BYTES_PER_SECOND * 5 / 4
+}
+
+/// The most buffers a pipe can have.
+pub const MAX_BUFFERS: usize = 8;
+
+/// A message from a [`RecordingStream`] to the file writer.
+enum Block {
+    /// A buffer of compressed samples.
+    Samples(Vec<u8>),
+    /// Data written after the samples. Not returned to the stream.
+    Trailer(Vec<u8>),
+    /// Marks the end of the file.
+    End,
+    /// No more recordings will be written.
+    Close,
+}
+
+/// Connects [`RecordingStream`]s to the files they are stored in.
+pub struct RecordingPipe {
+    /// Blocks waiting to be written. Every buffer fits, with room for the ends of two files and
+    /// closing the pipe.
+    full: Channel<NoopRawMutex, Block, { MAX_BUFFERS + 5 }>,
+    /// Buffers that can be filled.
+    empty: Channel<NoopRawMutex, Vec<u8>, MAX_BUFFERS>,
+    /// The first block of the next file, received while waiting for it.
+    next: Cell<Option<Block>>,
+    /// Set when writing a file failed. Streams stop recording.
+    failed: Cell<bool>,
+}
+
+impl RecordingPipe {
+    /// Allocates a pipe with `buffers` buffers, at most [`MAX_BUFFERS`]. More buffers can bridge
+    /// longer stalls.
+    pub fn new(buffers: usize) -> Result<Self, ()> {
+        let pipe = Self {
+            full: Channel::new(),
+            empty: Channel::new(),
+            next: Cell::new(None),
+            failed: Cell::new(false),
+        };
+
+        for _ in 0..buffers.min(MAX_BUFFERS) {
+            let mut buffer = Vec::new();
+            if buffer.try_reserve_exact(BUFFER_SIZE).is_err() {
+                return Err(());
+            }
+            unwrap!(pipe.empty.try_send(buffer).ok());
+        }
+
+        Ok(pipe)
+    }
+
+    /// Starts a new stream that writes `prefix` before the samples, if a buffer is available to
+    /// record into.
+    pub fn try_stream(&self, prefix: &[u8]) -> Option<RecordingStream<'_>> {
+        let mut buffer = self.empty.try_receive().ok()?;
+        buffer.extend_from_slice(prefix);
+        Some(RecordingStream {
+            pipe: self,
+            format: EkgFormat::new(),
+            buffer: Some(buffer),
+            samples: 0,
+            bytes: 0,
+            stopped: false,
+        })
+    }
+
+    /// Returns whether writing a file failed.
+    pub fn is_failed(&self) -> bool {
+        self.failed.get()
+    }
+
+    /// Stops the streams, because their files can't be written.
+    pub fn fail(&self) {
+        self.failed.set(true);
+    }
+
+    /// Signals [`RecordingPipe::wait_for_recording`] that no more recordings will be written.
+    pub fn close(&self) {
+        unwrap!(self.full.try_send(Block::Close).ok());
+    }
+
+    /// Waits until the next stream has data to write. Returns `false` if the pipe was closed.
+    pub async fn wait_for_recording(&self) -> bool {
+        loop {
+            let block = match self.next.take() {
+                Some(block) => block,
+                None => self.full.receive().await,
+            };
+
+            // Streams that ended without writing anything don't need a file.
+            if matches!(block, Block::End) {
+                continue;
+            }
+
+            let recording = !matches!(block, Block::Close);
+            self.next.set(Some(block));
+
+            return recording;
+        }
+    }
+
+    /// Writes the buffers of the stream into the file `name` until the stream is finished.
+    pub async fn store<M>(&self, storage: &mut Storage<M>, name: &str) -> Result<(), StorageError>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        let result = storage
+            .store_writer(name, &RecordingWriter(self), OnCollision::Overwrite)
+            .await;
+
+        if result.is_err() {
+            self.fail();
+        }
+
+        result
+    }
+}
+
+struct RecordingWriter<'a>(&'a RecordingPipe);
+
+impl FileDataWriter for RecordingWriter<'_> {
+    async fn write<M>(
+        &self,
+        writer: &mut norfs::writer::Writer<M>,
+        storage: &mut Storage<M>,
+    ) -> Result<(), StorageError>
+    where
+        M: StorageMedium,
+        [(); M::BLOCK_COUNT]:,
+    {
+        let pipe = self.0;
+        loop {
+            let block = match pipe.next.take() {
+                Some(block) => block,
+                None => pipe.full.receive().await,
+            };
+
+            let (mut buffer, is_samples) = match block {
+                Block::Samples(buffer) => (buffer, true),
+                Block::Trailer(buffer) => (buffer, false),
+                Block::End => return Ok(()),
+                Block::Close => {
+                    // Let `wait_for_recording` see it.
+                    pipe.next.set(Some(Block::Close));
+                    return Ok(());
+                }
+            };
+
+            // Write in small pieces, so that the measurement can process the queued samples.
+            let mut result = Ok(());
+            for chunk in buffer.chunks(256) {
+                result = writer.bind(storage).write_all(chunk).await;
+                if result.is_err() {
+                    break;
+                }
+                yield_now().await;
+            }
+
+            if is_samples {
+                buffer.clear();
+                unwrap!(pipe.empty.try_send(buffer).ok());
+            }
+
+            result?;
+        }
+    }
+
+    fn estimate_length(&self) -> usize {
+        // We don't know how long the recording will be, but two buffers need to fit.
+        2 * BUFFER_SIZE
+    }
+}
+
+/// The length of a finished recording.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RecordedFile {
+    /// The number of samples stored.
+    pub samples: usize,
+    /// The size of the compressed samples, in bytes.
+    pub bytes: usize,
+}
+
+/// Compresses samples into the buffers of a [`RecordingPipe`].
+pub struct RecordingStream<'a> {
+    pipe: &'a RecordingPipe,
+    format: EkgFormat,
+    buffer: Option<Vec<u8>>,
+    samples: usize,
+    bytes: usize,
+    stopped: bool,
+}
+
+impl RecordingStream<'_> {
+    /// Returns whether new samples are recorded.
+    pub fn is_recording(&self) -> bool {
+        !self.stopped && !self.pipe.is_failed()
+    }
+
+    /// Returns whether the file can't be written.
+    pub fn is_failed(&self) -> bool {
+        self.pipe.is_failed()
+    }
+
+    /// Returns the number of samples recorded.
+    pub fn len(&self) -> usize {
+        self.samples
+    }
+
+    /// Compresses a sample. Returns `false` if the recording has stopped and the sample was
+    /// dropped.
+    pub fn push(&mut self, sample: i32) -> bool {
+        if !self.is_recording() {
+            return false;
+        }
+
+        if self.samples == MAX_RECORDING_SAMPLES {
+            info!("Maximum recording length reached");
+            self.stopped = true;
+            return false;
+        }
+
+        let mut block = [0; EkgFormat::MAX_BLOCK_SIZE];
+        let bytes = unwrap!(self.format.write(sample, &mut &mut block[..]));
+
+        self.samples += 1;
+        self.append(&block[..bytes])
+    }
+
+    fn append(&mut self, block: &[u8]) -> bool {
+        if block.is_empty() {
+            return true;
+        }
+
+        let buffer_full = self
+            .buffer
+            .as_ref()
+            .map_or(true, |buffer| buffer.len() + block.len() > BUFFER_SIZE);
+        if buffer_full {
+            if let Some(buffer) = self.buffer.take() {
+                unwrap!(self.pipe.full.try_send(Block::Samples(buffer)).ok());
+            }
+            self.buffer = self.pipe.empty.try_receive().ok();
+        }
+
+        let Some(buffer) = self.buffer.as_mut() else {
+            // All buffers are waiting to be written. The samples in the current block are lost,
+            // so we can't continue the recording.
+            warn!("Recording buffer overrun");
+            self.samples -= block[0] as usize;
+            self.stopped = true;
+            return false;
+        };
+
+        buffer.extend_from_slice(block);
+        self.bytes += block.len();
+
+        true
+    }
+
+    fn flush(&mut self) {
+        // After an overrun, the samples waiting in the encoder don't belong to the recording.
+        if self.buffer.is_some() {
+            let mut block = [0; EkgFormat::MAX_BLOCK_SIZE];
+            let bytes = unwrap!(self.format.flush(&mut &mut block[..]));
+            self.append(&block[..bytes]);
+        }
+    }
+
+    fn send_buffer(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            if buffer.is_empty() {
+                unwrap!(self.pipe.empty.try_send(buffer).ok());
+            } else {
+                unwrap!(self.pipe.full.try_send(Block::Samples(buffer)).ok());
+            }
+        }
+    }
+
+    /// Writes out the remaining samples, then `trailer` followed by the length of the samples as
+    /// `u32`, and closes the file. This is how measurements in the
+    /// [streamed format](signal_processing::measurement::STREAMED_FORMAT_VERSION) end.
+    pub fn finish_with_trailer(mut self, mut trailer: Vec<u8>) -> RecordedFile {
+        self.flush();
+        self.send_buffer();
+        trailer.extend_from_slice(&(self.bytes as u32).to_le_bytes());
+        unwrap!(self.pipe.full.try_send(Block::Trailer(trailer)).ok());
+
+        RecordedFile {
+            samples: self.samples,
+            bytes: self.bytes,
+        }
+    }
+}
+
+impl Drop for RecordingStream<'_> {
+    fn drop(&mut self) {
+        self.send_buffer();
+
+        // Ends the recording.
+        unwrap!(self.pipe.full.try_send(Block::End).ok());
+    }
+}
@@ -1,26 +1,29 @@
-use core::cell::Cell;
+use core::{cell::Cell, convert::Infallible};
 
 use embassy_futures::select::{select, Either};
-use embassy_time::{with_timeout, Duration, Instant, Timer};
-use embedded_io_async::BufRead;
+use embassy_time::{Duration, Instant, Timer};
 use reqwless::{request::Method, response::Status};
 use ufmt::{uwrite, uwriteln};
 
 use crate::{
     board::initialized::{Context, StaMode},
     human_readable::{BinarySize, Throughput},
-    states::menu::AppMenu,
+    states::{
+        backend::{Backend, BackendError, Request, ResponseSink},
+        menu::AppMenu,
+    },
     AppState, SerialNumber,
 };
 
-const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
 const READ_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Clone, Copy, PartialEq)]
 enum TestError {
+    NotRegistered,
     WifiNotEnabled,
     WifiNotConnected,
     InternalError,
+    TimeUnknown,
     HttpConnectionFailed,
     HttpConnectionTimeout,
     HttpRequestTimeout,
@@ -49,9 +52,11 @@ pub async fn throughput(context: &mut Context) -> AppState {
             &message
         }
         TestResult::Failed(e) => match e {
+            TestError::NotRegistered => "Device not registered",
             TestError::WifiNotEnabled => "WiFi not enabled",
             TestError::WifiNotConnected => "Could not connect to WiFi",
             TestError::InternalError => "Test failed: internal error",
+            TestError::TimeUnknown => "Test failed: time unknown",
             TestError::HttpConnectionFailed => "Failed to connect to server",
             TestError::HttpConnectionTimeout => "Connection to server timed out",
             TestError::HttpRequestTimeout => "Test request timed out",
@@ -67,6 +72,10 @@ pub async fn throughput(context: &mut Context) -> AppState {
 }
 
 async fn run_test(context: &mut Context) -> TestResult {
+    if !context.config.is_registered() {
+        return TestResult::Failed(TestError::NotRegistered);
+    }
+
     let sta = if let Some(sta) = context.enable_wifi_sta(StaMode::Enable).await {
         if sta.wait_for_connection(context).await {
             sta
@@ -77,16 +86,17 @@ async fn run_test(context: &mut Context) -> TestResult {
         return TestResult::Failed(TestError::WifiNotEnabled);
     };
 
-    let Ok(mut client_resources) = sta.https_client_resources() else {
+    let backend = unwrap!(Backend::new(context));
+
+    let Ok(mut client_resources) = backend.client_resources(&sta) else {
         return TestResult::Failed(TestError::InternalError);
     };
     let mut client = client_resources.client();
 
-    let mut url = heapless::String::<128>::new();
+    let mut path = heapless::String::<96>::new();
     if uwrite!(
-        &mut url,
-        "{}/firmware/{}/{}/0000000",
-        context.config.backend_url.as_str(),
+        &mut path,
+        "/firmware/{}/{}/0000000",
         env!("HW_VERSION"),
         SerialNumber
     )
@@ -96,90 +106,31 @@ async fn run_test(context: &mut Context) -> TestResult {
         return TestResult::Failed(TestError::InternalError);
     }
 
-    debug!("Testing throughput using {}", url.as_str());
-
-    let connect = with_timeout(CONNECT_TIMEOUT, async {
-        let futures = select(client.request(Method::GET, &url), async {
-            loop {
-                // A message is displayed for at least 300ms so we don't need to wait here.
-                context.display_message("Connecting to server...").await;
-            }
-        });
-        match futures.await {
-            Either::First(request) => request,
-            Either::Second(_) => unreachable!(),
-        }
-    });
+    debug!("Testing throughput using {}", path.as_str());
 
-    let mut request = match connect.await {
-        Ok(Ok(request)) => request,
-        Ok(Err(e)) => {
-            warn!("HTTP connect error: {:?}", e);
-            return TestResult::Failed(TestError::HttpConnectionFailed);
-        }
-        _ => return TestResult::Failed(TestError::HttpConnectionTimeout),
+    let started = Cell::new(None);
+    let size = Cell::new(None);
+    let received_since = Cell::new(0);
+    let mut sink = CountingSink {
+        started: &started,
+        size: &size,
+        received: &received_since,
     };
 
+    let request = Request::new(Method::GET, &path, READ_TIMEOUT);
     let mut rx_buffer = [0; 4096];
-    let result = match with_timeout(READ_TIMEOUT, request.send(&mut rx_buffer)).await {
-        Ok(result) => result,
-        _ => return TestResult::Failed(TestError::HttpRequestTimeout),
-    };
-
-    let response = match result {
-        Ok(response) => match response.status {
-            Status::Ok => response,
-            _ => {
-                warn!("HTTP response error: {:?}", response.status);
-                return TestResult::Failed(TestError::HttpRequestFailed);
-            }
-        },
-        Err(e) => {
-            warn!("HTTP response error: {:?}", e);
-            return TestResult::Failed(TestError::HttpRequestFailed);
-        }
-    };
-
-    for header in response.headers() {
-        if !header.0.is_empty() {
-            debug!(
-                "Header {}: {}",
-                header.0,
-                core::str::from_utf8(header.1).unwrap_or("not a string")
-            );
-        }
-    }
-
-    let size = response.content_length;
     let mut received_total = 0;
-
-    let mut reader = response.body().reader();
-
-    let started = Instant::now();
-    let received_since = Cell::new(0);
     let result = select(
-        async {
-            loop {
-                match with_timeout(READ_TIMEOUT, reader.fill_buf()).await {
-                    Ok(result) => match result {
-                        Ok(&[]) => break None,
-                        Ok(read) => {
-                            let read_len = read.len();
-                            received_since.set(received_since.get() + read_len);
-                            reader.consume(read_len);
-                        }
-                        Err(e) => {
-                            warn!("HTTP read error: {:?}", e);
-                            break Some(TestError::DownloadFailed);
-                        }
-                    },
-                    _ => break Some(TestError::DownloadTimeout),
-                };
-            }
-        },
+        backend.request(&mut client, request, &mut rx_buffer, &mut sink),
         async {
             let mut last_print = Instant::now();
             loop {
+                let Some(started) = started.get() else {
+                    // A message is displayed for at least 300ms so we don't need to wait here.
+                    context.display_message("Connecting to server...").await;
+                    continue;
+                };
+
                 Timer::after(Duration::from_millis(500)).await;
                 let received = received_since.take();
                 received_total += received;
@@ -189,19 +140,70 @@ async fn run_test(context: &mut Context) -> TestResult {
 
                 last_print = Instant::now();
 
-                print_progress(context, received_total, size, speed, avg_speed).await;
+                print_progress(context, received_total, size.get(), speed, avg_speed).await;
             }
         },
     )
     .await;
 
+    let result = match result {
+        Either::First(result) => result,
+        Either::Second(_) => unreachable!(),
+    };
+
     match result {
-        Either::First(Some(error)) => TestResult::Failed(error),
-        Either::First(None) => TestResult::Success(Throughput(
+        Ok(Status::Ok) => TestResult::Success(Throughput(
             received_total + received_since.get(),
-            started.elapsed(),
+            unwrap!(started.get()).elapsed(),
         )),
-        Either::Second(_) => unreachable!(),
+        Ok(status) => {
+            warn!("HTTP response error: {:?}", status);
+            TestResult::Failed(TestError::HttpRequestFailed)
+        }
+        Err(e) => TestResult::Failed(match e {
+            BackendError::Internal => TestError::InternalError,
+            BackendError::TimeUnknown => TestError::TimeUnknown,
+            BackendError::Connect => TestError::HttpConnectionFailed,
+            BackendError::ConnectTimeout => TestError::HttpConnectionTimeout,
+            BackendError::Request => TestError::HttpRequestFailed,
+            BackendError::RequestTimeout => TestError::HttpRequestTimeout,
+            BackendError::Read => TestError::DownloadFailed,
+            BackendError::ReadTimeout => TestError::DownloadTimeout,
+            BackendError::Sink(never) => match never {},
+        }),
+    }
+}
+
+/// Counts the received bytes.
+struct CountingSink<'a> {
+    /// Set when the download starts.
+    started: &'a Cell<Option<Instant>>,
+    size: &'a Cell<Option<usize>>,
+    /// Bytes received since the progress was last displayed.
+    received: &'a Cell<usize>,
+}
+
+impl ResponseSink for CountingSink<'_> {
+    type Error = Infallible;
+
+    async fn start(
+        &mut self,
+        status: Status,
+        content_length: Option<usize>,
+    ) -> Result<bool, Self::Error> {
+        if status != Status::Ok {
+            return Ok(false);
+        }
+
+        self.size.set(content_length);
+        self.started.set(Some(Instant::now()));
+
+        Ok(true)
+    }
+
+    async fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.received.set(self.received.get() + data.len());
+        Ok(())
     }
 }
 
@@ -1,10 +1,10 @@
 use core::{
+    cell::{Cell, RefCell},
     mem::{self, MaybeUninit},
-    str,
 };
 
 use alloc::{boxed::Box, vec::Vec};
-use embassy_time::{with_timeout, Duration};
+use embassy_time::{Duration, Instant};
 use embedded_menu::items::menu_item::{MenuItem, SelectValue};
 use embedded_nal_async::{Dns, TcpConnect};
 use gui::screens::create_menu;
@@ -12,21 +12,35 @@ use norfs::{
     medium::StorageMedium, read_dir::DirEntry, writer::FileDataWriter, OnCollision, Storage,
     StorageError,
 };
+use request_signing::Digest;
 use reqwless::{
     client::HttpClient,
-    request::{Method, RequestBody, RequestBuilder},
+    request::{Method, RequestBody},
     response::Status,
 };
-use signal_processing::compressing_buffer::{CompressingBuffer, EkgFormat};
-use ufmt::uwrite;
+use signal_processing::{
+    heart_rate::Beat,
+    measurement::{
+        MeasurementHeader, FORMAT_VERSION, STREAMED_FORMAT_VERSION, UPLOAD_FORMAT_VERSION,
+    },
+    rhythm::Rhythm,
+};
 
 use crate::{
     board::{
-        config::types::MeasurementAction,
+        catalog::{measurement_file_name, Catalog, CatalogEntry, MeasurementInfo, UploadState},
+        config::types::{MeasurementAction, PoorSignalAction},
+        drivers::frontend,
         initialized::{Context, InnerContext, StaMode},
     },
     human_readable::BinarySize,
-    states::menu::{AppMenuBuilder, MenuScreen},
+    states::{
+        backend::{hash_body, Backend, IgnoreBody, Request},
+        chunked_upload::{upload_chunked, ChunkedUpload, CHUNKED_UPLOAD_THRESHOLD},
+        measure::{EcgRecording, RecordedSamples},
+        menu::{AppMenuBuilder, MenuScreen},
+        MESSAGE_DURATION,
+    },
     uformat, AppState, SerialNumber,
 };
 
@@ -44,17 +58,66 @@ pub async fn upload_stored_measurements(context: &mut Context, next_state: AppSt
     next_state
 }
 
-pub async fn upload_or_store_measurement<const SIZE: usize>(
+pub async fn upload_or_store_measurement(
     context: &mut Context,
-    mut buffer: Box<CompressingBuffer<SIZE>>,
+    recording: EcgRecording,
     next_state: AppState,
 ) -> AppState {
-    let sample_count = buffer.len();
-    let samples = buffer.make_contiguous();
+    let file_id = match recording.samples {
+        RecordedSamples::File { id, .. } => Some(id),
+        RecordedSamples::Memory(_) => None,
+    };
+
+    let next_state = upload_or_store(context, recording, next_state).await;
 
-    const SAMPLE_RATE: usize = 1000; // samples/sec
+    if let (Some(id), Some(storage)) = (file_id, context.storage.as_mut()) {
+        // A recording that has been uploaded or discarded is not in the catalog.
+        if Catalog::load(storage).await.get(id).is_none() {
+            if let Err(e) = storage.delete(&measurement_file_name(id)).await {
+                warn!("Failed to delete recording: {:?}", e);
+            }
+        }
+    }
 
-    debug!("Measurement length: {} samples", sample_count);
+    next_state
+}
+
+async fn upload_or_store(
+    context: &mut Context,
+    mut recording: EcgRecording,
+    next_state: AppState,
+) -> AppState {
+    let sample_count = recording.samples.len();
+    let header = recording.header();
+    let mut info = MeasurementInfo::from_header(0, &header);
+    let Ok(header) = encode_header(&header) else {
+        context.display_message("Out of memory").await;
+        return next_state;
+    };
+    let measurement = match &mut recording.samples {
+        RecordedSamples::Memory(samples) => {
+            samples.make_contiguous();
+            let recording = RecordingRef {
+                header: &header,
+                samples: samples.as_slices().0,
+                beats: &recording.beats,
+            };
+            info.size = (1 + recording.payload_len()) as u32;
+            NewMeasurement::Memory(recording)
+        }
+        RecordedSamples::File { id, file } => {
+            info.size = (1 + payload_len(header.len(), file.bytes, recording.beats.len())) as u32;
+            NewMeasurement::File(*id)
+        }
+    };
+
+    const SAMPLE_RATE: usize = frontend::SAMPLE_RATE as usize;
+
+    debug!(
+        "Measurement length: {} samples, {} beats",
+        sample_count,
+        recording.beats.len()
+    );
 
     if sample_count < 20 * SAMPLE_RATE {
         if context.config.measurement_action != MeasurementAction::Discard {
@@ -67,6 +130,28 @@ pub async fn upload_or_store_measurement<const SIZE: usize>(
         return next_state;
     }
 
+    let poor_signal = recording
+        .quality
+        .is_some_and(|quality| !quality.is_usable());
+    if poor_signal {
+        match context.config.poor_signal_action {
+            PoorSignalAction::Discard => {
+                if context.config.measurement_action != MeasurementAction::Discard {
+                    context
+                        .display_message("Poor signal quality, discarding")
+                        .await;
+                }
+                return next_state;
+            }
+            PoorSignalAction::Warn => {
+                context.display_message("Poor signal quality").await;
+                context.wait_for_message(MESSAGE_DURATION).await;
+            }
+        }
+    }
+
+    display_rhythm(context, recording.rhythm).await;
+
     let (can_upload, can_store) = match context.config.measurement_action {
         MeasurementAction::Ask => ask_for_measurement_action(context).await,
         MeasurementAction::Auto => (true, true),
@@ -76,7 +161,7 @@ pub async fn upload_or_store_measurement<const SIZE: usize>(
     };
 
     let store_after_upload = if can_upload {
-        let upload_result = try_to_upload(context, samples).await;
+        let upload_result = try_to_upload(context, measurement, info).await;
         debug!("Upload result: {:?}", upload_result);
         upload_result == StoreMeasurement::Store
     } else {
@@ -84,10 +169,14 @@ pub async fn upload_or_store_measurement<const SIZE: usize>(
     };
 
     if can_store && store_after_upload {
-        let store_result = try_store_measurement(context, samples).await;
+        let store_result = try_store_measurement(context, measurement, info).await;
 
         if let Err(e) = store_result {
-            context.display_message("Could not store measurement").await;
+            let message = match e {
+                StoreError::Full => "Storage full",
+                StoreError::Storage(_) => "Could not store measurement",
+            };
+            context.display_message(message).await;
             error!("Failed to store measurement: {:?}", e);
         }
     }
@@ -95,7 +184,8 @@ pub async fn upload_or_store_measurement<const SIZE: usize>(
     // Only upload if we did not store.
     if can_upload && !store_after_upload {
         // Drop to free up 90kB of memory.
-        mem::drop(buffer);
+        mem::drop(header);
+        mem::drop(recording);
 
         if context.sta_has_work().await {
             upload_stored(context).await;
@@ -105,6 +195,19 @@ pub async fn upload_or_store_measurement<const SIZE: usize>(
     next_state
 }
 
+async fn display_rhythm(context: &mut Context, rhythm: Rhythm) {
+    info!("Rhythm: {:?}", rhythm);
+
+    let message = match rhythm {
+        Rhythm::Inconclusive => "Rhythm: inconclusive",
+        Rhythm::Regular => "Regular rhythm",
+        Rhythm::Irregular => "Irregular rhythm detected. Consider seeing a doctor.",
+    };
+
+    context.display_message(message).await;
+    context.wait_for_message(MESSAGE_DURATION).await;
+}
+
 async fn ask_for_measurement_action(context: &mut Context) -> (bool, bool) {
     let network_configured =
         !context.config.backend_url.is_empty() && !context.config.known_networks.is_empty();
@@ -186,12 +289,22 @@ impl MenuScreen for AskForMeasurementActionMenu {
     }
 }
 
-async fn try_to_upload(context: &mut Context, buffer: &[u8]) -> StoreMeasurement {
+async fn try_to_upload(
+    context: &mut Context,
+    measurement: NewMeasurement<'_>,
+    info: MeasurementInfo,
+) -> StoreMeasurement {
     if context.config.backend_url.is_empty() {
         debug!("No backend URL configured, not uploading.");
         return StoreMeasurement::Store;
     }
 
+    if !context.config.is_registered() {
+        context.display_message("Device not registered").await;
+        context.wait_for_message(MESSAGE_DURATION).await;
+        return StoreMeasurement::Store;
+    }
+
     let sta = if let Some(sta) = context.enable_wifi_sta(StaMode::Enable).await {
         if sta.wait_for_connection(context).await {
             sta
@@ -203,24 +316,50 @@ async fn try_to_upload(context: &mut Context, buffer: &[u8]) -> StoreMeasurement
         return StoreMeasurement::Store;
     };
 
+    let backend = unwrap!(Backend::new(context));
+
     // If we found a network, attempt to upload.
-    // TODO: only try to upload if we are registered.
     debug!("Trying to upload measurement");
 
-    let Ok(mut client_resources) = sta.https_client_resources() else {
+    let Ok(mut client_resources) = backend.client_resources(&sta) else {
         context.display_message("Out of memory").await;
         return StoreMeasurement::Store;
     };
     let mut client = client_resources.client();
 
-    match upload_measurement(
-        &mut client,
-        0,
-        MeasurementRef { version: 0, buffer },
-        &mut context.inner,
-    )
-    .await
-    {
+    let result = match measurement {
+        NewMeasurement::Memory(recording) => {
+            let timestamp = info.start_time.unwrap_or(0);
+            let body = RecordingBody(recording);
+            upload_measurement(&mut client, &backend, timestamp, body, &mut context.inner).await
+        }
+        NewMeasurement::File(id) => {
+            let Some(storage) = context.storage.as_mut() else {
+                return StoreMeasurement::Store;
+            };
+
+            let uploading_msg = uformat!(
+                32,
+                "Uploading measurement: {}",
+                BinarySize(info.size as usize)
+            );
+            context.inner.display_message(uploading_msg.as_str()).await;
+
+            // The recording is a complete measurement file, it is uploaded like a stored one.
+            let entry = CatalogEntry {
+                id,
+                upload: UploadState::Pending,
+                failures: 0,
+                info,
+            };
+            let display = Some(&mut context.inner);
+            upload_stored_file(&mut client, &backend, &entry, storage, display, None)
+                .await
+                .map_err(|_| ())
+        }
+    };
+
+    match result {
         Ok(_) => {
             // Upload successful, do not store in file.
             context.display_message("Upload successful").await;
@@ -235,6 +374,11 @@ async fn try_to_upload(context: &mut Context, buffer: &[u8]) -> StoreMeasurement
 }
 
 async fn upload_stored(context: &mut Context) {
+    if !context.config.is_registered() {
+        context.display_message("Device not registered").await;
+        return;
+    }
+
     let sta = if let Some(sta) = context.enable_wifi_sta(StaMode::OnDemand).await {
         if sta.wait_for_connection(context).await {
             sta
@@ -247,6 +391,8 @@ async fn upload_stored(context: &mut Context) {
         return;
     };
 
+    let backend = unwrap!(Backend::new(context));
+
     context
         .display_message("Uploading stored measurements...")
         .await;
@@ -256,65 +402,37 @@ async fn upload_stored(context: &mut Context) {
         return;
     };
 
-    let Ok(mut dir) = storage.read_dir().await else {
-        context.display_message("Could not read storage").await;
-        return;
-    };
+    let mut catalog = Catalog::load(storage).await;
+    let pending = catalog.pending_uploads().copied().collect::<Vec<_>>();
 
-    let mut fn_buffer = [0; 64];
-
-    let Ok(mut client_resources) = sta.https_client_resources() else {
+    let Ok(mut client_resources) = backend.client_resources(&sta) else {
         context.display_message("Out of memory").await;
         return;
     };
     let mut client = client_resources.client();
 
     let mut success = true;
-    loop {
-        match dir.next(storage).await {
-            Ok(file) => {
-                let Some(file) = file else {
-                    debug!("File is None");
-                    break;
-                };
-
-                match file.name(storage, &mut fn_buffer).await {
-                    Ok(name) if name.starts_with("meas.") => {
-                        let Ok((file, buffer)) = load_measurement(file, storage).await else {
-                            warn!("Failed to load {}", name);
-                            continue;
-                        };
-
-                        if let Err(e) =
-                            upload_measurement(&mut client, 0, buffer.as_ref(), &mut context.inner)
-                                .await
-                        {
-                            warn!("Failed to upload {}: {:?}", name, e);
-                            success = false;
-                            break;
-                        }
-
-                        info!("Uploaded {}", name);
-                        if let Err(e) = file.delete(storage).await {
-                            warn!("Failed to delete file: {:?}", e);
-                        }
-                    }
-                    Ok(_) | Err(StorageError::InsufficientBuffer) => {
-                        // not a measurement file, ignore
-                    }
-                    Err(e) => {
-                        warn!("Failed to read file name: {:?}", e);
-                        success = false;
-                        break;
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("Failed to read directory: {:?}", e);
+    for entry in pending {
+        let uploading_msg = uformat!(
+            32,
+            "Uploading measurement: {}",
+            BinarySize(entry.info.size as usize)
+        );
+        context.inner.display_message(uploading_msg.as_str()).await;
+
+        let display = Some(&mut context.inner);
+        match upload_stored_file(&mut client, &backend, &entry, storage, display, None).await {
+            Ok(()) => {}
+            Err(UploadError::Load) => continue,
+            Err(UploadError::Send | UploadError::Paused) => {
                 success = false;
                 break;
             }
         }
+
+        if let Err(e) = catalog.mark_uploaded(storage, entry.id).await {
+            warn!("Failed to update catalog: {:?}", e);
+        }
     }
 
     let message = if success {
@@ -324,59 +442,450 @@ async fn upload_stored(context: &mut Context) {
     };
     context.display_message(message).await;
 
-    context.signal_sta_work_available(!success);
+    let inner = &mut context.inner;
+    if success {
+        inner.upload_schedule.succeeded(false);
+    } else {
+        inner.upload_schedule.failed(&inner.clock);
+    }
 }
 
-struct Measurement {
-    version: u32,
-    buffer: Box<[u8]>,
+/// Uploads a single stored measurement, even if it has been uploaded before.
+pub async fn upload_stored_measurement(context: &mut Context, id: u32) {
+    if !context.config.is_registered() {
+        context.display_message("Device not registered").await;
+        return;
+    }
+
+    let sta = if let Some(sta) = context.enable_wifi_sta(StaMode::Enable).await {
+        if sta.wait_for_connection(context).await {
+            sta
+        } else {
+            context.display_message("Failed to connect to WiFi").await;
+            return;
+        }
+    } else {
+        context.display_message("WiFi not available").await;
+        return;
+    };
+
+    let backend = unwrap!(Backend::new(context));
+
+    let Some(storage) = context.storage.as_mut() else {
+        context.display_message("Storage not available").await;
+        return;
+    };
+
+    let mut catalog = Catalog::load(storage).await;
+    let Some(entry) = catalog.get(id).copied() else {
+        context.display_message("Measurement not found").await;
+        return;
+    };
+
+    let Ok(mut client_resources) = backend.client_resources(&sta) else {
+        context.display_message("Out of memory").await;
+        return;
+    };
+    let mut client = client_resources.client();
+
+    let uploading_msg = uformat!(
+        32,
+        "Uploading measurement: {}",
+        BinarySize(entry.info.size as usize)
+    );
+    context.inner.display_message(uploading_msg.as_str()).await;
+
+    let display = Some(&mut context.inner);
+    let upload = upload_stored_file(&mut client, &backend, &entry, storage, display, None);
+    let message = match upload.await {
+        Ok(()) => {
+            if let Err(e) = catalog.mark_uploaded(storage, id).await {
+                warn!("Failed to update catalog: {:?}", e);
+            }
+            "Upload successful"
+        }
+        Err(_) => "Upload failed",
+    };
+    context.inner.display_message(message).await;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(super) enum UploadError {
+    /// The file could not be read. Other files may still be uploaded.
+    Load,
+    /// The server could not be reached, or it rejected the measurement.
+    Send,
+    /// The deadline passed before all chunks were sent. The upload continues where it stopped
+    /// when it is tried again.
+    Paused,
+}
+
+/// Uploads a stored measurement. Large measurements are uploaded in chunks, showing the progress
+/// on `display`, if given, and stopping at `deadline`, if given.
+async fn upload_stored_file<T, DNS, M>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    entry: &CatalogEntry,
+    storage: &mut Storage<M>,
+    display: Option<&mut InnerContext>,
+    deadline: Option<Instant>,
+) -> Result<(), UploadError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    let name = measurement_file_name(entry.id);
+    // The backend orders measurements by their start time. 0 means unknown.
+    let timestamp = entry.info.start_time.unwrap_or(0);
+
+    let Ok(measurement) = load_measurement(&name, storage).await else {
+        warn!("Failed to load {}", name);
+        return Err(UploadError::Load);
+    };
+
+    let result = match measurement {
+        StoredMeasurement::Legacy { prefix, size } => {
+            let body = StoredFileBody::new(&name, UPLOAD_FORMAT_VERSION, &prefix, size, storage);
+            send_stored_file(client, backend, timestamp, &body).await
+        }
+        StoredMeasurement::File { version, size } if size + 4 > CHUNKED_UPLOAD_THRESHOLD => {
+            let upload = ChunkedUpload {
+                id: entry.id,
+                version,
+                size,
+                timestamp,
+                deadline,
+            };
+            upload_chunked(client, backend, upload, storage, display).await
+        }
+        StoredMeasurement::File { version, size } => {
+            let body = StoredFileBody::new(&name, version as u32, &[], size, storage);
+            send_stored_file(client, backend, timestamp, &body).await
+        }
+    };
+
+    if let Err(e) = result {
+        warn!("Failed to upload {}: {:?}", name, e);
+        return Err(e);
+    }
+
+    info!("Uploaded {}", name);
+    Ok(())
 }
 
-impl Measurement {
-    fn as_ref(&self) -> MeasurementRef<'_> {
-        MeasurementRef {
-            version: self.version,
-            buffer: &self.buffer,
+/// Uploads a single stored measurement, without using the display. Large measurements are sent
+/// in chunks until `deadline`, and continue with the next chunk on the next call. Returns
+/// `Ok(false)` if there is nothing to upload.
+pub(super) async fn upload_one_stored<T, DNS, M>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    storage: &mut Storage<M>,
+    deadline: Instant,
+) -> Result<bool, ()>
+where
+    T: TcpConnect,
+    DNS: Dns,
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    let mut catalog = Catalog::load(storage).await;
+    let pending = catalog.pending_uploads().copied().collect::<Vec<_>>();
+
+    for entry in pending {
+        match upload_stored_file(client, backend, &entry, storage, None, Some(deadline)).await {
+            Ok(()) => {
+                if let Err(e) = catalog.mark_uploaded(storage, entry.id).await {
+                    warn!("Failed to update catalog: {:?}", e);
+                }
+                return Ok(true);
+            }
+            Err(UploadError::Paused) => return Ok(true),
+            Err(UploadError::Load) => {}
+            Err(UploadError::Send) => return Err(()),
         }
     }
+
+    Ok(false)
 }
 
-struct MeasurementRef<'a> {
+/// Uploads the stored measurements without using the display. Measurements that have failed to
+/// upload fewer times are tried first, so that one that keeps failing doesn't hold back the
+/// others. Stops at the first measurement that can't be sent, or before the next measurement once
+/// `stop` is set. Returns `Err` if any measurement failed, except ones that have been given up.
+pub(super) async fn upload_pending<T, DNS, M>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    storage: &mut Storage<M>,
+    stop: &Cell<bool>,
+) -> Result<(), ()>
+where
+    T: TcpConnect,
+    DNS: Dns,
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    let mut catalog = Catalog::load(storage).await;
+    let mut pending = catalog.pending_uploads().copied().collect::<Vec<_>>();
+    pending.sort_by_key(|entry| (entry.failures, entry.id));
+
+    let mut result = Ok(());
+    for entry in pending {
+        if stop.get() {
+            break;
+        }
+
+        let upload = upload_stored_file(client, backend, &entry, storage, None, None).await;
+        let update = match upload {
+            Ok(()) => catalog.mark_uploaded(storage, entry.id).await,
+            Err(UploadError::Load) => catalog.mark_unreadable(storage, entry.id).await,
+            Err(_) => catalog.mark_failed(storage, entry.id).await,
+        };
+        if let Err(e) = update {
+            warn!("Failed to update catalog: {:?}", e);
+        }
+
+        match upload {
+            Ok(()) => {}
+            Err(UploadError::Load) => {
+                let given_up = catalog
+                    .get(entry.id)
+                    .is_some_and(|entry| entry.upload == UploadState::Failed);
+                if !given_up {
+                    result = Err(());
+                }
+            }
+            Err(UploadError::Send | UploadError::Paused) => return Err(()),
+        }
+    }
+
+    result
+}
+
+/// A measurement file prepared for uploading. `size` is the length of the data after the format
+/// version, which is uploaded directly from the file.
+enum StoredMeasurement {
+    /// An older measurement without a header. It is converted to the current format while
+    /// uploading, by sending `prefix` in front of the data.
+    Legacy { prefix: Box<[u8]>, size: usize },
+    /// A measurement in the current or the streamed format.
+    File { version: u8, size: usize },
+}
+
+/// Encodes a measurement header, so that it can be written by async writers.
+fn encode_header(header: &MeasurementHeader<'_>) -> Result<Box<[u8]>, ()> {
+    let mut buffer = buffer_with_capacity(header.encoded_len(), 0)?;
+    unwrap!(header.encode(&mut &mut buffer[..]).ok());
+    Ok(buffer)
+}
+
+/// A measurement that has not been stored yet.
+#[derive(Clone, Copy)]
+enum NewMeasurement<'a> {
+    Memory(RecordingRef<'a>),
+    /// The measurement is written into the file of measurement `id`, but it is not in the
+    /// catalog.
+    File(u32),
+}
+
+/// A measurement recorded into memory.
+#[derive(Clone, Copy)]
+struct RecordingRef<'a> {
+    /// The encoded measurement header.
+    header: &'a [u8],
+    /// The compressed samples.
+    samples: &'a [u8],
+    beats: &'a [Beat],
+}
+
+impl RecordingRef<'_> {
+    fn payload_len(&self) -> usize {
+        payload_len(self.header.len(), self.samples.len(), self.beats.len())
+    }
+}
+
+/// The length of a measurement's data following the format version.
+fn payload_len(header_len: usize, sample_bytes: usize, beats: usize) -> usize {
+    header_len + 4 + sample_bytes + beats * Beat::ENCODED_SIZE
+}
+
+/// Size of the chunks we read files in.
+const CHUNK_SIZE: usize = 256;
+
+/// Uploads a measurement recorded into memory.
+struct RecordingBody<'a>(RecordingRef<'a>);
+
+impl RequestBody for RecordingBody<'_> {
+    fn len(&self) -> Option<usize> {
+        Some(self.0.payload_len() + 4)
+    }
+
+    async fn write<W: embedded_io_async::Write>(&self, writer: &mut W) -> Result<(), W::Error> {
+        let recording = &self.0;
+
+        writer
+            .write_all(&UPLOAD_FORMAT_VERSION.to_le_bytes())
+            .await?;
+        writer.write_all(recording.header).await?;
+        writer
+            .write_all(&(recording.samples.len() as u32).to_le_bytes())
+            .await?;
+        writer.write_all(recording.samples).await?;
+        for beat in recording.beats {
+            writer.write_all(&beat.to_bytes()).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Uploads a stored measurement.
+///
+/// Storage errors can't be returned as the writer's error, so the body ends early and keeps the
+/// error, to be reported by [`StoredFileBody::check`].
+struct StoredFileBody<'a, M>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    name: &'a str,
     version: u32,
-    buffer: &'a [u8],
+    /// Sent between the format version and the data of the file.
+    prefix: &'a [u8],
+    /// The length of the data following the format version.
+    size: usize,
+    storage: RefCell<&'a mut Storage<M>>,
+    read_error: Cell<Option<StorageError>>,
+}
+
+impl<'a, M> StoredFileBody<'a, M>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    fn new(
+        name: &'a str,
+        version: u32,
+        prefix: &'a [u8],
+        size: usize,
+        storage: &'a mut Storage<M>,
+    ) -> Self {
+        Self {
+            name,
+            version,
+            prefix,
+            size,
+            storage: RefCell::new(storage),
+            read_error: Cell::new(None),
+        }
+    }
+
+    /// Returns `Err` if the file could not be read while writing the body.
+    fn check(&self) -> Result<(), UploadError> {
+        match self.read_error.take() {
+            Some(e) => {
+                warn!("Failed to read {}: {:?}", self.name, e);
+                Err(UploadError::Load)
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn write_file<W: embedded_io_async::Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<Result<(), StorageError>, W::Error> {
+        let mut storage = self.storage.borrow_mut();
+        let storage = &mut **storage;
+
+        let mut reader = match storage.read(self.name).await {
+            Ok(reader) => reader,
+            Err(e) => return Ok(Err(e)),
+        };
+
+        // Files start with a single byte format version, which we have already sent.
+        if let Err(e) = reader.read_all(storage, &mut [0]).await {
+            return Ok(Err(e));
+        }
+
+        let mut buffer = [0; CHUNK_SIZE];
+        let mut remaining = self.size;
+        while remaining > 0 {
+            let chunk = &mut buffer[..remaining.min(CHUNK_SIZE)];
+            if let Err(e) = reader.read_all(storage, chunk).await {
+                return Ok(Err(e));
+            }
+            writer.write_all(chunk).await?;
+            remaining -= chunk.len();
+        }
+
+        Ok(Ok(()))
+    }
 }
 
-impl RequestBody for MeasurementRef<'_> {
+impl<M> RequestBody for &StoredFileBody<'_, M>
+where
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
     fn len(&self) -> Option<usize> {
-        Some(self.buffer.len() + 4)
+        Some(4 + self.prefix.len() + self.size)
     }
 
     async fn write<W: embedded_io_async::Write>(&self, writer: &mut W) -> Result<(), W::Error> {
         writer.write_all(&self.version.to_le_bytes()).await?;
-        writer.write_all(self.buffer).await?;
+        writer.write_all(self.prefix).await?;
+
+        if let Err(e) = self.write_file(writer).await? {
+            self.read_error.set(Some(e));
+        }
 
         Ok(())
     }
 }
 
-async fn load_measurement<M>(
-    file: DirEntry<M>,
-    storage: &mut Storage<M>,
-) -> Result<(DirEntry<M>, Measurement), ()>
+/// Sends a stored measurement in a single request.
+async fn send_stored_file<T, DNS, M>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    timestamp: u64,
+    body: &StoredFileBody<'_, M>,
+) -> Result<(), UploadError>
+where
+    T: TcpConnect,
+    DNS: Dns,
+    M: StorageMedium,
+    [(); M::BLOCK_COUNT]:,
+{
+    // Hashing reads the whole file, so a file that can't be read is not sent.
+    let body_hash = hash_body(&body).await;
+    body.check()?;
+
+    let sent = send_measurement(client, backend, timestamp, body, body_hash).await;
+    body.check()?;
+
+    sent.map_err(|_| UploadError::Send)
+}
+
+async fn load_measurement<M>(name: &str, storage: &mut Storage<M>) -> Result<StoredMeasurement, ()>
 where
     M: StorageMedium,
     [(); M::BLOCK_COUNT]:,
 {
+    let file = match storage.read(name).await {
+        Ok(reader) => DirEntry::from_reader(reader),
+        Err(e) => {
+            warn!("Failed to open file: {:?}", e);
+            return Err(());
+        }
+    };
     let Ok(size) = file.size(storage).await else {
         warn!("Failed to read size");
         return Err(());
     };
 
-    let Ok(mut buffer) = buffer_with_capacity(size, 0) else {
-        warn!("Failed to allocate {} bytes", size);
-        return Err(());
-    };
-
     let mut reader = file.open();
     let version = reader.read_loadable::<u8>(storage).await;
     let version = match version {
@@ -387,21 +896,36 @@ where
         }
     };
 
-    if let Err(e) = reader.read_all(storage, buffer.as_mut()).await {
-        warn!("Failed to read data: {:?}", e);
+    // Recordings can be larger than the available memory, so we upload them from the file.
+    let size = size - 1;
+    if version == FORMAT_VERSION || version == STREAMED_FORMAT_VERSION {
+        return Ok(StoredMeasurement::File { version, size });
+    }
+
+    let Some(header) = MeasurementHeader::legacy(version as u32) else {
+        warn!("Unsupported format version: {}", version);
+        return Err(());
+    };
+
+    // Convert older measurements, so that we always upload a header. Version 1 and 2 files are
+    // laid out like version 3 after the header, version 0 files only lack the length of the
+    // samples.
+    let samples_len = (version == 0).then_some(size as u32);
+    let header_len = header.encoded_len();
+    let prefix_len = header_len + if samples_len.is_some() { 4 } else { 0 };
+    let Ok(mut prefix) = buffer_with_capacity(prefix_len, 0) else {
+        warn!("Failed to allocate {} bytes", prefix_len);
         return Err(());
     };
+    unwrap!(header.encode(&mut &mut prefix[..header_len]).ok());
+    if let Some(samples_len) = samples_len {
+        prefix[header_len..].copy_from_slice(&samples_len.to_le_bytes());
+    }
 
-    Ok((
-        DirEntry::from_reader(reader),
-        Measurement {
-            version: version as u32,
-            buffer,
-        },
-    ))
+    Ok(StoredMeasurement::Legacy { prefix, size })
 }
 
-fn buffer_with_capacity<T: Copy>(size: usize, init_val: T) -> Result<Box<[T]>, ()> {
+pub(super) fn buffer_with_capacity<T: Copy>(size: usize, init_val: T) -> Result<Box<[T]>, ()> {
     let mut buffer = Vec::new();
 
     if buffer.try_reserve_exact(size).is_err() {
@@ -420,8 +944,9 @@ fn buffer_with_capacity<T: Copy>(size: usize, init_val: T) -> Result<Box<[T]>, (
 
 async fn upload_measurement<T, DNS>(
     client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
     meas_timestamp: u64,
-    samples: MeasurementRef<'_>,
+    measurement: impl RequestBody,
     context: &mut InnerContext,
 ) -> Result<(), ()>
 where
@@ -431,148 +956,120 @@ where
     let uploading_msg = uformat!(
         32,
         "Uploading measurement: {}",
-        BinarySize(samples.buffer.len())
+        BinarySize(measurement.len().unwrap_or(0))
     );
     context.display_message(uploading_msg.as_str()).await;
 
-    const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
-    const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
-
-    let mut upload_url = heapless::String::<128>::new();
-    if uwrite!(
-        &mut upload_url,
-        "{}/upload_data/{}",
-        context.config.backend_url.as_str(),
-        SerialNumber
-    )
-    .is_err()
-    {
-        warn!("URL too long");
-        return Err(());
-    }
-
-    let mut timestamp = heapless::String::<32>::new();
-    unwrap!(uwrite!(&mut timestamp, "{}", meas_timestamp));
+    let body_hash = hash_body(&measurement).await;
+    send_measurement(client, backend, meas_timestamp, measurement, body_hash).await
+}
 
-    debug!("Uploading measurement to {}", upload_url);
+async fn send_measurement<T, DNS>(
+    client: &mut HttpClient<'_, T, DNS>,
+    backend: &Backend,
+    meas_timestamp: u64,
+    measurement: impl RequestBody,
+    body_hash: Digest,
+) -> Result<(), ()>
+where
+    T: TcpConnect,
+    DNS: Dns,
+{
+    const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
 
+    let path = uformat!(48, "/upload_data/{}", SerialNumber);
+    let timestamp = uformat!(32, "{}", meas_timestamp);
     let headers = [("X-Timestamp", timestamp.as_str())];
+    let request = Request {
+        method: Method::POST,
+        path: &path,
+        headers: &headers,
+        body: measurement,
+        body_hash,
+        timeout: UPLOAD_TIMEOUT,
+    };
 
-    let mut request =
-        match with_timeout(CONNECT_TIMEOUT, client.request(Method::POST, &upload_url)).await {
-            Ok(Ok(request)) => request.headers(&headers).body(samples),
-            Ok(Err(e)) => {
-                warn!("HTTP connect error: {:?}", e);
-                return Err(());
-            }
-            _ => {
-                warn!("Conect timeout");
-                return Err(());
-            }
-        };
+    debug!("Uploading measurement");
 
     let mut rx_buffer = [0; 512];
-    match with_timeout(UPLOAD_TIMEOUT, request.send(&mut rx_buffer)).await {
-        Ok(Ok(response)) => {
-            if [Status::Ok, Status::Created].contains(&response.status) {
-                return Ok(());
-            }
-
-            warn!("HTTP upload failed: {:?}", response.status);
-            for header in response.headers() {
-                if !header.0.is_empty() {
-                    debug!(
-                        "Header {}: {}",
-                        header.0,
-                        str::from_utf8(header.1).unwrap_or("not a string")
-                    );
-                }
-            }
+    match backend
+        .request(client, request, &mut rx_buffer, &mut IgnoreBody)
+        .await
+    {
+        Ok(Status::Ok | Status::Created) => Ok(()),
+        Ok(status) => {
+            warn!("HTTP upload failed: {:?}", status);
+            Err(())
         }
-        Ok(Err(e)) => warn!("HTTP upload error: {:?}", e),
-        _ => warn!("Timeout"),
+        Err(_) => Err(()),
     }
-    Err(())
 }
 
 async fn try_store_measurement(
     context: &mut Context,
-    measurement: &[u8],
-) -> Result<(), StorageError> {
+    measurement: NewMeasurement<'_>,
+    info: MeasurementInfo,
+) -> Result<(), StoreError> {
     debug!("Trying to store measurement");
 
-    let saving_msg = uformat!(32, "Saving measurement: {}", BinarySize(measurement.len()));
+    let saving_msg = uformat!(32, "Saving measurement: {}", BinarySize(info.size as usize));
     context.display_message(&saving_msg).await;
+    let policy = context.config.retention_policy();
     let Some(storage) = context.storage.as_mut() else {
         return Ok(());
     };
 
-    let meas_idx = find_measurement_index(storage).await?;
-
-    let mut filename = heapless::String::<16>::new();
-    unwrap!(uwrite!(&mut filename, "meas.{}", meas_idx));
+    let mut catalog = Catalog::load(storage).await;
+    let id = match measurement {
+        NewMeasurement::File(id) => {
+            // The recording is already written, so its size is taken from the free space. Only
+            // the other limits of the policy may need measurements to be deleted.
+            if !catalog.make_room(storage, &policy, 0).await? {
+                warn!("Storage is over its limits");
+            }
+            id
+        }
+        NewMeasurement::Memory(recording) => {
+            let id = catalog.next_id();
+            let writer = MeasurementWriter(recording);
+            if !catalog
+                .make_room(storage, &policy, writer.estimate_length())
+                .await?
+            {
+                return Err(StoreError::Full);
+            }
 
-    storage
-        .store_writer(
-            &filename,
-            &MeasurementWriter(measurement),
-            OnCollision::Fail,
-        )
-        .await?;
+            storage
+                .store_writer(&measurement_file_name(id), &writer, OnCollision::Overwrite)
+                .await?;
+            id
+        }
+    };
+    catalog.add(storage, id, info).await?;
 
-    info!("Measurement saved to {}", filename);
+    info!("Measurement saved to {}", measurement_file_name(id));
 
-    context.signal_sta_work_available(true);
+    context.upload_schedule.set_pending(true);
 
     Ok(())
 }
 
-async fn find_measurement_index<M>(storage: &mut Storage<M>) -> Result<u32, StorageError>
-where
-    M: StorageMedium,
-    [(); M::BLOCK_COUNT]:,
-{
-    let mut max_index = None;
-    let mut dir = storage.read_dir().await?;
-    let mut buffer = [0; 64];
-    while let Some(file) = dir.next(storage).await? {
-        match file.name(storage, &mut buffer).await {
-            Ok(name) => {
-                if let Some(idx) = name
-                    .strip_prefix("meas.")
-                    .and_then(|s| s.parse::<u32>().ok())
-                {
-                    let update_max = if let Some(max) = max_index {
-                        idx > max
-                    } else {
-                        true
-                    };
-
-                    if update_max {
-                        max_index = Some(idx);
-                    }
-                }
-            }
-            Err(StorageError::InsufficientBuffer) => {
-                // not a measurement file, ignore
-            }
-            Err(e) => {
-                warn!("Failed to read file name: {:?}", e);
-                return Err(e);
-            }
-        }
-    }
-
-    Ok(max_index.map(|idx| idx + 1).unwrap_or(0))
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum StoreError {
+    /// The retention policy doesn't allow deleting enough measurements.
+    Full,
+    Storage(StorageError),
 }
 
-struct MeasurementWriter<'a>(&'a [u8]);
-
-impl<'a> MeasurementWriter<'a> {
-    // We're good with a straight u8 until 127 samples, then we can consider switching to varint.
-    const FORMAT_VERSION: u8 = EkgFormat::VERSION;
+impl From<StorageError> for StoreError {
+    fn from(e: StorageError) -> Self {
+        Self::Storage(e)
+    }
 }
 
+struct MeasurementWriter<'a>(RecordingRef<'a>);
+
 impl FileDataWriter for MeasurementWriter<'_> {
     async fn write<M>(
         &self,
@@ -586,17 +1083,24 @@ impl FileDataWriter for MeasurementWriter<'_> {
         // Here we only store differences, but not the initial sample. The DC offset does not
         // matter for the analysis, and we can reconstruct everything else from the differences.
 
+        let samples = self.0.samples;
+
         let mut writer = writer.bind(storage);
 
+        writer.write_all(&FORMAT_VERSION.to_le_bytes()).await?;
+        writer.write_all(self.0.header).await?;
         writer
-            .write_all(&Self::FORMAT_VERSION.to_le_bytes())
+            .write_all(&(samples.len() as u32).to_le_bytes())
             .await?;
-        writer.write_all(self.0).await?;
+        writer.write_all(samples).await?;
+        for beat in self.0.beats {
+            writer.write_all(&beat.to_bytes()).await?;
+        }
 
         Ok(())
     }
 
     fn estimate_length(&self) -> usize {
-        Self::FORMAT_VERSION.to_le_bytes().len() + self.0.len()
+        FORMAT_VERSION.to_le_bytes().len() + self.0.payload_len()
     }
 }
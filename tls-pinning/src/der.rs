@@ -0,0 +1,141 @@
+//! Just enough DER parsing to find the public key of an X.509 certificate.
+
+const INTEGER: u8 = 0x02;
+const SEQUENCE: u8 = 0x30;
+const BIT_STRING: u8 = 0x03;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+/// `[0] EXPLICIT`, the version of the certificate.
+const VERSION: u8 = 0xA0;
+
+/// 1.2.840.10045.2.1
+const EC_PUBLIC_KEY: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+/// 1.2.840.10045.3.1.7
+const PRIME256V1: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Malformed;
+
+/// A DER value: its tag, its contents, and the whole encoding including the header.
+struct Tlv<'a> {
+    tag: u8,
+    contents: &'a [u8],
+    encoded: &'a [u8],
+}
+
+/// Splits the first value off `input`.
+fn next<'a>(input: &mut &'a [u8]) -> Result<Tlv<'a>, Malformed> {
+    let data = *input;
+    let [tag, first_len, rest @ ..] = data else {
+        return Err(Malformed);
+    };
+
+    let (len, header_len) = if first_len & 0x80 == 0 {
+        (*first_len as usize, 2)
+    } else {
+        let len_bytes = (first_len & 0x7F) as usize;
+        if len_bytes == 0 || len_bytes > 3 || rest.len() < len_bytes {
+            return Err(Malformed);
+        }
+        let len = rest[..len_bytes]
+            .iter()
+            .fold(0, |len, byte| len << 8 | *byte as usize);
+        (len, 2 + len_bytes)
+    };
+
+    let end = header_len.checked_add(len).ok_or(Malformed)?;
+    if data.len() < end {
+        return Err(Malformed);
+    }
+
+    *input = &data[end..];
+    Ok(Tlv {
+        tag: *tag,
+        contents: &data[header_len..end],
+        encoded: &data[..end],
+    })
+}
+
+fn expect<'a>(input: &mut &'a [u8], tag: u8) -> Result<Tlv<'a>, Malformed> {
+    let value = next(input)?;
+    if value.tag == tag {
+        Ok(value)
+    } else {
+        Err(Malformed)
+    }
+}
+
+/// Returns the encoded SubjectPublicKeyInfo of a DER encoded certificate.
+pub fn subject_public_key_info(certificate: &[u8]) -> Result<&[u8], Malformed> {
+    let mut input = certificate;
+    let mut certificate = expect(&mut input, SEQUENCE)?.contents;
+    let mut tbs_certificate = expect(&mut certificate, SEQUENCE)?.contents;
+
+    let mut serial_number = next(&mut tbs_certificate)?;
+    if serial_number.tag == VERSION {
+        serial_number = next(&mut tbs_certificate)?;
+    }
+    if serial_number.tag != INTEGER {
+        return Err(Malformed);
+    }
+
+    // signature, issuer, validity, subject
+    for _ in 0..4 {
+        expect(&mut tbs_certificate, SEQUENCE)?;
+    }
+
+    expect(&mut tbs_certificate, SEQUENCE).map(|spki| spki.encoded)
+}
+
+/// Returns the SEC1 encoded point of a P-256 SubjectPublicKeyInfo, or `None` if the key is of a
+/// different type.
+pub fn p256_public_key(spki: &[u8]) -> Result<Option<&[u8]>, Malformed> {
+    let mut input = spki;
+    let mut spki = expect(&mut input, SEQUENCE)?.contents;
+    let mut algorithm = expect(&mut spki, SEQUENCE)?.contents;
+
+    let algorithm_id = expect(&mut algorithm, OBJECT_IDENTIFIER)?.contents;
+    if algorithm_id != EC_PUBLIC_KEY {
+        return Ok(None);
+    }
+    let curve = expect(&mut algorithm, OBJECT_IDENTIFIER)?.contents;
+    if curve != PRIME256V1 {
+        return Ok(None);
+    }
+
+    // The first byte is the number of unused bits, which is 0 for a key.
+    match expect(&mut spki, BIT_STRING)?.contents {
+        [0, point @ ..] => Ok(Some(point)),
+        _ => Err(Malformed),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GOOD: &[u8] = include_bytes!("../tests/data/good.der");
+    const RSA: &[u8] = include_bytes!("../tests/data/rsa.der");
+
+    #[test]
+    fn finds_p256_key() {
+        let spki = subject_public_key_info(GOOD).unwrap();
+        let point = p256_public_key(spki).unwrap().unwrap();
+
+        // Uncompressed point
+        assert_eq!(point.len(), 65);
+        assert_eq!(point[0], 0x04);
+    }
+
+    #[test]
+    fn ignores_other_key_types() {
+        let spki = subject_public_key_info(RSA).unwrap();
+        assert_eq!(p256_public_key(spki), Ok(None));
+    }
+
+    #[test]
+    fn rejects_truncated_certificate() {
+        for len in [0, 1, 10, GOOD.len() / 2, GOOD.len() - 1] {
+            assert_eq!(subject_public_key_info(&GOOD[..len]), Err(Malformed));
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Server authentication by public key pinning, for `embedded-tls`.
+//!
+//! The server is identified by the SHA-256 hash of the SubjectPublicKeyInfo of its certificate,
+//! which can be computed with:
+//!
+//! ```sh
+//! openssl x509 -in cert.pem -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256
+//! ```
+//!
+//! The certificate chain, the expiry and the server name are not checked, the pinned key alone
+//! identifies the server. The pin has to be updated when the server's key changes. Only ECDSA
+//! P-256 keys are supported.
+
+#![cfg_attr(not(test), no_std)]
+
+mod der;
+
+use embedded_tls::{
+    Certificate, CertificateEntryRef, CertificateRef, CertificateVerifyRef, CryptoProvider,
+    SignatureScheme, TlsCipherSuite, TlsError, TlsVerifier,
+};
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use rand_core::CryptoRngCore;
+use sha2::{Digest, Sha256};
+
+/// The SHA-256 hash of a SubjectPublicKeyInfo.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyPin([u8; 32]);
+
+impl KeyPin {
+    /// Parses a pin written as 64 hex digits.
+    pub fn parse(hex: &str) -> Option<Self> {
+        let hex = hex.as_bytes();
+        if hex.len() != 64 {
+            return None;
+        }
+
+        let mut pin = [0; 32];
+        for (byte, pair) in pin.iter_mut().zip(hex.chunks_exact(2)) {
+            let high = (pair[0] as char).to_digit(16)?;
+            let low = (pair[1] as char).to_digit(16)?;
+            *byte = (high << 4 | low) as u8;
+        }
+
+        Some(Self(pin))
+    }
+
+    /// Returns the pin of an encoded SubjectPublicKeyInfo.
+    pub fn of(spki: &[u8]) -> Self {
+        Self(Sha256::digest(spki).into())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PinError {
+    InvalidCertificate,
+    /// The key of the certificate is not an ECDSA P-256 key.
+    UnsupportedKey,
+    /// The key of the certificate is not the pinned key.
+    KeyMismatch,
+}
+
+/// Returns the public key of a DER encoded certificate, if it is the pinned key.
+pub fn check_certificate(certificate: &[u8], pin: &KeyPin) -> Result<VerifyingKey, PinError> {
+    let spki =
+        der::subject_public_key_info(certificate).map_err(|_| PinError::InvalidCertificate)?;
+
+    if KeyPin::of(spki) != *pin {
+        return Err(PinError::KeyMismatch);
+    }
+
+    match der::p256_public_key(spki) {
+        Ok(Some(point)) => {
+            VerifyingKey::from_sec1_bytes(point).map_err(|_| PinError::InvalidCertificate)
+        }
+        Ok(None) => Err(PinError::UnsupportedKey),
+        Err(_) => Err(PinError::InvalidCertificate),
+    }
+}
+
+/// Accepts a server if its certificate has the pinned key, and it proves that it has the private
+/// key by signing the handshake.
+pub struct PinnedVerifier<CipherSuite: TlsCipherSuite> {
+    pin: KeyPin,
+    key: Option<VerifyingKey>,
+    /// The handshake transcript up to the server's certificate, which the server signs.
+    transcript: Option<CipherSuite::Hash>,
+}
+
+impl<CipherSuite: TlsCipherSuite> PinnedVerifier<CipherSuite> {
+    pub fn new(pin: KeyPin) -> Self {
+        Self {
+            pin,
+            key: None,
+            transcript: None,
+        }
+    }
+}
+
+impl<CipherSuite: TlsCipherSuite> TlsVerifier<CipherSuite> for PinnedVerifier<CipherSuite> {
+    fn set_hostname_verification(&mut self, _hostname: &str) -> Result<(), TlsError> {
+        Ok(())
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &CipherSuite::Hash,
+        _ca: &Option<Certificate>,
+        cert: CertificateRef,
+    ) -> Result<(), TlsError> {
+        // The server's own certificate comes first.
+        let Some(CertificateEntryRef::X509(certificate)) = cert.entries.first() else {
+            return Err(TlsError::InvalidCertificate);
+        };
+
+        let key =
+            check_certificate(certificate, &self.pin).map_err(|_| TlsError::InvalidCertificate)?;
+
+        self.key = Some(key);
+        self.transcript = Some(transcript.clone());
+
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: CertificateVerifyRef) -> Result<(), TlsError> {
+        let (Some(key), Some(transcript)) = (self.key.take(), self.transcript.take()) else {
+            return Err(TlsError::InvalidSignature);
+        };
+
+        if !matches!(
+            verify.signature_scheme,
+            SignatureScheme::EcdsaSecp256r1Sha256
+        ) {
+            return Err(TlsError::InvalidSignatureScheme);
+        }
+        let signature =
+            Signature::from_der(verify.signature).map_err(|_| TlsError::InvalidSignature)?;
+
+        // RFC 8446, section 4.4.3
+        let mut message = heapless::Vec::<u8, 130>::new();
+        let prefix = [0x20; 64];
+        let context = b"TLS 1.3, server CertificateVerify\x00";
+        let transcript_hash = transcript.finalize();
+        for part in [&prefix[..], &context[..], &transcript_hash[..]] {
+            message
+                .extend_from_slice(part)
+                .map_err(|_| TlsError::EncodeError)?;
+        }
+
+        key.verify(&message, &signature)
+            .map_err(|_| TlsError::InvalidSignature)
+    }
+}
+
+/// A [`CryptoProvider`] that authenticates the server with a [`PinnedVerifier`].
+pub struct PinningProvider<CipherSuite: TlsCipherSuite, RNG> {
+    rng: RNG,
+    verifier: PinnedVerifier<CipherSuite>,
+}
+
+impl<CipherSuite: TlsCipherSuite, RNG: CryptoRngCore> PinningProvider<CipherSuite, RNG> {
+    pub fn new(rng: RNG, pin: KeyPin) -> Self {
+        Self {
+            rng,
+            verifier: PinnedVerifier::new(pin),
+        }
+    }
+}
+
+impl<CipherSuite: TlsCipherSuite, RNG: CryptoRngCore> CryptoProvider
+    for PinningProvider<CipherSuite, RNG>
+{
+    type CipherSuite = CipherSuite;
+    type Signature = &'static [u8];
+
+    fn rng(&mut self) -> impl CryptoRngCore {
+        &mut self.rng
+    }
+
+    fn verifier(&mut self) -> Result<&mut impl TlsVerifier<CipherSuite>, TlsError> {
+        Ok(&mut self.verifier)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GOOD: &[u8] = include_bytes!("../tests/data/good.der");
+    const OTHER: &[u8] = include_bytes!("../tests/data/other.der");
+    const RSA: &[u8] = include_bytes!("../tests/data/rsa.der");
+
+    // Computed with openssl, as in the crate documentation.
+    const GOOD_PIN: &str = "03ab38f76235a528b259b487de4fbf590137f89593dca352acab2ff1850d3be5";
+    const RSA_PIN: &str = "e0a28f5eb072abb9ce9f45ef9e0557fc6033777c1f108543d08808b20ce6c703";
+
+    #[test]
+    fn parses_pin() {
+        assert!(KeyPin::parse(GOOD_PIN).is_some());
+        assert!(KeyPin::parse(&GOOD_PIN.to_uppercase()).is_some());
+        assert!(KeyPin::parse(&GOOD_PIN[1..]).is_none());
+        assert!(KeyPin::parse(&GOOD_PIN.replace('a', "x")).is_none());
+    }
+
+    #[test]
+    fn accepts_pinned_key() {
+        let pin = KeyPin::parse(GOOD_PIN).unwrap();
+        assert!(check_certificate(GOOD, &pin).is_ok());
+    }
+
+    #[test]
+    fn rejects_other_key() {
+        let pin = KeyPin::parse(GOOD_PIN).unwrap();
+        assert_eq!(
+            check_certificate(OTHER, &pin).err(),
+            Some(PinError::KeyMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_key() {
+        let pin = KeyPin::parse(RSA_PIN).unwrap();
+        assert_eq!(
+            check_certificate(RSA, &pin).err(),
+            Some(PinError::UnsupportedKey)
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Connects to a local `openssl s_server` with pinned and unpinned keys.
+//!
+//! The tests need the `openssl` command line tool, and fail if it is not installed.
+
+use std::{
+    net::{TcpListener, TcpStream},
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use embedded_io::{Read, Write};
+use embedded_io_adapters::std::FromStd;
+use embedded_tls::{blocking::TlsConnection, Aes128GcmSha256, TlsConfig, TlsContext, TlsError};
+use rand_core::OsRng;
+use tls_pinning::{KeyPin, PinningProvider};
+
+const GOOD_PIN: &str = "03ab38f76235a528b259b487de4fbf590137f89593dca352acab2ff1850d3be5";
+
+struct Server {
+    process: Child,
+    port: u16,
+}
+
+impl Server {
+    /// Starts a TLS 1.3 server that uses the `{name}.der` certificate and `{name}.key` key.
+    fn start(name: &str) -> Self {
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        let file = |extension: &str| data.join(format!("{name}.{extension}"));
+
+        // Find a free port. Another process may take it before the server starts, but that is
+        // unlikely enough for a test.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let process = Command::new("openssl")
+            .arg("s_server")
+            .arg("-tls1_3")
+            .arg("-www")
+            .args(["-accept", &port.to_string()])
+            .arg("-cert")
+            .arg(file("der"))
+            .args(["-certform", "DER"])
+            .arg("-key")
+            .arg(file("key"))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start openssl, is it installed?");
+
+        let server = Self { process, port };
+        for _ in 0..50 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return server;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        panic!("openssl s_server did not start");
+    }
+
+    /// Performs a handshake with the pinned key, and sends a request if it succeeds.
+    fn connect(&self, pin: &str) -> Result<(), TlsError> {
+        let stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+
+        let mut read_buffer = [0; 16640];
+        let mut write_buffer = [0; 4096];
+        let mut connection: TlsConnection<_, Aes128GcmSha256> =
+            TlsConnection::new(FromStd::new(stream), &mut read_buffer, &mut write_buffer);
+
+        let config = TlsConfig::new().with_server_name("localhost");
+        let provider = PinningProvider::new(OsRng, KeyPin::parse(pin).unwrap());
+        connection.open(TlsContext::new(&config, provider))?;
+
+        connection.write_all(b"GET / HTTP/1.0\r\n\r\n")?;
+        connection.flush()?;
+
+        let mut response = [0; 16];
+        let len = connection.read(&mut response)?;
+        assert!(response[..len].starts_with(b"HTTP/1.0 200"));
+
+        Ok(())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+#[test]
+fn connects_to_pinned_server() {
+    let server = Server::start("good");
+
+    assert!(server.connect(GOOD_PIN).is_ok());
+}
+
+#[test]
+fn rejects_server_with_other_key() {
+    let server = Server::start("other");
+
+    assert!(matches!(
+        server.connect(GOOD_PIN),
+        Err(TlsError::InvalidCertificate)
+    ));
+}
@@ -0,0 +1,179 @@
+//! When to retry background uploads.
+//!
+//! After a failed attempt, the next one waits according to a [`Backoff`]. The firmware keeps the
+//! [`SavedSchedule`] in memory that survives deep sleep, but not a power loss, so it is checked
+//! before it is used. Times are measured by a clock that keeps running in deep sleep, and that
+//! can be reset.
+
+#![cfg_attr(not(test), no_std)]
+
+/// Exponential backoff. Every failure doubles the wait, up to a limit, so an upload that can't
+/// succeed for a long time (e.g. because the server is down) is attempted rarely, but never given
+/// up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    /// Wait after the first failure, in seconds.
+    pub first_delay: u32,
+    /// The longest wait, in seconds.
+    pub max_delay: u32,
+}
+
+impl Backoff {
+    /// Returns how long to wait after `failures` consecutive failures, in seconds.
+    pub fn delay(&self, failures: u32) -> u32 {
+        let Some(doublings) = failures.checked_sub(1) else {
+            return 0;
+        };
+
+        // `first_delay` fits in 32 bits, so shifting by 32 can't overflow.
+        let delay = (self.first_delay as u64) << doublings.min(32);
+        delay.min(self.max_delay as u64) as u32
+    }
+}
+
+/// Marks a valid [`SavedSchedule`]. Memory that isn't cleared on boot holds garbage after a
+/// power loss.
+const MAGIC: u32 = 0x5C4E_D01E;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct SavedSchedule {
+    magic: u32,
+    /// Consecutive failed attempts.
+    failures: u32,
+    /// Time of the next attempt, in microseconds.
+    retry_at_us: u64,
+    /// Bitwise inverse of `retry_at_us`, mixed with `failures`.
+    check: u64,
+}
+
+impl SavedSchedule {
+    /// No failed attempts, the next attempt can be made right away.
+    pub const EMPTY: Self = Self::new(0, 0);
+
+    /// A schedule that is never valid, to initialize memory with.
+    pub const INVALID: Self = Self {
+        magic: 0,
+        failures: 0,
+        retry_at_us: 0,
+        check: 0,
+    };
+
+    const fn new(failures: u32, retry_at_us: u64) -> Self {
+        Self {
+            magic: MAGIC,
+            failures,
+            retry_at_us,
+            check: !(retry_at_us ^ failures as u64),
+        }
+    }
+
+    /// Returns the schedule, or [`EMPTY`](Self::EMPTY) if it is garbage.
+    pub fn validated(self) -> Self {
+        let valid = self.magic == MAGIC && self.check == !(self.retry_at_us ^ self.failures as u64);
+        if valid {
+            self
+        } else {
+            Self::EMPTY
+        }
+    }
+
+    /// Returns the number of consecutive failed attempts.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Returns the schedule after an attempt failed at `now_us`.
+    pub fn failed(self, backoff: &Backoff, now_us: u64) -> Self {
+        let failures = self.failures.saturating_add(1);
+        let delay_us = backoff.delay(failures) as u64 * 1_000_000;
+
+        Self::new(failures, now_us.saturating_add(delay_us))
+    }
+
+    /// Returns how long until the next attempt at `now_us`, in microseconds.
+    pub fn wait_us(&self, backoff: &Backoff, now_us: u64) -> u64 {
+        let wait_us = self.retry_at_us.saturating_sub(now_us);
+        if wait_us > backoff.max_delay as u64 * 1_000_000 {
+            // The clock has been reset, so the saved time is meaningless.
+            return 0;
+        }
+
+        wait_us
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BACKOFF: Backoff = Backoff {
+        first_delay: 60,
+        max_delay: 3600,
+    };
+
+    const SECOND: u64 = 1_000_000;
+
+    #[test]
+    fn no_wait_before_first_failure() {
+        assert_eq!(BACKOFF.delay(0), 0);
+    }
+
+    #[test]
+    fn wait_doubles_with_every_failure() {
+        assert_eq!(BACKOFF.delay(1), 60);
+        assert_eq!(BACKOFF.delay(2), 120);
+        assert_eq!(BACKOFF.delay(3), 240);
+        assert_eq!(BACKOFF.delay(6), 1920);
+    }
+
+    #[test]
+    fn wait_is_limited() {
+        assert_eq!(BACKOFF.delay(7), 3600);
+        assert_eq!(BACKOFF.delay(33), 3600);
+        assert_eq!(BACKOFF.delay(u32::MAX), 3600);
+
+        let long = Backoff {
+            first_delay: u32::MAX,
+            max_delay: u32::MAX,
+        };
+        assert_eq!(long.delay(40), u32::MAX);
+    }
+
+    #[test]
+    fn garbage_is_not_a_schedule() {
+        assert_eq!(SavedSchedule::INVALID.validated(), SavedSchedule::EMPTY);
+
+        let mut corrupted = SavedSchedule::EMPTY.failed(&BACKOFF, 1000 * SECOND);
+        corrupted.retry_at_us += 1;
+        assert_eq!(corrupted.validated(), SavedSchedule::EMPTY);
+
+        let mut corrupted = SavedSchedule::EMPTY.failed(&BACKOFF, 1000 * SECOND);
+        corrupted.failures += 1;
+        assert_eq!(corrupted.validated(), SavedSchedule::EMPTY);
+    }
+
+    #[test]
+    fn failures_delay_the_next_attempt() {
+        let schedule = SavedSchedule::EMPTY;
+        assert_eq!(schedule.wait_us(&BACKOFF, 1000 * SECOND), 0);
+
+        let schedule = schedule.failed(&BACKOFF, 1000 * SECOND).validated();
+        assert_eq!(schedule.failures(), 1);
+        assert_eq!(schedule.wait_us(&BACKOFF, 1000 * SECOND), 60 * SECOND);
+        assert_eq!(schedule.wait_us(&BACKOFF, 1030 * SECOND), 30 * SECOND);
+        assert_eq!(schedule.wait_us(&BACKOFF, 1060 * SECOND), 0);
+
+        let schedule = schedule.failed(&BACKOFF, 1060 * SECOND).validated();
+        assert_eq!(schedule.failures(), 2);
+        assert_eq!(schedule.wait_us(&BACKOFF, 1060 * SECOND), 120 * SECOND);
+    }
+
+    #[test]
+    fn clock_reset_makes_the_attempt_due() {
+        let schedule = SavedSchedule::EMPTY.failed(&BACKOFF, 100_000 * SECOND);
+
+        // The clock restarted from zero, the wait looks longer than any backoff.
+        assert_eq!(schedule.wait_us(&BACKOFF, 5 * SECOND), 0);
+    }
+}
@@ -0,0 +1,680 @@
+//! Decodes measurements recorded by the firmware and exports them in formats that other tools
+//! can open.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context as _, Result as AnyResult};
+use clap::ValueEnum;
+use signal_processing::{
+    compressing_buffer::EkgDecoder,
+    heart_rate::Beat,
+    measurement::{LeadOff, Measurement},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Comma separated values: time, voltage and beat markers.
+    Csv,
+    /// European Data Format, with beats and lead-off periods as annotations.
+    Edf,
+    /// PhysioNet WFDB record: header, signal and beat annotation files.
+    Wfdb,
+}
+
+/// Where the measurement blob comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Source {
+    /// A file stored on the device. The format version is a single byte.
+    Stored,
+    /// The body of an upload request. The format version is a 4-byte little-endian number.
+    Uploaded,
+}
+
+/// A decoded measurement.
+struct Recording {
+    sample_rate: u16,
+    start_time: Option<u64>,
+    firmware: Option<String>,
+    hardware: Option<String>,
+    /// Samples in millivolts.
+    samples: Vec<f32>,
+    beats: Vec<Beat>,
+    lead_off: Vec<LeadOff>,
+}
+
+impl Recording {
+    fn decode(bytes: &[u8], source: Source) -> AnyResult<Self> {
+        let (version, payload) = match source {
+            Source::Stored => {
+                let [version, payload @ ..] = bytes else {
+                    bail!("File is empty");
+                };
+                (*version as u32, payload)
+            }
+            Source::Uploaded => {
+                let [v0, v1, v2, v3, payload @ ..] = bytes else {
+                    bail!("File is too short");
+                };
+                (u32::from_le_bytes([*v0, *v1, *v2, *v3]), payload)
+            }
+        };
+
+        let measurement = Measurement::parse(version, payload)
+            .map_err(|e| anyhow!("Failed to parse measurement: {e:?}"))?;
+        let header = &measurement.header;
+
+        let Some(mut decoder) = EkgDecoder::new(header.sample_format) else {
+            bail!("Unsupported sample format: {}", header.sample_format);
+        };
+        let volts_per_lsb = header
+            .volts_per_lsb()
+            .context("The measurement does not describe the ADC settings")?;
+
+        let mut reader = measurement.samples;
+        let mut samples = Vec::new();
+        while let Some(sample) = decoder
+            .read(&mut reader)
+            .map_err(|e| anyhow!("Failed to decode samples: {e:?}"))?
+        {
+            samples.push(sample as f32 * volts_per_lsb * 1000.0);
+        }
+
+        Ok(Self {
+            sample_rate: header.sample_rate.unwrap_or(1000),
+            start_time: header.start_time,
+            firmware: header.firmware.map(String::from),
+            hardware: header.hardware.map(String::from),
+            samples,
+            beats: measurement.beats().collect(),
+            lead_off: header.lead_off.iter().collect(),
+        })
+    }
+
+    fn time(&self, sample: u32) -> f64 {
+        sample as f64 / self.sample_rate as f64
+    }
+
+    fn duration(&self) -> f64 {
+        self.time(self.samples.len() as u32)
+    }
+
+    /// Returns the scaling used by the 16-bit formats, so that the samples use the whole range.
+    fn quantization(&self) -> Quantization {
+        let (min, max) = self
+            .samples
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), sample| {
+                (min.min(*sample), max.max(*sample))
+            });
+        if min > max {
+            return Quantization {
+                gain: 1000.0,
+                baseline: 0,
+            };
+        }
+
+        // Leave at least 1 uV per unit to avoid blowing up noise in flat recordings.
+        let half_range = ((max - min) as f64 / 2.0).max(i16::MAX as f64 / 1000.0);
+        let gain = i16::MAX as f64 / half_range;
+        let middle = (max + min) as f64 / 2.0;
+
+        Quantization {
+            gain,
+            baseline: -(middle * gain).round() as i32,
+        }
+    }
+
+    fn digital_samples(&self, quantization: Quantization) -> impl Iterator<Item = i16> + '_ {
+        self.samples
+            .iter()
+            .map(move |sample| quantization.digital(*sample))
+    }
+}
+
+/// Conversion between millivolts and 16-bit ADC units.
+#[derive(Clone, Copy)]
+struct Quantization {
+    /// ADC units per millivolt.
+    gain: f64,
+    /// The ADC value of 0 mV.
+    baseline: i32,
+}
+
+impl Quantization {
+    fn digital(self, millivolts: f32) -> i16 {
+        let value = (millivolts as f64 * self.gain).round() as i32 + self.baseline;
+        value.clamp(-(i16::MAX as i32), i16::MAX as i32) as i16
+    }
+
+    fn physical(self, digital: i16) -> f64 {
+        (digital as i32 - self.baseline) as f64 / self.gain
+    }
+}
+
+pub fn export(
+    input: &Path,
+    source: Source,
+    format: ExportFormat,
+    output: Option<PathBuf>,
+) -> AnyResult<()> {
+    let bytes = fs::read(input).with_context(|| format!("Failed to read {}", input.display()))?;
+    let recording = Recording::decode(&bytes, source)?;
+
+    println!(
+        "📈  {} samples ({:.1} s), {} beats, {} lead-off periods",
+        recording.samples.len(),
+        recording.duration(),
+        recording.beats.len(),
+        recording.lead_off.len()
+    );
+
+    let output = match output {
+        Some(output) => output,
+        None => default_output(input, format)?,
+    };
+
+    match format {
+        ExportFormat::Csv => export_csv(&recording, &output)?,
+        ExportFormat::Edf => export_edf(&recording, &output)?,
+        ExportFormat::Wfdb => export_wfdb(&recording, &output)?,
+    }
+
+    println!("💾  Exported to {}", output.display());
+
+    Ok(())
+}
+
+/// Returns the path of the exported file next to `input`. The extension is appended, because the
+/// measurement files are named `meas.N`.
+fn default_output(input: &Path, format: ExportFormat) -> AnyResult<PathBuf> {
+    let file_name = input
+        .file_name()
+        .and_then(|name| name.to_str())
+        .context("Invalid input file name")?;
+
+    let file_name = match format {
+        ExportFormat::Csv => format!("{file_name}.csv"),
+        ExportFormat::Edf => format!("{file_name}.edf"),
+        // WFDB record names may only contain letters, digits and underscores.
+        ExportFormat::Wfdb => format!("{}.hea", file_name.replace('.', "_")),
+    };
+
+    Ok(input.with_file_name(file_name))
+}
+
+fn export_csv(recording: &Recording, output: &Path) -> AnyResult<()> {
+    let mut csv = String::from("time_s,ecg_mv,beat,lead_off\n");
+
+    let mut beats = recording.beats.iter().peekable();
+    for (i, sample) in recording.samples.iter().enumerate() {
+        let i = i as u32;
+
+        let mut beat = false;
+        while let Some(next) = beats.next_if(|beat| beat.sample_index <= i) {
+            beat |= next.sample_index == i;
+        }
+        let lead_off = recording
+            .lead_off
+            .iter()
+            .any(|interval| (interval.start..interval.end).contains(&i));
+
+        writeln!(
+            csv,
+            "{:.3},{:.4},{},{}",
+            recording.time(i),
+            sample,
+            beat as u8,
+            lead_off as u8
+        )?;
+    }
+
+    fs::write(output, csv)?;
+
+    Ok(())
+}
+
+/// Returns the civil date and time of a Unix timestamp, as (year, month, day, hour, min, sec).
+fn civil_time(timestamp: u64) -> (u64, u64, u64, u64, u64, u64) {
+    let days = timestamp / 86400;
+    let secs = timestamp % 86400;
+
+    // Howard Hinnant's `civil_from_days`, for days after 1970-01-01.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    (year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Appends a space-padded ASCII field. EDF only allows printable ASCII characters, others are
+/// replaced by `_`.
+fn edf_field(header: &mut String, value: &str, len: usize) {
+    let value = value
+        .chars()
+        .map(|c| match c {
+            ' '..='~' => c,
+            _ => '_',
+        })
+        .take(len)
+        .collect::<String>();
+    write!(header, "{value:<len$}").unwrap();
+}
+
+/// Formats a number so that it fits in an 8 character EDF field.
+fn edf_number(value: f64) -> String {
+    let mut formatted = format!("{value:.3}");
+    formatted.truncate(8);
+    formatted.trim_end_matches('.').to_string()
+}
+
+fn export_edf(recording: &Recording, output: &Path) -> AnyResult<()> {
+    // One second long data records.
+    let samples_per_record = recording.sample_rate as usize;
+    let record_count = recording.samples.len().div_ceil(samples_per_record);
+
+    // Time-stamped annotation lists of each record. The first one in each record is the record's
+    // start time.
+    let mut annotations = (0..record_count)
+        .map(|record| format!("+{record}\x14\x14\0"))
+        .collect::<Vec<_>>();
+    for beat in &recording.beats {
+        let record = beat.sample_index as usize / samples_per_record;
+        if let Some(tal) = annotations.get_mut(record) {
+            write!(
+                tal,
+                "+{:.3}\x14Beat\x14\0",
+                recording.time(beat.sample_index)
+            )?;
+        }
+    }
+    for interval in &recording.lead_off {
+        let record = interval.start as usize / samples_per_record;
+        if let Some(tal) = annotations.get_mut(record) {
+            write!(
+                tal,
+                "+{:.3}\x15{:.3}\x14Lead off\x14\0",
+                recording.time(interval.start),
+                recording.time(interval.end) - recording.time(interval.start)
+            )?;
+        }
+    }
+    let annotation_samples = annotations
+        .iter()
+        .map(|tal| tal.len().div_ceil(2))
+        .max()
+        .unwrap_or(1);
+
+    let quantization = recording.quantization();
+
+    let (year, month, day, hour, minute, second) = civil_time(recording.start_time.unwrap_or(0));
+    const MONTHS: [&str; 12] = [
+        "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+    ];
+    let start_date = if recording.start_time.is_some() {
+        format!("{day:02}-{}-{year}", MONTHS[month as usize - 1])
+    } else {
+        String::from("X")
+    };
+    let equipment = format!(
+        "Card/IO_{}_{}",
+        recording.hardware.as_deref().unwrap_or("X"),
+        recording.firmware.as_deref().unwrap_or("X")
+    );
+
+    const SIGNALS: usize = 2;
+    let mut header = String::new();
+    edf_field(&mut header, "0", 8);
+    edf_field(&mut header, "X X X X", 80);
+    edf_field(
+        &mut header,
+        &format!("Startdate {start_date} X X {}", equipment.replace(' ', "_")),
+        80,
+    );
+    edf_field(
+        &mut header,
+        &format!("{day:02}.{month:02}.{:02}", year % 100),
+        8,
+    );
+    edf_field(
+        &mut header,
+        &format!("{hour:02}.{minute:02}.{second:02}"),
+        8,
+    );
+    edf_field(&mut header, &(256 * (SIGNALS + 1)).to_string(), 8);
+    edf_field(&mut header, "EDF+C", 44);
+    edf_field(&mut header, &record_count.to_string(), 8);
+    edf_field(&mut header, "1", 8);
+    edf_field(&mut header, &SIGNALS.to_string(), 4);
+
+    let signal_fields: [(&str, &str, usize); 10] = [
+        ("ECG", "EDF Annotations", 16),
+        ("AgAgCl electrode", "", 80),
+        ("mV", "", 8),
+        (&edf_number(quantization.physical(-i16::MAX)), "-1", 8),
+        (&edf_number(quantization.physical(i16::MAX)), "1", 8),
+        ("-32767", "-32768", 8),
+        ("32767", "32767", 8),
+        ("", "", 80),
+        (
+            &samples_per_record.to_string(),
+            &annotation_samples.to_string(),
+            8,
+        ),
+        ("", "", 32),
+    ];
+    for (ecg, annotation, len) in signal_fields {
+        edf_field(&mut header, ecg, len);
+        edf_field(&mut header, annotation, len);
+    }
+
+    let mut edf = header.into_bytes();
+    let mut samples = recording.digital_samples(quantization);
+    for tal in annotations {
+        for _ in 0..samples_per_record {
+            // The last record is padded with zeros.
+            let sample = samples.next().unwrap_or(0);
+            edf.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        let mut tal = tal.into_bytes();
+        tal.resize(annotation_samples * 2, 0);
+        edf.extend_from_slice(&tal);
+    }
+
+    fs::write(output, edf)?;
+
+    Ok(())
+}
+
+/// Appends a WFDB annotation in MIT format.
+fn wfdb_annotation(atr: &mut Vec<u8>, code: u16, delta: u32) {
+    const SKIP: u16 = 59;
+
+    let delta = if delta > 1023 {
+        atr.extend_from_slice(&(SKIP << 10).to_le_bytes());
+        // The skip interval is stored with the most significant half first.
+        atr.extend_from_slice(&((delta >> 16) as u16).to_le_bytes());
+        atr.extend_from_slice(&(delta as u16).to_le_bytes());
+        0
+    } else {
+        delta as u16
+    };
+
+    atr.extend_from_slice(&(code << 10 | delta).to_le_bytes());
+}
+
+fn export_wfdb(recording: &Recording, output: &Path) -> AnyResult<()> {
+    const NORMAL: u16 = 1;
+
+    let record = output
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .context("Invalid output file name")?;
+    let dat_path = output.with_extension("dat");
+    let atr_path = output.with_extension("atr");
+
+    let quantization = recording.quantization();
+
+    let mut dat = Vec::with_capacity(recording.samples.len() * 2);
+    let mut checksum = 0_i16;
+    for sample in recording.digital_samples(quantization) {
+        dat.extend_from_slice(&sample.to_le_bytes());
+        checksum = checksum.wrapping_add(sample);
+    }
+    let first = recording.digital_samples(quantization).next().unwrap_or(0);
+
+    let mut hea = format!(
+        "{record} 1 {} {}\n",
+        recording.sample_rate,
+        recording.samples.len()
+    );
+    writeln!(
+        hea,
+        "{record}.dat 16 {:.3}({})/mV 16 0 {first} {checksum} 0 ECG",
+        quantization.gain, quantization.baseline
+    )?;
+    if let Some(firmware) = recording.firmware.as_deref() {
+        writeln!(hea, "# Firmware: {firmware}")?;
+    }
+    if let Some(hardware) = recording.hardware.as_deref() {
+        writeln!(hea, "# Hardware: {hardware}")?;
+    }
+
+    let mut atr = Vec::new();
+    let mut previous = 0;
+    for beat in &recording.beats {
+        wfdb_annotation(&mut atr, NORMAL, beat.sample_index - previous);
+        previous = beat.sample_index;
+    }
+    atr.extend_from_slice(&[0, 0]);
+
+    fs::write(output, hea)?;
+    fs::write(dat_path, dat)?;
+    fs::write(atr_path, atr)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn recording() -> Recording {
+        Recording {
+            sample_rate: 100,
+            start_time: Some(1_700_000_000),
+            firmware: Some(String::from("0.1.0-abcdef")),
+            hardware: Some(String::from("v6 ☂")),
+            samples: (0..250)
+                .map(|n| (n as f32 * 0.1).sin() * 1.5 + 0.25)
+                .collect(),
+            beats: [20, 100, 1200]
+                .into_iter()
+                .map(|sample_index| Beat {
+                    sample_index,
+                    rr_interval: None,
+                    confidence: 1.0,
+                })
+                .collect(),
+            lead_off: vec![LeadOff {
+                start: 150,
+                end: 170,
+            }],
+        }
+    }
+
+    fn output(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xtask-export-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    fn assert_samples_match(decoded: &[f64], recording: &Recording) {
+        // One step of the 16-bit formats is 1 uV for this recording.
+        assert_eq!(decoded.len(), recording.samples.len());
+        for (i, (decoded, sample)) in decoded.iter().zip(&recording.samples).enumerate() {
+            assert!(
+                (decoded - *sample as f64).abs() < 2e-3,
+                "{i}: {decoded} vs {sample}"
+            );
+        }
+    }
+
+    fn ascii_field(bytes: &[u8], start: usize, len: usize) -> &str {
+        std::str::from_utf8(&bytes[start..start + len])
+            .unwrap()
+            .trim_end()
+    }
+
+    #[test]
+    fn default_output_keeps_the_measurement_index() {
+        let input = Path::new("measurements/meas.3");
+
+        let output = |format| default_output(input, format).unwrap();
+        assert_eq!(
+            output(ExportFormat::Csv),
+            Path::new("measurements/meas.3.csv")
+        );
+        assert_eq!(
+            output(ExportFormat::Edf),
+            Path::new("measurements/meas.3.edf")
+        );
+        assert_eq!(
+            output(ExportFormat::Wfdb),
+            Path::new("measurements/meas_3.hea")
+        );
+    }
+
+    #[test]
+    fn edf_fields_are_ascii() {
+        let mut header = String::new();
+        edf_field(&mut header, "Card/IO_v6 ☂", 12);
+        edf_field(&mut header, "ab", 4);
+        assert_eq!(header, "Card/IO_v6 _ab  ");
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let recording = recording();
+        let path = output("recording.csv");
+        export_csv(&recording, &path).unwrap();
+
+        let csv = fs::read_to_string(&path).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("time_s,ecg_mv,beat,lead_off"));
+
+        let mut samples = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let [time, ecg, beat, lead_off] = line.split(',').collect::<Vec<_>>()[..] else {
+                panic!("{line}");
+            };
+            assert_eq!(time.parse::<f64>().unwrap(), recording.time(i as u32));
+            assert_eq!(beat == "1", [20, 100].contains(&i));
+            assert_eq!(lead_off == "1", (150..170).contains(&i));
+            samples.push(ecg.parse::<f64>().unwrap());
+        }
+
+        assert_samples_match(&samples, &recording);
+    }
+
+    #[test]
+    fn edf_round_trip() {
+        let recording = recording();
+        let path = output("recording.edf");
+        export_edf(&recording, &path).unwrap();
+
+        let edf = fs::read(&path).unwrap();
+        let header_len = ascii_field(&edf, 184, 8).parse::<usize>().unwrap();
+        let records = ascii_field(&edf, 236, 8).parse::<usize>().unwrap();
+        let signals = ascii_field(&edf, 252, 4).parse::<usize>().unwrap();
+        assert_eq!((header_len, records, signals), (768, 3, 2));
+        assert!(edf[..header_len].is_ascii());
+        assert!(ascii_field(&edf, 88, 80).contains("Card/IO_v6__"));
+
+        // Signal fields are stored for all signals, one field after the other.
+        let signal_field = |offset: usize, len: usize, signal: usize| {
+            ascii_field(&edf, 256 + offset * signals + len * signal, len)
+        };
+        assert_eq!(signal_field(0, 16, 0), "ECG");
+        assert_eq!(signal_field(0, 16, 1), "EDF Annotations");
+        let number =
+            |offset: usize, signal: usize| signal_field(offset, 8, signal).parse::<f64>().unwrap();
+        let (physical_min, physical_max) = (number(104, 0), number(112, 0));
+        let (digital_min, digital_max) = (number(120, 0), number(128, 0));
+        let ecg_samples = number(216, 0) as usize;
+        let annotation_samples = number(216, 1) as usize;
+        assert_eq!(ecg_samples, 100);
+
+        let gain = (physical_max - physical_min) / (digital_max - digital_min);
+        let record_len = 2 * (ecg_samples + annotation_samples);
+        let mut samples = Vec::new();
+        let mut annotations = String::new();
+        for record in edf[header_len..].chunks(record_len) {
+            let (ecg, tal) = record.split_at(2 * ecg_samples);
+            for sample in ecg.chunks(2) {
+                let digital = i16::from_le_bytes([sample[0], sample[1]]) as f64;
+                samples.push(physical_min + (digital - digital_min) * gain);
+            }
+            annotations.push_str(std::str::from_utf8(tal).unwrap());
+        }
+        assert_eq!(edf.len(), header_len + records * record_len);
+
+        // The last record is padded
+        samples.truncate(recording.samples.len());
+        assert_samples_match(&samples, &recording);
+
+        assert!(annotations.starts_with("+0\x14\x14\0+0.200\x14Beat\x14\0"));
+        assert!(annotations.contains("+1.000\x14Beat\x14\0"));
+        assert!(annotations.contains("+1.500\x150.200\x14Lead off\x14\0"));
+        // Beats after the end of the recording are dropped
+        assert!(!annotations.contains("+12.000"));
+    }
+
+    #[test]
+    fn wfdb_round_trip() {
+        let recording = recording();
+        let path = output("recording.hea");
+        export_wfdb(&recording, &path).unwrap();
+
+        let hea = fs::read_to_string(&path).unwrap();
+        let mut lines = hea.lines();
+        assert_eq!(lines.next(), Some("recording 1 100 250"));
+
+        let signal = lines.next().unwrap().split(' ').collect::<Vec<_>>();
+        assert_eq!(signal[0], "recording.dat");
+        let (gain, baseline) = signal[2]
+            .strip_suffix(")/mV")
+            .and_then(|gain| gain.split_once('('))
+            .unwrap();
+        let gain = gain.parse::<f64>().unwrap();
+        let baseline = baseline.parse::<f64>().unwrap();
+
+        let dat = fs::read(path.with_extension("dat")).unwrap();
+        let digital = dat
+            .chunks(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect::<Vec<_>>();
+        assert_eq!(signal[5], digital[0].to_string());
+        let checksum = digital.iter().fold(0_i16, |sum, s| sum.wrapping_add(*s));
+        assert_eq!(signal[6], checksum.to_string());
+
+        let samples = digital
+            .iter()
+            .map(|sample| (*sample as f64 - baseline) / gain)
+            .collect::<Vec<_>>();
+        assert_samples_match(&samples, &recording);
+
+        let atr = fs::read(path.with_extension("atr")).unwrap();
+        let mut beats = Vec::new();
+        let mut time = 0;
+        let mut words = atr
+            .chunks(2)
+            .map(|word| u16::from_le_bytes([word[0], word[1]]));
+        while let Some(word) = words.next() {
+            match (word >> 10, word & 0x3FF) {
+                (0, 0) => break,
+                (59, _) => {
+                    let high = words.next().unwrap() as u32;
+                    let low = words.next().unwrap() as u32;
+                    time += (high << 16) | low;
+                }
+                (1, delta) => {
+                    time += delta as u32;
+                    beats.push(time);
+                }
+                other => panic!("Unexpected annotation {other:?}"),
+            }
+        }
+        assert_eq!(beats, [20, 100, 1200]);
+    }
+}
@@ -5,6 +5,10 @@ use clap::{Parser, Subcommand, ValueEnum};
 
 use duct::{cmd, Expression};
 
+use crate::export::{ExportFormat, Source};
+
+mod export;
+
 #[derive(Debug, Subcommand)]
 pub enum Subcommands {
     /// Builds the firmware.
@@ -72,6 +76,24 @@ pub enum Subcommands {
         #[clap(long)]
         watch: bool,
     },
+
+    /// Exports a recorded measurement to a format that other tools can open.
+    Export {
+        /// The measurement file.
+        input: PathBuf,
+
+        /// The format to export to.
+        #[clap(long, short, value_enum, default_value = "csv")]
+        format: ExportFormat,
+
+        /// Where the measurement file comes from.
+        #[clap(long, value_enum, default_value = "stored")]
+        source: Source,
+
+        /// The output file. Defaults to the input file with the extension of the format.
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
 }
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
@@ -259,9 +281,18 @@ fn extra_checks(config: BuildConfig) -> AnyResult<()> {
 }
 
 fn test() -> AnyResult<()> {
-    let packages = ["signal-processing"];
+    let packages = [
+        "signal-processing",
+        "backend-server",
+        "fir-design",
+        "request-signing",
+        "sntp-packet",
+        "tls-pinning",
+        "upload-schedule",
+        "xtask",
+    ];
 
-    let mut args = vec!["test", "--features=dyn_filter"];
+    let mut args = vec!["test", "--features=signal-processing/dyn_filter"];
 
     for p in packages {
         args.push("-p");
@@ -337,6 +368,12 @@ fn main() -> AnyResult<()> {
             name,
             watch,
         } => example(package, name, watch),
+        Subcommands::Export {
+            input,
+            format,
+            source,
+            output,
+        } => export::export(&input, source, format, output),
     }
 }
 